@@ -5,392 +5,659 @@
 //!
 //! - **Device Monitoring**: Continuously listening for USB device insertion and removal events.
 //! - **Device Management**: Maintaining an in-memory tree of connected devices (`DeviceTracker`).
-//! - **Access Control**: Enforcing a whitelist policy to automatically disable unauthorized devices (WIP).
-//! - **Inter-Process Communication (IPC)**: Hosting a TCP server (IOAPI) to allow external tools (like the CLI or GUI shell) to query device status and issue commands.
+//! - **Access Control**: Enforcing the rule-based device policy, disabling unauthorized devices as they are connected.
+//! - **Inter-Process Communication (IPC)**: Hosting the IOAPI server (a Unix domain socket on Linux, optionally localhost TCP) to allow external tools (like the CLI or GUI shell) to query device status and issue commands.
 //!
 //! ## Architecture
 //!
-//! The core service runs a single-threaded event loop that polls for two types of events:
-//! 1. **Network Events**: New TCP connections or incoming data on existing connections.
-//! 2. **System Events**: USB hardware changes detected by the `UsbConnectionCallbacksHandle`.
+//! The core service runs a single-threaded event loop that sleeps in `IoApiServer::poll` until
+//! one of two types of events arrives:
+//! 1. **Network Events**: New TCP connections or complete commands on existing connections.
+//! 2. **System Events**: USB hardware changes detected by the `UsbConnectionCallbacksHandle`,
+//!    whose listener wakes the server up whenever it queues an event.
+//!
+//! The loop also wakes up once per `POLICY_RELOAD_INTERVAL` to check the policy file.
+//!
+//! Which devices are tracked is decided by the device filter file (`device_filter_path`), which
+//! is read once at startup.
+//!
+//! Both kinds of events may produce `IoApiEvent`s, which are pushed to every connection that
+//! subscribed to them.
+//!
+//! Every device event, policy decision, device state change and refused IOAPI command is
+//! written to the audit log.
 //!
 //! ## Usage
 //!
 //! This binary is intended to be run as a background service (daemon) with administrative privileges,
 //! as it requires access to the Windows SetupAPI to enable/disable drivers.
+//!
+//! `core verify-audit-log [PATH]` verifies the audit log instead of starting the service; the
+//! exit code is non-zero if the log was tampered with.
 
 use std::{
-    io::{Read, Write},
-    net::{Ipv4Addr, TcpListener, TcpStream},
-    rc::Rc,
+    collections::HashMap,
+    net::{Ipv4Addr, TcpListener},
+    path::PathBuf,
     sync::mpsc::TryRecvError,
+    time::{Duration, Instant},
 };
 
 use anyhow::Result;
 
-use comp_gate::{
-    helper::{device_managment::DeviceId, ioapi::connection_file_path},
-    *,
-};
-use error::PollEventError;
+use comp_gate::*;
+use error::{DeviceBackendError, PolicyFileError, PollEventError};
 use helper::{
-    device_managment::{DeviceTracker, device_path_to_device_id},
-    ioapi::IoApiCommand,
+    audit_log::{AuditEntry, AuditLog, audit_log_path, read_audit_log, verify_audit_log},
+    connection_events::handle_connection_event,
+    device_backend::default_backend,
+    device_filter::{DeviceFilter, device_filter_path},
+    device_managment::{Device, DeviceId, DeviceIterator, DeviceState, DeviceTracker},
+    ioapi::{
+        EventFilter, IoApiCommand, IoApiEvent, IoApiEventKind, IoApiResponse, IoApiResponseBody,
+        IoApiStatus, PROTOCOL_VERSION, connection_file_path, runtime_dir,
+    },
+    ioapi_access::{IoApiAccess, Role, ioapi_access_path},
+    ioapi_auth::{AuthSecret, ioapi_token_path, prepare_runtime_dir, replace_runtime_file},
+    ioapi_server::{ConnectionId, IoApiListener, IoApiServer, ServerEvent},
+    policy::PolicyAction,
     usb_connection_callback::{UsbConnectionCallbacksHandle, UsbConnectionEvent},
-    whitelist::Whitelist,
+    whitelist::{EnforcementRecord, Whitelist},
 };
 
+/// The number of connection log lines kept in memory (and served by `GetDeviceConnectionLogs`).
+const MAX_CONNECTION_LOGS: usize = 1000;
+
+/// How often the policy file is checked for changes.
+const POLICY_RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The number of IOAPI clients served at the same time.
+const MAX_IOAPI_CONNECTIONS: usize = 64;
+
+/// The number of IOAPI clients served at the same time per user (or, for TCP clients that did
+/// not authenticate yet, in total).
+const MAX_IOAPI_CONNECTIONS_PER_PEER: usize = 16;
+
 // TODO list of tasks to implement:
 // - [#] Implement device tracking functionality
 // - [#] Implement device blocking functionality
 // - [#] Implement whitelist functionality
-// - [_] Combine last three points into a Whitelist/Blacklist system
-// - [_] Implement GUI using egui around the core functionality
+// - [#] Combine last three points into a Whitelist/Blacklist system
+// - [#] Implement GUI using egui around the core functionality
 
 /// The main entry point for the Core service.
 ///
 /// It performs the following initialization steps:
-/// 1. Binds a TCP listener to a random local port for the IOAPI.
-/// 2. Writes the connection address to a known file path so clients can find it.
+/// 1. Starts the IOAPI server on the IOAPI socket (Linux) and/or a random local TCP port.
+/// 2. Writes the connection addresses to a known file path so clients can find them, and the
+///    secret TCP clients authenticate with to a file only the user of the core can read. Both
+///    live in the runtime directory, which only the user of the core can write to.
 /// 3. Loads the initial state of connected USB/HID devices.
-/// 4. Initializes the whitelist system.
-/// 5. Starts the background thread for USB event monitoring.
+/// 4. Loads the device policy from the policy file (creating it on first start) and enforces it.
+/// 5. Opens the audit log and restores the recent connection logs from it.
+/// 6. Starts the background thread for USB event monitoring, waking the IOAPI server on events.
 ///
 /// Then it enters the main event loop.
 fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    if let Some(command) = args.next() {
+        if command != "verify-audit-log" {
+            anyhow::bail!(
+                "Unknown command `{}`, expected `verify-audit-log [PATH]`",
+                command
+            );
+        }
+        let path = args
+            .next()
+            .map(PathBuf::from)
+            .unwrap_or_else(audit_log_path);
+        let records = verify_audit_log(&path)?;
+        println!("{}: {} records, hash chain intact", path.display(), records);
+        return Ok(());
+    }
+
     // IO API stuff
-    let ioapi_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
-    ioapi_listener.set_nonblocking(true)?;
-    println!(
-        "Application IO API on address: {}",
-        ioapi_listener.local_addr()?
-    );
-    std::fs::write(
-        connection_file_path(),
-        ioapi_listener.local_addr()?.to_string(),
+    prepare_runtime_dir(&runtime_dir())?;
+    let ioapi_access = IoApiAccess::load(&ioapi_access_path())?;
+    let mut ioapi_listeners: Vec<IoApiListener> = vec![];
+    #[cfg(target_os = "linux")]
+    ioapi_listeners.push(IoApiListener::bind_unix(
+        &helper::ioapi::ioapi_socket_path(),
+    )?);
+    if ioapi_access.tcp_enabled() {
+        ioapi_listeners.push(TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?.into());
+    }
+
+    let ioapi_secret = AuthSecret::generate()?;
+    ioapi_secret.write(&ioapi_token_path())?;
+
+    let mut ioapi_server = IoApiServer::new(ioapi_listeners, MAX_IOAPI_CONNECTIONS)?
+        .with_auth_secret(ioapi_secret)
+        .with_max_connections_per_peer(MAX_IOAPI_CONNECTIONS_PER_PEER);
+    let ioapi_addresses = ioapi_server.addresses()?;
+    for address in ioapi_addresses.iter() {
+        println!("Application IO API on address: {}", address);
+    }
+    replace_runtime_file(
+        &connection_file_path(),
+        ioapi_addresses
+            .iter()
+            .map(|address| format!("{}\n", address))
+            .collect::<String>()
+            .as_bytes(),
+        false,
     )?;
 
-    let mut ioapi_connections: Vec<TcpStream> = vec![];
+    let mut ioapi_sessions: HashMap<ConnectionId, IoApiSession> = HashMap::new();
 
     // Device Tracker stuff
-    let device_tracker = DeviceTracker::load()?;
+    let device_filter = DeviceFilter::load(&device_filter_path())?;
+    let mut device_tracker = DeviceTracker::load_with_filter(default_backend()?, device_filter)?;
+    // Devices connected from now on wait for the policy before they are enabled.
+    device_tracker.disable_new_devices()?;
     println!("{}", device_tracker);
 
     let mut whitelist = Whitelist::new(device_tracker)?;
+    let mut last_policy_check = Instant::now();
 
-    let callback_handle = UsbConnectionCallbacksHandle::setup_connection_callbacks()?;
+    let waker = ioapi_server.waker()?;
+    let callback_handle =
+        UsbConnectionCallbacksHandle::setup_connection_callbacks_with_waker(move || waker.wake())?;
 
-    let mut device_connection_logs: Vec<Box<str>> = vec![];
+    // Audit log stuff
+    let audit_path = audit_log_path();
+    if let Err(e) = verify_audit_log(&audit_path) {
+        println!("Warning: Audit log verification failed: {}", e);
+    }
+    let mut device_connection_logs: Vec<Box<str>> = read_audit_log(&audit_path)
+        .unwrap_or_default()
+        .iter()
+        .rev()
+        .take(MAX_CONNECTION_LOGS)
+        .rev()
+        .map(|record| record.message.clone().into_boxed_str())
+        .collect();
+    let mut audit_log = AuditLog::open(&audit_path)?;
+
+    for record in whitelist.apply_whitelist() {
+        record_enforcement(
+            record,
+            &whitelist.device_tracker,
+            &mut device_connection_logs,
+            &mut audit_log,
+            &mut vec![],
+        );
+    }
 
-    loop {
-        // IO API logic
-        handle_new_ioapi_connection(&ioapi_listener, &mut ioapi_connections);
+    'event_loop: loop {
+        // Sleep until a client or the USB listener needs attention, or the policy is due.
+        let policy_check_due = POLICY_RELOAD_INTERVAL.saturating_sub(last_policy_check.elapsed());
+        let server_events = ioapi_server.poll(policy_check_due)?;
 
-        let mut closed_connections = Vec::new();
-        for (index, connection) in ioapi_connections.iter_mut().enumerate() {
-            // Read message length (first 4 bytes)
-            let mut length_buf = [0u8; 4];
-            match connection.read_exact(&mut length_buf) {
-                Ok(_) => {
-                    let message_length = u32::from_be_bytes(length_buf) as usize;
-                    println!("recving a packet of size {}", message_length);
-
-                    let cmd = parse_cmd_message(connection, message_length);
-                    let cmd = if cmd.is_some() {
-                        println!("Command parsed successfully: {:?}", cmd);
-                        cmd.unwrap()
-                    } else {
-                        println!("Error parsing command message");
+        // IO API logic
+        let mut pending_events = Vec::new();
+        for server_event in server_events {
+            match server_event {
+                ServerEvent::Connected { id, peer } => {
+                    ioapi_sessions.insert(
+                        id,
+                        IoApiSession {
+                            actor: format!("ioapi:{}", peer),
+                            role: ioapi_access.role_of(&peer),
+                            handshake_completed: false,
+                            subscription: None,
+                        },
+                    );
+                }
+                ServerEvent::Disconnected(id) => {
+                    ioapi_sessions.remove(&id);
+                }
+                ServerEvent::Command { id, command } => {
+                    let Some(session) = ioapi_sessions.get_mut(&id) else {
                         continue;
                     };
 
-                    handle_ioapi_command(
-                        cmd,
-                        &whitelist.device_tracker,
-                        connection,
-                        &mut device_connection_logs,
-                    );
-                }
-                Err(e) if e.kind() != std::io::ErrorKind::WouldBlock => {
-                    closed_connections.push(index);
-                    println!("Error reading from IO API connection: {}, {}", e, e.kind());
+                    let response = match command {
+                        Ok(cmd) => {
+                            println!("Command parsed successfully: {:?}", cmd);
+                            let mut command_events = Vec::new();
+                            let response = handle_ioapi_command(
+                                cmd,
+                                &mut whitelist,
+                                session,
+                                &device_connection_logs,
+                                &mut command_events,
+                            );
+                            let mut policy_changed = false;
+                            for event in command_events.iter() {
+                                device_connection_logs.push(event.message.clone().into_boxed_str());
+                                let entry = audit_entry(
+                                    event,
+                                    &session.actor,
+                                    &whitelist.device_tracker.devices,
+                                );
+                                audit(&mut audit_log, entry);
+                                policy_changed |= event.kind == IoApiEventKind::PolicyChanged;
+                            }
+                            pending_events.extend(command_events);
+
+                            if policy_changed {
+                                for record in whitelist.apply_whitelist() {
+                                    record_enforcement(
+                                        record,
+                                        &whitelist.device_tracker,
+                                        &mut device_connection_logs,
+                                        &mut audit_log,
+                                        &mut pending_events,
+                                    );
+                                }
+                            }
+                            response
+                        }
+                        Err(e) => {
+                            println!("Error parsing command message: {}", e);
+                            IoApiResponse::error(IoApiStatus::BadRequest, e.to_string())
+                        }
+                    };
+
+                    ioapi_server.send(id, &response.to_frame());
                 }
-                _ => {}
             }
         }
 
-        for index in closed_connections {
-            ioapi_connections.remove(index);
-        }
-
         // Device Tracking logic
-        match callback_handle.poll_events() {
-            Ok(event) => handle_connection_event(
+        loop {
+            let event = match callback_handle.poll_events() {
+                Ok(event) => event,
+                Err(PollEventError::ThreadRecvError(TryRecvError::Empty)) => break,
+                Err(PollEventError::ThreadFinished) => {
+                    println!("USB connection callback thread has finished");
+                    break 'event_loop;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            // Removed devices are gone from the tracker afterwards; their USB identity is taken
+            // from the tree before the event.
+            let previous_devices = match event {
+                UsbConnectionEvent::Connected(_) => HashMap::new(),
+                _ => whitelist.device_tracker.devices.clone(),
+            };
+            let events = handle_connection_event(
                 event,
                 &mut whitelist.device_tracker,
                 &mut device_connection_logs,
-            ),
-            Err(e) => match e {
-                PollEventError::ThreadFinished => {
-                    println!("USB connection callback thread has finished");
-                    break;
+            );
+            // The policy decides about root-level devices when they arrive; sub-devices showing
+            // up later (e.g. interfaces) follow the decision already made for their root.
+            let mut enforced_devices = Vec::new();
+            for event in events.iter() {
+                let mut entry = audit_entry(event, "system", &whitelist.device_tracker.devices);
+                if entry.vendor_id.is_none() {
+                    entry = audit_entry(event, "system", &previous_devices);
+                }
+                audit(&mut audit_log, entry);
+                if let (IoApiEventKind::Connected, Some(device_id)) = (event.kind, &event.device_id)
+                    && whitelist.device_tracker.devices.contains_key(device_id)
+                    && !enforced_devices.contains(device_id)
+                {
+                    enforced_devices.push(device_id.clone());
+                }
+            }
+            pending_events.extend(events);
+
+            for device_id in enforced_devices {
+                if let Some(record) = whitelist.enforce_device(&device_id) {
+                    record_enforcement(
+                        record,
+                        &whitelist.device_tracker,
+                        &mut device_connection_logs,
+                        &mut audit_log,
+                        &mut pending_events,
+                    );
                 }
-                PollEventError::ThreadRecvError(TryRecvError::Empty) => {}
-                _ => {
-                    return Err(e.into());
+            }
+        }
+
+        // Policy logic
+        if last_policy_check.elapsed() >= POLICY_RELOAD_INTERVAL {
+            last_policy_check = Instant::now();
+            match whitelist.reload_policy_if_changed() {
+                Ok(true) => {
+                    let log = format!(
+                        "Policy reloaded: {} rules, default action {}",
+                        whitelist.policy().rules.len(),
+                        whitelist.policy().default_action
+                    );
+                    println!("{}", log);
+                    device_connection_logs.push(log.clone().into_boxed_str());
+                    let event = IoApiEvent::new(IoApiEventKind::PolicyChanged, None, log);
+                    audit(
+                        &mut audit_log,
+                        AuditEntry::from_event(&event, "policy_file"),
+                    );
+                    pending_events.push(event);
+
+                    for record in whitelist.apply_whitelist() {
+                        record_enforcement(
+                            record,
+                            &whitelist.device_tracker,
+                            &mut device_connection_logs,
+                            &mut audit_log,
+                            &mut pending_events,
+                        );
+                    }
                 }
-            },
+                Ok(false) => {}
+                Err(e) => println!("Keeping the last good policy: {}", e),
+            }
+        }
+
+        push_events(&mut ioapi_server, &ioapi_sessions, &pending_events);
+
+        if device_connection_logs.len() > MAX_CONNECTION_LOGS {
+            let excess = device_connection_logs.len() - MAX_CONNECTION_LOGS;
+            device_connection_logs.drain(..excess);
         }
     }
 
     Ok(())
 }
 
-/// Accepts new incoming TCP connections on the IOAPI listener.
+/// The protocol state of an IOAPI connection, as seen by the command handler.
+struct IoApiSession {
+    /// Who is on the other side of the connection, as recorded in the audit log.
+    actor: String,
+    /// The permissions of the client.
+    role: Role,
+    /// Whether the client completed the protocol handshake.
+    handshake_completed: bool,
+    /// The events the client subscribed to, if it did.
+    subscription: Option<EventFilter>,
+}
+
+/// Executes an IOAPI command and builds the response for it.
 ///
-/// This function is non-blocking. It accepts all currently pending connections
-/// and adds them to the `connections` vector.
+/// Every command other than `Handshake` is refused until the handshake succeeded, and commands
+/// the role of the client does not permit are refused with an `AccessDenied` event.
 ///
 /// # Arguments
 ///
-/// * `listener` - The bound TCP listener.
-/// * `connections` - The vector to store active connections.
-fn handle_new_ioapi_connection(listener: &TcpListener, connections: &mut Vec<TcpStream>) {
-    loop {
-        match listener.accept() {
-            Ok((tcp_connection, _addr)) => {
-                connections.push(tcp_connection);
-            }
-            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                // No more pending connections right now
-                break;
-            }
-            Err(e) => {
-                println!("Error accepting IO API connection: {}", e);
-                break;
-            }
+/// * `cmd` - The command to execute.
+/// * `whitelist` - The policy and the tracker holding the current device tree.
+/// * `session` - The protocol state of the connection the command came from.
+/// * `device_connection_logs` - The log of connection events.
+/// * `events` - Receives the events caused by the command.
+fn handle_ioapi_command(
+    cmd: IoApiCommand,
+    whitelist: &mut Whitelist,
+    session: &mut IoApiSession,
+    device_connection_logs: &[Box<str>],
+    events: &mut Vec<IoApiEvent>,
+) -> IoApiResponse {
+    if let IoApiCommand::Handshake { protocol_version } = cmd {
+        if protocol_version != PROTOCOL_VERSION {
+            return IoApiResponse::error(
+                IoApiStatus::VersionMismatch,
+                format!(
+                    "Protocol version {} is not supported, the core speaks version {}",
+                    protocol_version, PROTOCOL_VERSION
+                ),
+            );
         }
+
+        session.handshake_completed = true;
+        return IoApiResponse::ok(IoApiResponseBody::Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            role: session.role,
+        });
     }
-}
 
-/// Parses a raw byte message from a TCP stream into an `IoApiCommand`.
-///
-/// # Arguments
-///
-/// * `connection` - The TCP stream to read from.
-/// * `message_length` - The expected length of the message payload.
-///
-/// # Returns
-///
-/// * `Some(IoApiCommand)` - If parsing is successful.
-/// * `None` - If reading fails or the command is invalid.
-fn parse_cmd_message(connection: &mut TcpStream, message_length: usize) -> Option<IoApiCommand> {
-    let mut message_buf = vec![0u8; message_length];
-    // TODO WARING: logical BUG if the read_exact return would block this code bugs out everything
-    if let Ok(_) = connection.read_exact(&mut message_buf) {
-        if message_buf.len() >= 1 {
-            let command_code = message_buf[0];
-
-            let args_data = &message_buf[1..];
-            let args_str = String::from_utf8_lossy(args_data);
-            let arguments: Vec<Rc<str>> = args_str.split(" ").map(Rc::from).collect();
-            return IoApiCommand::try_from((command_code, arguments)).ok();
-        }
+    if !session.handshake_completed {
+        return IoApiResponse::error(
+            IoApiStatus::HandshakeRequired,
+            "The handshake has to be completed first",
+        );
     }
-    None
-}
 
-/// Helper function to wrap a byte slice into a length-prefixed payload.
-///
-/// The format is `[4 bytes length (Big Endian)][payload]`.
-fn convert_bytes_to_payload(bytes: &[u8]) -> Box<[u8]> {
-    let length_prefix = (bytes.len() as u32).to_be_bytes();
-    [&length_prefix, bytes].concat().into_boxed_slice()
-}
+    if let Err(e) = session.role.authorize(&cmd) {
+        let message = format!("Refused command from {}: {}", session.actor, e);
+        println!("{}", message);
+        let device_id = match &cmd {
+            IoApiCommand::EnableDevice(device_id)
+            | IoApiCommand::DisableDevice(device_id)
+            | IoApiCommand::ApproveDevice(device_id) => Some(device_id.clone()),
+            _ => None,
+        };
+        events.push(IoApiEvent::new(
+            IoApiEventKind::AccessDenied,
+            device_id,
+            message,
+        ));
+
+        return IoApiResponse::error(IoApiStatus::Forbidden, e.to_string());
+    }
 
-fn handle_ioapi_command(
-    cmd: IoApiCommand,
-    device_tracker: &DeviceTracker,
-    connection: &mut TcpStream,
-    device_connection_logs: &Vec<Box<str>>,
-) {
+    if matches!(
+        cmd,
+        IoApiCommand::GetDeviceList | IoApiCommand::QueryDevices(_)
+    ) {
+        whitelist.device_tracker.refresh_device_states();
+    }
+
+    let device_tracker = &whitelist.device_tracker;
     match cmd {
+        IoApiCommand::Handshake { .. } => unreachable!("handled above"),
         IoApiCommand::GetDeviceList => {
-            let payload = convert_bytes_to_payload(device_tracker.to_string().as_bytes());
+            let devices = device_tracker
+                .devices
+                .iter()
+                .map(|(id, device)| (id.clone(), device.clone()))
+                .collect();
 
-            connection.write_all(&payload).unwrap_or_else(|err| {
-                println!("Error writing to IO API connection: {}", err);
-            });
+            IoApiResponse::ok(IoApiResponseBody::DeviceList { devices })
+        }
+        IoApiCommand::QueryDevices(query) => {
+            let devices = device_tracker
+                .query(&query)
+                .into_iter()
+                .map(|device| Device {
+                    devices: HashMap::new(),
+                    ..device.clone()
+                })
+                .collect();
+
+            IoApiResponse::ok(IoApiResponseBody::Devices { devices })
         }
         IoApiCommand::GetDeviceConnectionLogs => {
-            let mut core_payload = vec![0u8; 1024];
-            for log in device_connection_logs.iter() {
-                core_payload.extend_from_slice(&log.as_bytes());
-                core_payload.push(b'\n');
-            }
-
-            connection
-                .write_all(&convert_bytes_to_payload(&core_payload))
-                .unwrap_or_else(|err| {
-                    println!("Error writing to IO API connection: {}", err);
-                });
+            IoApiResponse::ok(IoApiResponseBody::DeviceConnectionLogs {
+                logs: device_connection_logs
+                    .iter()
+                    .map(|log| log.to_string())
+                    .collect(),
+            })
         }
         IoApiCommand::EnableDevice(device_id) => {
             println!("Enabling device: {}", device_id);
-            let payload = if let Err(e) = device_tracker
-                .set_device_state(&device_id, helper::device_managment::DeviceState::Enable)
-            {
-                convert_bytes_to_payload(format!("Enabling device failed: {}", e).as_bytes())
-            } else {
-                convert_bytes_to_payload(b"Device enabled.")
-            };
-
-            connection
-                .write_all(&convert_bytes_to_payload(&payload))
-                .unwrap_or_else(|err| {
-                    println!("Error writing to IO API connection: {}", err);
-                });
+            match device_tracker.set_device_state(&device_id, DeviceState::Enable) {
+                Ok(_) => {
+                    events.push(IoApiEvent::new(
+                        IoApiEventKind::Unblocked,
+                        Some(device_id.clone()),
+                        format!("USB Device enabled: {}", device_id),
+                    ));
+                    IoApiResponse::ok_with_message("Device enabled.")
+                }
+                Err(e) => state_change_error_response("Enabling device failed", e),
+            }
         }
-        IoApiCommand::DisableDevice(device_id) => {
-            println!("Disabling device: {}", device_id);
-            let payload = if let Err(e) = device_tracker
-                .set_device_state(&device_id, helper::device_managment::DeviceState::Disable)
-            {
-                convert_bytes_to_payload(format!("Disabling device failed: {}", e).as_bytes())
-            } else {
-                convert_bytes_to_payload(b"Device disabled.")
-            };
-
-            connection
-                .write_all(&convert_bytes_to_payload(&payload))
-                .unwrap_or_else(|err| {
-                    println!("Error writing to IO API connection: {}", err);
-                });
+        IoApiCommand::ApproveDevice(device_id) => {
+            println!("Approving device: {}", device_id);
+            match whitelist.approve_device(&device_id) {
+                // The policy change makes the caller apply the policy, which enables the device.
+                Ok(Some(root_id)) => {
+                    events.push(IoApiEvent::new(
+                        IoApiEventKind::PolicyChanged,
+                        Some(root_id.clone()),
+                        format!("USB Device approved: {}", root_id),
+                    ));
+                    IoApiResponse::ok_with_message("Device approved.")
+                }
+                Ok(None) => IoApiResponse::error(
+                    IoApiStatus::NotFound,
+                    format!("Device {} is not waiting for approval", device_id),
+                ),
+                Err(e) => IoApiResponse::error(
+                    IoApiStatus::InternalError,
+                    format!("Saving the policy failed: {}", e),
+                ),
+            }
         }
-    }
-}
-
-fn handle_connection_event(
-    event: UsbConnectionEvent,
-    device_tracker: &mut DeviceTracker,
-    device_connection_logs: &mut Vec<Box<str>>,
-) {
-    match event {
-        UsbConnectionEvent::Connected(device_path) => {
-            let device_id = device_path_to_device_id(&device_path);
-
-            let log = format!("USB Device connected: {}", device_id);
-            println!("{}", log);
-            device_connection_logs.push(log.into_boxed_str());
-
-            match device_tracker.insert_device_by_id(&device_id) {
-                Ok(_) => {
-                    println!("- Device inserted into tracker");
-                    println!("- Current device tracker state:\n{}", device_tracker);
-                    if device_id.starts_with("HID") {
-                        handle_hid_device_insertion(
-                            &device_id,
-                            device_tracker,
-                            device_connection_logs,
-                        );
-                    }
+        IoApiCommand::GetPolicy => IoApiResponse::ok(IoApiResponseBody::Policy {
+            policy: whitelist.policy().clone(),
+        }),
+        IoApiCommand::SetPolicy(policy) => {
+            println!("Replacing the policy");
+            match whitelist.set_policy(policy) {
+                Ok(()) => {
+                    events.push(IoApiEvent::new(
+                        IoApiEventKind::PolicyChanged,
+                        None,
+                        format!(
+                            "Policy replaced: {} rules, default action {}",
+                            whitelist.policy().rules.len(),
+                            whitelist.policy().default_action
+                        ),
+                    ));
+                    IoApiResponse::ok_with_message("Policy replaced.")
+                }
+                Err(e @ PolicyFileError::Invalid(_)) => {
+                    IoApiResponse::error(IoApiStatus::BadRequest, e.to_string())
                 }
-                Err(e) => println!("- Error inserting device into tracker: {}", e),
+                Err(e) => IoApiResponse::error(
+                    IoApiStatus::InternalError,
+                    format!("Saving the policy failed: {}", e),
+                ),
             }
         }
-        UsbConnectionEvent::Disconnected(device_path) => {
-            let device_id = device_path_to_device_id(&device_path);
-
-            let log = format!("USB Device disconnected: {}", device_id);
-            println!("{}", log);
-            device_connection_logs.push(log.into_boxed_str());
-
-            match device_tracker.remove_device_by_id(&device_id) {
-                None => {
-                    println!("- Device removed from tracker");
-                    println!("- Current device tracker state:\n{}", device_tracker);
-                    if device_id.starts_with("HID") {
-                        handle_hid_device_removal(
-                            &device_id,
-                            device_tracker,
-                            device_connection_logs,
-                        );
-                    }
+        IoApiCommand::Subscribe(filter) => {
+            println!("Subscribing connection to events: {:?}", filter);
+            session.subscription = Some(filter);
+            IoApiResponse::ok_with_message("Subscribed to events.")
+        }
+        IoApiCommand::DisableDevice(device_id) => {
+            println!("Disabling device: {}", device_id);
+            match device_tracker.set_device_state(&device_id, DeviceState::Disable) {
+                Ok(_) => {
+                    events.push(IoApiEvent::new(
+                        IoApiEventKind::Blocked,
+                        Some(device_id.clone()),
+                        format!("USB Device disabled: {}", device_id),
+                    ));
+                    IoApiResponse::ok_with_message("Device disabled.")
                 }
-                Some(device) => println!("- Error removing device {} from tracker.", device),
+                Err(e) => state_change_error_response("Disabling device failed", e),
             }
         }
     }
 }
 
-fn handle_hid_device_insertion(
-    hid_device_id: &DeviceId,
-    device_tracker: &mut DeviceTracker,
+/// Records the outcome of enforcing the policy on a device.
+///
+/// The decision, including the rule that caused it, is logged and reported as a `Blocked` or
+/// `Unblocked` event, as a `PendingApproval` event for devices waiting for an operator (policy
+/// action `ask`), or as an `EnforcementFailed` event carrying the error if the device could not
+/// be put into its state.
+///
+/// # Arguments
+///
+/// * `record` - The decision and its outcome.
+/// * `device_tracker` - The tracker holding the device, for its USB identity.
+/// * `device_connection_logs` - The log of connection events.
+/// * `audit_log` - The audit log the decision is written to.
+/// * `events` - Receives the event for the decision.
+fn record_enforcement(
+    record: EnforcementRecord,
+    device_tracker: &DeviceTracker,
     device_connection_logs: &mut Vec<Box<str>>,
+    audit_log: &mut AuditLog,
+    events: &mut Vec<IoApiEvent>,
 ) {
-    let hid_device = device_tracker.find_device(hid_device_id);
-    if hid_device.is_none() {
-        return;
-    }
-    let hid_device = hid_device.unwrap();
-    let parent_device_id = hid_device.parent_id.clone();
-    if parent_device_id.is_none() {
-        return;
-    }
-    let parent_device_id = parent_device_id.unwrap();
-
-    let log = format!("USB Device connected: {}", parent_device_id);
+    let log = record.to_string();
     println!("{}", log);
-    device_connection_logs.push(log.into_boxed_str());
-
-    match device_tracker.insert_device_by_id(&parent_device_id) {
-        Ok(_) => {
-            println!("- Device inserted into tracker");
-            println!("- Current device tracker state:\n{}", device_tracker);
-            if parent_device_id.starts_with("HID") {
-                handle_hid_device_insertion(
-                    &parent_device_id,
-                    device_tracker,
-                    device_connection_logs,
-                );
-            }
-        }
-        Err(e) => println!("- Error inserting device into tracker: {}", e),
-    }
+    device_connection_logs.push(log.clone().into_boxed_str());
+
+    let kind = match (&record.result, record.decision.action) {
+        (Err(_), _) => IoApiEventKind::EnforcementFailed,
+        (Ok(_), PolicyAction::Allow) => IoApiEventKind::Unblocked,
+        (Ok(_), PolicyAction::Deny) => IoApiEventKind::Blocked,
+        (Ok(_), PolicyAction::Ask) => IoApiEventKind::PendingApproval,
+    };
+    let event = IoApiEvent::new(kind, Some(record.device_id), log);
+    audit(
+        audit_log,
+        audit_entry(&event, "policy", &device_tracker.devices).with_decision(record.decision),
+    );
+    events.push(event);
 }
 
-fn handle_hid_device_removal(
-    hid_device_id: &DeviceId,
-    device_tracker: &mut DeviceTracker,
-    device_connection_logs: &mut Vec<Box<str>>,
-) {
-    let hid_device = device_tracker.find_device(hid_device_id);
-    if hid_device.is_none() {
-        return;
+/// Creates the audit entry for an event, with the USB identity of its device if the device is
+/// part of a device tree.
+///
+/// # Arguments
+///
+/// * `event` - The event to record.
+/// * `actor` - Who caused the event.
+/// * `devices` - The device tree to look the device up in.
+fn audit_entry(event: &IoApiEvent, actor: &str, devices: &HashMap<DeviceId, Device>) -> AuditEntry {
+    let entry = AuditEntry::from_event(event, actor);
+    let device = event.device_id.as_ref().and_then(|device_id| {
+        DeviceIterator::new(devices).find(|device| &device.device_id == device_id)
+    });
+
+    match device {
+        Some(device) => entry.with_device(device),
+        None => entry,
     }
-    let hid_device = hid_device.unwrap();
-    let parent_device_id = hid_device.parent_id.clone();
-    if parent_device_id.is_none() {
-        return;
+}
+
+/// Writes an entry to the audit log, reporting (but otherwise ignoring) failures.
+fn audit(audit_log: &mut AuditLog, entry: AuditEntry) {
+    if let Err(e) = audit_log.append(entry) {
+        println!("Error writing to the audit log: {}", e);
     }
-    let parent_device_id = parent_device_id.unwrap();
+}
 
-    let log = format!("USB Device disconnected: {}", parent_device_id);
-    println!("{}", log);
-    device_connection_logs.push(log.into_boxed_str());
-
-    match device_tracker.remove_device_by_id(&parent_device_id) {
-        None => {
-            println!("- Device removed from tracker");
-            println!("- Current device tracker state:\n{}", device_tracker);
-            if parent_device_id.starts_with("HID") {
-                handle_hid_device_removal(
-                    &parent_device_id,
-                    device_tracker,
-                    device_connection_logs,
-                );
-            }
+/// Pushes events to every connection subscribed to them.
+///
+/// # Arguments
+///
+/// * `server` - The server the connections belong to.
+/// * `sessions` - The protocol state of the open connections.
+/// * `events` - The events to push, oldest first.
+fn push_events(
+    server: &mut IoApiServer,
+    sessions: &HashMap<ConnectionId, IoApiSession>,
+    events: &[IoApiEvent],
+) {
+    for (id, session) in sessions.iter() {
+        let Some(filter) = &session.subscription else {
+            continue;
+        };
+
+        for event in events.iter().filter(|event| filter.matches(event)) {
+            let frame = IoApiResponse::ok(IoApiResponseBody::Event(event.clone())).to_frame();
+            server.send(*id, &frame);
         }
-        Some(device) => println!("- Error removing device {} from tracker", device),
     }
 }
+
+/// Maps a failed device state change onto an error response.
+fn state_change_error_response(context: &str, error: DeviceBackendError) -> IoApiResponse {
+    let status = match error {
+        DeviceBackendError::DeviceNotFound(_) => IoApiStatus::NotFound,
+        _ => IoApiStatus::InternalError,
+    };
+
+    IoApiResponse::error(status, format!("{}: {}", context, error))
+}
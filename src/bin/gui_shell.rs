@@ -1,18 +1,969 @@
 //! # GUI Shell Binary
 //!
-//! This binary is intended to provide a Graphical User Interface (GUI) for the `comp-gate` system.
-//! It will serve as a user-friendly alternative to the command-line `shell` binary.
+//! This binary provides the Graphical User Interface (GUI) of the `comp-gate` core service, a
+//! user-friendly alternative to the command-line `shell` binary. It talks to the core over the
+//! IOAPI, using the addresses found in the connection file or the address given with `--addr`.
 //!
-//! ## Planned Features
+//! ## Features
 //!
-//! - Visual representation of the device tree.
-//! - Real-time updates of device connection status.
-//! - Interactive controls to enable/disable devices.
-//! - Management of the device whitelist.
+//! - A collapsible tree of the connected devices, kept up to date by the events of the core.
+//! - A filter box narrowing the tree down to the devices matching a query (see the
+//!   `device_query` module for the syntax).
+//! - A toggle per device enabling or disabling it (for clients with the operator role).
+//! - A pane with the connection log of the core, updated in real time.
+//! - An editor for the policy, validated while typing, which can allow or deny a device from
+//!   the device tree (applying it requires the admin role).
 //!
-//! ## Current Status
+//! ## Architecture
 //!
-//! This is currently a placeholder entry point. The implementation is planned to use
-//! a Rust GUI framework (likely `egui`) to communicate with the core service via the IOAPI.
+//! The window never waits for the core. Commands are run by a worker thread owning a
+//! `CoreClient`, and a second thread holds a `Subscription` to all events; both report back
+//! through a channel and request a repaint. When the core is unreachable, the last known state
+//! stays visible (read-only) and the worker keeps trying to reconnect.
+//!
+//! ## Usage
+//!
+//! `gui_shell [--addr <ADDRESS>] [--timeout <SECONDS>]`
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
+
+use clap::Parser;
+use comp_gate::{
+    client::{CoreClient, CoreTarget, DEFAULT_TIMEOUT},
+    error::{ClientError, PolicyValidationError},
+    helper::{
+        device_managment::{Device, DeviceState},
+        device_query::DeviceQuery,
+        ioapi::{CoreAddress, EventFilter, IoApiEventKind},
+        ioapi_access::Role,
+        policy::{Policy, PolicyAction, PolicyRule},
+        policy_file::validate_policy,
+        usb_identity::UsbIdentity,
+    },
+};
+
+/// How long to wait before trying to reach an unreachable core again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The command line of the GUI shell.
+#[derive(Parser)]
+#[command(
+    name = "gui_shell",
+    version,
+    about = "Shows and controls the devices guarded by the comp-gate core service"
+)]
+struct Cli {
+    /// Connect to this address (`unix:<path>` or `tcp:<ip>:<port>`) instead of the ones in the
+    /// connection file
+    #[arg(long, value_name = "ADDRESS")]
+    addr: Option<CoreAddress>,
+
+    /// How long to wait for the core, in seconds; 0 waits forever
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_TIMEOUT.as_secs())]
+    timeout: u64,
+}
+
+/// The main entry point for the GUI Shell.
+///
+/// It parses the command line, starts the threads talking to the core and opens the window.
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+
+    let target = match cli.addr {
+        Some(address) => CoreTarget::Address(address),
+        None => CoreTarget::ConnectionFile,
+    };
+    let timeout = (cli.timeout > 0).then(|| Duration::from_secs(cli.timeout));
+
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default()
+            .with_title("comp-gate")
+            .with_inner_size([1000.0, 720.0]),
+        ..Default::default()
+    };
+
+    eframe::run_native(
+        "comp-gate",
+        options,
+        Box::new(move |cc| {
+            Ok(Box::new(GuiShell::new(
+                cc.egui_ctx.clone(),
+                target,
+                timeout,
+            )))
+        }),
+    )
+    .map_err(|e| anyhow::anyhow!("The window could not be opened: {}", e))
+}
+
+/// A command for the worker thread.
+enum Request {
+    /// Fetch the device tree and the connection logs.
+    Refresh,
+    /// Fetch the devices matching a query.
+    Query(DeviceQuery),
+    /// Fetch the active policy.
+    LoadPolicy,
+    /// Enable or disable a device.
+    SetDeviceState {
+        /// The Instance ID of the device.
+        device_id: String,
+        /// The state to put the device into.
+        state: DeviceState,
+    },
+    /// Approve a device waiting for an operator decision.
+    Approve(String),
+    /// Replace the active policy.
+    ApplyPolicy(Policy),
+}
+
+/// A message from the worker or event thread to the window.
+enum Update {
+    /// A connection to the core was opened.
+    Connected {
+        /// The address of the core.
+        address: String,
+        /// The role the core assigned to the connection.
+        role: Role,
+    },
+    /// The core cannot be reached; the worker keeps trying.
+    Unreachable(String),
+    /// The current device tree.
+    Devices {
+        /// The root-level devices, sorted by device ID.
+        devices: Vec<DeviceNode>,
+        /// The devices waiting for an operator decision.
+        pending_approvals: HashSet<String>,
+    },
+    /// The devices matching the filter, sorted by device ID.
+    QueryResults(Vec<DeviceNode>),
+    /// The connection logs of the core, oldest first.
+    Logs(Vec<String>),
+    /// The active policy.
+    Policy(Policy),
+    /// A device was put into a state on request of the window.
+    DeviceStateChanged {
+        /// The Instance ID of the device.
+        device_id: String,
+        /// The state the device was put into.
+        state: DeviceState,
+    },
+    /// The core pushed an event.
+    Event {
+        /// What happened.
+        kind: IoApiEventKind,
+        /// The device the event refers to, if any.
+        device_id: Option<String>,
+    },
+    /// A command finished; the message is shown in the status bar.
+    Status(String),
+}
+
+/// Sends updates to the window and wakes it up to process them.
+#[derive(Clone)]
+struct UpdateSender {
+    /// The channel the window receives updates on.
+    sender: Sender<Update>,
+    /// The context of the window, used to request a repaint.
+    ctx: egui::Context,
+}
+
+impl UpdateSender {
+    /// Sends an update; returns `false` once the window was closed.
+    fn send(&self, update: Update) -> bool {
+        let sent = self.sender.send(update).is_ok();
+        self.ctx.request_repaint();
+        sent
+    }
+}
+
+/// A device as shown in the device tree.
+///
+/// Devices hold reference-counted strings and cannot cross threads, so the worker converts them
+/// into this form.
+struct DeviceNode {
+    /// The Instance ID of the device.
+    device_id: String,
+    /// The name shown in the tree.
+    title: String,
+    /// The properties shown when the device is expanded, as label and value.
+    details: Vec<(&'static str, String)>,
+    /// A rule matching the device, used to allow or deny it from the tree.
+    rule: PolicyRule,
+    /// Whether the core reported the device as enabled, or `None` if its state is unknown.
+    enabled: Option<bool>,
+    /// The sub-devices, sorted by device ID.
+    children: Vec<DeviceNode>,
+}
+
+impl DeviceNode {
+    /// Converts a device and its sub-devices.
+    fn from_device(device: &Device) -> Self {
+        let title = device
+            .device_friendly_name
+            .as_deref()
+            .or(device.product_name.as_deref())
+            .or(device.bus_reported_description.as_deref())
+            .or(device.device_description.as_deref())
+            .unwrap_or("Unknown device")
+            .to_string();
+
+        let property = |value: Option<&str>, missing: &str| value.unwrap_or(missing).to_string();
+        let list = |values: &[std::rc::Rc<str>]| match values {
+            [] => "None".to_string(),
+            values => values.join("\n"),
+        };
+        let details = vec![
+            (
+                "USB Identity",
+                UsbIdentity::from(&device.device_id).to_string(),
+            ),
+            ("Vendor", property(device.vendor_name.as_deref(), "Unknown")),
+            (
+                "Product",
+                property(device.product_name.as_deref(), "Unknown"),
+            ),
+            (
+                "Service",
+                property(device.device_service.as_deref(), "None"),
+            ),
+            ("Class", property(device.device_class.as_deref(), "None")),
+            ("Type", property(device.device_type.as_deref(), "None")),
+            (
+                "Description",
+                property(device.device_description.as_deref(), "None"),
+            ),
+            (
+                "Manufacturer",
+                property(device.manufacturer.as_deref(), "Unknown"),
+            ),
+            (
+                "Bus Reported Description",
+                property(device.bus_reported_description.as_deref(), "None"),
+            ),
+            ("Hardware IDs", list(&device.hardware_ids)),
+            ("Compatible IDs", list(&device.compatible_ids)),
+            ("Location Paths", list(&device.location_paths)),
+            (
+                "Location Info",
+                property(device.location_info.as_deref(), "None"),
+            ),
+            (
+                "Driver",
+                match (&device.driver_provider, &device.driver_version) {
+                    (None, None) => "None".to_string(),
+                    (provider, version) => format!(
+                        "{} {}",
+                        provider.as_deref().unwrap_or("Unknown provider"),
+                        version.as_deref().unwrap_or("(unknown version)")
+                    ),
+                },
+            ),
+            (
+                "Install Date",
+                device
+                    .install_date
+                    .map(|date| date.to_string())
+                    .unwrap_or_else(|| "Unknown".to_string()),
+            ),
+            (
+                "Container ID",
+                property(device.container_id.as_deref(), "None"),
+            ),
+            (
+                "Problem",
+                device
+                    .problem_code
+                    .map(|code| format!("Code {}", code))
+                    .unwrap_or_else(|| "None".to_string()),
+            ),
+            (
+                "Status",
+                device
+                    .status
+                    .map(|status| format!("0x{:08X}", status))
+                    .unwrap_or_else(|| "Unknown".to_string()),
+            ),
+        ];
+
+        Self {
+            device_id: device.device_id.to_string(),
+            title,
+            details,
+            rule: PolicyRule::for_device(device, PolicyAction::Allow),
+            enabled: device.enabled,
+            children: sorted_nodes(device.devices.values()),
+        }
+    }
+}
+
+/// Converts devices into tree nodes sorted by device ID.
+fn sorted_nodes<'a>(devices: impl Iterator<Item = &'a Device>) -> Vec<DeviceNode> {
+    let mut nodes: Vec<DeviceNode> = devices.map(DeviceNode::from_device).collect();
+    nodes.sort_by(|a, b| a.device_id.cmp(&b.device_id));
+    nodes
+}
+
+/// Runs the commands requested by the window until it is closed.
+///
+/// While the core is unreachable, the worker retries a `Refresh` every `RETRY_INTERVAL`. The
+/// policy is fetched whenever a connection is (re-)established.
+fn run_worker(
+    target: CoreTarget,
+    timeout: Option<Duration>,
+    requests: Receiver<Request>,
+    updates: UpdateSender,
+) {
+    let mut client = CoreClient::new(target).with_timeout(timeout);
+    let mut connected = false;
+
+    loop {
+        let request = match requests.recv_timeout(RETRY_INTERVAL) {
+            Ok(request) => request,
+            Err(RecvTimeoutError::Timeout) if !connected => Request::Refresh,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+
+        let mut result = run_request(&mut client, request, &updates);
+        if result.is_ok() && !connected {
+            connected = true;
+            if let (Some(address), Some(role)) = (client.address(), client.role()) {
+                updates.send(Update::Connected {
+                    address: address.to_string(),
+                    role,
+                });
+            }
+            result = run_request(&mut client, Request::LoadPolicy, &updates);
+        }
+
+        match result {
+            Ok(()) => {}
+            // The core answered, so the connection is fine.
+            Err(e @ ClientError::Rejected { .. }) => {
+                updates.send(Update::Status(format!("Error: {}", e)));
+            }
+            Err(e) => {
+                connected = false;
+                client.disconnect();
+                if !updates.send(Update::Unreachable(e.to_string())) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Runs a single request and reports its outcome to the window.
+fn run_request(
+    client: &mut CoreClient,
+    request: Request,
+    updates: &UpdateSender,
+) -> Result<(), ClientError> {
+    match request {
+        Request::Refresh => {
+            let devices = client.list_devices()?;
+            // Devices already waiting when the window connected were never announced by an
+            // event, so the policy is evaluated like the core does to find them.
+            let policy = client.policy()?;
+            let pending_approvals = devices
+                .values()
+                .filter(|device| policy.evaluate(device).action == PolicyAction::Ask)
+                .map(|device| device.device_id.to_string())
+                .collect();
+            updates.send(Update::Devices {
+                devices: sorted_nodes(devices.values()),
+                pending_approvals,
+            });
+            updates.send(Update::Logs(client.logs()?));
+        }
+        Request::Query(query) => {
+            let devices = client.query_devices(&query)?;
+            updates.send(Update::QueryResults(sorted_nodes(devices.iter())));
+        }
+        Request::LoadPolicy => {
+            updates.send(Update::Policy(client.policy()?));
+        }
+        Request::SetDeviceState { device_id, state } => {
+            let verb = match state {
+                DeviceState::Enable => {
+                    client.enable(device_id.as_str())?;
+                    "enabled"
+                }
+                DeviceState::Disable => {
+                    client.disable(device_id.as_str())?;
+                    "disabled"
+                }
+            };
+            updates.send(Update::Status(format!("Device {}: {}", verb, device_id)));
+            updates.send(Update::DeviceStateChanged { device_id, state });
+        }
+        Request::Approve(device_id) => {
+            client.approve(device_id.as_str())?;
+            updates.send(Update::Status(format!("Device approved: {}", device_id)));
+        }
+        Request::ApplyPolicy(policy) => {
+            client.set_policy(policy)?;
+            updates.send(Update::Status("Policy applied.".to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Forwards every event of the core to the window, subscribing again whenever the connection
+/// is lost for good.
+fn run_event_listener(target: CoreTarget, timeout: Option<Duration>, updates: UpdateSender) {
+    loop {
+        let client = CoreClient::new(target.clone()).with_timeout(timeout);
+        if let Ok(subscription) = client.subscribe(EventFilter::default()) {
+            for event in subscription {
+                let Ok(event) = event else { break };
+                let update = Update::Event {
+                    kind: event.kind,
+                    device_id: event.device_id.map(|device_id| device_id.to_string()),
+                };
+                if !updates.send(update) {
+                    return;
+                }
+            }
+        }
+
+        thread::sleep(RETRY_INTERVAL);
+    }
+}
+
+/// The state of the connection to the core, as shown in the status bar.
+enum ConnectionStatus {
+    /// No connection was attempted yet.
+    Connecting,
+    /// The core answers commands.
+    Connected {
+        /// The address of the core.
+        address: String,
+        /// The role the core assigned to the connection.
+        role: Role,
+    },
+    /// The core cannot be reached.
+    Unreachable(String),
+}
+
+/// The page shown in the central panel.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tab {
+    /// The device tree.
+    Devices,
+    /// The policy editor.
+    Policy,
+}
+
+/// Something the user asked for while the device tree was drawn.
+enum TreeAction {
+    /// Enable or disable a device.
+    SetDeviceState(String, DeviceState),
+    /// Approve a device waiting for an operator decision.
+    Approve(String),
+    /// Add a rule to the top of the policy draft.
+    AddRule(Box<PolicyRule>),
+}
+
+/// The policy being edited.
+#[derive(Default)]
+struct PolicyEditor {
+    /// The TOML source of the draft.
+    source: String,
+    /// Whether the draft differs from the policy last received from the core.
+    dirty: bool,
+    /// The problems found in the draft; empty if it is a valid policy.
+    errors: Vec<PolicyValidationError>,
+}
+
+impl PolicyEditor {
+    /// Replaces the draft with a policy, unless the user is editing it.
+    fn load(&mut self, policy: &Policy, force: bool) {
+        if self.dirty && !force {
+            return;
+        }
+
+        self.source = toml::to_string_pretty(policy).unwrap_or_default();
+        self.dirty = false;
+        self.errors.clear();
+    }
+
+    /// Validates the draft, remembering the problems found.
+    fn validate(&mut self) -> Option<Policy> {
+        match validate_policy(&self.source) {
+            Ok(policy) => {
+                self.errors.clear();
+                Some(policy)
+            }
+            Err(errors) => {
+                self.errors = errors;
+                None
+            }
+        }
+    }
+}
+
+/// The GUI application.
+struct GuiShell {
+    /// Sends commands to the worker thread.
+    requests: Sender<Request>,
+    /// Receives the updates of the worker and event threads.
+    updates: Receiver<Update>,
+
+    /// The state of the connection to the core.
+    connection: ConnectionStatus,
+    /// The message of the last finished command.
+    status: Option<String>,
+    /// The page shown in the central panel.
+    tab: Tab,
+    /// Whether events arrived that the device tree and logs do not reflect yet.
+    refresh_pending: bool,
+
+    /// The device tree, as last received from the core.
+    devices: Vec<DeviceNode>,
+    /// The text of the filter box.
+    filter: String,
+    /// Why the text of the filter box is not a valid query.
+    filter_error: Option<String>,
+    /// The query of the filter box; the tree is shown unfiltered if `None`.
+    query: Option<DeviceQuery>,
+    /// The devices matching the query, as last received from the core.
+    matches: Vec<DeviceNode>,
+    /// The state of devices that were enabled or disabled since the device tree was last
+    /// received; it takes precedence over the state in the tree.
+    device_states: HashMap<String, DeviceState>,
+    /// The devices waiting for an operator decision, as of the last device tree and the events
+    /// received since.
+    pending_approvals: HashSet<String>,
+    /// The connection logs of the core, oldest first.
+    logs: Vec<String>,
+    /// The policy being edited.
+    policy_editor: PolicyEditor,
+}
+
+impl GuiShell {
+    /// Creates the application and starts the threads talking to the core.
+    ///
+    /// # Arguments
+    ///
+    /// * `ctx` - The context of the window, used by the threads to request repaints.
+    /// * `target` - Where to look for the core.
+    /// * `timeout` - The longest time to wait for the core; `None` waits forever.
+    fn new(ctx: egui::Context, target: CoreTarget, timeout: Option<Duration>) -> Self {
+        let (request_sender, request_receiver) = mpsc::channel();
+        let (update_sender, update_receiver) = mpsc::channel();
+        let updates = UpdateSender {
+            sender: update_sender,
+            ctx,
+        };
+
+        let worker_target = target.clone();
+        let worker_updates = updates.clone();
+        thread::spawn(move || run_worker(worker_target, timeout, request_receiver, worker_updates));
+        thread::spawn(move || run_event_listener(target, timeout, updates));
+
+        // The first refresh connects to the core.
+        let _ = request_sender.send(Request::Refresh);
+
+        Self {
+            requests: request_sender,
+            updates: update_receiver,
+            connection: ConnectionStatus::Connecting,
+            status: None,
+            tab: Tab::Devices,
+            refresh_pending: false,
+            devices: vec![],
+            filter: String::new(),
+            filter_error: None,
+            query: None,
+            matches: vec![],
+            device_states: HashMap::new(),
+            pending_approvals: HashSet::new(),
+            logs: vec![],
+            policy_editor: PolicyEditor::default(),
+        }
+    }
+
+    /// Returns the role of the connection, or `None` if the core cannot be reached.
+    fn role(&self) -> Option<Role> {
+        match &self.connection {
+            ConnectionStatus::Connected { role, .. } => Some(*role),
+            _ => None,
+        }
+    }
+
+    /// Sends a command to the worker thread.
+    fn request(&self, request: Request) {
+        let _ = self.requests.send(request);
+    }
+
+    /// Fetches the device tree and the logs, and the matches of the filter if one is set.
+    fn refresh(&self) {
+        self.request(Request::Refresh);
+        if let Some(query) = &self.query {
+            self.request(Request::Query(query.clone()));
+        }
+    }
+
+    /// Applies the updates received since the last frame.
+    fn process_updates(&mut self) {
+        while let Ok(update) = self.updates.try_recv() {
+            match update {
+                Update::Connected { address, role } => {
+                    self.connection = ConnectionStatus::Connected { address, role };
+                }
+                Update::Unreachable(error) => {
+                    self.connection = ConnectionStatus::Unreachable(error);
+                }
+                Update::Devices {
+                    devices,
+                    pending_approvals,
+                } => {
+                    // The tree carries the current states.
+                    self.device_states.clear();
+                    self.devices = devices;
+                    self.pending_approvals = pending_approvals;
+                }
+                Update::QueryResults(matches) => self.matches = matches,
+                Update::Logs(logs) => self.logs = logs,
+                Update::Policy(policy) => self.policy_editor.load(&policy, false),
+                Update::DeviceStateChanged { device_id, state } => {
+                    self.device_states.insert(device_id, state);
+                }
+                Update::Event { kind, device_id } => {
+                    match (kind, device_id) {
+                        (IoApiEventKind::Blocked, Some(device_id)) => {
+                            self.pending_approvals.remove(&device_id);
+                            self.device_states.insert(device_id, DeviceState::Disable);
+                        }
+                        (IoApiEventKind::Unblocked, Some(device_id)) => {
+                            self.pending_approvals.remove(&device_id);
+                            self.device_states.insert(device_id, DeviceState::Enable);
+                        }
+                        (IoApiEventKind::PendingApproval, Some(device_id)) => {
+                            self.pending_approvals.insert(device_id.clone());
+                            self.device_states.insert(device_id, DeviceState::Disable);
+                        }
+                        (IoApiEventKind::PolicyChanged, _) if !self.policy_editor.dirty => {
+                            self.request(Request::LoadPolicy);
+                        }
+                        _ => {}
+                    }
+                    self.refresh_pending = true;
+                }
+                Update::Status(message) => self.status = Some(message),
+            }
+        }
+
+        // Bursts of events (e.g. a hub with many interfaces) cause a single refresh.
+        if self.refresh_pending {
+            self.refresh_pending = false;
+            self.refresh();
+        }
+    }
+
+    /// Draws the connection state, the page selection and the status of the last command.
+    fn status_bar_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            match &self.connection {
+                ConnectionStatus::Connecting => {
+                    ui.spinner();
+                    ui.label("Connecting to the core service...");
+                }
+                ConnectionStatus::Connected { address, role } => {
+                    ui.colored_label(egui::Color32::GREEN, "●");
+                    ui.label(format!("Connected to {} as {}", address, role));
+                }
+                ConnectionStatus::Unreachable(error) => {
+                    ui.colored_label(egui::Color32::RED, "●");
+                    ui.label("Core service unreachable, retrying")
+                        .on_hover_text(error);
+                }
+            }
+
+            ui.separator();
+            ui.selectable_value(&mut self.tab, Tab::Devices, "Devices");
+            ui.selectable_value(&mut self.tab, Tab::Policy, "Policy");
+            ui.separator();
+            if ui.button("Refresh").clicked() {
+                self.refresh();
+            }
+            if let Some(status) = &self.status {
+                ui.separator();
+                ui.label(status);
+            }
+        });
+    }
+
+    /// Draws the connection log of the core.
+    fn logs_ui(&self, ui: &mut egui::Ui) {
+        ui.heading("Connection Log");
+        egui::ScrollArea::vertical()
+            .stick_to_bottom(true)
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                for log in self.logs.iter() {
+                    ui.monospace(log);
+                }
+            });
+    }
+
+    /// Draws the device tree and runs the actions the user triggered in it.
+    fn devices_ui(&mut self, ui: &mut egui::Ui) {
+        let can_toggle = self.role().is_some_and(|role| role >= Role::Operator);
+        let mut actions = vec![];
+
+        ui.horizontal(|ui| {
+            ui.label("Filter:");
+            let filter = ui
+                .add(
+                    egui::TextEdit::singleline(&mut self.filter)
+                        .hint_text("e.g. class:HIDClass and not under:USB\\VID_05E3*")
+                        .desired_width(f32::INFINITY),
+                )
+                .on_hover_text(
+                    "Terms: id, class, service, name, description, vendor, product, serial \
+                     (e.g. `vendor:Logi*`), vid:/pid: (hexadecimal), under:, has:, \
+                     combined with and, or, not and parentheses",
+                );
+            if filter.changed() {
+                self.apply_filter();
+            }
+        });
+        if let Some(error) = &self.filter_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                // Matches are shown as a flat list, each with its own sub-devices.
+                let (nodes, empty) = match self.query {
+                    Some(_) => (&self.matches, "No matching devices."),
+                    None => (&self.devices, "No devices."),
+                };
+                if nodes.is_empty() {
+                    ui.label(empty);
+                }
+                for node in nodes.iter() {
+                    device_node_ui(
+                        ui,
+                        node,
+                        &self.device_states,
+                        &self.pending_approvals,
+                        can_toggle,
+                        &mut actions,
+                    );
+                }
+            });
+
+        for action in actions {
+            match action {
+                TreeAction::SetDeviceState(device_id, state) => {
+                    self.request(Request::SetDeviceState { device_id, state });
+                }
+                TreeAction::Approve(device_id) => self.request(Request::Approve(device_id)),
+                TreeAction::AddRule(rule) => self.add_rule(*rule),
+            }
+        }
+    }
+
+    /// Parses the text of the filter box and fetches the matching devices.
+    ///
+    /// An invalid query keeps the last valid one in effect; an empty one removes the filter.
+    fn apply_filter(&mut self) {
+        if self.filter.trim().is_empty() {
+            self.filter_error = None;
+            self.query = None;
+            self.matches.clear();
+            return;
+        }
+
+        match self.filter.parse::<DeviceQuery>() {
+            Ok(query) => {
+                self.filter_error = None;
+                self.request(Request::Query(query.clone()));
+                self.query = Some(query);
+            }
+            Err(e) => self.filter_error = Some(e.to_string()),
+        }
+    }
+
+    /// Adds a rule to the top of the policy draft and opens the policy editor.
+    fn add_rule(&mut self, rule: PolicyRule) {
+        let Some(mut policy) = self.policy_editor.validate() else {
+            self.status = Some("The policy draft has to be fixed first.".to_string());
+            self.tab = Tab::Policy;
+            return;
+        };
+
+        policy.rules.insert(0, rule);
+        self.policy_editor.load(&policy, true);
+        self.policy_editor.dirty = true;
+        self.status = Some("Rule added to the policy draft.".to_string());
+        self.tab = Tab::Policy;
+    }
+
+    /// Draws the policy editor.
+    fn policy_ui(&mut self, ui: &mut egui::Ui) {
+        let can_apply = self.role().is_some_and(|role| role >= Role::Admin);
+
+        ui.horizontal(|ui| {
+            let apply = ui
+                .add_enabled(
+                    can_apply && self.policy_editor.dirty && self.policy_editor.errors.is_empty(),
+                    egui::Button::new("Apply"),
+                )
+                .on_disabled_hover_text(
+                    "Applying requires a valid, edited draft and the admin role",
+                );
+            if apply.clicked()
+                && let Some(policy) = self.policy_editor.validate()
+            {
+                self.policy_editor.dirty = false;
+                self.request(Request::ApplyPolicy(policy));
+            }
+
+            let revert = ui.add_enabled(self.role().is_some(), egui::Button::new("Revert"));
+            if revert.clicked() {
+                self.policy_editor.dirty = false;
+                self.request(Request::LoadPolicy);
+            }
+
+            if self.policy_editor.dirty {
+                ui.label("Draft not applied yet.");
+            }
+        });
+
+        for error in self.policy_editor.errors.iter() {
+            ui.colored_label(egui::Color32::RED, error.to_string());
+        }
+
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                let editor = ui.add(
+                    egui::TextEdit::multiline(&mut self.policy_editor.source)
+                        .code_editor()
+                        .desired_rows(30)
+                        .desired_width(f32::INFINITY),
+                );
+                if editor.changed() {
+                    self.policy_editor.dirty = true;
+                    self.policy_editor.validate();
+                }
+            });
+    }
+}
+
+impl eframe::App for GuiShell {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.process_updates();
+
+        egui::TopBottomPanel::top("status_bar").show(ctx, |ui| self.status_bar_ui(ui));
+        egui::TopBottomPanel::bottom("connection_log")
+            .resizable(true)
+            .default_height(180.0)
+            .show(ctx, |ui| self.logs_ui(ui));
+        egui::CentralPanel::default().show(ctx, |ui| match self.tab {
+            Tab::Devices => self.devices_ui(ui),
+            Tab::Policy => self.policy_ui(ui),
+        });
+    }
+}
+
+/// Draws a device and its sub-devices as collapsible entries.
+///
+/// # Arguments
+///
+/// * `ui` - The UI to draw into.
+/// * `node` - The device to draw.
+/// * `device_states` - The states of devices changed since the tree was received; other devices
+///   are shown in the state the core reported.
+/// * `pending_approvals` - The devices waiting for an operator decision.
+/// * `can_toggle` - Whether the role of the connection permits enabling, disabling and
+///   approving devices.
+/// * `actions` - Receives the actions the user triggered.
+fn device_node_ui(
+    ui: &mut egui::Ui,
+    node: &DeviceNode,
+    device_states: &HashMap<String, DeviceState>,
+    pending_approvals: &HashSet<String>,
+    can_toggle: bool,
+    actions: &mut Vec<TreeAction>,
+) {
+    egui::CollapsingHeader::new(format!("{}  ({})", node.title, node.device_id))
+        .id_salt(&node.device_id)
+        .default_open(node.children.is_empty())
+        .show(ui, |ui| {
+            ui.horizontal(|ui| {
+                let state = device_states
+                    .get(&node.device_id)
+                    .map(|state| *state == DeviceState::Enable)
+                    .or(node.enabled);
+                let mut enabled = state.unwrap_or(false);
+                let toggle = ui
+                    .add_enabled(
+                        can_toggle,
+                        egui::Checkbox::new(&mut enabled, "Enabled").indeterminate(state.is_none()),
+                    )
+                    .on_disabled_hover_text(
+                        "Enabling and disabling devices requires the operator role",
+                    );
+                if toggle.changed() {
+                    let state = if enabled {
+                        DeviceState::Enable
+                    } else {
+                        DeviceState::Disable
+                    };
+                    actions.push(TreeAction::SetDeviceState(node.device_id.clone(), state));
+                }
+
+                if pending_approvals.contains(&node.device_id)
+                    && ui
+                        .add_enabled(can_toggle, egui::Button::new("Approve"))
+                        .on_disabled_hover_text("Approving devices requires the operator role")
+                        .clicked()
+                {
+                    actions.push(TreeAction::Approve(node.device_id.clone()));
+                }
+                if ui.button("Allow in policy").clicked() {
+                    actions.push(TreeAction::AddRule(Box::new(node.rule.clone())));
+                }
+                if ui.button("Deny in policy").clicked() {
+                    actions.push(TreeAction::AddRule(Box::new(PolicyRule {
+                        action: PolicyAction::Deny,
+                        ..node.rule.clone()
+                    })));
+                }
+            });
+
+            egui::Grid::new(("device_details", &node.device_id))
+                .num_columns(2)
+                .show(ui, |ui| {
+                    for (label, value) in node.details.iter() {
+                        ui.label(*label);
+                        ui.label(value);
+                        ui.end_row();
+                    }
+                });
 
-fn main() {}
+            for child in node.children.iter() {
+                device_node_ui(
+                    ui,
+                    child,
+                    device_states,
+                    pending_approvals,
+                    can_toggle,
+                    actions,
+                );
+            }
+        });
+}
@@ -1,92 +1,569 @@
 //! # Shell CLI Binary
 //!
-//! This binary provides a simple command-line interface (CLI) for interacting with the `comp-gate` core service.
-//! It connects to the core service via TCP (using the address found in the connection file) and allows the user
-//! to send commands interactively.
+//! This binary provides the command-line interface (CLI) of the `comp-gate` core service.
+//! It connects to the core service (using the addresses found in the connection file, or the
+//! address given with `--addr`) and either runs a single command or an interactive shell.
 //!
-//! ## Supported Commands
+//! ## Commands
 //!
-//! - `get_device_list`: Retrieves the tree of connected devices.
-//! - `get_device_connection_logs`: Retrieves the history of connection events.
-//! - `disable_device <ID>`: Disables a specific device.
-//! - `enable_device <ID>`: Enables a specific device.
+//! - `list`: Prints the tree of connected devices.
+//! - `query <QUERY>`: Prints the connected devices matching a query, e.g.
+//!   `query class:HIDClass and vendor:Logi*` (see the `device_query` module for the syntax).
+//! - `logs`: Prints the history of connection events.
+//! - `enable <DEVICE_ID>`: Enables a specific device.
+//! - `disable <DEVICE_ID>`: Disables a specific device.
+//! - `approve <DEVICE_ID>`: Approves a device waiting for an operator decision (policy action
+//!   `ask`), adding a rule that allows it.
+//! - `policy`: Prints the active policy.
+//! - `set-policy <FILE>`: Replaces the active policy with a TOML policy file.
+//! - `snapshot <FILE>`: Saves the tree of connected devices as a JSON snapshot.
+//! - `diff <BASELINE>`: Prints how the tree of connected devices differs from a snapshot.
+//! - `subscribe [KIND...] [--prefix <PREFIX>]`: Prints pushed events until the connection is
+//!   lost.
 //!
 //! ## Usage
 //!
-//! Run this binary in a terminal. It will prompt with `>` for input.
+//! `shell [--addr <ADDRESS>] [--json] [--timeout <SECONDS>] [COMMAND]`
+//!
+//! With a command, the shell runs it and exits, which makes it usable from scripts. With
+//! `--json`, responses are printed as the JSON sent by the core (and events one JSON object per
+//! line). The exit code tells the outcome apart:
+//! - `0`: The command succeeded.
+//! - `1`: The core rejected the command, or its input (e.g. a policy file) is invalid.
+//! - `2`: The command line is invalid.
+//! - `3`: The core could not be reached, or the connection was lost.
+//! - `4`: The client lacks the role required for the command.
+//!
+//! Without a command, the shell prompts with `>` for the same commands. It keeps a history
+//! (stored in `~/.comp-gate_history`) and completes commands, event kinds and the IDs of the
+//! connected devices with Tab. `exit`, `quit` or Ctrl-D leave the shell.
 
 use std::{
-    io::{Read, Write},
-    net,
+    cell::RefCell,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    rc::Rc,
+    time::Duration,
+};
+
+use clap::{Parser, Subcommand};
+use comp_gate::{
+    client::{CoreClient, CoreTarget, DEFAULT_TIMEOUT},
+    error::{ClientError, PolicyFileError},
+    helper::{
+        device_diff::DeviceTreeDiff,
+        device_managment::Device,
+        device_query::DeviceQuery,
+        device_snapshot::DeviceSnapshot,
+        ioapi::{
+            CoreAddress, EventFilter, IoApiCommand, IoApiEventKind, IoApiResponse,
+            IoApiResponseBody, IoApiStatus,
+        },
+        policy_file::validate_policy,
+    },
 };
+use rustyline::{
+    Context, Editor, Helper, completion::Completer, error::ReadlineError, highlight::Highlighter,
+    hint::Hinter, history::DefaultHistory, validate::Validator,
+};
+
+/// The exit code for a command the core rejected, or whose input is invalid.
+const EXIT_FAILED: u8 = 1;
+/// The exit code for a core that could not be reached or a lost connection.
+const EXIT_UNREACHABLE: u8 = 3;
+/// The exit code for a command the client lacks the role for.
+const EXIT_DENIED: u8 = 4;
+
+/// The name of the file in the home directory the REPL history is stored in.
+const HISTORY_FILE_NAME: &str = ".comp-gate_history";
+
+/// The command line of the shell.
+#[derive(Parser)]
+#[command(
+    name = "shell",
+    version,
+    about = "Inspects and controls the devices guarded by the comp-gate core service"
+)]
+struct Cli {
+    /// Connect to this address (`unix:<path>` or `tcp:<ip>:<port>`) instead of the ones in the
+    /// connection file
+    #[arg(long, value_name = "ADDRESS")]
+    addr: Option<CoreAddress>,
+
+    /// Print responses and events as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// How long to wait for the core, in seconds; 0 waits forever
+    #[arg(long, value_name = "SECONDS", default_value_t = DEFAULT_TIMEOUT.as_secs())]
+    timeout: u64,
+
+    /// The command to run; the interactive shell is started if none is given
+    #[command(subcommand)]
+    command: Option<ShellCommand>,
+}
+
+/// A line entered in the interactive shell.
+#[derive(Parser)]
+#[command(
+    name = "",
+    about = "The commands of the interactive shell",
+    no_binary_name = true,
+    disable_version_flag = true,
+    after_help = "Type `exit` or `quit` (or press Ctrl-D) to leave the shell."
+)]
+struct ReplLine {
+    #[command(subcommand)]
+    command: ShellCommand,
+}
+
+/// The commands of the shell.
+#[derive(Subcommand)]
+enum ShellCommand {
+    /// Print the tree of connected devices
+    List,
+    /// Print the connected devices matching a query
+    Query {
+        /// The query, e.g. `class:HIDClass and not under:USB\VID_05E3*`
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        query: Vec<String>,
+    },
+    /// Print the history of connection events
+    Logs,
+    /// Enable a device
+    Enable {
+        /// The Instance ID of the device
+        device_id: String,
+    },
+    /// Disable a device
+    Disable {
+        /// The Instance ID of the device
+        device_id: String,
+    },
+    /// Approve a device waiting for an operator decision
+    Approve {
+        /// The Instance ID of the device
+        device_id: String,
+    },
+    /// Print the active policy
+    Policy,
+    /// Replace the active policy with a policy file
+    SetPolicy {
+        /// The TOML policy file
+        file: PathBuf,
+    },
+    /// Save the tree of connected devices as a JSON snapshot
+    Snapshot {
+        /// The file to write the snapshot to
+        file: PathBuf,
+    },
+    /// Print how the tree of connected devices differs from a saved snapshot
+    Diff {
+        /// The snapshot to compare against
+        baseline: PathBuf,
+    },
+    /// Print events pushed by the core until the connection is lost
+    Subscribe {
+        /// The kinds of events to print; all kinds if none are given
+        #[arg(value_name = "KIND", value_parser = parse_event_kind)]
+        kinds: Vec<IoApiEventKind>,
+
+        /// Only print events of devices whose ID starts with this prefix
+        #[arg(long)]
+        prefix: Option<String>,
+    },
+}
 
-use comp_gate::helper::ioapi::{IoApiCommand, IoApiRequest, get_core_connection_addr};
+/// The names of the commands, completed at the start of a REPL line.
+const COMMAND_NAMES: [&str; 14] = [
+    "list",
+    "query",
+    "logs",
+    "enable",
+    "disable",
+    "approve",
+    "policy",
+    "set-policy",
+    "snapshot",
+    "diff",
+    "subscribe",
+    "help",
+    "exit",
+    "quit",
+];
 
 /// The main entry point for the Shell CLI.
 ///
 /// It performs the following:
-/// 1. Connects to the core service using `get_core_connection_addr`.
-/// 2. Enters a Read-Eval-Print Loop (REPL).
-/// 3. Reads user input from stdin.
-/// 4. Parses the input into an `IoApiCommand`.
-/// 5. Sends the command request to the core.
-/// 6. Waits for and prints the response.
-fn main() -> anyhow::Result<()> {
-    let mut ioapi_stream = net::TcpStream::connect(get_core_connection_addr()?)
-        .expect("Failed to connect to comp-gate core");
+/// 1. Parses the command line; `clap` exits with code `2` if it is invalid.
+/// 2. Creates a `CoreClient` for the connection file or the `--addr` address.
+/// 3. Runs the given command, or the interactive shell if there is none.
+/// 4. Maps the outcome onto the exit code.
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let target = match cli.addr {
+        Some(address) => CoreTarget::Address(address),
+        None => CoreTarget::ConnectionFile,
+    };
+    let timeout = (cli.timeout > 0).then(|| Duration::from_secs(cli.timeout));
+    let mut client = CoreClient::new(target).with_timeout(timeout);
+
+    let result = match cli.command {
+        Some(command) => run_command(&mut client, command, cli.json),
+        None => run_repl(client, cli.json),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+/// Runs the interactive shell until the user leaves it.
+///
+/// # Arguments
+///
+/// * `client` - The client for the core; it is connected before the first prompt.
+/// * `json` - Whether responses are printed as JSON.
+fn run_repl(mut client: CoreClient, json: bool) -> anyhow::Result<()> {
+    client.reconnect()?;
+    if let (Some(address), Some(protocol_version), Some(role)) =
+        (client.address(), client.protocol_version(), client.role())
+    {
+        println!(
+            "Connected to {} using protocol version {} as {}",
+            address, protocol_version, role
+        );
+    }
+
+    let client = Rc::new(RefCell::new(client));
+    let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new()?;
+    editor.set_helper(Some(ShellHelper {
+        client: client.clone(),
+    }));
+
+    let history_path = history_path();
+    if let Some(path) = &history_path {
+        // A missing history file is expected on the first run.
+        let _ = editor.load_history(path);
+    }
 
     loop {
-        print!(">");
-        // Ensure the prompt is displayed immediately
-        let _ = std::io::stdout().flush();
-
-        let mut cmd_buffer: String = String::new();
-        std::io::stdin()
-            .read_line(&mut cmd_buffer)
-            .expect("Failed to read line");
-
-        // Trim whitespace/newlines to ensure clean parsing
-        let cmd_input = cmd_buffer.trim();
-        if cmd_input.is_empty() {
+        let line = match editor.readline(">") {
+            Ok(line) => line,
+            // Ctrl-C discards the current line.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
             continue;
-        } else if cmd_input.starts_with("exit") || cmd_input.starts_with("quit") {
+        }
+        let _ = editor.add_history_entry(line);
+        if line == "exit" || line == "quit" {
             break;
         }
 
-        let request: IoApiRequest =
-            match IoApiCommand::try_from(cmd_input.split(" ").collect::<Vec<&str>>().as_slice())
-                .ok()
-            {
-                Some(cmd) => cmd.into(),
-                None => {
-                    println!("Invalid command");
-                    continue;
-                }
-            };
+        let command = match ReplLine::try_parse_from(line.split_whitespace()) {
+            Ok(repl_line) => repl_line.command,
+            Err(e) => {
+                // Also covers `help`, which is reported as an "error" by clap.
+                let _ = e.print();
+                continue;
+            }
+        };
 
-        ioapi_stream
-            .write_all(&request)
-            .expect("Failed to write request");
+        if let Err(e) = run_command(&mut client.borrow_mut(), command, json) {
+            eprintln!("Error: {}", e);
+        }
+    }
 
-        let mut prefix_buf = [0u8; 4];
-        ioapi_stream
-            .read_exact(&mut prefix_buf)
-            .expect("Failed to read prefix size");
+    if let Some(path) = &history_path
+        && let Err(e) = editor.save_history(path)
+    {
+        println!("Warning: The history could not be saved: {}", e);
+    }
 
-        let prefix_size: u32 = u32::from_be_bytes(prefix_buf);
+    Ok(())
+}
 
-        let mut body = vec![0u8; prefix_size as usize];
-        if prefix_size > 0 {
-            ioapi_stream
-                .read_exact(&mut body)
-                .expect("Failed to read message body");
+/// Runs a single command.
+///
+/// # Arguments
+///
+/// * `client` - The client for the core.
+/// * `command` - The command to run.
+/// * `json` - Whether responses are printed as JSON.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the core executed the command (or, for `subscribe`, never).
+/// * `Err(ClientError::Rejected)` - If the core rejected the command.
+/// * `Err(_)` - If the core could not be reached or the input of the command is invalid.
+fn run_command(client: &mut CoreClient, command: ShellCommand, json: bool) -> anyhow::Result<()> {
+    let command = match command {
+        ShellCommand::List => IoApiCommand::GetDeviceList,
+        ShellCommand::Query { query } => {
+            IoApiCommand::QueryDevices(query.join(" ").parse::<DeviceQuery>()?)
+        }
+        ShellCommand::Logs => IoApiCommand::GetDeviceConnectionLogs,
+        ShellCommand::Enable { device_id } => IoApiCommand::EnableDevice(device_id.as_str().into()),
+        ShellCommand::Disable { device_id } => {
+            IoApiCommand::DisableDevice(device_id.as_str().into())
+        }
+        ShellCommand::Approve { device_id } => {
+            IoApiCommand::ApproveDevice(device_id.as_str().into())
         }
+        ShellCommand::Policy => IoApiCommand::GetPolicy,
+        ShellCommand::SetPolicy { file } => IoApiCommand::SetPolicy(read_policy_file(&file)?),
+        ShellCommand::Snapshot { file } => return save_snapshot(client, &file),
+        ShellCommand::Diff { baseline } => return print_diff(client, &baseline, json),
+        ShellCommand::Subscribe { kinds, prefix } => {
+            return subscribe(
+                client,
+                EventFilter {
+                    kinds,
+                    device_id_prefix: prefix,
+                },
+                json,
+            );
+        }
+    };
 
-        match std::str::from_utf8(&body) {
-            Ok(s) => println!("{}", s),
-            Err(_) => println!("{:?}", body),
+    let response = client.request(command)?;
+    if json {
+        println!("{}", serde_json::to_string(&response)?);
+    }
+    if !response.is_ok() {
+        return Err(ClientError::Rejected {
+            status: response.status,
+            message: response.message.unwrap_or_default(),
         }
+        .into());
+    }
+    if !json {
+        print_response(response);
     }
 
     Ok(())
 }
+
+/// Prints the events matching a filter until the connection to the core is lost for good.
+fn subscribe(client: &CoreClient, filter: EventFilter, json: bool) -> anyhow::Result<()> {
+    let subscription = client.subscribe(filter)?;
+    if !json {
+        println!("Subscribed to events.");
+    }
+
+    for event in subscription {
+        let event = event?;
+        if json {
+            println!("{}", serde_json::to_string(&event)?);
+        } else {
+            println!("{}", event);
+        }
+    }
+
+    Ok(())
+}
+
+/// Saves the tree of connected devices as a JSON snapshot.
+fn save_snapshot(client: &mut CoreClient, path: &Path) -> anyhow::Result<()> {
+    let snapshot = DeviceSnapshot::new(client.list_devices()?);
+    snapshot
+        .save(path)
+        .map_err(|e| anyhow::anyhow!("Writing {} failed: {}", path.display(), e))?;
+    println!(
+        "Saved {} devices to {}",
+        snapshot.iter().count(),
+        path.display()
+    );
+
+    Ok(())
+}
+
+/// Prints how the tree of connected devices differs from a saved snapshot.
+fn print_diff(client: &mut CoreClient, baseline: &Path, json: bool) -> anyhow::Result<()> {
+    let baseline = DeviceSnapshot::load(baseline)
+        .map_err(|e| anyhow::anyhow!("Reading {} failed: {}", baseline.display(), e))?;
+    let diff = DeviceTreeDiff::between(&baseline.devices, &client.list_devices()?);
+
+    if json {
+        println!("{}", serde_json::to_string(&diff)?);
+    } else if diff.is_empty() {
+        println!("No changes.");
+    } else {
+        print!("{}", diff);
+    }
+
+    Ok(())
+}
+
+/// Reads and validates a policy file, reporting every problem found with its location.
+fn read_policy_file(path: &Path) -> anyhow::Result<comp_gate::helper::policy::Policy> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Reading {} failed: {}", path.display(), e))?;
+
+    Ok(validate_policy(&source).map_err(PolicyFileError::Invalid)?)
+}
+
+/// Prints a successful response of the core in a human readable form.
+fn print_response(response: IoApiResponse) {
+    match response.body {
+        IoApiResponseBody::DeviceList { devices } => {
+            for device in devices.values() {
+                println!("{}", device);
+            }
+        }
+        IoApiResponseBody::Devices { devices } => {
+            if devices.is_empty() {
+                println!("No matching devices.");
+            }
+            for device in devices {
+                // The matches are printed as a flat list.
+                println!(
+                    "{}",
+                    Device {
+                        tree_level: 0,
+                        ..device
+                    }
+                );
+            }
+        }
+        IoApiResponseBody::DeviceConnectionLogs { logs } => {
+            for log in logs {
+                println!("{}", log);
+            }
+        }
+        IoApiResponseBody::Handshake {
+            protocol_version,
+            role,
+        } => {
+            println!(
+                "Connected using protocol version {} as {}",
+                protocol_version, role
+            );
+        }
+        IoApiResponseBody::Policy { policy } => match toml::to_string_pretty(&policy) {
+            Ok(policy) => println!("{}", policy),
+            Err(e) => println!("Error: The policy could not be displayed: {}", e),
+        },
+        IoApiResponseBody::Event(event) => {
+            println!("{}", event);
+        }
+        IoApiResponseBody::AuthChallenge { .. }
+        | IoApiResponseBody::AuthProof { .. }
+        | IoApiResponseBody::Empty => {
+            println!("{}", response.message.unwrap_or_default());
+        }
+    }
+}
+
+/// Maps the error a command failed with onto the exit code of the shell.
+fn exit_code(error: &anyhow::Error) -> u8 {
+    match error.downcast_ref::<ClientError>() {
+        Some(ClientError::Rejected {
+            status: IoApiStatus::Unauthorized | IoApiStatus::Forbidden,
+            ..
+        }) => EXIT_DENIED,
+        Some(ClientError::Rejected { .. }) => EXIT_FAILED,
+        Some(_) => EXIT_UNREACHABLE,
+        None => EXIT_FAILED,
+    }
+}
+
+/// Parses the name of an event kind given on the command line.
+fn parse_event_kind(kind: &str) -> Result<IoApiEventKind, String> {
+    kind.parse().map_err(|_| {
+        format!(
+            "expected one of: {}",
+            IoApiEventKind::ALL.map(|kind| kind.as_str()).join(", ")
+        )
+    })
+}
+
+/// Returns the location of the REPL history file, if the home directory is known.
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(HISTORY_FILE_NAME))
+}
+
+/// Tab completion for the interactive shell.
+///
+/// Device IDs are fetched from the core whenever they are completed, so newly connected devices
+/// are offered right away.
+struct ShellHelper {
+    /// The client shared with the REPL.
+    client: Rc<RefCell<CoreClient>>,
+}
+
+impl ShellHelper {
+    /// Returns the IDs of all connected devices, or none if the core cannot be reached.
+    fn device_ids(&self) -> Vec<String> {
+        fn collect(device: &Device, ids: &mut Vec<String>) {
+            ids.push(device.device_id.to_string());
+            for child in device.devices.values() {
+                collect(child, ids);
+            }
+        }
+
+        let mut ids = vec![];
+        if let Ok(devices) = self.client.borrow_mut().list_devices() {
+            for device in devices.values() {
+                collect(device, &mut ids);
+            }
+        }
+        ids.sort();
+        ids
+    }
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line
+            .rfind(char::is_whitespace)
+            .map(|index| index + 1)
+            .unwrap_or(0);
+        let word = &line[start..];
+
+        let candidates = match line[..start].split_whitespace().collect::<Vec<_>>()[..] {
+            [] => COMMAND_NAMES.map(String::from).to_vec(),
+            ["enable" | "disable" | "approve"] => self.device_ids(),
+            ["subscribe", ..] => IoApiEventKind::ALL.map(|kind| kind.to_string()).to_vec(),
+            _ => vec![],
+        };
+
+        Ok((
+            start,
+            candidates
+                .into_iter()
+                .filter(|candidate| candidate.starts_with(word))
+                .collect(),
+        ))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
@@ -0,0 +1,588 @@
+//! # Client Module
+//!
+//! This module provides `CoreClient`, the IOAPI client used by the tools that talk to the core
+//! service (the CLI and GUI shells).
+//!
+//! A `CoreClient` takes care of everything below the commands themselves:
+//! - Locating the core, through the connection file or an explicit `CoreAddress`.
+//! - Authenticating TCP connections with the token file and performing the protocol handshake.
+//! - Timeouts for connecting and for every command (`DEFAULT_TIMEOUT`).
+//! - Reconnecting when the connection is lost, e.g. because the core was restarted
+//!   (`DEFAULT_RECONNECT_ATTEMPTS`).
+//! - Turning responses into typed results and `ClientError`s.
+//!
+//! Events are received through a `Subscription`, which uses a connection of its own so the
+//! client stays available for commands.
+
+use std::{collections::HashMap, io, thread, time::Duration};
+
+use crate::{
+    error::{ClientError, IoApiError},
+    helper::{
+        device_managment::{Device, DeviceId},
+        device_query::DeviceQuery,
+        ioapi::{
+            CoreAddress, EventFilter, IoApiCommand, IoApiEvent, IoApiResponse, IoApiResponseBody,
+            IoApiStatus, IoApiStream, PROTOCOL_VERSION, get_core_connection_addrs, read_event,
+            send_command,
+        },
+        ioapi_access::Role,
+        ioapi_auth::{AuthSecret, authenticate, ioapi_token_path},
+        policy::Policy,
+    },
+};
+
+/// The longest time to wait for a connection or a response by default.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often a lost connection is re-established for a single command by default.
+pub const DEFAULT_RECONNECT_ATTEMPTS: u32 = 3;
+
+/// The pause before the first reconnection attempt; it grows with every further attempt.
+const RECONNECT_DELAY: Duration = Duration::from_millis(200);
+
+/// Where a `CoreClient` looks for the core.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoreTarget {
+    /// The addresses listed in the connection file, read anew on every connection attempt.
+    ConnectionFile,
+    /// A fixed address.
+    Address(CoreAddress),
+}
+
+/// An open, authenticated connection that completed the handshake.
+struct Session {
+    /// The stream to the core.
+    stream: IoApiStream,
+    /// The address the stream is connected to.
+    address: CoreAddress,
+    /// The protocol version of the core.
+    protocol_version: u32,
+    /// The role the core assigned to the connection.
+    role: Role,
+}
+
+impl Session {
+    /// Opens a session with the first reachable address of a target.
+    fn open(
+        target: &CoreTarget,
+        timeout: Option<Duration>,
+        secret: Option<&AuthSecret>,
+    ) -> Result<Self, ClientError> {
+        let addresses = match target {
+            CoreTarget::ConnectionFile => {
+                get_core_connection_addrs().map_err(ClientError::ConnectionFile)?
+            }
+            CoreTarget::Address(address) => vec![address.clone()],
+        };
+
+        let mut last_error = ClientError::Disconnected;
+        for address in addresses {
+            match Self::open_address(&address, timeout, secret) {
+                Ok(session) => return Ok(session),
+                Err(source) => {
+                    last_error = ClientError::Connect {
+                        address: address.to_string(),
+                        source,
+                    }
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+    /// Opens a session with an address.
+    fn open_address(
+        address: &CoreAddress,
+        timeout: Option<Duration>,
+        secret: Option<&AuthSecret>,
+    ) -> Result<Self, IoApiError> {
+        let mut stream = match timeout {
+            Some(timeout) => address.connect_timeout(timeout)?,
+            None => address.connect()?,
+        };
+        stream.set_timeout(timeout)?;
+
+        if let CoreAddress::Tcp(_) = address {
+            match secret {
+                Some(secret) => authenticate(&mut stream, secret)?,
+                None => authenticate(&mut stream, &read_token_file()?)?,
+            }
+        }
+
+        let response = send_command(
+            &mut stream,
+            IoApiCommand::Handshake {
+                protocol_version: PROTOCOL_VERSION,
+            },
+        )?;
+        match (response.status, response.body) {
+            (
+                IoApiStatus::Ok,
+                IoApiResponseBody::Handshake {
+                    protocol_version,
+                    role,
+                },
+            ) => Ok(Self {
+                stream,
+                address: address.clone(),
+                protocol_version,
+                role,
+            }),
+            (status, _) => Err(IoApiError::HandshakeRejected(
+                status,
+                response.message.unwrap_or_default(),
+            )),
+        }
+    }
+}
+
+/// A client of the core service.
+///
+/// The client connects lazily: a client created with `new` (or one whose connection was lost)
+/// connects on its next command.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::client::{CoreClient, CoreTarget};
+/// use comp_gate::helper::ioapi::{IoApiCommand, IoApiResponse, IoApiResponseBody, PROTOCOL_VERSION};
+/// use comp_gate::helper::ioapi_access::Role;
+/// use comp_gate::helper::ioapi_auth::AuthSecret;
+/// use comp_gate::helper::ioapi_server::{IoApiServer, ServerEvent};
+/// use std::net::{Ipv4Addr, TcpListener};
+/// use std::time::Duration;
+///
+/// // A stand-in for the core that knows no devices.
+/// let secret = AuthSecret::generate().unwrap();
+/// let server_secret = secret.clone();
+/// let (address_sender, address_receiver) = std::sync::mpsc::channel();
+/// std::thread::spawn(move || {
+///     let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+///     let mut server = IoApiServer::new(vec![listener.into()], 8)
+///         .unwrap()
+///         .with_auth_secret(server_secret);
+///     address_sender.send(server.addresses().unwrap().remove(0)).unwrap();
+///
+///     loop {
+///         for event in server.poll(Duration::from_secs(1)).unwrap() {
+///             let ServerEvent::Command { id, command } = event else { continue };
+///             let body = match command.unwrap() {
+///                 IoApiCommand::Handshake { .. } => IoApiResponseBody::Handshake {
+///                     protocol_version: PROTOCOL_VERSION,
+///                     role: Role::Operator,
+///                 },
+///                 IoApiCommand::GetDeviceList => IoApiResponseBody::DeviceList {
+///                     devices: Default::default(),
+///                 },
+///                 _ => IoApiResponseBody::Empty,
+///             };
+///             server.send(id, &IoApiResponse::ok(body).to_frame());
+///         }
+///     }
+/// });
+///
+/// let address = address_receiver.recv().unwrap();
+/// // The client connects on its first command.
+/// let mut client = CoreClient::new(CoreTarget::Address(address)).with_auth_secret(secret);
+/// assert!(client.list_devices().unwrap().is_empty());
+/// assert_eq!(client.role(), Some(Role::Operator));
+/// client.disable("1-1").unwrap();
+/// ```
+pub struct CoreClient {
+    /// Where the core is looked for.
+    target: CoreTarget,
+    /// The longest time to wait for a connection or a response; `None` waits forever.
+    timeout: Option<Duration>,
+    /// How often a lost connection is re-established for a single command.
+    reconnect_attempts: u32,
+    /// The secret TCP connections authenticate with; read from the token file if `None`.
+    auth_secret: Option<AuthSecret>,
+    /// The current connection, if there is one.
+    session: Option<Session>,
+}
+
+impl CoreClient {
+    /// Creates a client without connecting yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `target` - Where to look for the core.
+    pub fn new(target: CoreTarget) -> Self {
+        Self {
+            target,
+            timeout: Some(DEFAULT_TIMEOUT),
+            reconnect_attempts: DEFAULT_RECONNECT_ATTEMPTS,
+            auth_secret: None,
+            session: None,
+        }
+    }
+
+    /// Connects to the core at the addresses listed in the connection file.
+    pub fn connect() -> Result<Self, ClientError> {
+        let mut client = Self::new(CoreTarget::ConnectionFile);
+        client.reconnect()?;
+        Ok(client)
+    }
+
+    /// Connects to the core at an explicit address.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - The address of the core.
+    pub fn connect_to(address: CoreAddress) -> Result<Self, ClientError> {
+        let mut client = Self::new(CoreTarget::Address(address));
+        client.reconnect()?;
+        Ok(client)
+    }
+
+    /// Sets the longest time to wait for a connection or a response; `None` waits forever.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        if let Some(session) = &self.session
+            && session.stream.set_timeout(timeout).is_err()
+        {
+            self.session = None;
+        }
+        self
+    }
+
+    /// Sets how often a lost connection is re-established for a single command.
+    pub fn with_reconnect_attempts(mut self, reconnect_attempts: u32) -> Self {
+        self.reconnect_attempts = reconnect_attempts;
+        self
+    }
+
+    /// Sets the secret TCP connections authenticate with, instead of reading the token file.
+    pub fn with_auth_secret(mut self, secret: AuthSecret) -> Self {
+        self.auth_secret = Some(secret);
+        self
+    }
+
+    /// Returns where the client looks for the core.
+    pub fn target(&self) -> &CoreTarget {
+        &self.target
+    }
+
+    /// Returns whether the client is connected to the core.
+    pub fn is_connected(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Returns the address the client is connected to.
+    pub fn address(&self) -> Option<&CoreAddress> {
+        self.session.as_ref().map(|session| &session.address)
+    }
+
+    /// Returns the protocol version of the core the client is connected to.
+    pub fn protocol_version(&self) -> Option<u32> {
+        self.session
+            .as_ref()
+            .map(|session| session.protocol_version)
+    }
+
+    /// Returns the role the core assigned to the connection.
+    pub fn role(&self) -> Option<Role> {
+        self.session.as_ref().map(|session| session.role)
+    }
+
+    /// Closes the current connection (if any) and opens a new one.
+    pub fn reconnect(&mut self) -> Result<(), ClientError> {
+        self.session = None;
+        self.session = Some(Session::open(
+            &self.target,
+            self.timeout,
+            self.auth_secret.as_ref(),
+        )?);
+        Ok(())
+    }
+
+    /// Closes the connection; the next command opens a new one.
+    pub fn disconnect(&mut self) {
+        self.session = None;
+    }
+
+    /// Sends a command and returns the response of the core, whatever its status.
+    ///
+    /// If the connection is lost (or cannot be opened), it is re-established up to
+    /// `reconnect_attempts` times before the command fails. A connection that timed out is
+    /// closed, since a late response would be taken for the response to the next command.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command to send.
+    pub fn request(&mut self, command: IoApiCommand) -> Result<IoApiResponse, ClientError> {
+        let mut failed_attempts = 0;
+        loop {
+            let result = self.session().and_then(|session| {
+                send_command(&mut session.stream, command.clone()).map_err(classify_error)
+            });
+
+            match result {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    self.session = None;
+                    if !is_transient(&e) || failed_attempts >= self.reconnect_attempts {
+                        return Err(e);
+                    }
+                    failed_attempts += 1;
+                    thread::sleep(RECONNECT_DELAY * failed_attempts);
+                }
+            }
+        }
+    }
+
+    /// Retrieves the tree of connected devices, keyed by the root-level device IDs.
+    pub fn list_devices(&mut self) -> Result<HashMap<DeviceId, Device>, ClientError> {
+        match self.execute(IoApiCommand::GetDeviceList)? {
+            IoApiResponseBody::DeviceList { devices } => Ok(devices),
+            _ => Err(ClientError::UnexpectedResponse("a device list")),
+        }
+    }
+
+    /// Retrieves the connected devices matching a query, ordered by device ID.
+    ///
+    /// The devices are returned without their sub-devices.
+    ///
+    /// # Arguments
+    ///
+    /// * `query` - The devices to select.
+    pub fn query_devices(&mut self, query: &DeviceQuery) -> Result<Vec<Device>, ClientError> {
+        match self.execute(IoApiCommand::QueryDevices(query.clone()))? {
+            IoApiResponseBody::Devices { devices } => Ok(devices),
+            _ => Err(ClientError::UnexpectedResponse("a list of devices")),
+        }
+    }
+
+    /// Enables a device.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - The Instance ID of the device.
+    pub fn enable(&mut self, device_id: impl Into<DeviceId>) -> Result<(), ClientError> {
+        self.execute(IoApiCommand::EnableDevice(device_id.into()))
+            .map(|_| ())
+    }
+
+    /// Approves a device waiting for an operator decision; the core adds a rule allowing it to
+    /// the policy and enables it.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - The Instance ID of the device.
+    pub fn approve(&mut self, device_id: impl Into<DeviceId>) -> Result<(), ClientError> {
+        self.execute(IoApiCommand::ApproveDevice(device_id.into()))
+            .map(|_| ())
+    }
+
+    /// Disables a device.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - The Instance ID of the device.
+    pub fn disable(&mut self, device_id: impl Into<DeviceId>) -> Result<(), ClientError> {
+        self.execute(IoApiCommand::DisableDevice(device_id.into()))
+            .map(|_| ())
+    }
+
+    /// Retrieves the device connection logs, oldest first.
+    pub fn logs(&mut self) -> Result<Vec<String>, ClientError> {
+        match self.execute(IoApiCommand::GetDeviceConnectionLogs)? {
+            IoApiResponseBody::DeviceConnectionLogs { logs } => Ok(logs),
+            _ => Err(ClientError::UnexpectedResponse("connection logs")),
+        }
+    }
+
+    /// Retrieves the active policy.
+    pub fn policy(&mut self) -> Result<Policy, ClientError> {
+        match self.execute(IoApiCommand::GetPolicy)? {
+            IoApiResponseBody::Policy { policy } => Ok(policy),
+            _ => Err(ClientError::UnexpectedResponse("a policy")),
+        }
+    }
+
+    /// Replaces the active policy.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The new policy.
+    pub fn set_policy(&mut self, policy: Policy) -> Result<(), ClientError> {
+        self.execute(IoApiCommand::SetPolicy(policy)).map(|_| ())
+    }
+
+    /// Subscribes to the events matching a filter.
+    ///
+    /// The subscription uses a new connection with the settings of this client; waiting for
+    /// events does not time out unless `Subscription::with_timeout` is used.
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The events to receive.
+    pub fn subscribe(&self, filter: EventFilter) -> Result<Subscription, ClientError> {
+        let mut client = Self::new(self.target.clone())
+            .with_timeout(self.timeout)
+            .with_reconnect_attempts(self.reconnect_attempts);
+        client.auth_secret = self.auth_secret.clone();
+
+        let mut subscription = Subscription {
+            client,
+            filter,
+            timeout: None,
+        };
+        subscription.subscribe()?;
+
+        Ok(subscription)
+    }
+
+    /// Sends a command and returns the body of its response, failing unless its status is `Ok`.
+    fn execute(&mut self, command: IoApiCommand) -> Result<IoApiResponseBody, ClientError> {
+        let response = self.request(command)?;
+        if response.status != IoApiStatus::Ok {
+            return Err(ClientError::Rejected {
+                status: response.status,
+                message: response.message.unwrap_or_default(),
+            });
+        }
+
+        Ok(response.body)
+    }
+
+    /// Returns the current session, opening one if there is none.
+    fn session(&mut self) -> Result<&mut Session, ClientError> {
+        if self.session.is_none() {
+            self.session = Some(Session::open(
+                &self.target,
+                self.timeout,
+                self.auth_secret.as_ref(),
+            )?);
+        }
+
+        Ok(self.session.as_mut().expect("the session was just opened"))
+    }
+}
+
+/// A connection receiving the events matching a filter.
+///
+/// If the connection is lost, it is re-established and the filter subscribed again; events
+/// that happened in between are missed.
+pub struct Subscription {
+    /// The client owning the subscribed connection.
+    client: CoreClient,
+    /// The events to receive.
+    filter: EventFilter,
+    /// The longest time to wait for an event; `None` waits forever.
+    timeout: Option<Duration>,
+}
+
+impl Subscription {
+    /// Sets the longest time `next_event` waits for an event; `None` waits forever.
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        if let Some(session) = &self.client.session
+            && session.stream.set_timeout(timeout).is_err()
+        {
+            self.client.session = None;
+        }
+        self
+    }
+
+    /// Returns the events the subscription receives.
+    pub fn filter(&self) -> &EventFilter {
+        &self.filter
+    }
+
+    /// Waits for the next event.
+    ///
+    /// After a `ClientError::Timeout`, the connection is re-established by the next call, since
+    /// part of an event may already have been read.
+    pub fn next_event(&mut self) -> Result<IoApiEvent, ClientError> {
+        let mut failed_attempts = 0;
+        loop {
+            let result = self.read_event();
+
+            match result {
+                Ok(event) => return Ok(event),
+                Err(e) => {
+                    self.client.session = None;
+                    if !is_transient(&e) || failed_attempts >= self.client.reconnect_attempts {
+                        return Err(e);
+                    }
+                    failed_attempts += 1;
+                    thread::sleep(RECONNECT_DELAY * failed_attempts);
+                }
+            }
+        }
+    }
+
+    /// Reads the next event, subscribing a new connection first if there is none.
+    fn read_event(&mut self) -> Result<IoApiEvent, ClientError> {
+        if !self.client.is_connected() {
+            self.subscribe()?;
+        }
+
+        let session = self.client.session()?;
+        read_event(&mut session.stream).map_err(classify_error)
+    }
+
+    /// Sends the filter over the connection of the client and switches to the event timeout.
+    fn subscribe(&mut self) -> Result<(), ClientError> {
+        self.client
+            .execute(IoApiCommand::Subscribe(self.filter.clone()))?;
+
+        let session = self.client.session()?;
+        session
+            .stream
+            .set_timeout(self.timeout)
+            .map_err(|e| classify_error(e.into()))
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = Result<IoApiEvent, ClientError>;
+
+    /// Waits for the next event; never returns `None`.
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.next_event())
+    }
+}
+
+/// Reads the secret of the running core from the token file.
+fn read_token_file() -> io::Result<AuthSecret> {
+    let token_path = ioapi_token_path();
+    AuthSecret::read(&token_path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "Reading the token file {} failed: {}",
+                token_path.display(),
+                e
+            ),
+        )
+    })
+}
+
+/// Maps a failed exchange with the core onto a `ClientError`.
+fn classify_error(error: IoApiError) -> ClientError {
+    let IoApiError::IoError(e) = error else {
+        return ClientError::Protocol(error);
+    };
+
+    match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ClientError::Timeout,
+        io::ErrorKind::UnexpectedEof
+        | io::ErrorKind::BrokenPipe
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::NotConnected => ClientError::Disconnected,
+        _ => ClientError::Protocol(IoApiError::IoError(e)),
+    }
+}
+
+/// Returns whether an error may go away by connecting again (e.g. while the core restarts).
+fn is_transient(error: &ClientError) -> bool {
+    matches!(
+        error,
+        ClientError::Disconnected | ClientError::Connect { .. } | ClientError::ConnectionFile(_)
+    )
+}
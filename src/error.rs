@@ -171,6 +171,10 @@ pub enum PollEventError {
     #[error("Win32 error occurred: {0}")]
     Win32Error(#[from] Win32Error),
 
+    /// The listener thread failed while reading from the system.
+    #[error("I/O error occurred: {0}")]
+    IoError(#[from] std::io::Error),
+
     /// Failed to receive a message from a channel (e.g., when communicating with the UI thread).
     #[error("Thread receive error: {0}")]
     ThreadRecvError(#[from] std::sync::mpsc::TryRecvError),
@@ -180,26 +184,261 @@ pub enum PollEventError {
     ThreadFinished,
 }
 
-/// Errors related to detecting and processing a new device insertion.
+/// Errors reported by a `DeviceBackend` implementation.
 #[derive(Error, Debug)]
-pub enum DeviceInsertionError {
-    /// A Windows API call failed during device inspection.
+pub enum DeviceBackendError {
+    /// A Windows API call failed.
     #[error("Win32 error occurred: {0}")]
     Win32Error(#[from] Win32Error),
 
-    /// The detected device was filtered out because it is not a USB device.
-    #[error("Device filtered out not a USB device")]
-    DeviceFilteredNotUsb,
+    /// Reading from or writing to the system failed.
+    #[error("I/O error occurred: {0}")]
+    IoError(#[from] std::io::Error),
+
+    /// The requested device is not known to the backend.
+    #[error("Device {0} does not exist")]
+    DeviceNotFound(String),
+
+    /// No device backend is available for the platform the application was built for.
+    #[error("Device management is not supported on this platform")]
+    UnsupportedPlatform,
+}
+
+/// Errors related to detecting and processing a new device insertion.
+#[derive(Error, Debug)]
+pub enum DeviceInsertionError {
+    /// The device backend failed during device inspection.
+    #[error("Device backend error occurred: {0}")]
+    BackendError(#[from] DeviceBackendError),
+
+    /// The detected device was excluded by the device filter.
+    #[error("Device filtered out by the device filter")]
+    DeviceFilteredOut,
 }
 
 /// Errors encountered when retrieving string properties from a device.
 #[derive(Error, Debug)]
 pub enum DeviceStringPropertyError {
-    /// A Windows API call failed while querying the property.
-    #[error("Win32 error occurred: {0}")]
-    Win32Error(#[from] Win32Error),
+    /// The device backend failed while querying the property.
+    #[error("Device backend error occurred: {0}")]
+    BackendError(#[from] DeviceBackendError),
 
     /// The requested property exists but is not of a string type (REG_SZ).
     #[error("Property is not a string property")]
     PropertyNotString,
 }
+
+/// Errors encountered while exchanging IOAPI messages.
+#[derive(Error, Debug)]
+pub enum IoApiError {
+    /// Reading from or writing to the connection failed.
+    #[error("I/O error occurred: {0}")]
+    IoError(#[from] std::io::Error),
+
+    /// A message could not be encoded or decoded.
+    #[error("Malformed IOAPI message: {0}")]
+    MalformedMessage(#[from] serde_json::Error),
+
+    /// The other side announced a frame larger than the protocol allows.
+    #[error("IOAPI frame of {0} bytes exceeds the maximum frame length")]
+    FrameTooLarge(u32),
+
+    /// The core refused the protocol handshake.
+    #[error("Handshake rejected with status {0:?}: {1}")]
+    HandshakeRejected(crate::helper::ioapi::IoApiStatus, String),
+
+    /// The core did not accept the authentication of the client.
+    #[error("Authentication failed with status {0:?}: {1}")]
+    AuthenticationFailed(crate::helper::ioapi::IoApiStatus, String),
+
+    /// An address of the core service could not be parsed.
+    #[error("Invalid core address `{0}`, expected `unix:<path>` or `tcp:<ip>:<port>`")]
+    InvalidAddress(String),
+}
+
+/// A problem found while validating a policy file, located by line and column (both 1-based).
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("{line}:{column}: {message}")]
+pub struct PolicyValidationError {
+    /// The line the problem was found on.
+    pub line: usize,
+    /// The column the problem was found at.
+    pub column: usize,
+    /// A description of the problem.
+    pub message: String,
+}
+
+/// Errors encountered while loading or storing a policy file.
+#[derive(Error, Debug)]
+pub enum PolicyFileError {
+    /// Reading or writing the policy file failed.
+    #[error("I/O error occurred: {0}")]
+    IoError(#[from] std::io::Error),
+
+    /// The policy file is not a valid policy.
+    #[error("Invalid policy file:\n{}", .0.iter().map(|e| e.to_string()).collect::<Vec<_>>().join("\n"))]
+    Invalid(Vec<PolicyValidationError>),
+
+    /// The policy could not be serialized.
+    #[error("Policy could not be serialized: {0}")]
+    SerializeError(#[from] toml::ser::Error),
+}
+
+/// Errors encountered while loading the device filter file.
+#[derive(Error, Debug)]
+pub enum DeviceFilterError {
+    /// Reading the device filter file failed.
+    #[error("I/O error occurred: {0}")]
+    IoError(#[from] std::io::Error),
+
+    /// The device filter file is not valid.
+    #[error("Invalid device filter file: {0}")]
+    ParseError(#[from] toml::de::Error),
+}
+
+/// Errors encountered while loading the IOAPI access file or checking the role of a client.
+#[derive(Error, Debug)]
+pub enum IoApiAccessError {
+    /// Reading the access file failed.
+    #[error("I/O error occurred: {0}")]
+    IoError(#[from] std::io::Error),
+
+    /// The access file is not valid.
+    #[error("Invalid IOAPI access file: {0}")]
+    ParseError(#[from] toml::de::Error),
+
+    /// The role of the client does not permit the command.
+    #[error(
+        "Permission denied: `{command}` requires the {required} role, the client has the {role} role"
+    )]
+    PermissionDenied {
+        /// The command that was refused.
+        command: &'static str,
+        /// The role the command requires.
+        required: crate::helper::ioapi_access::Role,
+        /// The role of the client.
+        role: crate::helper::ioapi_access::Role,
+    },
+}
+
+/// Errors returned by the `CoreClient`.
+#[derive(Error, Debug)]
+pub enum ClientError {
+    /// The addresses of the core could not be read from the connection file.
+    #[error("The core service could not be located: {0}")]
+    ConnectionFile(IoApiError),
+
+    /// No connection to the core could be opened.
+    #[error("Connecting to {address} failed: {source}")]
+    Connect {
+        /// The address that was tried last.
+        address: String,
+        /// Why the connection failed.
+        source: IoApiError,
+    },
+
+    /// The core did not answer in time.
+    #[error("The core service did not answer in time")]
+    Timeout,
+
+    /// The connection to the core was lost and could not be restored.
+    #[error("The connection to the core service was lost")]
+    Disconnected,
+
+    /// The core refused or failed to execute the command.
+    #[error("The core service answered with status {status:?}: {message}")]
+    Rejected {
+        /// The status of the response.
+        status: crate::helper::ioapi::IoApiStatus,
+        /// The message of the response.
+        message: String,
+    },
+
+    /// The core answered with a response that does not belong to the command.
+    #[error("Unexpected response from the core service, expected {0}")]
+    UnexpectedResponse(&'static str),
+
+    /// The messages exchanged with the core were not valid.
+    #[error(transparent)]
+    Protocol(#[from] IoApiError),
+}
+
+/// Errors encountered while writing or verifying the audit log.
+#[derive(Error, Debug)]
+pub enum AuditLogError {
+    /// Reading or writing the audit log failed.
+    #[error("I/O error occurred: {0}")]
+    IoError(#[from] std::io::Error),
+
+    /// A line of the audit log is not a valid record.
+    #[error("Line {line} of the audit log is not a valid record: {source}")]
+    MalformedRecord {
+        /// The line of the malformed record (1-based).
+        line: usize,
+        /// The reason the record could not be parsed.
+        source: serde_json::Error,
+    },
+
+    /// A record does not continue the hash chain of the records before it.
+    #[error("The audit log was modified at line {line}: {reason}")]
+    BrokenChain {
+        /// The line of the first record that does not fit the chain (1-based).
+        line: usize,
+        /// What does not fit.
+        reason: String,
+    },
+
+    /// The audit log ends before the last record written to it.
+    #[error("The audit log was truncated: {0}")]
+    Truncated(String),
+}
+
+/// Errors encountered while writing or reading a device tree snapshot.
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    /// Reading or writing the snapshot file failed.
+    #[error("I/O error occurred: {0}")]
+    IoError(#[from] std::io::Error),
+
+    /// The snapshot is not valid JSON or does not describe a device tree.
+    #[error("Malformed device snapshot: {0}")]
+    Malformed(#[from] serde_json::Error),
+
+    /// The snapshot was written in a format this build does not understand.
+    #[error("Device snapshot format version {0} is not supported, expected version {1}")]
+    UnsupportedVersion(u32, u32),
+}
+
+/// Errors encountered while parsing a device query.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum DeviceQueryError {
+    /// A term names a field that does not exist.
+    #[error(
+        "Unknown field `{0}`, expected one of: id, class, service, name, description, vendor, product, serial, manufacturer, driver, hwid, compatid, location, container, problem"
+    )]
+    UnknownField(String),
+
+    /// A `vid:` or `pid:` term does not carry a hexadecimal USB ID.
+    #[error("Invalid USB ID `{0}`, expected 4 hexadecimal digits")]
+    InvalidUsbId(String),
+
+    /// A token appeared where it is not allowed (e.g. a word without `:` or a stray `)`).
+    #[error("Unexpected `{0}` in device query")]
+    UnexpectedToken(String),
+
+    /// The query ended in the middle of an expression.
+    #[error("Unexpected end of device query")]
+    UnexpectedEnd,
+
+    /// A quoted value is not closed.
+    #[error("Unclosed quote in device query")]
+    UnclosedQuote,
+
+    /// Parentheses or `not`s are nested deeper than `MAX_QUERY_DEPTH`.
+    #[error("Device query is nested deeper than {0} levels")]
+    TooDeeplyNested(usize),
+
+    /// The query is longer than `MAX_QUERY_LENGTH` bytes.
+    #[error("Device query is {0} bytes long, at most {1} bytes are allowed")]
+    TooLong(usize, usize),
+}
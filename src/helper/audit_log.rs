@@ -0,0 +1,473 @@
+//! # Audit Log Module
+//!
+//! This module keeps a persistent, tamper-evident record of everything that happened to devices:
+//! connections, disconnections, policy decisions and changes made through the IOAPI.
+//!
+//! The audit log is an append-only file with one JSON `AuditRecord` per line. Every record
+//! carries the SHA-256 hash of the record before it and its own hash, forming a hash chain:
+//! editing, inserting or removing a record breaks the chain at that point. The number of records
+//! and the hash of the last one are additionally kept in a head file next to the log
+//! (`<log>.head`), so cutting records off the end of the log is detected as well.
+//!
+//! `verify_audit_log` checks a log file against its chain and head.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::AuditLogError,
+    helper::{
+        device_managment::{Device, DeviceId},
+        ioapi::{IoApiEvent, IoApiEventKind},
+        policy::{PolicyAction, PolicyDecision},
+        policy_file::write_atomically,
+        usb_ids::usb_ids,
+    },
+};
+
+/// The environment variable overriding the location of the audit log.
+pub const AUDIT_LOG_ENV: &str = "COMP_GATE_AUDIT_LOG";
+
+/// The `previous_hash` of the first record of a log.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Returns the location of the audit log.
+///
+/// The location can be overridden with the `COMP_GATE_AUDIT_LOG` environment variable.
+/// Otherwise it is:
+/// - On Windows: `%ProgramData%\comp-gate\audit.log`
+/// - Elsewhere: `/var/log/comp-gate/audit.log`
+pub fn audit_log_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(AUDIT_LOG_ENV) {
+        return PathBuf::from(path);
+    }
+
+    if cfg!(windows) {
+        let program_data =
+            std::env::var_os("ProgramData").unwrap_or_else(|| r"C:\ProgramData".into());
+        PathBuf::from(program_data)
+            .join("comp-gate")
+            .join("audit.log")
+    } else {
+        PathBuf::from("/var/log/comp-gate/audit.log")
+    }
+}
+
+/// Something to be recorded in the audit log.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    /// When it happened, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+    /// What happened.
+    pub kind: IoApiEventKind,
+    /// The device concerned, if any.
+    pub device_id: Option<DeviceId>,
+    /// The USB vendor ID of the device, if known.
+    pub vendor_id: Option<u16>,
+    /// The USB product ID of the device, if known.
+    pub product_id: Option<u16>,
+    /// The policy decision that caused it, if any.
+    pub decision: Option<PolicyDecision>,
+    /// Who caused it (e.g. `system`, `policy` or the IOAPI client).
+    pub actor: String,
+    /// A human readable description.
+    pub message: String,
+}
+
+impl AuditEntry {
+    /// Creates an entry for an event.
+    ///
+    /// # Arguments
+    ///
+    /// * `event` - The event to record.
+    /// * `actor` - Who caused the event.
+    pub fn from_event(event: &IoApiEvent, actor: impl Into<String>) -> Self {
+        Self {
+            timestamp: event.timestamp,
+            kind: event.kind,
+            device_id: event.device_id.clone(),
+            vendor_id: None,
+            product_id: None,
+            decision: None,
+            actor: actor.into(),
+            message: event.message.clone(),
+        }
+    }
+
+    /// Attaches the USB vendor and product ID of the device concerned, as read from the backend.
+    ///
+    /// The names of the vendor and product are resolved from them when the entry is appended.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::audit_log::{AuditEntry, AuditLog};
+    /// use comp_gate::helper::device_backend::DevicePropertyKey;
+    /// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+    /// use comp_gate::helper::device_managment::{DeviceId, DeviceTracker};
+    /// use comp_gate::helper::device_property::DeviceProperty;
+    /// use comp_gate::helper::ioapi::{IoApiEvent, IoApiEventKind};
+    ///
+    /// // A kernel name, like on Linux, carries no USB identity; the backend reports it.
+    /// let backend = SimulatedBackend::new();
+    /// let usb_id = |data| DeviceProperty::UInt16Property { data };
+    /// backend.add_device(
+    ///     SimulatedDevice::new("1-1")
+    ///         .with_property_value(DevicePropertyKey::VendorId, usb_id(0x046D))
+    ///         .with_property_value(DevicePropertyKey::ProductId, usb_id(0xC52B)),
+    /// );
+    /// let tracker = DeviceTracker::load_with_backend(Box::new(backend)).unwrap();
+    /// let device = tracker.find_device(&DeviceId::from("1-1")).unwrap();
+    ///
+    /// let path = std::env::temp_dir().join("comp-gate-audit-device-doc.log");
+    /// let _ = std::fs::remove_file(&path);
+    /// let _ = std::fs::remove_file(path.with_extension("log.head"));
+    ///
+    /// let event = IoApiEvent::new(IoApiEventKind::Connected, Some(device.device_id.clone()), "1-1");
+    /// let record = AuditLog::open(&path)
+    ///     .unwrap()
+    ///     .append(AuditEntry::from_event(&event, "system").with_device(device))
+    ///     .unwrap();
+    /// assert_eq!(record.vendor_id.as_deref(), Some("046D"));
+    /// assert_eq!(record.product_id.as_deref(), Some("C52B"));
+    /// assert_eq!(record.vendor_name.as_deref(), Some("Logitech, Inc."));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// # std::fs::remove_file(path.with_extension("log.head")).unwrap();
+    /// ```
+    pub fn with_device(mut self, device: &Device) -> Self {
+        self.vendor_id = device.vendor_id;
+        self.product_id = device.product_id;
+        self
+    }
+
+    /// Attaches the policy decision that caused the entry.
+    pub fn with_decision(mut self, decision: PolicyDecision) -> Self {
+        self.decision = Some(decision);
+        self
+    }
+}
+
+/// A record of the audit log, as stored on disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// The position of the record in the log, starting at 0.
+    pub sequence: u64,
+    /// When it happened, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+    /// What happened.
+    pub kind: IoApiEventKind,
+    /// The device concerned, if any.
+    pub device_id: Option<DeviceId>,
+    /// The USB vendor ID of the device, if known.
+    pub vendor_id: Option<String>,
+    /// The USB product ID of the device, if known.
+    pub product_id: Option<String>,
+    /// The vendor name from the `usb.ids` database, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor_name: Option<String>,
+    /// The product name from the `usb.ids` database, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_name: Option<String>,
+    /// The action decided by the policy, if the record was caused by a policy decision.
+    pub decision: Option<PolicyAction>,
+    /// The rule behind the decision; `None` if the default action applied.
+    pub rule: Option<String>,
+    /// Who caused it.
+    pub actor: String,
+    /// A human readable description.
+    pub message: String,
+    /// The hash of the previous record.
+    pub previous_hash: String,
+    /// The hash of this record, covering every other field.
+    pub hash: String,
+}
+
+impl AuditRecord {
+    /// Computes the hash of the record over every field except `hash`.
+    pub fn compute_hash(&self) -> String {
+        let unhashed = AuditRecord {
+            hash: String::new(),
+            ..self.clone()
+        };
+        let bytes = serde_json::to_vec(&unhashed).expect("AuditRecord always serializes to JSON");
+
+        Sha256::digest(&bytes)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+impl std::fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "#{} [{}] {} by {}: {}",
+            self.sequence, self.timestamp, self.kind, self.actor, self.message
+        )?;
+
+        let names: Vec<&str> = [&self.vendor_name, &self.product_name]
+            .into_iter()
+            .filter_map(Option::as_deref)
+            .collect();
+        if !names.is_empty() {
+            write!(f, " ({})", names.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The end of the hash chain, stored in the head file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct AuditHead {
+    /// The number of records in the log.
+    records: u64,
+    /// The hash of the last record.
+    hash: String,
+}
+
+/// An audit log opened for appending.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::audit_log::{AuditEntry, AuditLog, verify_audit_log};
+/// use comp_gate::helper::ioapi::{IoApiEvent, IoApiEventKind};
+///
+/// let path = std::env::temp_dir().join("comp-gate-audit-doc.log");
+/// let _ = std::fs::remove_file(&path);
+/// let _ = std::fs::remove_file(path.with_extension("log.head"));
+///
+/// let mut log = AuditLog::open(&path).unwrap();
+/// for kind in [IoApiEventKind::Connected, IoApiEventKind::Disconnected] {
+///     let event = IoApiEvent::new(kind, None, "Receiver");
+///     log.append(AuditEntry::from_event(&event, "system")).unwrap();
+/// }
+/// assert_eq!(verify_audit_log(&path).unwrap(), 2);
+///
+/// // Editing a record breaks the chain.
+/// let content = std::fs::read_to_string(&path).unwrap();
+/// std::fs::write(&path, content.replacen("Receiver", "Keyboard", 1)).unwrap();
+/// assert!(verify_audit_log(&path).is_err());
+///
+/// // So does cutting off the last record.
+/// let first_line = content.lines().next().unwrap();
+/// std::fs::write(&path, format!("{}\n", first_line)).unwrap();
+/// assert!(verify_audit_log(&path).is_err());
+/// # std::fs::remove_file(&path).unwrap();
+/// # std::fs::remove_file(path.with_extension("log.head")).unwrap();
+/// ```
+pub struct AuditLog {
+    /// The file the records are appended to.
+    file: File,
+    /// The location of the head file.
+    head_path: PathBuf,
+    /// The current end of the hash chain.
+    head: AuditHead,
+}
+
+impl AuditLog {
+    /// Opens (or creates) an audit log for appending.
+    ///
+    /// The chain is continued from the head file. If the head file is missing, it is continued
+    /// from the last record of the log.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The location of the audit log.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, AuditLogError> {
+        let path = path.into();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let head_path = head_path(&path);
+        let head = match read_head(&head_path)? {
+            Some(head) => head,
+            None => match read_audit_log(&path)?.last() {
+                Some(record) => AuditHead {
+                    records: record.sequence + 1,
+                    hash: record.hash.clone(),
+                },
+                None => AuditHead {
+                    records: 0,
+                    hash: GENESIS_HASH.to_string(),
+                },
+            },
+        };
+
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+
+        Ok(Self {
+            file,
+            head_path,
+            head,
+        })
+    }
+
+    /// Appends an entry to the log and flushes it to disk.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(AuditRecord)` - The record that was written.
+    /// * `Err(AuditLogError)` - If writing the record or the head file fails.
+    pub fn append(&mut self, entry: AuditEntry) -> Result<AuditRecord, AuditLogError> {
+        let vendor_name = entry
+            .vendor_id
+            .and_then(|vendor_id| usb_ids().vendor_name(vendor_id))
+            .map(str::to_string);
+        let product_name = entry
+            .vendor_id
+            .zip(entry.product_id)
+            .and_then(|(vendor_id, product_id)| usb_ids().product_name(vendor_id, product_id))
+            .map(str::to_string);
+        let vendor_id = entry.vendor_id.map(|id| format!("{:04X}", id));
+        let product_id = entry.product_id.map(|id| format!("{:04X}", id));
+        let (decision, rule) = match entry.decision {
+            Some(decision) => (Some(decision.action), decision.rule),
+            None => (None, None),
+        };
+
+        let mut record = AuditRecord {
+            sequence: self.head.records,
+            timestamp: entry.timestamp,
+            kind: entry.kind,
+            device_id: entry.device_id,
+            vendor_id,
+            product_id,
+            vendor_name,
+            product_name,
+            decision,
+            rule,
+            actor: entry.actor,
+            message: entry.message,
+            previous_hash: self.head.hash.clone(),
+            hash: String::new(),
+        };
+        record.hash = record.compute_hash();
+
+        let mut line = serde_json::to_vec(&record).expect("AuditRecord always serializes to JSON");
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+
+        let head = AuditHead {
+            records: record.sequence + 1,
+            hash: record.hash.clone(),
+        };
+        let head_bytes = serde_json::to_vec(&head).expect("AuditHead always serializes to JSON");
+        write_atomically(&self.head_path, &head_bytes)?;
+        self.head = head;
+
+        Ok(record)
+    }
+}
+
+/// Reads every record of an audit log, without verifying the chain.
+///
+/// A missing log reads as empty.
+pub fn read_audit_log(path: &Path) -> Result<Vec<AuditRecord>, AuditLogError> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut records = vec![];
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record =
+            serde_json::from_str(&line).map_err(|source| AuditLogError::MalformedRecord {
+                line: index + 1,
+                source,
+            })?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// Verifies the hash chain of an audit log against its head file.
+///
+/// # Returns
+///
+/// * `Ok(u64)` - The number of records, if the log is intact.
+/// * `Err(AuditLogError::BrokenChain)` - If a record was edited, inserted or removed.
+/// * `Err(AuditLogError::Truncated)` - If the log ends before its last written record.
+/// * `Err(AuditLogError)` - If the log cannot be read or parsed.
+pub fn verify_audit_log(path: &Path) -> Result<u64, AuditLogError> {
+    let records = read_audit_log(path)?;
+
+    let mut previous_hash = GENESIS_HASH.to_string();
+    for (index, record) in records.iter().enumerate() {
+        let broken = |reason: String| AuditLogError::BrokenChain {
+            line: index + 1,
+            reason,
+        };
+
+        if record.sequence != index as u64 {
+            return Err(broken(format!(
+                "expected record #{}, found record #{}",
+                index, record.sequence
+            )));
+        }
+        if record.previous_hash != previous_hash {
+            return Err(broken(
+                "the record does not follow the record before it".to_string(),
+            ));
+        }
+        if record.hash != record.compute_hash() {
+            return Err(broken(
+                "the content of the record does not match its hash".to_string(),
+            ));
+        }
+        previous_hash = record.hash.clone();
+    }
+
+    let records = records.len() as u64;
+    match read_head(&head_path(path))? {
+        Some(head) if head.records > records => Err(AuditLogError::Truncated(format!(
+            "{} records were written, only {} are left",
+            head.records, records
+        ))),
+        Some(head) if head.records != records || head.hash != previous_hash => {
+            Err(AuditLogError::BrokenChain {
+                line: records as usize,
+                reason: "the end of the log does not match its head file".to_string(),
+            })
+        }
+        Some(_) => Ok(records),
+        None if records == 0 => Ok(0),
+        None => Err(AuditLogError::Truncated(
+            "the head file of the log is missing".to_string(),
+        )),
+    }
+}
+
+/// Returns the location of the head file of an audit log.
+fn head_path(path: &Path) -> PathBuf {
+    let mut head_name = path.file_name().unwrap_or_default().to_os_string();
+    head_name.push(".head");
+    path.with_file_name(head_name)
+}
+
+/// Reads the head file of an audit log, if it exists.
+fn read_head(head_path: &Path) -> Result<Option<AuditHead>, AuditLogError> {
+    match fs::read(head_path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map(Some)
+            .map_err(|source| AuditLogError::MalformedRecord { line: 1, source }),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
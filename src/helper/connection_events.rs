@@ -0,0 +1,380 @@
+//! # Connection Events Module
+//!
+//! This module keeps a `DeviceTracker` in sync with the `UsbConnectionEvent`s reported by
+//! the connection listener. It lives in the library (rather than the core binary) so the
+//! handling can be exercised against a simulated backend.
+//!
+//! Every handled event is reported back as `IoApiEvent`s, which the core pushes to subscribed
+//! IOAPI connections.
+//!
+//! HID devices are special-cased: when one arrives, its parent USB device is inserted as well,
+//! and when one leaves, its parent is removed with it.
+//!
+//! When the listener lost events, the tree is re-enumerated and the devices that appeared or
+//! vanished in the meantime are reported as if their events had arrived.
+
+use crate::helper::{
+    device_diff::{DeviceChange, DeviceTreeDiff},
+    device_managment::{DeviceId, DeviceTracker, device_path_to_device_id},
+    ioapi::{IoApiEvent, IoApiEventKind},
+    usb_connection_callback::UsbConnectionEvent,
+    usb_identity::UsbIdentity,
+};
+
+/// Applies a single connection event to the device tracker.
+///
+/// Every event is also appended to `device_connection_logs`.
+///
+/// # Returns
+///
+/// The connect/disconnect events of every device added to or removed from the tracker,
+/// including the parents of HID devices.
+///
+/// # Arguments
+///
+/// * `event` - The event reported by the connection listener.
+/// * `device_tracker` - The tracker to update.
+/// * `device_connection_logs` - The log of connection events.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::connection_events::handle_connection_event;
+/// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+/// use comp_gate::helper::device_managment::{DeviceId, DeviceTracker};
+/// use comp_gate::helper::ioapi::IoApiEventKind;
+/// use comp_gate::helper::usb_connection_callback::UsbConnectionEvent;
+/// use std::rc::Rc;
+///
+/// let backend = SimulatedBackend::new();
+/// let mut tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+/// let events = backend.callbacks_handle();
+/// let mut logs = vec![];
+///
+/// // A keyboard arrives: the HID device pulls its USB parent into the tracker.
+/// backend.add_device(SimulatedDevice::new(r"USB\VID_046D&PID_C52B\5&2752457F&0&2"));
+/// backend.connect_device(
+///     SimulatedDevice::new(r"HID\VID_046D&PID_C52B\6&1A2B3C4D&0&0000")
+///         .with_parent(r"USB\VID_046D&PID_C52B\5&2752457F&0&2"),
+/// );
+/// let pushed = handle_connection_event(events.poll_events().unwrap(), &mut tracker, &mut logs);
+///
+/// assert_eq!(pushed.len(), 2);
+/// let parent = DeviceId::from(Rc::<str>::from(r"USB\VID_046D&PID_C52B\5&2752457F&0&2"));
+/// assert_eq!(tracker.find_device(&parent).unwrap().devices.len(), 1);
+/// assert_eq!(logs.len(), 2);
+///
+/// // Unplugging the HID device takes its parent with it.
+/// backend.disconnect_device(r"HID\VID_046D&PID_C52B\6&1A2B3C4D&0&0000");
+/// handle_connection_event(events.poll_events().unwrap(), &mut tracker, &mut logs);
+/// assert!(tracker.devices.is_empty());
+///
+/// // After events were lost, the tree is re-enumerated: the new stick and the receiver, which
+/// // is still present in the backend, are reported as connected.
+/// backend.add_device(SimulatedDevice::new(r"USB\VID_0781&PID_5581\4C530001"));
+/// backend.inject_event(UsbConnectionEvent::EventsLost);
+/// let pushed = handle_connection_event(events.poll_events().unwrap(), &mut tracker, &mut logs);
+/// assert_eq!(pushed.len(), 2);
+/// assert!(pushed.iter().all(|event| event.kind == IoApiEventKind::Connected));
+/// assert_eq!(tracker.devices.len(), 2);
+/// ```
+pub fn handle_connection_event(
+    event: UsbConnectionEvent,
+    device_tracker: &mut DeviceTracker,
+    device_connection_logs: &mut Vec<Box<str>>,
+) -> Vec<IoApiEvent> {
+    let mut events = vec![];
+
+    match event {
+        UsbConnectionEvent::Connected(device_path) => {
+            let device_id = device_path_to_device_id(&device_path);
+
+            let log = format!("USB Device connected: {}", device_id);
+            println!("{}", log);
+            device_connection_logs.push(log.clone().into_boxed_str());
+
+            match device_tracker.insert_device_by_id(&device_id) {
+                Ok(_) => {
+                    println!("- Device inserted into tracker");
+                    println!("- Current device tracker state:\n{}", device_tracker);
+                    events.push(IoApiEvent::new(
+                        IoApiEventKind::Connected,
+                        Some(device_id.clone()),
+                        log,
+                    ));
+                    if UsbIdentity::from(&device_id).is_hid() {
+                        handle_hid_device_insertion(
+                            &device_id,
+                            device_tracker,
+                            device_connection_logs,
+                            &mut events,
+                        );
+                    }
+                }
+                Err(e) => println!("- Error inserting device into tracker: {}", e),
+            }
+        }
+        UsbConnectionEvent::Disconnected(device_path) => {
+            let device_id = device_path_to_device_id(&device_path);
+
+            let log = format!("USB Device disconnected: {}", device_id);
+            println!("{}", log);
+            device_connection_logs.push(log.clone().into_boxed_str());
+
+            match device_tracker.remove_device_by_id(&device_id) {
+                Some(device) => {
+                    println!("- Device removed from tracker");
+                    println!("- Current device tracker state:\n{}", device_tracker);
+                    events.push(IoApiEvent::new(
+                        IoApiEventKind::Disconnected,
+                        Some(device_id.clone()),
+                        log,
+                    ));
+                    if UsbIdentity::from(&device_id).is_hid() {
+                        handle_hid_device_removal(
+                            device.parent_id.as_ref(),
+                            device_tracker,
+                            device_connection_logs,
+                            &mut events,
+                        );
+                    }
+                }
+                None => println!("- Error removing device {} from tracker.", device_id),
+            }
+        }
+        UsbConnectionEvent::EventsLost => {
+            let log = "USB connection events were lost, re-enumerating devices".to_string();
+            println!("{}", log);
+            device_connection_logs.push(log.into_boxed_str());
+
+            let old_devices = device_tracker.devices.clone();
+            if let Err(e) = device_tracker.reload() {
+                println!("- Error re-enumerating devices: {}", e);
+                return events;
+            }
+
+            for change in DeviceTreeDiff::between(&old_devices, &device_tracker.devices).changes {
+                let (kind, device_id, verb) = match change {
+                    DeviceChange::Added { device_id, .. } => {
+                        (IoApiEventKind::Connected, device_id, "connected")
+                    }
+                    DeviceChange::Removed { device_id, .. } => {
+                        (IoApiEventKind::Disconnected, device_id, "disconnected")
+                    }
+                    _ => continue,
+                };
+
+                let log = format!("USB Device {}: {}", verb, device_id);
+                println!("{}", log);
+                device_connection_logs.push(log.clone().into_boxed_str());
+                events.push(IoApiEvent::new(kind, Some(device_id), log));
+            }
+        }
+    }
+
+    events
+}
+
+/// Inserts the parent of a newly inserted HID device into the tracker.
+///
+/// HID devices are reported without their USB parent, which is the device that can
+/// actually be enabled or disabled, so the parent is inserted (recursively) as well. A parent
+/// that is already tracked is left alone and not reported again.
+///
+/// # Arguments
+///
+/// * `hid_device_id` - The Instance ID of the HID device that was just inserted.
+/// * `device_tracker` - The tracker to update.
+/// * `device_connection_logs` - The log of connection events.
+/// * `events` - Receives a `Connected` event for every inserted device.
+pub fn handle_hid_device_insertion(
+    hid_device_id: &DeviceId,
+    device_tracker: &mut DeviceTracker,
+    device_connection_logs: &mut Vec<Box<str>>,
+    events: &mut Vec<IoApiEvent>,
+) {
+    let Some(hid_device) = device_tracker.find_device(hid_device_id) else {
+        return;
+    };
+    let Some(parent_device_id) = hid_device.parent_id.clone() else {
+        return;
+    };
+    // Composite devices report one HID device per interface; only the first inserts the parent.
+    if device_tracker.find_device(&parent_device_id).is_some() {
+        return;
+    }
+
+    let log = format!("USB Device connected: {}", parent_device_id);
+    println!("{}", log);
+    device_connection_logs.push(log.clone().into_boxed_str());
+
+    match device_tracker.insert_device_by_id(&parent_device_id) {
+        Ok(_) => {
+            println!("- Device inserted into tracker");
+            println!("- Current device tracker state:\n{}", device_tracker);
+            events.push(IoApiEvent::new(
+                IoApiEventKind::Connected,
+                Some(parent_device_id.clone()),
+                log,
+            ));
+            if UsbIdentity::from(&parent_device_id).is_hid() {
+                handle_hid_device_insertion(
+                    &parent_device_id,
+                    device_tracker,
+                    device_connection_logs,
+                    events,
+                );
+            }
+        }
+        Err(e) => println!("- Error inserting device into tracker: {}", e),
+    }
+}
+
+/// Removes the parent of a removed HID device from the tracker.
+///
+/// # Arguments
+///
+/// * `parent_device_id` - The Instance ID of the parent of the HID device that was removed.
+/// * `device_tracker` - The tracker to update.
+/// * `device_connection_logs` - The log of connection events.
+/// * `events` - Receives a `Disconnected` event for every removed device.
+pub fn handle_hid_device_removal(
+    parent_device_id: Option<&DeviceId>,
+    device_tracker: &mut DeviceTracker,
+    device_connection_logs: &mut Vec<Box<str>>,
+    events: &mut Vec<IoApiEvent>,
+) {
+    let Some(parent_device_id) = parent_device_id else {
+        return;
+    };
+
+    let log = format!("USB Device disconnected: {}", parent_device_id);
+    println!("{}", log);
+    device_connection_logs.push(log.clone().into_boxed_str());
+
+    match device_tracker.remove_device_by_id(parent_device_id) {
+        Some(device) => {
+            println!("- Device removed from tracker");
+            println!("- Current device tracker state:\n{}", device_tracker);
+            events.push(IoApiEvent::new(
+                IoApiEventKind::Disconnected,
+                Some(parent_device_id.clone()),
+                log,
+            ));
+            if UsbIdentity::from(parent_device_id).is_hid() {
+                handle_hid_device_removal(
+                    device.parent_id.as_ref(),
+                    device_tracker,
+                    device_connection_logs,
+                    events,
+                );
+            }
+        }
+        None => println!("- Error removing device {} from tracker", parent_device_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+
+    const RECEIVER: &str = r"USB\VID_046D&PID_C52B\5&2752457F&0&2";
+    const KEYBOARD: &str = r"HID\VID_046D&PID_C52B&MI_00\7&1";
+    const MOUSE: &str = r"HID\VID_046D&PID_C52B&MI_01\7&2";
+
+    fn id(device_id: &str) -> DeviceId {
+        DeviceId::from(Rc::<str>::from(device_id))
+    }
+
+    fn connected(device_id: &str) -> Vec<(IoApiEventKind, Option<DeviceId>)> {
+        vec![(IoApiEventKind::Connected, Some(id(device_id)))]
+    }
+
+    fn kinds(events: &[IoApiEvent]) -> Vec<(IoApiEventKind, Option<DeviceId>)> {
+        events
+            .iter()
+            .map(|event| (event.kind, event.device_id.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn second_hid_interface_does_not_reconnect_parent() {
+        let backend = SimulatedBackend::new();
+        let mut tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+        let handle = backend.callbacks_handle();
+        let mut logs = vec![];
+
+        backend.add_device(SimulatedDevice::new(RECEIVER));
+        backend.connect_device(SimulatedDevice::new(KEYBOARD).with_parent(RECEIVER));
+        let events =
+            handle_connection_event(handle.poll_events().unwrap(), &mut tracker, &mut logs);
+        assert_eq!(
+            kinds(&events),
+            [connected(KEYBOARD), connected(RECEIVER)].concat()
+        );
+
+        // The second interface of the composite device only reports itself.
+        backend.connect_device(SimulatedDevice::new(MOUSE).with_parent(RECEIVER));
+        let events =
+            handle_connection_event(handle.poll_events().unwrap(), &mut tracker, &mut logs);
+        assert_eq!(kinds(&events), connected(MOUSE));
+        assert_eq!(logs.len(), 3);
+        assert_eq!(tracker.find_device(&id(RECEIVER)).unwrap().devices.len(), 2);
+    }
+
+    #[test]
+    fn device_gone_before_insertion_is_not_reported() {
+        let backend = SimulatedBackend::new();
+        let mut tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+        let handle = backend.callbacks_handle();
+        let mut logs = vec![];
+
+        // The device is unplugged again before its arrival is handled.
+        backend.connect_device(SimulatedDevice::new(RECEIVER));
+        backend.remove_device(RECEIVER);
+        let events =
+            handle_connection_event(handle.poll_events().unwrap(), &mut tracker, &mut logs);
+
+        assert!(events.is_empty());
+        assert_eq!(logs.len(), 1);
+        assert!(tracker.devices.is_empty());
+    }
+
+    #[test]
+    fn hid_device_with_missing_parent_is_tracked_alone() {
+        let backend = SimulatedBackend::new();
+        let mut tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+        let handle = backend.callbacks_handle();
+        let mut logs = vec![];
+
+        // The parent vanished already; inserting it fails, the HID device stays.
+        backend.connect_device(SimulatedDevice::new(KEYBOARD).with_parent(RECEIVER));
+        let events =
+            handle_connection_event(handle.poll_events().unwrap(), &mut tracker, &mut logs);
+
+        assert_eq!(kinds(&events), connected(KEYBOARD));
+        assert!(tracker.find_device(&id(KEYBOARD)).is_some());
+        assert!(tracker.find_device(&id(RECEIVER)).is_none());
+    }
+
+    #[test]
+    fn unknown_device_disconnect_is_not_reported() {
+        let backend = SimulatedBackend::new();
+        backend.add_device(SimulatedDevice::new(RECEIVER));
+        let mut tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+        let handle = backend.callbacks_handle();
+        let mut logs = vec![];
+
+        backend.add_device(SimulatedDevice::new(MOUSE));
+        backend.disconnect_device(MOUSE);
+        let events =
+            handle_connection_event(handle.poll_events().unwrap(), &mut tracker, &mut logs);
+
+        assert!(events.is_empty());
+        assert_eq!(logs.len(), 1);
+        assert_eq!(tracker.devices.len(), 1);
+    }
+}
@@ -0,0 +1,222 @@
+//! # Device Backend Module
+//!
+//! This module defines the `DeviceBackend` trait, the abstraction that sits between the
+//! `DeviceTracker` and the operating system's device APIs. The tracker only ever talks to a
+//! backend, which keeps the tree-building, merging and whitelist logic platform-neutral.
+//!
+//! Available backends:
+//!
+//! - `setupapi`: The Windows implementation built on SetupAPI and the Configuration Manager API.
+//! - `sysfs`: The Linux implementation built on the sysfs USB device tree.
+//! - `simulated`: An in-memory implementation with a scriptable device tree, used for testing.
+
+#[cfg(windows)]
+pub mod setupapi;
+pub mod simulated;
+#[cfg(target_os = "linux")]
+pub mod sysfs;
+
+use std::rc::Rc;
+
+use crate::{
+    error::{DeviceBackendError, DeviceStringPropertyError},
+    helper::{
+        device_managment::{DeviceId, DeviceState},
+        device_property::DeviceProperty,
+        usb_identity::UsbIdentity,
+    },
+};
+
+/// Identifies a device property in a platform-neutral way.
+///
+/// Each backend maps these keys onto its own property store
+/// (e.g., `DEVPKEY_Device_Service` on Windows).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DevicePropertyKey {
+    /// The name of the service (driver) driving the device.
+    Service,
+    /// The device setup class (e.g., "USB", "HIDClass").
+    Class,
+    /// The friendly name of the device.
+    FriendlyName,
+    /// The device type identifier.
+    DeviceType,
+    /// The description of the device.
+    Description,
+    /// The hardware IDs of the device, most specific first.
+    HardwareIds,
+    /// The compatible IDs of the device, most specific first.
+    CompatibleIds,
+    /// The manufacturer of the device.
+    Manufacturer,
+    /// The location paths of the device (e.g. `PCIROOT(0)#PCI(1400)#USBROOT(0)#USB(2)`).
+    LocationPaths,
+    /// The bus-specific physical location of the device (e.g. `Port_#0002.Hub_#0001`).
+    LocationInfo,
+    /// The description the device reports over its bus.
+    BusReportedDescription,
+    /// The version of the driver bound to the device.
+    DriverVersion,
+    /// The provider of the driver bound to the device.
+    DriverProvider,
+    /// When the device was first installed.
+    InstallDate,
+    /// The GUID grouping the devices that belong to one physical device.
+    ContainerId,
+    /// The code of the problem the device has, if any.
+    ProblemCode,
+    /// The status flags of the device node.
+    Status,
+    /// The USB vendor ID (`idVendor`) of the device.
+    VendorId,
+    /// The USB product ID (`idProduct`) of the device.
+    ProductId,
+    /// The serial number reported by the device.
+    Serial,
+}
+
+/// The operations a platform has to provide for `DeviceTracker` to manage its devices.
+///
+/// Devices are always addressed by their Instance ID, so implementations are free to
+/// resolve them to whatever native handle they need on every call.
+pub trait DeviceBackend {
+    /// Enumerates the Instance IDs of all present devices the backend can see.
+    ///
+    /// Which of them are tracked is decided by the `DeviceFilter` of the `DeviceTracker`.
+    fn enumerate_devices(&self) -> Result<Vec<DeviceId>, DeviceBackendError>;
+
+    /// Retrieves a property of a device.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - The Instance ID of the device.
+    /// * `key` - The property to retrieve.
+    fn query_property(
+        &self,
+        device_id: &DeviceId,
+        key: DevicePropertyKey,
+    ) -> Result<DeviceProperty, DeviceBackendError>;
+
+    /// Retrieves the Instance ID of the parent of a device.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(DeviceId))` - The parent of the device.
+    /// * `Ok(None)` - If the device has no parent.
+    fn query_parent_id(&self, device_id: &DeviceId)
+    -> Result<Option<DeviceId>, DeviceBackendError>;
+
+    /// Retrieves the current state of a device.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - The Instance ID of the device.
+    fn query_device_state(&self, device_id: &DeviceId) -> Result<DeviceState, DeviceBackendError>;
+
+    /// Changes the state of a device (Enable/Disable).
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - The Instance ID of the device to modify.
+    /// * `state` - The desired state.
+    fn change_device_state(
+        &self,
+        device_id: &DeviceId,
+        state: DeviceState,
+    ) -> Result<(), DeviceBackendError>;
+
+    /// Makes devices connected from now on start out disabled, so a device is only ever enabled
+    /// once the policy allowed it.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(true)` - New devices start out disabled; devices the policy never decides about
+    ///   have to be enabled explicitly.
+    /// * `Ok(false)` - The backend cannot hold new devices back, they have to be disabled after
+    ///   they arrived (the default).
+    fn disable_new_devices(&self) -> Result<bool, DeviceBackendError> {
+        Ok(false)
+    }
+
+    /// Retrieves a property of a device that is expected to be a string.
+    fn query_string_property(
+        &self,
+        device_id: &DeviceId,
+        key: DevicePropertyKey,
+    ) -> Result<Rc<str>, DeviceStringPropertyError> {
+        match self.query_property(device_id, key)? {
+            DeviceProperty::StringProperty { data } => Ok(Rc::from(data)),
+            _ => Err(DeviceStringPropertyError::PropertyNotString),
+        }
+    }
+}
+
+/// Derives the `VendorId`, `ProductId` and `Serial` properties from a Windows Instance ID,
+/// which carries them in its hardware ID and instance suffix.
+///
+/// # Returns
+///
+/// * The property, or `EmptyProperty` if the ID does not carry it (e.g. Linux kernel names)
+///   or `key` is not one of the three identity properties.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::device_backend::{DevicePropertyKey, identity_property};
+/// use comp_gate::helper::device_managment::DeviceId;
+/// use comp_gate::helper::device_property::DeviceProperty;
+///
+/// let device_id = DeviceId::from(r"USB\VID_0781&PID_5581\4C530001");
+/// assert_eq!(
+///     identity_property(&device_id, DevicePropertyKey::VendorId),
+///     DeviceProperty::UInt16Property { data: 0x0781 }
+/// );
+/// assert_eq!(
+///     identity_property(&DeviceId::from("1-1"), DevicePropertyKey::VendorId),
+///     DeviceProperty::EmptyProperty
+/// );
+/// ```
+pub fn identity_property(device_id: &DeviceId, key: DevicePropertyKey) -> DeviceProperty {
+    let identity = UsbIdentity::from(device_id);
+    let property = match key {
+        DevicePropertyKey::VendorId => identity
+            .vendor_id
+            .map(|data| DeviceProperty::UInt16Property { data }),
+        DevicePropertyKey::ProductId => identity
+            .product_id
+            .map(|data| DeviceProperty::UInt16Property { data }),
+        DevicePropertyKey::Serial => {
+            identity
+                .serial()
+                .map(|serial| DeviceProperty::StringProperty {
+                    data: serial.to_string(),
+                })
+        }
+        _ => None,
+    };
+
+    property.unwrap_or(DeviceProperty::EmptyProperty)
+}
+
+/// Creates the backend for the platform the application was built for.
+///
+/// # Returns
+///
+/// * `Ok(Box<dyn DeviceBackend>)` - The native backend.
+/// * `Err(DeviceBackendError::UnsupportedPlatform)` - If there is no backend for this platform.
+pub fn default_backend() -> Result<Box<dyn DeviceBackend>, DeviceBackendError> {
+    #[cfg(windows)]
+    {
+        Ok(Box::new(setupapi::SetupApiBackend))
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        Ok(Box::new(sysfs::SysfsBackend::default()))
+    }
+
+    #[cfg(not(any(windows, target_os = "linux")))]
+    {
+        Err(DeviceBackendError::UnsupportedPlatform)
+    }
+}
@@ -0,0 +1,367 @@
+//! # SetupAPI Device Backend
+//!
+//! The Windows implementation of `DeviceBackend`. Devices are enumerated with SetupAPI
+//! (`SetupDiGetClassDevs`) and queried or modified through the Configuration Manager API
+//! using DEVINST handles, so no `HDEVINFO` set has to be kept alive between calls.
+//!
+//! The USB vendor ID, product ID and serial number are not stored as device properties; they
+//! are parsed from the Instance ID (`USB\VID_xxxx&PID_xxxx\<serial>`).
+//!
+//! Every present device is enumerated, whatever its enumerator or class; which of them are
+//! tracked is decided by the `DeviceFilter` of the tracker.
+
+use std::{
+    ops::Deref,
+    ptr::{null, null_mut},
+    rc::Rc,
+};
+
+use windows_sys::Win32::{
+    Devices::{
+        DeviceAndDriverInstallation::*,
+        Properties::{
+            DEVPKEY_Device_BusReportedDeviceDesc, DEVPKEY_Device_Class,
+            DEVPKEY_Device_CompatibleIds, DEVPKEY_Device_ContainerId, DEVPKEY_Device_DevNodeStatus,
+            DEVPKEY_Device_DevType, DEVPKEY_Device_DeviceDesc, DEVPKEY_Device_DriverProvider,
+            DEVPKEY_Device_DriverVersion, DEVPKEY_Device_FriendlyName, DEVPKEY_Device_HardwareIds,
+            DEVPKEY_Device_InstallDate, DEVPKEY_Device_LocationInfo, DEVPKEY_Device_LocationPaths,
+            DEVPKEY_Device_Manufacturer, DEVPKEY_Device_Parent, DEVPKEY_Device_ProblemCode,
+            DEVPKEY_Device_Service, DEVPROPTYPE,
+        },
+    },
+    Foundation::*,
+};
+
+use crate::{
+    error::{ConfigManagerError, DeviceBackendError, Win32Error},
+    helper::{
+        device_backend::{DeviceBackend, DevicePropertyKey, identity_property},
+        device_managment::{DeviceId, DeviceState},
+        device_property::DeviceProperty,
+    },
+};
+
+/// A Configuration Manager device instance handle (DEVINST).
+pub struct DeviceInstance(u32);
+
+impl Deref for DeviceInstance {
+    type Target = u32;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl TryFrom<u32> for DeviceInstance {
+    type Error = ConfigManagerError;
+
+    fn try_from(raw_devinst: u32) -> Result<Self, Self::Error> {
+        let devinst = DeviceInstance(raw_devinst);
+        if !devinst.is_device_instance_valid() {
+            return Err(ConfigManagerError::InvalidDeviceInstance);
+        }
+
+        Ok(devinst)
+    }
+}
+
+impl TryFrom<&str> for DeviceInstance {
+    type Error = ConfigManagerError;
+
+    fn try_from(id: &str) -> Result<Self, Self::Error> {
+        let device_id_wide: Vec<u16> = id.encode_utf16().chain(std::iter::once(0)).collect();
+
+        let mut devinst: u32 = 0;
+        let result = unsafe {
+            CM_Locate_DevNodeW(
+                &mut devinst,
+                device_id_wide.as_ptr(),
+                CM_LOCATE_DEVNODE_NORMAL,
+            )
+        };
+
+        if result != CR_SUCCESS {
+            return Err(ConfigManagerError::from(result));
+        }
+
+        Ok(DeviceInstance(devinst))
+    }
+}
+
+impl DeviceInstance {
+    /// Retrieves the Device Instance ID string.
+    fn retrieve_device_id(&self) -> Result<Rc<str>, Win32Error> {
+        if !self.is_device_instance_valid() {
+            return Err(Win32Error::InvalidParameter);
+        }
+
+        let mut buffer: Vec<u16> = vec![0; 512];
+        let mut buffer_size = buffer.len() as u32;
+
+        // SAFETY: This call is safe because we are passing a valid pointer to a mutable buffer
+        //  and valid device instance.
+        let call_result = unsafe { CM_Get_Device_ID_Size(&mut buffer_size as *mut _, **self, 0) };
+        if call_result != CR_SUCCESS {
+            return Err(ConfigManagerError::from(call_result).into());
+        }
+
+        buffer_size += 1;
+        buffer.resize(buffer_size as usize, 0);
+
+        // First call to get the required size
+
+        let call_result = unsafe { CM_Get_Device_IDW(**self, buffer.as_mut_ptr(), buffer_size, 0) };
+        if call_result != CR_SUCCESS {
+            return Err(ConfigManagerError::from(call_result).into());
+        }
+
+        let len = if buffer_size == 0 {
+            buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len())
+        } else {
+            (buffer_size as usize).saturating_sub(1)
+        };
+        let device_instance_id: Rc<str> = String::from_utf16_lossy(&buffer[..len])
+            .to_uppercase()
+            .into();
+        Ok(device_instance_id)
+    }
+
+    /// Retrieves a raw property from the device.
+    fn retrieve_device_property(
+        &self,
+        property: &DEVPROPKEY,
+    ) -> Result<(Vec<u8>, DEVPROPTYPE), Win32Error> {
+        if !self.is_device_instance_valid() {
+            return Err(Win32Error::InvalidParameter);
+        }
+
+        let mut buffer: Vec<u8> = vec![];
+        let mut required_size: u32 = 0;
+        let mut property_type: DEVPROPTYPE = 0;
+
+        // First call to get the required size
+        // SAFETY: We are passing a valid device instance and property key.
+        let call_result = unsafe {
+            CM_Get_DevNode_PropertyW(
+                **self,
+                property as *const _,
+                &mut property_type as *mut DEVPROPTYPE,
+                null_mut(),
+                &mut required_size as *mut _,
+                0,
+            )
+        };
+
+        // CR_BUFFER_SMALL (26) is expected here - it means the property exists but we need a buffer
+        const CR_BUFFER_SMALL: u32 = 26;
+        if call_result != CR_SUCCESS && call_result != CR_BUFFER_SMALL {
+            return Err(ConfigManagerError::from(call_result).into());
+        }
+
+        buffer.resize(required_size as usize, 0u8);
+
+        // SAFETY: We are passing a valid device instance, property key, and buffer.
+        let call_result = unsafe {
+            CM_Get_DevNode_PropertyW(
+                **self,
+                property as *const _,
+                &mut property_type as *mut DEVPROPTYPE,
+                buffer.as_mut_ptr(),
+                &mut required_size as *mut u32,
+                0,
+            )
+        };
+        if call_result != CR_SUCCESS {
+            return Err(ConfigManagerError::from(call_result).into());
+        }
+
+        Ok((buffer, property_type))
+    }
+
+    fn is_device_instance_valid(&self) -> bool {
+        let mut status = 0u32;
+        let mut problem_number = 0u32;
+
+        let call_result = unsafe {
+            CM_Get_DevNode_Status(
+                &mut status as *mut _,
+                &mut problem_number as *mut _,
+                **self,
+                0,
+            )
+        };
+        call_result == CR_SUCCESS
+    }
+}
+
+/// Returns the Windows property key backing a `DevicePropertyKey`.
+///
+/// The USB identity properties have no property key; they are parsed from the Instance ID.
+fn property_key(key: DevicePropertyKey) -> Option<&'static DEVPROPKEY> {
+    Some(match key {
+        DevicePropertyKey::Service => &DEVPKEY_Device_Service,
+        DevicePropertyKey::Class => &DEVPKEY_Device_Class,
+        DevicePropertyKey::FriendlyName => &DEVPKEY_Device_FriendlyName,
+        DevicePropertyKey::DeviceType => &DEVPKEY_Device_DevType,
+        DevicePropertyKey::Description => &DEVPKEY_Device_DeviceDesc,
+        DevicePropertyKey::HardwareIds => &DEVPKEY_Device_HardwareIds,
+        DevicePropertyKey::CompatibleIds => &DEVPKEY_Device_CompatibleIds,
+        DevicePropertyKey::Manufacturer => &DEVPKEY_Device_Manufacturer,
+        DevicePropertyKey::LocationPaths => &DEVPKEY_Device_LocationPaths,
+        DevicePropertyKey::LocationInfo => &DEVPKEY_Device_LocationInfo,
+        DevicePropertyKey::BusReportedDescription => &DEVPKEY_Device_BusReportedDeviceDesc,
+        DevicePropertyKey::DriverVersion => &DEVPKEY_Device_DriverVersion,
+        DevicePropertyKey::DriverProvider => &DEVPKEY_Device_DriverProvider,
+        DevicePropertyKey::InstallDate => &DEVPKEY_Device_InstallDate,
+        DevicePropertyKey::ContainerId => &DEVPKEY_Device_ContainerId,
+        DevicePropertyKey::ProblemCode => &DEVPKEY_Device_ProblemCode,
+        DevicePropertyKey::Status => &DEVPKEY_Device_DevNodeStatus,
+        DevicePropertyKey::VendorId | DevicePropertyKey::ProductId | DevicePropertyKey::Serial => {
+            return None;
+        }
+    })
+}
+
+/// Device backend built on the Windows SetupAPI and Configuration Manager API.
+pub struct SetupApiBackend;
+
+impl SetupApiBackend {
+    /// Helper to get a handle to the present devices of a specific enumerator, or of all
+    /// enumerators if `class_name` is null.
+    fn get_class_devs(class_name: *const u8) -> Result<HDEVINFO, Win32Error> {
+        let devinfo_set: HDEVINFO = unsafe {
+            SetupDiGetClassDevsA(
+                null(),
+                class_name,
+                null_mut(),
+                DIGCF_ALLCLASSES | DIGCF_PRESENT,
+            )
+        };
+
+        if devinfo_set == INVALID_HANDLE_VALUE as HDEVINFO {
+            return Err(unsafe { GetLastError().into() });
+        }
+
+        Ok(devinfo_set)
+    }
+
+    /// Enumerates the Instance IDs of all devices in a given `HDEVINFO` set.
+    fn get_listed_device_ids(devinfoset: HDEVINFO) -> Result<Vec<DeviceId>, Win32Error> {
+        let mut device_ids = Vec::new();
+        let mut index: u32 = 0;
+
+        loop {
+            unsafe {
+                let mut device_data: SP_DEVINFO_DATA = std::mem::zeroed();
+                device_data.cbSize = std::mem::size_of::<SP_DEVINFO_DATA>() as u32;
+                let operation_result = SetupDiEnumDeviceInfo(
+                    devinfoset,
+                    index,
+                    &mut device_data as *mut SP_DEVINFO_DATA,
+                ) == TRUE;
+
+                if operation_result {
+                    let device_instance = DeviceInstance::try_from(device_data.DevInst)?;
+                    device_ids.push(device_instance.retrieve_device_id()?.into());
+                    index += 1;
+                } else {
+                    let error = GetLastError();
+                    if error == ERROR_NO_MORE_ITEMS {
+                        break;
+                    } else {
+                        return Err(error.into());
+                    }
+                }
+            }
+        }
+
+        Ok(device_ids)
+    }
+}
+
+impl DeviceBackend for SetupApiBackend {
+    fn enumerate_devices(&self) -> Result<Vec<DeviceId>, DeviceBackendError> {
+        let set = Self::get_class_devs(null())?;
+        let listed = Self::get_listed_device_ids(set);
+
+        // free the device information set
+        unsafe {
+            let _ = SetupDiDestroyDeviceInfoList(set);
+        }
+
+        Ok(listed?)
+    }
+
+    fn query_property(
+        &self,
+        device_id: &DeviceId,
+        key: DevicePropertyKey,
+    ) -> Result<DeviceProperty, DeviceBackendError> {
+        let devinst = DeviceInstance::try_from(device_id.as_ref()).map_err(Win32Error::from)?;
+        let Some(devpkey) = property_key(key) else {
+            return Ok(identity_property(device_id, key));
+        };
+
+        match devinst.retrieve_device_property(devpkey) {
+            Ok((raw_data, property_type)) => {
+                Ok(DeviceProperty::from((raw_data.as_slice(), property_type)))
+            }
+            // The property is not set for this device.
+            Err(Win32Error::ConfigManagerError(ConfigManagerError::UnknownError(
+                CR_NO_SUCH_VALUE,
+            ))) => Ok(DeviceProperty::EmptyProperty),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn query_device_state(&self, device_id: &DeviceId) -> Result<DeviceState, DeviceBackendError> {
+        // Disabled devices report the CM_PROB_DISABLED problem.
+        match self
+            .query_property(device_id, DevicePropertyKey::ProblemCode)?
+            .as_u32()
+        {
+            Some(CM_PROB_DISABLED) => Ok(DeviceState::Disable),
+            _ => Ok(DeviceState::Enable),
+        }
+    }
+
+    fn query_parent_id(
+        &self,
+        device_id: &DeviceId,
+    ) -> Result<Option<DeviceId>, DeviceBackendError> {
+        let devinst = DeviceInstance::try_from(device_id.as_ref()).map_err(Win32Error::from)?;
+
+        match devinst.retrieve_device_property(&DEVPKEY_Device_Parent) {
+            Ok((raw_data, property_type)) => {
+                match DeviceProperty::from((raw_data.as_slice(), property_type)) {
+                    DeviceProperty::StringProperty { data } => {
+                        Ok(Some(DeviceId::from(Rc::from(data.to_uppercase()))))
+                    }
+                    _ => Ok(None),
+                }
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn change_device_state(
+        &self,
+        device_id: &DeviceId,
+        state: DeviceState,
+    ) -> Result<(), DeviceBackendError> {
+        let devinst = DeviceInstance::try_from(device_id.as_ref()).map_err(Win32Error::from)?;
+
+        let result = unsafe {
+            match state {
+                DeviceState::Enable => CM_Enable_DevNode(*devinst, 0),
+                DeviceState::Disable => CM_Disable_DevNode(*devinst, 0),
+            }
+        };
+
+        if result != CR_SUCCESS {
+            return Err(Win32Error::from(ConfigManagerError::from(result)).into());
+        }
+
+        Ok(())
+    }
+}
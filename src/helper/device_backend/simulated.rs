@@ -0,0 +1,378 @@
+//! # Simulated Device Backend
+//!
+//! An in-memory implementation of `DeviceBackend` for tests and demos. It holds a scriptable
+//! device tree, records every enable/disable call, can make state changes fail and can inject
+//! `UsbConnectionEvent`s, so the device tracking and enforcement logic can run without real
+//! hardware.
+//!
+//! Clones of a `SimulatedBackend` share their state: one clone can be handed to a
+//! `DeviceTracker` while another is used to script the scenario and inspect the results.
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    io,
+    rc::Rc,
+    sync::mpsc::{Sender, channel},
+};
+
+use crate::{
+    error::DeviceBackendError,
+    helper::{
+        device_backend::{DeviceBackend, DevicePropertyKey, identity_property},
+        device_managment::{DeviceId, DeviceState},
+        device_property::DeviceProperty,
+        usb_connection_callback::{UsbConnectionCallbacksHandle, UsbConnectionEvent},
+    },
+};
+
+/// The device interface class GUID appended to simulated Windows device paths.
+const GUID_DEVINTERFACE_USB_DEVICE: &str = "{a5dcbf10-6530-11d2-901f-00c04fb951ed}";
+
+/// A device present in a `SimulatedBackend`.
+#[derive(Debug, Clone)]
+pub struct SimulatedDevice {
+    /// The Instance ID of the device.
+    pub device_id: DeviceId,
+    /// The Instance ID of the parent device, if any.
+    pub parent_id: Option<DeviceId>,
+    /// The properties reported for the device.
+    ///
+    /// Unset USB identity properties (`VendorId`, `ProductId`, `Serial`) are parsed from the
+    /// Instance ID, like the SetupAPI backend does.
+    pub properties: HashMap<DevicePropertyKey, DeviceProperty>,
+    /// The current state of the device.
+    pub state: DeviceState,
+}
+
+impl SimulatedDevice {
+    /// Creates an enabled device without a parent or properties.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - The Instance ID of the device.
+    pub fn new(device_id: &str) -> Self {
+        Self {
+            device_id: DeviceId::from(Rc::<str>::from(device_id)),
+            parent_id: None,
+            properties: HashMap::new(),
+            state: DeviceState::Enable,
+        }
+    }
+
+    /// Sets the parent of the device.
+    pub fn with_parent(mut self, parent_id: &str) -> Self {
+        self.parent_id = Some(DeviceId::from(Rc::<str>::from(parent_id)));
+        self
+    }
+
+    /// Sets a string property of the device.
+    pub fn with_property(mut self, key: DevicePropertyKey, value: &str) -> Self {
+        self.properties.insert(
+            key,
+            DeviceProperty::StringProperty {
+                data: value.to_string(),
+            },
+        );
+        self
+    }
+
+    /// Sets a property of any type, e.g. the hardware IDs of the device.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_backend::DevicePropertyKey;
+    /// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+    /// use comp_gate::helper::device_managment::{DeviceId, DeviceTracker};
+    /// use comp_gate::helper::device_property::DeviceProperty;
+    ///
+    /// let backend = SimulatedBackend::new();
+    /// backend.add_device(
+    ///     SimulatedDevice::new(r"USB\VID_046D&PID_C52B\5&2752457F&0&2")
+    ///         .with_property_value(
+    ///             DevicePropertyKey::HardwareIds,
+    ///             DeviceProperty::StringListProperty {
+    ///                 data: vec![
+    ///                     r"USB\VID_046D&PID_C52B&REV_1211".to_string(),
+    ///                     r"USB\VID_046D&PID_C52B".to_string(),
+    ///                 ],
+    ///             },
+    ///         )
+    ///         .with_property_value(
+    ///             DevicePropertyKey::ProblemCode,
+    ///             DeviceProperty::UInt32Property { data: 22 },
+    ///         ),
+    /// );
+    /// let tracker = DeviceTracker::load_with_backend(Box::new(backend)).unwrap();
+    ///
+    /// let device = tracker
+    ///     .find_device(&DeviceId::from(r"USB\VID_046D&PID_C52B\5&2752457F&0&2"))
+    ///     .unwrap();
+    /// assert_eq!(device.hardware_ids.len(), 2);
+    /// assert_eq!(device.problem_code, Some(22));
+    /// assert_eq!(tracker.query(&r"hwid:*REV_1211 has:problem".parse().unwrap()).len(), 1);
+    /// ```
+    pub fn with_property_value(mut self, key: DevicePropertyKey, value: DeviceProperty) -> Self {
+        self.properties.insert(key, value);
+        self
+    }
+}
+
+/// The state shared between clones of a `SimulatedBackend`.
+struct SimulatedState {
+    /// The present devices, in insertion order.
+    devices: Vec<SimulatedDevice>,
+    /// Every successful enable/disable call, in call order.
+    state_changes: Vec<(DeviceId, DeviceState)>,
+    /// The sender feeding the handle returned by `callbacks_handle`.
+    event_sender: Option<Sender<UsbConnectionEvent>>,
+    /// Whether devices added from now on start out disabled (see `disable_new_devices`).
+    new_devices_disabled: bool,
+    /// The devices whose state cannot be changed (see `fail_state_changes`).
+    failing_devices: HashSet<DeviceId>,
+}
+
+/// Device backend backed by an in-memory, scriptable device tree.
+#[derive(Clone)]
+pub struct SimulatedBackend {
+    state: Rc<RefCell<SimulatedState>>,
+}
+
+impl Default for SimulatedBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SimulatedBackend {
+    /// Creates a backend without any devices.
+    pub fn new() -> Self {
+        Self {
+            state: Rc::new(RefCell::new(SimulatedState {
+                devices: Vec::new(),
+                state_changes: Vec::new(),
+                event_sender: None,
+                new_devices_disabled: false,
+                failing_devices: HashSet::new(),
+            })),
+        }
+    }
+
+    /// Makes a device present without reporting a connection event.
+    ///
+    /// A device with the same Instance ID is replaced. After `disable_new_devices` was called,
+    /// the device starts out disabled.
+    pub fn add_device(&self, mut device: SimulatedDevice) {
+        let mut state = self.state.borrow_mut();
+        if state.new_devices_disabled {
+            device.state = DeviceState::Disable;
+        }
+        state.devices.retain(|d| d.device_id != device.device_id);
+        state.devices.push(device);
+    }
+
+    /// Removes a device without reporting a disconnection event.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(SimulatedDevice)` - The removed device.
+    /// * `None` - If no such device was present.
+    pub fn remove_device(&self, device_id: &str) -> Option<SimulatedDevice> {
+        let mut state = self.state.borrow_mut();
+        let index = state
+            .devices
+            .iter()
+            .position(|d| d.device_id.as_ref() == device_id)?;
+        Some(state.devices.remove(index))
+    }
+
+    /// Makes a device present and reports a `Connected` event for it.
+    pub fn connect_device(&self, device: SimulatedDevice) {
+        let device_path = device_id_to_device_path(&device.device_id);
+        self.add_device(device);
+        self.inject_event(UsbConnectionEvent::Connected(device_path.into()));
+    }
+
+    /// Removes a device and reports a `Disconnected` event for it.
+    pub fn disconnect_device(&self, device_id: &str) {
+        if let Some(device) = self.remove_device(device_id) {
+            let device_path = device_id_to_device_path(&device.device_id);
+            self.inject_event(UsbConnectionEvent::Disconnected(device_path.into()));
+        }
+    }
+
+    /// Reports an arbitrary event to the handle returned by `callbacks_handle`.
+    ///
+    /// Events injected before a handle was created are dropped.
+    pub fn inject_event(&self, event: UsbConnectionEvent) {
+        if let Some(sender) = &self.state.borrow().event_sender {
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Creates a callbacks handle that receives the events of this backend.
+    ///
+    /// Creating a new handle detaches the previous one.
+    pub fn callbacks_handle(&self) -> UsbConnectionCallbacksHandle {
+        let (event_sender, event_receiver) = channel::<UsbConnectionEvent>();
+        self.state.borrow_mut().event_sender = Some(event_sender);
+
+        UsbConnectionCallbacksHandle::from_event_receiver(event_receiver)
+    }
+
+    /// Makes every enable/disable call for a device fail with a permission error, like for a
+    /// device whose driver refuses to let go of it.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+    /// use comp_gate::helper::device_managment::{DeviceId, DeviceState, DeviceTracker};
+    ///
+    /// let backend = SimulatedBackend::new();
+    /// backend.add_device(SimulatedDevice::new(r"USB\VID_1234&PID_5678\1"));
+    /// backend.fail_state_changes(r"USB\VID_1234&PID_5678\1");
+    /// let tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+    ///
+    /// let id = DeviceId::from(r"USB\VID_1234&PID_5678\1");
+    /// assert!(tracker.set_device_state(&id, DeviceState::Disable).is_err());
+    /// assert!(backend.state_changes().is_empty());
+    /// assert_eq!(backend.device_state(r"USB\VID_1234&PID_5678\1"), Some(DeviceState::Enable));
+    /// ```
+    pub fn fail_state_changes(&self, device_id: &str) {
+        self.state
+            .borrow_mut()
+            .failing_devices
+            .insert(DeviceId::from(Rc::<str>::from(device_id)));
+    }
+
+    /// Returns every successful enable/disable call, in call order.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+    /// use comp_gate::helper::device_managment::{DeviceId, DeviceState, DeviceTracker};
+    /// use std::rc::Rc;
+    ///
+    /// let backend = SimulatedBackend::new();
+    /// backend.add_device(SimulatedDevice::new(r"USB\VID_1234&PID_5678\1"));
+    /// let tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+    ///
+    /// let id = DeviceId::from(Rc::<str>::from(r"USB\VID_1234&PID_5678\1"));
+    /// tracker.set_device_state(&id, DeviceState::Disable).unwrap();
+    ///
+    /// assert_eq!(backend.state_changes(), vec![(id, DeviceState::Disable)]);
+    /// assert_eq!(backend.device_state(r"USB\VID_1234&PID_5678\1"), Some(DeviceState::Disable));
+    /// ```
+    pub fn state_changes(&self) -> Vec<(DeviceId, DeviceState)> {
+        self.state.borrow().state_changes.clone()
+    }
+
+    /// Returns the current state of a device, if it is present.
+    pub fn device_state(&self, device_id: &str) -> Option<DeviceState> {
+        self.state
+            .borrow()
+            .devices
+            .iter()
+            .find(|d| d.device_id.as_ref() == device_id)
+            .map(|d| d.state)
+    }
+
+    /// Runs a closure on a present device, failing if the device does not exist.
+    fn with_device<T>(
+        &self,
+        device_id: &DeviceId,
+        f: impl FnOnce(&mut SimulatedDevice) -> T,
+    ) -> Result<T, DeviceBackendError> {
+        let mut state = self.state.borrow_mut();
+        state
+            .devices
+            .iter_mut()
+            .find(|d| d.device_id == *device_id)
+            .map(f)
+            .ok_or_else(|| DeviceBackendError::DeviceNotFound(device_id.to_string()))
+    }
+}
+
+impl DeviceBackend for SimulatedBackend {
+    fn enumerate_devices(&self) -> Result<Vec<DeviceId>, DeviceBackendError> {
+        Ok(self
+            .state
+            .borrow()
+            .devices
+            .iter()
+            .map(|d| d.device_id.clone())
+            .collect())
+    }
+
+    fn query_property(
+        &self,
+        device_id: &DeviceId,
+        key: DevicePropertyKey,
+    ) -> Result<DeviceProperty, DeviceBackendError> {
+        self.with_device(device_id, |device| {
+            device
+                .properties
+                .get(&key)
+                .cloned()
+                .unwrap_or_else(|| identity_property(device_id, key))
+        })
+    }
+
+    fn query_parent_id(
+        &self,
+        device_id: &DeviceId,
+    ) -> Result<Option<DeviceId>, DeviceBackendError> {
+        self.with_device(device_id, |device| device.parent_id.clone())
+    }
+
+    fn query_device_state(&self, device_id: &DeviceId) -> Result<DeviceState, DeviceBackendError> {
+        self.with_device(device_id, |device| device.state)
+    }
+
+    fn disable_new_devices(&self) -> Result<bool, DeviceBackendError> {
+        self.state.borrow_mut().new_devices_disabled = true;
+        Ok(true)
+    }
+
+    fn change_device_state(
+        &self,
+        device_id: &DeviceId,
+        state: DeviceState,
+    ) -> Result<(), DeviceBackendError> {
+        if self.state.borrow().failing_devices.contains(device_id) {
+            self.with_device(device_id, |_| ())?;
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("simulated failure changing the state of {}", device_id),
+            )
+            .into());
+        }
+
+        self.with_device(device_id, |device| device.state = state)?;
+        self.state
+            .borrow_mut()
+            .state_changes
+            .push((device_id.clone(), state));
+
+        Ok(())
+    }
+}
+
+/// Builds the device path a real listener would report for a device.
+///
+/// Windows style Instance IDs become device interface paths, everything else is treated
+/// as a Linux kernel name and becomes a sysfs path.
+fn device_id_to_device_path(device_id: &DeviceId) -> String {
+    if device_id.contains('\\') {
+        format!(
+            r"\\?\{}#{}",
+            device_id.replace('\\', "#"),
+            GUID_DEVINTERFACE_USB_DEVICE
+        )
+    } else {
+        format!("/sys/bus/usb/devices/{}", device_id)
+    }
+}
@@ -0,0 +1,665 @@
+//! # Sysfs Device Backend
+//!
+//! The Linux implementation of `DeviceBackend`. Devices are enumerated from
+//! `/sys/bus/usb/devices`, where the kernel lists every USB device (`1-1.2`), root hub (`usb1`)
+//! and interface (`1-1.2:1.0`). Device IDs are these kernel names, so the parent of a device can
+//! be derived from its name alone.
+//!
+//! Devices are enabled and disabled through their `authorized` attribute. Writing `0` to the
+//! `authorized_default` attribute of every root hub (`disable_new_devices`) makes the kernel
+//! leave newly connected devices unauthorized until the policy allowed them, so a device never
+//! gets to talk to its driver before the policy decided about it. This setting outlives the
+//! core: while it is not running, new devices stay unauthorized.
+//!
+//! sysfs has no counterpart for some Windows properties (compatible IDs, driver provider,
+//! install date, container ID, problem code and status); these are reported as empty. The
+//! `modalias` of a device, which the kernel matches drivers against, serves as its hardware ID.
+//! Kernel names carry no USB identity, so the vendor ID, product ID and serial number are read
+//! from the `idVendor`, `idProduct` and `serial` attributes. Class codes are resolved to names
+//! with the `usb.ids` database (see the `usb_ids` module).
+//!
+//! The sysfs root is configurable so the backend can be pointed at a fake directory tree.
+
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::{
+    error::DeviceBackendError,
+    helper::{
+        device_backend::{DeviceBackend, DevicePropertyKey},
+        device_managment::{DeviceId, DeviceState},
+        device_property::DeviceProperty,
+        usb_ids::usb_ids,
+    },
+};
+
+/// The default mount point of sysfs.
+pub const DEFAULT_SYSFS_ROOT: &str = "/sys";
+
+/// Device backend built on the Linux sysfs USB tree.
+///
+/// # Example
+///
+/// Policy rules match the VID/PID and serial number read from sysfs, so they follow a device
+/// from port to port:
+///
+/// ```rust
+/// use comp_gate::helper::device_backend::sysfs::SysfsBackend;
+/// use comp_gate::helper::device_managment::{DeviceId, DeviceTracker};
+/// use comp_gate::helper::policy::{Policy, PolicyAction, PolicyRule};
+///
+/// let root = std::env::temp_dir().join("comp-gate-sysfs-policy-doc");
+/// let devices = root.join("bus/usb/devices");
+/// let _ = std::fs::remove_dir_all(&root);
+///
+/// for (name, attributes) in [
+///     ("usb1", &[("bDeviceClass", "09")][..]),
+///     (
+///         "1-1",
+///         &[
+///             ("bDeviceClass", "00"),
+///             ("idVendor", "046d"),
+///             ("idProduct", "c52b"),
+///             ("serial", "4C530001"),
+///         ][..],
+///     ),
+/// ] {
+///     std::fs::create_dir_all(devices.join(name)).unwrap();
+///     for (attribute, value) in attributes {
+///         std::fs::write(devices.join(name).join(attribute), value).unwrap();
+///     }
+/// }
+///
+/// let tracker = DeviceTracker::load_with_backend(Box::new(SysfsBackend::new(&root))).unwrap();
+/// let receiver = &tracker.devices[&DeviceId::from("1-1")];
+/// assert_eq!(receiver.vendor_id, Some(0x046D));
+///
+/// let policy = Policy {
+///     rules: vec![PolicyRule {
+///         vendor_id: Some(0x046D),
+///         product_id: Some(0xC52B),
+///         ..PolicyRule::new(PolicyAction::Allow)
+///     }],
+///     default_action: PolicyAction::Deny,
+/// };
+/// assert_eq!(policy.evaluate(receiver).action, PolicyAction::Allow);
+///
+/// // Rules generated for the device do not depend on the port it is plugged into.
+/// let rule = PolicyRule::for_device(receiver, PolicyAction::Allow);
+/// assert_eq!(rule.serial.as_deref(), Some("4C530001"));
+/// assert_eq!(rule.device_id, None);
+/// # std::fs::remove_dir_all(&root).unwrap();
+/// ```
+pub struct SysfsBackend {
+    /// The root of the sysfs tree (normally `/sys`).
+    sysfs_root: PathBuf,
+}
+
+impl Default for SysfsBackend {
+    fn default() -> Self {
+        Self::new(DEFAULT_SYSFS_ROOT)
+    }
+}
+
+impl SysfsBackend {
+    /// Creates a backend reading from the given sysfs root.
+    ///
+    /// # Arguments
+    ///
+    /// * `sysfs_root` - The directory to use in place of `/sys`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_backend::sysfs::SysfsBackend;
+    /// use comp_gate::helper::device_managment::{DeviceId, DeviceTracker};
+    /// use std::rc::Rc;
+    ///
+    /// let root = std::env::temp_dir().join("comp-gate-sysfs-doc");
+    /// let devices = root.join("bus/usb/devices");
+    /// let _ = std::fs::remove_dir_all(&root);
+    ///
+    /// for (name, attributes) in [
+    ///     ("usb1", &[("bDeviceClass", "09")][..]),
+    ///     ("1-1", &[("bDeviceClass", "00"), ("product", "USB Receiver")][..]),
+    ///     (
+    ///         "1-1:1.0",
+    ///         &[("bInterfaceClass", "03"), ("bInterfaceSubClass", "01"), ("bInterfaceProtocol", "01")][..],
+    ///     ),
+    /// ] {
+    ///     std::fs::create_dir_all(devices.join(name)).unwrap();
+    ///     for (attribute, value) in attributes {
+    ///         std::fs::write(devices.join(name).join(attribute), value).unwrap();
+    ///     }
+    /// }
+    ///
+    /// let tracker = DeviceTracker::load_with_backend(Box::new(SysfsBackend::new(&root))).unwrap();
+    ///
+    /// // The root hub is filtered out, the interface sits below its device.
+    /// let device = &tracker.devices[&DeviceId::from(Rc::<str>::from("1-1"))];
+    /// assert_eq!(device.device_description.as_deref(), Some("USB Receiver"));
+    /// assert_eq!(device.devices.len(), 1);
+    ///
+    /// // Class codes are resolved with the usb.ids database.
+    /// let interface = &device.devices[&DeviceId::from(Rc::<str>::from("1-1:1.0"))];
+    /// assert_eq!(interface.device_class.as_deref(), Some("Human Interface Device"));
+    /// assert_eq!(interface.device_description.as_deref(), Some("Keyboard"));
+    /// # std::fs::remove_dir_all(&root).unwrap();
+    /// ```
+    pub fn new(sysfs_root: impl Into<PathBuf>) -> Self {
+        Self {
+            sysfs_root: sysfs_root.into(),
+        }
+    }
+
+    /// Returns the directory listing all USB devices and interfaces.
+    fn devices_dir(&self) -> PathBuf {
+        self.sysfs_root.join("bus/usb/devices")
+    }
+
+    /// Returns the sysfs directory of a device, failing if the device does not exist.
+    fn device_dir(&self, device_id: &DeviceId) -> Result<PathBuf, DeviceBackendError> {
+        let device_dir = self.devices_dir().join(device_id.as_ref());
+        if !device_dir.exists() {
+            return Err(DeviceBackendError::DeviceNotFound(device_id.to_string()));
+        }
+
+        Ok(device_dir)
+    }
+
+    /// Reads a single-line attribute of a device, returning `None` if it does not exist.
+    fn read_attribute(device_dir: &Path, attribute: &str) -> Option<String> {
+        fs::read_to_string(device_dir.join(attribute))
+            .ok()
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// Reads a hexadecimal USB ID attribute (`idVendor`, `idProduct`).
+    fn read_usb_id(device_dir: &Path, attribute: &str) -> Option<u16> {
+        u16::from_str_radix(&Self::read_attribute(device_dir, attribute)?, 16).ok()
+    }
+
+    /// Reads the `DEVTYPE` entry of the device's `uevent` attribute.
+    fn read_device_type(device_dir: &Path) -> Option<String> {
+        let uevent = fs::read_to_string(device_dir.join("uevent")).ok()?;
+        uevent
+            .lines()
+            .find_map(|line| line.strip_prefix("DEVTYPE="))
+            .map(str::to_string)
+    }
+
+    /// Returns the name of the driver bound to the device.
+    fn read_driver(device_dir: &Path) -> Option<String> {
+        let driver = fs::read_link(device_dir.join("driver")).ok()?;
+        driver
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+    }
+
+    /// Reads the class, subclass and protocol code of an interface, or of a device.
+    ///
+    /// A missing subclass or protocol code is read as `00`.
+    fn read_class_codes(device_dir: &Path) -> Option<(u8, u8, u8)> {
+        let prefix = if device_dir.join("bInterfaceClass").exists() {
+            "bInterface"
+        } else {
+            "bDevice"
+        };
+        let code = |name: &str| {
+            Self::read_attribute(device_dir, &format!("{}{}", prefix, name))
+                .and_then(|code| u8::from_str_radix(&code, 16).ok())
+        };
+
+        Some((
+            code("Class")?,
+            code("SubClass").unwrap_or(0),
+            code("Protocol").unwrap_or(0),
+        ))
+    }
+
+    /// Returns the class name of the device, based on its USB class code.
+    ///
+    /// Interfaces carry their own class code; devices reporting `00` defer to their
+    /// interfaces and are reported as plain `USB` devices. Codes missing from the `usb.ids`
+    /// database are reported in hex.
+    fn read_class(device_dir: &Path) -> Option<String> {
+        let (class, _, _) = Self::read_class_codes(device_dir)?;
+        if class == 0 {
+            return Some("USB".to_string());
+        }
+
+        Some(
+            usb_ids()
+                .class_name(class)
+                .map(str::to_string)
+                .unwrap_or_else(|| format!("{:02X}", class)),
+        )
+    }
+
+    /// Returns a human readable description of the device.
+    ///
+    /// Devices and interfaces without a description of their own are described by the names
+    /// of their protocol or subclass (e.g. `Keyboard`).
+    fn read_description(device_dir: &Path) -> Option<String> {
+        Self::read_attribute(device_dir, "interface")
+            .or_else(|| Self::read_attribute(device_dir, "product"))
+            .or_else(|| {
+                let (class, subclass, protocol) = Self::read_class_codes(device_dir)?;
+                let usb_ids = usb_ids();
+                let name = match (subclass, protocol) {
+                    (_, 1..) => usb_ids.protocol_name(class, subclass, protocol),
+                    (1.., 0) => usb_ids.subclass_name(class, subclass),
+                    (0, 0) => None,
+                };
+                name.map(str::to_string)
+            })
+    }
+
+    /// Returns the version of the kernel module of the driver bound to the device.
+    fn read_driver_version(device_dir: &Path) -> Option<String> {
+        Self::read_attribute(&device_dir.join("driver/module"), "version")
+    }
+
+    /// Returns the path of the device below `/sys/devices` (e.g. `pci0000:00/0000:00:14.0/usb1/1-1`).
+    fn read_location_path(&self, device_dir: &Path) -> Option<String> {
+        let devices_root = fs::canonicalize(self.sysfs_root.join("devices")).ok()?;
+        let device_dir = fs::canonicalize(device_dir).ok()?;
+
+        device_dir
+            .strip_prefix(devices_root)
+            .ok()
+            .map(|path| path.to_string_lossy().into_owned())
+    }
+
+    /// Returns the bus number and port chain of the device (e.g. `Bus 1, port 1.2`).
+    fn read_location_info(device_dir: &Path) -> Option<String> {
+        let bus = Self::read_attribute(device_dir, "busnum")?;
+        let port = Self::read_attribute(device_dir, "devpath")?;
+
+        Some(format!("Bus {}, port {}", bus, port))
+    }
+
+    /// Returns the manufacturer and product name of the device.
+    fn read_friendly_name(device_dir: &Path) -> Option<String> {
+        let manufacturer = Self::read_attribute(device_dir, "manufacturer")?;
+        let product = Self::read_attribute(device_dir, "product")?;
+
+        Some(format!("{} {}", manufacturer, product))
+    }
+}
+
+impl DeviceBackend for SysfsBackend {
+    fn enumerate_devices(&self) -> Result<Vec<DeviceId>, DeviceBackendError> {
+        let mut device_ids: Vec<DeviceId> = fs::read_dir(self.devices_dir())?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| DeviceId::from(Rc::from(entry.file_name().to_string_lossy())))
+            .collect();
+        device_ids.sort_by(|a, b| a.as_ref().cmp(b.as_ref()));
+
+        Ok(device_ids)
+    }
+
+    fn query_property(
+        &self,
+        device_id: &DeviceId,
+        key: DevicePropertyKey,
+    ) -> Result<DeviceProperty, DeviceBackendError> {
+        let device_dir = self.device_dir(device_id)?;
+
+        let string = |value: Option<String>| match value {
+            Some(data) => DeviceProperty::StringProperty { data },
+            None => DeviceProperty::EmptyProperty,
+        };
+        let string_list = |value: Option<String>| match value {
+            Some(data) => DeviceProperty::StringListProperty { data: vec![data] },
+            None => DeviceProperty::EmptyProperty,
+        };
+        let usb_id = |value: Option<u16>| match value {
+            Some(data) => DeviceProperty::UInt16Property { data },
+            None => DeviceProperty::EmptyProperty,
+        };
+
+        Ok(match key {
+            DevicePropertyKey::Service => string(Self::read_driver(&device_dir)),
+            DevicePropertyKey::Class => string(Self::read_class(&device_dir)),
+            DevicePropertyKey::FriendlyName => string(Self::read_friendly_name(&device_dir)),
+            DevicePropertyKey::DeviceType => string(Self::read_device_type(&device_dir)),
+            DevicePropertyKey::Description => string(Self::read_description(&device_dir)),
+            DevicePropertyKey::HardwareIds => {
+                string_list(Self::read_attribute(&device_dir, "modalias"))
+            }
+            DevicePropertyKey::Manufacturer => {
+                string(Self::read_attribute(&device_dir, "manufacturer"))
+            }
+            DevicePropertyKey::LocationPaths => string_list(self.read_location_path(&device_dir)),
+            DevicePropertyKey::LocationInfo => string(Self::read_location_info(&device_dir)),
+            DevicePropertyKey::BusReportedDescription => {
+                string(Self::read_attribute(&device_dir, "product"))
+            }
+            DevicePropertyKey::DriverVersion => string(Self::read_driver_version(&device_dir)),
+            DevicePropertyKey::VendorId => usb_id(Self::read_usb_id(&device_dir, "idVendor")),
+            DevicePropertyKey::ProductId => usb_id(Self::read_usb_id(&device_dir, "idProduct")),
+            DevicePropertyKey::Serial => string(Self::read_attribute(&device_dir, "serial")),
+            DevicePropertyKey::CompatibleIds
+            | DevicePropertyKey::DriverProvider
+            | DevicePropertyKey::InstallDate
+            | DevicePropertyKey::ContainerId
+            | DevicePropertyKey::ProblemCode
+            | DevicePropertyKey::Status => DeviceProperty::EmptyProperty,
+        })
+    }
+
+    fn query_parent_id(
+        &self,
+        device_id: &DeviceId,
+    ) -> Result<Option<DeviceId>, DeviceBackendError> {
+        self.device_dir(device_id)?;
+
+        Ok(parent_kernel_name(device_id).map(|parent| DeviceId::from(Rc::from(parent))))
+    }
+
+    fn query_device_state(&self, device_id: &DeviceId) -> Result<DeviceState, DeviceBackendError> {
+        let device_dir = self.device_dir(device_id)?;
+
+        // Interfaces and root hubs without the attribute cannot be disabled.
+        match Self::read_attribute(&device_dir, "authorized").as_deref() {
+            Some("0") => Ok(DeviceState::Disable),
+            _ => Ok(DeviceState::Enable),
+        }
+    }
+
+    fn disable_new_devices(&self) -> Result<bool, DeviceBackendError> {
+        for entry in fs::read_dir(self.devices_dir())? {
+            let entry = entry?;
+            let authorized_default = entry.path().join("authorized_default");
+            if entry.file_name().to_string_lossy().starts_with("usb") && authorized_default.exists()
+            {
+                fs::write(authorized_default, "0")?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn change_device_state(
+        &self,
+        device_id: &DeviceId,
+        state: DeviceState,
+    ) -> Result<(), DeviceBackendError> {
+        let device_dir = self.device_dir(device_id)?;
+        let value = match state {
+            DeviceState::Enable => "1",
+            DeviceState::Disable => "0",
+        };
+
+        fs::write(device_dir.join("authorized"), value).map_err(|e| match e.kind() {
+            ErrorKind::NotFound => DeviceBackendError::DeviceNotFound(device_id.to_string()),
+            _ => e.into(),
+        })
+    }
+}
+
+/// Derives the kernel name of the parent of a USB device or interface.
+///
+/// - `1-1.2:1.0` (interface) -> `1-1.2`
+/// - `1-1.2` (device behind a hub port) -> `1-1`
+/// - `1-1` (device on a root hub port) -> `usb1`
+/// - `usb1` (root hub) -> no parent
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::device_backend::sysfs::parent_kernel_name;
+///
+/// assert_eq!(parent_kernel_name("1-1.2:1.0"), Some("1-1.2".to_string()));
+/// assert_eq!(parent_kernel_name("1-1.2"), Some("1-1".to_string()));
+/// assert_eq!(parent_kernel_name("1-1"), Some("usb1".to_string()));
+/// assert_eq!(parent_kernel_name("usb1"), None);
+/// ```
+pub fn parent_kernel_name(kernel_name: &str) -> Option<String> {
+    if let Some((device, _interface)) = kernel_name.split_once(':') {
+        return Some(device.to_string());
+    }
+
+    let (bus, ports) = kernel_name.split_once('-')?;
+    match ports.rsplit_once('.') {
+        Some((parent_ports, _)) => Some(format!("{}-{}", bus, parent_ports)),
+        None => Some(format!("usb{}", bus)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper::device_managment::DeviceTracker;
+
+    /// A fake sysfs tree in a temporary directory, removed when dropped.
+    struct FakeSysfs {
+        root: PathBuf,
+    }
+
+    impl FakeSysfs {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!(
+                "comp-gate-sysfs-test-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("bus/usb/devices")).unwrap();
+            Self { root }
+        }
+
+        /// Adds a device directory with the given attributes.
+        fn device(&self, name: &str, attributes: &[(&str, &str)]) -> &Self {
+            let device_dir = self.root.join("bus/usb/devices").join(name);
+            fs::create_dir_all(&device_dir).unwrap();
+            for (attribute, value) in attributes {
+                fs::write(device_dir.join(attribute), value).unwrap();
+            }
+            self
+        }
+
+        fn attribute(&self, name: &str, attribute: &str) -> Option<String> {
+            fs::read_to_string(self.root.join("bus/usb/devices").join(name).join(attribute)).ok()
+        }
+
+        fn backend(&self) -> SysfsBackend {
+            SysfsBackend::new(&self.root)
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    fn id(name: &str) -> DeviceId {
+        DeviceId::from(Rc::<str>::from(name))
+    }
+
+    #[test]
+    fn device_without_attributes_reports_empty_properties() {
+        let sysfs = FakeSysfs::new("bare");
+        sysfs.device("1-1", &[]);
+        let backend = sysfs.backend();
+
+        for key in [
+            DevicePropertyKey::Service,
+            DevicePropertyKey::Class,
+            DevicePropertyKey::FriendlyName,
+            DevicePropertyKey::DeviceType,
+            DevicePropertyKey::Description,
+            DevicePropertyKey::HardwareIds,
+            DevicePropertyKey::LocationPaths,
+            DevicePropertyKey::LocationInfo,
+            DevicePropertyKey::DriverVersion,
+            DevicePropertyKey::VendorId,
+            DevicePropertyKey::ProductId,
+            DevicePropertyKey::Serial,
+        ] {
+            assert_eq!(
+                backend.query_property(&id("1-1"), key).unwrap(),
+                DeviceProperty::EmptyProperty,
+                "{:?}",
+                key
+            );
+        }
+
+        // Without an `authorized` attribute the device counts as enabled.
+        assert_eq!(
+            backend.query_device_state(&id("1-1")).unwrap(),
+            DeviceState::Enable
+        );
+    }
+
+    #[test]
+    fn tracker_loads_devices_with_missing_attributes() {
+        let sysfs = FakeSysfs::new("tracker");
+        sysfs
+            .device("usb1", &[("bDeviceClass", "09")])
+            .device("1-1", &[])
+            .device("1-1:1.0", &[("bInterfaceClass", "03")]);
+
+        let tracker = DeviceTracker::load_with_backend(Box::new(sysfs.backend())).unwrap();
+        let device = &tracker.devices[&id("1-1")];
+        assert_eq!(device.device_class, None);
+        assert_eq!(device.device_description, None);
+        assert_eq!(device.vendor_id, None);
+        assert_eq!(device.vendor_name, None);
+        assert_eq!(device.enabled, Some(true));
+
+        // Neither a description nor subclass and protocol codes: no description.
+        let interface = &device.devices[&id("1-1:1.0")];
+        assert_eq!(
+            interface.device_class.as_deref(),
+            Some("Human Interface Device")
+        );
+        assert_eq!(interface.device_description, None);
+    }
+
+    #[test]
+    fn malformed_attributes_are_ignored() {
+        let sysfs = FakeSysfs::new("malformed");
+        sysfs.device(
+            "1-1",
+            &[
+                ("idVendor", "zzzz"),
+                ("idProduct", "12345"),
+                ("bDeviceClass", "not hex"),
+                ("serial", "  \n"),
+                ("uevent", "MAJOR=189\nMINOR=1\n"),
+            ],
+        );
+        let backend = sysfs.backend();
+
+        for key in [
+            DevicePropertyKey::VendorId,
+            DevicePropertyKey::ProductId,
+            DevicePropertyKey::Class,
+            DevicePropertyKey::Serial,
+            DevicePropertyKey::DeviceType,
+        ] {
+            assert_eq!(
+                backend.query_property(&id("1-1"), key).unwrap(),
+                DeviceProperty::EmptyProperty,
+                "{:?}",
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_class_codes_are_reported_in_hex() {
+        let sysfs = FakeSysfs::new("class");
+        sysfs.device("1-1", &[("bDeviceClass", "fd")]);
+
+        assert_eq!(
+            sysfs
+                .backend()
+                .query_property(&id("1-1"), DevicePropertyKey::Class)
+                .unwrap(),
+            DeviceProperty::StringProperty {
+                data: "FD".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn missing_devices_are_not_found() {
+        let sysfs = FakeSysfs::new("missing");
+        let backend = sysfs.backend();
+
+        assert!(matches!(
+            backend.query_property(&id("1-9"), DevicePropertyKey::Class),
+            Err(DeviceBackendError::DeviceNotFound(_))
+        ));
+        assert!(matches!(
+            backend.query_parent_id(&id("1-9")),
+            Err(DeviceBackendError::DeviceNotFound(_))
+        ));
+        assert!(matches!(
+            backend.query_device_state(&id("1-9")),
+            Err(DeviceBackendError::DeviceNotFound(_))
+        ));
+        assert!(matches!(
+            backend.change_device_state(&id("1-9"), DeviceState::Disable),
+            Err(DeviceBackendError::DeviceNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn missing_device_directory_fails_enumeration() {
+        let sysfs = FakeSysfs::new("empty");
+        fs::remove_dir_all(sysfs.root.join("bus")).unwrap();
+
+        assert!(matches!(
+            sysfs.backend().enumerate_devices(),
+            Err(DeviceBackendError::IoError(_))
+        ));
+        assert!(DeviceTracker::load_with_backend(Box::new(sysfs.backend())).is_err());
+    }
+
+    #[test]
+    fn state_changes_write_authorized() {
+        let sysfs = FakeSysfs::new("authorized");
+        sysfs.device("1-1", &[("authorized", "1")]);
+        let backend = sysfs.backend();
+
+        backend
+            .change_device_state(&id("1-1"), DeviceState::Disable)
+            .unwrap();
+        assert_eq!(sysfs.attribute("1-1", "authorized").as_deref(), Some("0"));
+        assert_eq!(
+            backend.query_device_state(&id("1-1")).unwrap(),
+            DeviceState::Disable
+        );
+    }
+
+    #[test]
+    fn disable_new_devices_skips_hubs_without_authorized_default() {
+        let sysfs = FakeSysfs::new("authorized-default");
+        sysfs
+            .device("usb1", &[("authorized_default", "1")])
+            .device("usb2", &[])
+            .device("1-1", &[("authorized_default", "1")]);
+
+        assert!(sysfs.backend().disable_new_devices().unwrap());
+        assert_eq!(
+            sysfs.attribute("usb1", "authorized_default").as_deref(),
+            Some("0")
+        );
+        assert_eq!(sysfs.attribute("usb2", "authorized_default"), None);
+        // Only root hubs are touched.
+        assert_eq!(
+            sysfs.attribute("1-1", "authorized_default").as_deref(),
+            Some("1")
+        );
+    }
+}
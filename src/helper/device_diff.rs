@@ -0,0 +1,279 @@
+//! # Device Diff Module
+//!
+//! This module compares two device trees, e.g. the live tree of a `DeviceTracker` and a
+//! `DeviceSnapshot` taken during the last audit, and reports what changed:
+//!
+//! - Devices that were added or removed.
+//! - Devices that were re-parented, i.e. appear under another device in the tree.
+//! - Devices whose properties changed (see `Device::properties`).
+//!
+//! Devices are matched by their Instance ID. The parent of a device is the device it is placed
+//! under in the tree; a device whose parent is not tracked is a root-level device.
+
+use std::{borrow::Cow, collections::HashMap};
+
+use serde::Serialize;
+
+use crate::helper::device_managment::{Device, DeviceId};
+
+/// A property whose value differs between two trees.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PropertyChange {
+    /// The name of the property, as returned by `Device::properties`.
+    pub property: &'static str,
+    /// The value in the old tree.
+    pub old: Option<String>,
+    /// The value in the new tree.
+    pub new: Option<String>,
+}
+
+impl std::fmt::Display for PropertyChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.property,
+            self.old.as_deref().unwrap_or("None"),
+            self.new.as_deref().unwrap_or("None")
+        )
+    }
+}
+
+/// A difference between two trees concerning a single device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum DeviceChange {
+    /// The device is only part of the new tree.
+    Added {
+        /// The Instance ID of the device.
+        device_id: DeviceId,
+        /// The device it was placed under, if any.
+        parent_id: Option<DeviceId>,
+    },
+    /// The device is only part of the old tree.
+    Removed {
+        /// The Instance ID of the device.
+        device_id: DeviceId,
+        /// The device it was placed under, if any.
+        parent_id: Option<DeviceId>,
+    },
+    /// The device is placed under another device.
+    Reparented {
+        /// The Instance ID of the device.
+        device_id: DeviceId,
+        /// The parent in the old tree, if any.
+        old_parent_id: Option<DeviceId>,
+        /// The parent in the new tree, if any.
+        new_parent_id: Option<DeviceId>,
+    },
+    /// Properties of the device changed.
+    PropertiesChanged {
+        /// The Instance ID of the device.
+        device_id: DeviceId,
+        /// The properties that changed, in the order of `Device::properties`.
+        changes: Vec<PropertyChange>,
+    },
+}
+
+impl DeviceChange {
+    /// Returns the Instance ID of the device the change concerns.
+    pub fn device_id(&self) -> &DeviceId {
+        match self {
+            DeviceChange::Added { device_id, .. }
+            | DeviceChange::Removed { device_id, .. }
+            | DeviceChange::Reparented { device_id, .. }
+            | DeviceChange::PropertiesChanged { device_id, .. } => device_id,
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let parent = |parent_id: &Option<DeviceId>| match parent_id {
+            Some(parent_id) => parent_id.to_string(),
+            None => "the root".to_string(),
+        };
+
+        match self {
+            DeviceChange::Added {
+                device_id,
+                parent_id,
+            } => write!(f, "+ {} (under {})", device_id, parent(parent_id)),
+            DeviceChange::Removed {
+                device_id,
+                parent_id,
+            } => write!(f, "- {} (under {})", device_id, parent(parent_id)),
+            DeviceChange::Reparented {
+                device_id,
+                old_parent_id,
+                new_parent_id,
+            } => write!(
+                f,
+                "~ {}: moved from under {} to under {}",
+                device_id,
+                parent(old_parent_id),
+                parent(new_parent_id)
+            ),
+            DeviceChange::PropertiesChanged { device_id, changes } => {
+                write!(f, "~ {}:", device_id)?;
+                for change in changes {
+                    write!(f, "\n    {}", change)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The differences between two device trees.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::device_backend::DevicePropertyKey;
+/// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+/// use comp_gate::helper::device_diff::{DeviceChange, DeviceTreeDiff};
+/// use comp_gate::helper::device_managment::{DeviceId, DeviceTracker};
+/// use comp_gate::helper::device_snapshot::DeviceSnapshot;
+///
+/// let backend = SimulatedBackend::new();
+/// backend.add_device(SimulatedDevice::new("1-1"));
+/// backend.add_device(SimulatedDevice::new("1-2"));
+/// backend.add_device(
+///     SimulatedDevice::new("1-1:1.0")
+///         .with_parent("1-1")
+///         .with_property(DevicePropertyKey::Service, "usbhid"),
+/// );
+/// let baseline = DeviceSnapshot::capture(
+///     &DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap(),
+/// );
+///
+/// backend.remove_device("1-2");
+/// backend.add_device(SimulatedDevice::new("1-3"));
+/// backend.add_device(
+///     SimulatedDevice::new("1-1:1.0")
+///         .with_parent("1-3")
+///         .with_property(DevicePropertyKey::Service, "usb-storage"),
+/// );
+/// let live = DeviceTracker::load_with_backend(Box::new(backend)).unwrap();
+///
+/// let diff = DeviceTreeDiff::between(&baseline.devices, &live.devices);
+/// let interface = DeviceId::from("1-1:1.0");
+/// assert_eq!(diff.changes.len(), 4);
+/// assert!(matches!(
+///     &diff.changes[0],
+///     DeviceChange::Reparented { device_id, new_parent_id: Some(parent), .. }
+///         if *device_id == interface && parent.as_ref() == "1-3"
+/// ));
+/// assert!(matches!(&diff.changes[1], DeviceChange::PropertiesChanged { changes, .. }
+///     if changes[0].new.as_deref() == Some("usb-storage")));
+/// assert!(matches!(&diff.changes[2], DeviceChange::Removed { device_id, .. }
+///     if device_id.as_ref() == "1-2"));
+/// assert!(matches!(&diff.changes[3], DeviceChange::Added { device_id, .. }
+///     if device_id.as_ref() == "1-3"));
+///
+/// assert!(DeviceTreeDiff::between(&live.devices, &live.devices).is_empty());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct DeviceTreeDiff {
+    /// The changes, ordered by device ID; a device may be both re-parented and changed.
+    pub changes: Vec<DeviceChange>,
+}
+
+impl DeviceTreeDiff {
+    /// Compares two device trees.
+    ///
+    /// # Arguments
+    ///
+    /// * `old` - The root-level devices of the earlier tree (e.g. a baseline snapshot).
+    /// * `new` - The root-level devices of the later tree (e.g. the live tree).
+    pub fn between(old: &HashMap<DeviceId, Device>, new: &HashMap<DeviceId, Device>) -> Self {
+        let old_devices = flatten(old);
+        let new_devices = flatten(new);
+
+        let mut changes = vec![];
+        for (device_id, (old_parent_id, old_device)) in old_devices.iter() {
+            let Some((new_parent_id, new_device)) = new_devices.get(device_id) else {
+                changes.push(DeviceChange::Removed {
+                    device_id: (*device_id).clone(),
+                    parent_id: old_parent_id.cloned(),
+                });
+                continue;
+            };
+
+            if old_parent_id != new_parent_id {
+                changes.push(DeviceChange::Reparented {
+                    device_id: (*device_id).clone(),
+                    old_parent_id: old_parent_id.cloned(),
+                    new_parent_id: new_parent_id.cloned(),
+                });
+            }
+
+            let property_changes: Vec<PropertyChange> = old_device
+                .properties()
+                .into_iter()
+                .zip(new_device.properties())
+                .filter(|((_, old), (_, new))| old != new)
+                .map(|((property, old), (_, new))| PropertyChange {
+                    property,
+                    old: old.map(Cow::into_owned),
+                    new: new.map(Cow::into_owned),
+                })
+                .collect();
+            if !property_changes.is_empty() {
+                changes.push(DeviceChange::PropertiesChanged {
+                    device_id: (*device_id).clone(),
+                    changes: property_changes,
+                });
+            }
+        }
+
+        for (device_id, (parent_id, _)) in new_devices.iter() {
+            if !old_devices.contains_key(device_id) {
+                changes.push(DeviceChange::Added {
+                    device_id: (*device_id).clone(),
+                    parent_id: parent_id.cloned(),
+                });
+            }
+        }
+
+        // The sort is stable, so the changes of one device keep the order they were found in.
+        changes.sort_by(|a, b| a.device_id().as_ref().cmp(b.device_id().as_ref()));
+
+        Self { changes }
+    }
+
+    /// Returns whether the trees are equal.
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+impl std::fmt::Display for DeviceTreeDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for change in self.changes.iter() {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps every device of a tree to the device it is placed under and the device itself.
+fn flatten(
+    devices: &HashMap<DeviceId, Device>,
+) -> HashMap<&DeviceId, (Option<&DeviceId>, &Device)> {
+    fn collect<'a>(
+        devices: &'a HashMap<DeviceId, Device>,
+        parent_id: Option<&'a DeviceId>,
+        collector: &mut HashMap<&'a DeviceId, (Option<&'a DeviceId>, &'a Device)>,
+    ) {
+        for (device_id, device) in devices.iter() {
+            collector.insert(device_id, (parent_id, device));
+            collect(&device.devices, Some(device_id), collector);
+        }
+    }
+
+    let mut collector = HashMap::new();
+    collect(devices, None, &mut collector);
+    collector
+}
@@ -0,0 +1,257 @@
+//! # Device Filter Module
+//!
+//! This module decides which devices a `DeviceTracker` tracks. A `DeviceFilter` is an ordered
+//! chain of include and exclude rules and a default action; the first matching rule decides,
+//! just like in a `Policy`. Devices that are not tracked are invisible to the policy, the shells
+//! and the audit log.
+//!
+//! Conditions that can be combined in a rule (all are wildcard patterns, see
+//! `policy::wildcard_match`, and ignore case):
+//! - `class`: The device setup class (e.g. `"HIDClass"`, `"Bluetooth"`, `"SmartCardReader"`).
+//! - `service`: The service (driver) bound to the device (e.g. `"usbhub*"`).
+//! - `enumerator`: The enumerator at the start of the Instance ID (e.g. `"USB"`, `"BTHENUM"`).
+//! - `device_id`: The Instance ID of the device.
+//!
+//! The filter is read from the device filter file (`device_filter_path`). Without one, the
+//! built-in chain (`DeviceFilter::default`) tracks the devices of the `USB` and `HID`
+//! enumerators and hides hubs:
+//!
+//! ```toml
+//! default_action = "exclude"
+//!
+//! # Remove these two rules to show hubs.
+//! [[rules]]
+//! action = "exclude"
+//! service = "usbhub*"
+//!
+//! [[rules]]
+//! action = "exclude"
+//! class = "Hub"
+//!
+//! [[rules]]
+//! action = "include"
+//! enumerator = "USB"
+//!
+//! [[rules]]
+//! action = "include"
+//! enumerator = "HID"
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::DeviceFilterError,
+    helper::{device_managment::Device, policy::wildcard_match, usb_identity::UsbIdentity},
+};
+
+/// The environment variable overriding the location of the device filter file.
+pub const DEVICE_FILTER_ENV: &str = "COMP_GATE_DEVICE_FILTER";
+
+/// Returns the location of the device filter file.
+///
+/// The location can be overridden with the `COMP_GATE_DEVICE_FILTER` environment variable.
+/// Otherwise it is:
+/// - On Windows: `%ProgramData%\comp-gate\devices.toml`
+/// - Elsewhere: `/etc/comp-gate/devices.toml`
+pub fn device_filter_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(DEVICE_FILTER_ENV) {
+        return PathBuf::from(path);
+    }
+
+    if cfg!(windows) {
+        let program_data =
+            std::env::var_os("ProgramData").unwrap_or_else(|| r"C:\ProgramData".into());
+        PathBuf::from(program_data)
+            .join("comp-gate")
+            .join("devices.toml")
+    } else {
+        PathBuf::from("/etc/comp-gate/devices.toml")
+    }
+}
+
+/// Whether a device is tracked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// The device is tracked.
+    Include,
+    /// The device is ignored.
+    Exclude,
+}
+
+/// A single rule of a `DeviceFilter`.
+///
+/// Conditions left unset are ignored; a rule without any condition matches every device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceFilterRule {
+    /// Whether matching devices are tracked.
+    pub action: FilterAction,
+
+    /// A wildcard pattern the class of the device must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    /// A wildcard pattern the service (driver) of the device must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// A wildcard pattern the enumerator of the device must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enumerator: Option<String>,
+    /// A wildcard pattern the Instance ID of the device must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+}
+
+impl DeviceFilterRule {
+    /// Creates a rule without conditions.
+    pub fn new(action: FilterAction) -> Self {
+        Self {
+            action,
+            class: None,
+            service: None,
+            enumerator: None,
+            device_id: None,
+        }
+    }
+
+    /// Returns whether all conditions of the rule hold for a device.
+    pub fn matches(&self, device: &Device) -> bool {
+        let property_matches = |pattern: &Option<String>, value: Option<&str>| match pattern {
+            Some(pattern) => value.is_some_and(|value| wildcard_match(pattern, value)),
+            None => true,
+        };
+
+        property_matches(&self.class, device.device_class.as_deref())
+            && property_matches(&self.service, device.device_service.as_deref())
+            && property_matches(
+                &self.enumerator,
+                UsbIdentity::from(&device.device_id).enumerator.as_deref(),
+            )
+            && property_matches(&self.device_id, Some(&device.device_id))
+    }
+}
+
+/// An ordered chain of rules deciding which devices are tracked.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::device_backend::DevicePropertyKey;
+/// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+/// use comp_gate::helper::device_filter::DeviceFilter;
+/// use comp_gate::helper::device_managment::{DeviceId, DeviceTracker};
+///
+/// let backend = SimulatedBackend::new();
+/// backend.add_device(
+///     SimulatedDevice::new(r"USB\ROOT_HUB30\4&1A2B3C4D&0&0")
+///         .with_property(DevicePropertyKey::Service, "usbhub3"),
+/// );
+/// backend.add_device(
+///     SimulatedDevice::new(r"USB\VID_046D&PID_C52B\5&2752457F&0&2")
+///         .with_parent(r"USB\ROOT_HUB30\4&1A2B3C4D&0&0"),
+/// );
+/// backend.add_device(
+///     SimulatedDevice::new(r"BTHENUM\{0000110B-0000-1000-8000-00805F9B34FB}\7&1")
+///         .with_property(DevicePropertyKey::Class, "Bluetooth"),
+/// );
+///
+/// // The built-in filter tracks USB and HID devices and hides hubs.
+/// let tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+/// assert_eq!(tracker.iter().count(), 1);
+///
+/// // A custom filter that also shows hubs and Bluetooth devices.
+/// let filter = DeviceFilter::from_toml(
+///     r#"
+///     default_action = "exclude"
+///
+///     [[rules]]
+///     action = "include"
+///     enumerator = "USB"
+///
+///     [[rules]]
+///     action = "include"
+///     class = "bluetooth"
+///     "#,
+/// )
+/// .unwrap();
+/// let tracker = DeviceTracker::load_with_filter(Box::new(backend), filter).unwrap();
+/// assert_eq!(tracker.iter().count(), 3);
+/// let receiver = tracker
+///     .find_device(&DeviceId::from(r"USB\VID_046D&PID_C52B\5&2752457F&0&2"))
+///     .unwrap();
+/// assert_eq!(receiver.tree_level, 1);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceFilter {
+    /// The rules, in evaluation order.
+    #[serde(default)]
+    pub rules: Vec<DeviceFilterRule>,
+    /// The action taken if no rule matches.
+    pub default_action: FilterAction,
+}
+
+impl Default for DeviceFilter {
+    /// The built-in filter: devices of the `USB` and `HID` enumerators, without hubs.
+    fn default() -> Self {
+        Self {
+            rules: vec![
+                DeviceFilterRule {
+                    service: Some("usbhub*".to_string()),
+                    ..DeviceFilterRule::new(FilterAction::Exclude)
+                },
+                DeviceFilterRule {
+                    class: Some("Hub".to_string()),
+                    ..DeviceFilterRule::new(FilterAction::Exclude)
+                },
+                DeviceFilterRule {
+                    enumerator: Some("USB".to_string()),
+                    ..DeviceFilterRule::new(FilterAction::Include)
+                },
+                DeviceFilterRule {
+                    enumerator: Some("HID".to_string()),
+                    ..DeviceFilterRule::new(FilterAction::Include)
+                },
+            ],
+            default_action: FilterAction::Exclude,
+        }
+    }
+}
+
+impl DeviceFilter {
+    /// Parses a filter from TOML.
+    pub fn from_toml(source: &str) -> Result<Self, DeviceFilterError> {
+        Ok(toml::from_str(source)?)
+    }
+
+    /// Loads the filter from a file.
+    ///
+    /// A missing file yields the built-in filter (`DeviceFilter::default`).
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The location of the device filter file.
+    pub fn load(path: &Path) -> Result<Self, DeviceFilterError> {
+        match std::fs::read_to_string(path) {
+            Ok(source) => Self::from_toml(&source),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns the action of the first rule matching a device, or the default action.
+    pub fn evaluate(&self, device: &Device) -> FilterAction {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(device))
+            .map(|rule| rule.action)
+            .unwrap_or(self.default_action)
+    }
+
+    /// Returns whether a device is tracked.
+    pub fn includes(&self, device: &Device) -> bool {
+        self.evaluate(device) == FilterAction::Include
+    }
+}
@@ -1,204 +1,33 @@
 //! # Device Management Module
 //!
-//! This module provides the core functionality for interacting with the Windows SetupAPI
-//! to manage USB and HID devices. It allows for:
+//! This module provides the platform-neutral device model used by `comp-gate`
+//! to manage USB, HID and other devices. It allows for:
 //!
-//! - Enumerating connected devices.
-//! - Retrieving device properties (ID, Class, Description, etc.).
+//! - Enumerating connected devices through a `DeviceBackend`.
+//! - Retrieving device properties (ID, Class, Description, hardware IDs, driver, etc.).
 //! - Organizing devices into a hierarchical tree structure based on parent-child relationships.
 //! - Enabling and disabling devices.
 //! - Tracking device insertion and removal at runtime.
-
-use crate::error::{
-    ConfigManagerError, DeviceInsertionError, DeviceStringPropertyError, Win32Error,
-};
-
-use std::{
-    collections::HashMap,
-    ops::Deref,
-    ptr::{null, null_mut},
-    rc::Rc,
-};
-use windows_sys::Win32::{
-    Devices::{
-        DeviceAndDriverInstallation::*,
-        Properties::{
-            DEVPKEY_Device_Class, DEVPKEY_Device_DevType, DEVPKEY_Device_DeviceDesc,
-            DEVPKEY_Device_FriendlyName, DEVPKEY_Device_Parent, DEVPKEY_Device_Service,
-            DEVPROP_MASK_TYPE, DEVPROP_TYPE_EMPTY, DEVPROP_TYPE_STRING, DEVPROPTYPE,
-        },
+//!
+//! All operating system specific calls live in the `device_backend` module.
+
+use crate::{
+    error::{DeviceBackendError, DeviceInsertionError, DeviceStringPropertyError},
+    helper::{
+        device_backend::{DeviceBackend, DevicePropertyKey, default_backend},
+        device_filter::DeviceFilter,
+        device_property::DeviceProperty,
+        device_query::DeviceQuery,
+        usb_identity::UsbIdentity,
+        usb_ids::usb_ids,
     },
-    Foundation::*,
 };
 
-pub struct DeviceInstance(u32);
-
-impl Deref for DeviceInstance {
-    type Target = u32;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl TryFrom<u32> for DeviceInstance {
-    type Error = ConfigManagerError;
-
-    fn try_from(raw_devinst: u32) -> Result<Self, Self::Error> {
-        let devinst = DeviceInstance(raw_devinst);
-        if !devinst.is_device_instance_valid() {
-            return Err(ConfigManagerError::InvalidDeviceInstance);
-        }
-
-        Ok(devinst)
-    }
-}
-
-impl TryFrom<&str> for DeviceInstance {
-    type Error = ConfigManagerError;
-
-    fn try_from(id: &str) -> Result<Self, Self::Error> {
-        let device_id_wide: Vec<u16> = id.encode_utf16().chain(std::iter::once(0)).collect();
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, collections::HashMap, ops::Deref, rc::Rc, time::UNIX_EPOCH};
 
-        let mut devinst: u32 = 0;
-        let result = unsafe {
-            CM_Locate_DevNodeW(
-                &mut devinst,
-                device_id_wide.as_ptr(),
-                CM_LOCATE_DEVNODE_NORMAL,
-            )
-        };
-
-        if result != CR_SUCCESS {
-            return Err(ConfigManagerError::from(result));
-        }
-
-        Ok(DeviceInstance(devinst))
-    }
-}
-
-impl DeviceInstance {
-    /// Retrieves the Device Instance ID string.
-    fn retrieve_device_id(&self) -> Result<Rc<str>, Win32Error> {
-        if !self.is_device_instance_valid() {
-            return Err(Win32Error::InvalidParameter);
-        }
-
-        let mut buffer: Vec<u16> = vec![0; 512];
-        let mut buffer_size = buffer.len() as u32;
-
-        // SAFETY: This call is safe because we are passing a valid pointer to a mutable buffer
-        //  and valid device instance.
-        let call_result = unsafe { CM_Get_Device_ID_Size(&mut buffer_size as *mut _, **self, 0) };
-        if call_result != CR_SUCCESS {
-            return Err(ConfigManagerError::from(call_result).into());
-        }
-
-        buffer_size += 1;
-        buffer.resize(buffer_size as usize, 0);
-
-        // First call to get the required size
-
-        let call_result = unsafe { CM_Get_Device_IDW(**self, buffer.as_mut_ptr(), buffer_size, 0) };
-        if call_result != CR_SUCCESS {
-            return Err(ConfigManagerError::from(call_result).into());
-        }
-
-        let len = if buffer_size == 0 {
-            buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len())
-        } else {
-            (buffer_size as usize).saturating_sub(1)
-        };
-        let device_instance_id: Rc<str> = String::from_utf16_lossy(&buffer[..len])
-            .to_uppercase()
-            .into();
-        Ok(device_instance_id)
-    }
-
-    /// Retrieves a raw property from the device.
-    fn retrieve_device_property(
-        &self,
-        property: &DEVPROPKEY,
-    ) -> Result<(Vec<u8>, DEVPROPTYPE), Win32Error> {
-        if !self.is_device_instance_valid() {
-            return Err(Win32Error::InvalidParameter);
-        }
-
-        let mut buffer: Vec<u8> = vec![];
-        let mut required_size: u32 = 0;
-        let mut property_type: DEVPROPTYPE = 0;
-
-        // First call to get the required size
-        // SAFETY: We are passing a valid device instance and property key.
-        let call_result = unsafe {
-            CM_Get_DevNode_PropertyW(
-                **self,
-                property as *const _,
-                &mut property_type as *mut DEVPROPTYPE,
-                null_mut(),
-                &mut required_size as *mut _,
-                0,
-            )
-        };
-
-        // CR_BUFFER_SMALL (26) is expected here - it means the property exists but we need a buffer
-        const CR_BUFFER_SMALL: u32 = 26;
-        if call_result != CR_SUCCESS && call_result != CR_BUFFER_SMALL {
-            return Err(ConfigManagerError::from(call_result).into());
-        }
-
-        buffer.resize(required_size as usize, 0u8);
-
-        // SAFETY: We are passing a valid device instance, property key, and buffer.
-        let call_result = unsafe {
-            CM_Get_DevNode_PropertyW(
-                **self,
-                property as *const _,
-                &mut property_type as *mut DEVPROPTYPE,
-                buffer.as_mut_ptr(),
-                &mut required_size as *mut u32,
-                0,
-            )
-        };
-        if call_result != CR_SUCCESS {
-            return Err(ConfigManagerError::from(call_result).into());
-        }
-
-        Ok((buffer.into(), property_type))
-    }
-
-    /// Retrieves a specific string property from the device.
-    fn retrieve_string_property(
-        &self,
-        property: &DEVPROPKEY,
-    ) -> Result<Rc<str>, DeviceStringPropertyError> {
-        let device_property = self.retrieve_device_property(property)?;
-        let device_property =
-            DeviceProperty::from((device_property.0.as_slice(), device_property.1));
-        let device_property = match device_property {
-            DeviceProperty::StringProperty { data } => Rc::from(data),
-            _ => return Err(DeviceStringPropertyError::PropertyNotString),
-        };
-        Ok(device_property)
-    }
-
-    fn is_device_instance_valid(&self) -> bool {
-        let mut status = 0u32;
-        let mut problem_number = 0u32;
-
-        let call_result = unsafe {
-            CM_Get_DevNode_Status(
-                &mut status as *mut _,
-                &mut problem_number as *mut _,
-                **self,
-                0,
-            )
-        };
-        call_result == CR_SUCCESS
-    }
-}
-
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct DeviceId(Rc<str>);
 
 impl std::fmt::Display for DeviceId {
@@ -221,74 +50,30 @@ impl From<Rc<str>> for DeviceId {
     }
 }
 
+impl From<&str> for DeviceId {
+    fn from(id: &str) -> Self {
+        DeviceId(Rc::from(id))
+    }
+}
+
 /// Represents the desired state of a device driver.
-#[repr(u32)]
+///
+/// Each `DeviceBackend` maps the state onto its platform (e.g. `CM_Enable_DevNode` or the sysfs
+/// `authorized` attribute).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum DeviceState {
     /// Enable the device driver.
-    Enable = DICS_ENABLE,
+    Enable,
     /// Disable the device driver.
-    Disable = DICS_DISABLE,
-}
-
-/// Represents a property retrieved from a device.
-///
-/// This enum handles different types of properties that can be queried from the SetupAPI.
-/// Currently, it focuses on string properties but handles unsupported types gracefully.
-pub enum DeviceProperty {
-    EmptyProperty,
-    /// Represents a string property (REG_SZ).
-    StringProperty {
-        /// The string value of the property.
-        data: String,
-    },
-    /// Represents a property type that is not explicitly handled by this wrapper.
-    UnsupportedProperty {
-        /// The raw byte data of the property.
-        raw_data: Rc<[u8]>,
-        /// The Windows property type identifier.
-        property_type: DEVPROPTYPE,
-    },
-}
-
-impl From<(&[u8], DEVPROPTYPE)> for DeviceProperty {
-    /// Converts a raw byte slice and property type into a `DeviceProperty`.
-    ///
-    /// This function handles the parsing of raw bytes into Rust types based on the `DEVPROPTYPE`.
-    fn from(value: (&[u8], DEVPROPTYPE)) -> Self {
-        match value.1 & DEVPROP_MASK_TYPE {
-            DEVPROP_TYPE_STRING => {
-                let u16_slice: &[u16] = unsafe {
-                    std::slice::from_raw_parts(
-                        value.0.as_ptr() as *const u16,
-                        value.0.len() / std::mem::size_of::<u16>(),
-                    )
-                };
-
-                let len = u16_slice
-                    .iter()
-                    .position(|&c| c == 0)
-                    .unwrap_or(u16_slice.len());
-
-                DeviceProperty::StringProperty {
-                    data: String::from_utf16_lossy(&u16_slice[..len]),
-                }
-            }
-            DEVPROP_TYPE_EMPTY => DeviceProperty::EmptyProperty,
-            _ => DeviceProperty::UnsupportedProperty {
-                raw_data: value.0.into(), // CLONING THE SLICE DATA
-                property_type: value.1,
-            },
-        }
-    }
+    Disable,
 }
 
 /// Represents a physical or logical device on the system.
 ///
 /// This struct holds metadata about the device and maintains a list of its child devices,
 /// forming a tree structure.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Device {
-    /// Internal Windows handle data for the device.
-    devinst: DeviceInstance,
     /// The unique Instance ID of the device (e.g., `USB\VID_XXXX&PID_XXXX\SN`).
     pub device_id: DeviceId,
     /// The Instance ID of the parent device, if any.
@@ -308,6 +93,64 @@ pub struct Device {
     pub device_type: Option<Rc<str>>,
     /// The description of the device.
     pub device_description: Option<Rc<str>>,
+
+    /// The USB vendor ID of the device.
+    #[serde(default)]
+    pub vendor_id: Option<u16>,
+    /// The USB product ID of the device.
+    #[serde(default)]
+    pub product_id: Option<u16>,
+    /// The serial number reported by the device.
+    #[serde(default)]
+    pub serial: Option<Rc<str>>,
+
+    /// The vendor name looked up in the `usb.ids` database by the device's VID.
+    #[serde(default)]
+    pub vendor_name: Option<Rc<str>>,
+    /// The product name looked up in the `usb.ids` database by the device's VID/PID.
+    #[serde(default)]
+    pub product_name: Option<Rc<str>>,
+
+    /// The manufacturer of the device.
+    #[serde(default)]
+    pub manufacturer: Option<Rc<str>>,
+    /// The description the device reports over its bus (e.g. the USB product string).
+    #[serde(default)]
+    pub bus_reported_description: Option<Rc<str>>,
+    /// The hardware IDs of the device, most specific first.
+    #[serde(default)]
+    pub hardware_ids: Vec<Rc<str>>,
+    /// The compatible IDs of the device, most specific first.
+    #[serde(default)]
+    pub compatible_ids: Vec<Rc<str>>,
+    /// The location paths of the device, identifying the port it is plugged into.
+    #[serde(default)]
+    pub location_paths: Vec<Rc<str>>,
+    /// The bus-specific physical location of the device (e.g. `Port_#0002.Hub_#0001`).
+    #[serde(default)]
+    pub location_info: Option<Rc<str>>,
+    /// The provider of the driver bound to the device.
+    #[serde(default)]
+    pub driver_provider: Option<Rc<str>>,
+    /// The version of the driver bound to the device.
+    #[serde(default)]
+    pub driver_version: Option<Rc<str>>,
+    /// When the device was first installed, in seconds since the UNIX epoch.
+    #[serde(default)]
+    pub install_date: Option<u64>,
+    /// The GUID shared by all devices of the same physical device.
+    #[serde(default)]
+    pub container_id: Option<Rc<str>>,
+    /// The code of the problem the device has (e.g. `22` for a disabled device), if any.
+    #[serde(default)]
+    pub problem_code: Option<u32>,
+    /// The status flags (`DN_*`) of the device node.
+    #[serde(default)]
+    pub status: Option<u32>,
+    /// Whether the device is enabled, as last read from the backend (see
+    /// `DeviceTracker::refresh_device_states`), or `None` if its state could not be read.
+    #[serde(default)]
+    pub enabled: Option<bool>,
 }
 
 impl std::fmt::Display for Device {
@@ -318,6 +161,24 @@ impl std::fmt::Display for Device {
             "\t".repeat(self.tree_level as usize),
             self.device_id
         )?;
+        writeln!(
+            f,
+            "{} - USB Identity: {}",
+            "\t".repeat(self.tree_level as usize),
+            UsbIdentity::from(&self.device_id)
+        )?;
+        writeln!(
+            f,
+            "{} - Vendor: {}",
+            "\t".repeat(self.tree_level as usize),
+            self.vendor_name.as_deref().unwrap_or("Unknown")
+        )?;
+        writeln!(
+            f,
+            "{} - Product: {}",
+            "\t".repeat(self.tree_level as usize),
+            self.product_name.as_deref().unwrap_or("Unknown")
+        )?;
         writeln!(
             f,
             "{} - Device Service: {}",
@@ -348,6 +209,62 @@ impl std::fmt::Display for Device {
             "\t".repeat(self.tree_level as usize),
             self.device_description.as_deref().unwrap_or("None")
         )?;
+        for (label, value) in [
+            (
+                "Vendor ID",
+                self.vendor_id.map(|id| format!("{:04X}", id).into()),
+            ),
+            (
+                "Product ID",
+                self.product_id.map(|id| format!("{:04X}", id).into()),
+            ),
+            ("Serial", self.serial.as_deref().map(Cow::from)),
+            ("Manufacturer", self.manufacturer.as_deref().map(Cow::from)),
+            (
+                "Bus Reported Description",
+                self.bus_reported_description.as_deref().map(Cow::from),
+            ),
+            ("Hardware IDs", join(&self.hardware_ids)),
+            ("Compatible IDs", join(&self.compatible_ids)),
+            ("Location Paths", join(&self.location_paths)),
+            (
+                "Location Info",
+                self.location_info.as_deref().map(Cow::from),
+            ),
+            (
+                "Driver Provider",
+                self.driver_provider.as_deref().map(Cow::from),
+            ),
+            (
+                "Driver Version",
+                self.driver_version.as_deref().map(Cow::from),
+            ),
+            (
+                "Install Date",
+                self.install_date.map(|date| date.to_string().into()),
+            ),
+            ("Container ID", self.container_id.as_deref().map(Cow::from)),
+            (
+                "Problem Code",
+                self.problem_code.map(|code| code.to_string().into()),
+            ),
+            (
+                "Status",
+                self.status.map(|status| format!("0x{:08X}", status).into()),
+            ),
+            (
+                "Enabled",
+                self.enabled.map(|enabled| enabled.to_string().into()),
+            ),
+        ] {
+            writeln!(
+                f,
+                "{} - {}: {}",
+                "\t".repeat(self.tree_level as usize),
+                label,
+                value.as_deref().unwrap_or("None")
+            )?;
+        }
         for (_, sub_device) in self.devices.iter() {
             writeln!(
                 f,
@@ -360,77 +277,186 @@ impl std::fmt::Display for Device {
     }
 }
 
-impl TryFrom<DeviceInstance> for Device {
-    type Error = Win32Error;
-
-    fn try_from(devinst: DeviceInstance) -> Result<Self, Self::Error> {
-        let device_id = devinst.retrieve_device_id()?.into();
+impl Device {
+    /// Returns the descriptive properties of the device, named like their serialized fields.
+    ///
+    /// The ID and the position in the tree are not included. Lists are joined with `, `, the
+    /// USB IDs and the status are written in hexadecimal.
+    pub fn properties(&self) -> [(&'static str, Option<Cow<'_, str>>); 22] {
+        fn string(value: &Option<Rc<str>>) -> Option<Cow<'_, str>> {
+            value.as_deref().map(Cow::from)
+        }
 
-        let parent_id = match devinst.retrieve_string_property(&DEVPKEY_Device_Parent) {
-            Ok(prop) => Some(DeviceId::from(Rc::from(prop.to_uppercase()))),
-            Err(_) => None,
-        };
+        [
+            ("device_service", string(&self.device_service)),
+            ("device_class", string(&self.device_class)),
+            ("device_friendly_name", string(&self.device_friendly_name)),
+            ("device_type", string(&self.device_type)),
+            ("device_description", string(&self.device_description)),
+            (
+                "vendor_id",
+                self.vendor_id.map(|id| format!("{:04X}", id).into()),
+            ),
+            (
+                "product_id",
+                self.product_id.map(|id| format!("{:04X}", id).into()),
+            ),
+            ("serial", string(&self.serial)),
+            ("vendor_name", string(&self.vendor_name)),
+            ("product_name", string(&self.product_name)),
+            ("manufacturer", string(&self.manufacturer)),
+            (
+                "bus_reported_description",
+                string(&self.bus_reported_description),
+            ),
+            ("hardware_ids", join(&self.hardware_ids)),
+            ("compatible_ids", join(&self.compatible_ids)),
+            ("location_paths", join(&self.location_paths)),
+            ("location_info", string(&self.location_info)),
+            ("driver_provider", string(&self.driver_provider)),
+            ("driver_version", string(&self.driver_version)),
+            (
+                "install_date",
+                self.install_date.map(|date| date.to_string().into()),
+            ),
+            ("container_id", string(&self.container_id)),
+            (
+                "problem_code",
+                self.problem_code.map(|code| code.to_string().into()),
+            ),
+            (
+                "status",
+                self.status.map(|status| format!("0x{:08X}", status).into()),
+            ),
+        ]
+    }
 
-        let device_service = match devinst.retrieve_string_property(&DEVPKEY_Device_Service) {
-            Ok(prop) => Some(prop.to_lowercase().into()),
-            Err(e) => {
-                println!(
-                    "Warning: Could not retrieve Device Service for Device ID {} because of an error: {:?}",
-                    device_id, e
-                );
-                None
+    /// Builds a `Device` by querying its properties through a `DeviceBackend`.
+    ///
+    /// Properties that are not set are stored as `None`. Properties that cannot be read, or that
+    /// should be strings but are not, are reported as warnings and stored as `None` as well.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend used to query the device.
+    /// * `device_id` - The Instance ID of the device.
+    pub fn from_backend(
+        backend: &dyn DeviceBackend,
+        device_id: DeviceId,
+    ) -> Result<Self, DeviceBackendError> {
+        let parent_id = backend.query_parent_id(&device_id)?;
+
+        // Properties that are not set are expected, so only failures are reported.
+        let query_typed = |key: DevicePropertyKey, name: &str| -> Option<DeviceProperty> {
+            match backend.query_property(&device_id, key) {
+                Ok(DeviceProperty::EmptyProperty | DeviceProperty::NullProperty) => None,
+                Ok(prop) => Some(prop),
+                Err(e) => {
+                    println!(
+                        "Warning: Could not retrieve {} for Device ID {} because of an error: {:?}",
+                        name, device_id, e
+                    );
+                    None
+                }
             }
         };
-
-        let device_class = match devinst.retrieve_string_property(&DEVPKEY_Device_Class) {
-            Ok(prop) => Some(prop),
-            Err(e) => {
-                println!(
-                    "Warning: Could not retrieve Device Class for Device ID {} because of an error: {:?}",
-                    device_id, e
-                );
-                None
+        let query = |key: DevicePropertyKey, name: &str| -> Option<Rc<str>> {
+            match query_typed(key, name)? {
+                DeviceProperty::StringProperty { data } => Some(Rc::from(data)),
+                _ => {
+                    println!(
+                        "Warning: Could not retrieve {} for Device ID {} because of an error: {:?}",
+                        name,
+                        device_id,
+                        DeviceStringPropertyError::PropertyNotString
+                    );
+                    None
+                }
             }
         };
 
-        let device_type = match devinst.retrieve_string_property(&DEVPKEY_Device_DevType) {
-            Ok(prop) => Some(prop),
-            Err(e) => {
-                println!(
-                    "Warning: Could not retrieve Device Type for Device ID {} because of an error: {:?}",
-                    device_id, e
-                );
-                None
-            }
+        let device_service = query(DevicePropertyKey::Service, "Device Service")
+            .map(|prop| prop.to_lowercase().into());
+        let device_class = query(DevicePropertyKey::Class, "Device Class");
+        let device_type = query(DevicePropertyKey::DeviceType, "Device Type");
+        let device_description = query(DevicePropertyKey::Description, "Device Description");
+        let device_friendly_name = query(DevicePropertyKey::FriendlyName, "Device Friendly Name");
+
+        let query_extended = |key: DevicePropertyKey, name: &str| -> Option<Rc<str>> {
+            query_typed(key, name).and_then(|prop| prop.as_str().map(Rc::from))
+        };
+        let query_list = |key: DevicePropertyKey, name: &str| -> Vec<Rc<str>> {
+            query_typed(key, name)
+                .and_then(DeviceProperty::into_strings)
+                .unwrap_or_default()
+                .into_iter()
+                .map(Rc::from)
+                .collect()
         };
 
-        let device_description = match devinst.retrieve_string_property(&DEVPKEY_Device_DeviceDesc)
-        {
-            Ok(prop) => Some(prop),
-            Err(e) => {
-                println!(
-                    "Warning: Could not retrieve Device Description for Device ID {} because of an error: {:?}",
-                    device_id, e
-                );
-                None
-            }
+        let manufacturer = query_extended(DevicePropertyKey::Manufacturer, "Manufacturer");
+        let bus_reported_description = query_extended(
+            DevicePropertyKey::BusReportedDescription,
+            "Bus Reported Description",
+        );
+        let hardware_ids = query_list(DevicePropertyKey::HardwareIds, "Hardware IDs");
+        let compatible_ids = query_list(DevicePropertyKey::CompatibleIds, "Compatible IDs");
+        let location_paths = query_list(DevicePropertyKey::LocationPaths, "Location Paths");
+        let location_info = query_extended(DevicePropertyKey::LocationInfo, "Location Info");
+        let driver_provider = query_extended(DevicePropertyKey::DriverProvider, "Driver Provider");
+        let driver_version = query_extended(DevicePropertyKey::DriverVersion, "Driver Version");
+        let install_date = match query_typed(DevicePropertyKey::InstallDate, "Install Date") {
+            Some(DeviceProperty::TimeProperty { data }) => data
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|duration| duration.as_secs()),
+            _ => None,
+        };
+        // Windows reports a GUID, other backends may report its string form.
+        let container_id = match query_typed(DevicePropertyKey::ContainerId, "Container ID") {
+            Some(
+                prop
+                @ (DeviceProperty::GuidProperty { .. } | DeviceProperty::StringProperty { .. }),
+            ) => Some(Rc::from(prop.to_string())),
+            _ => None,
         };
+        // A problem code of 0 (CM_PROB_NONE) means the device has no problem.
+        let problem_code = query_typed(DevicePropertyKey::ProblemCode, "Problem Code")
+            .and_then(|prop| prop.as_u32())
+            .filter(|code| *code != 0);
+        let status =
+            query_typed(DevicePropertyKey::Status, "Status").and_then(|prop| prop.as_u32());
+
+        let query_usb_id = |key: DevicePropertyKey, name: &str| -> Option<u16> {
+            query_typed(key, name)
+                .and_then(|prop| prop.as_u32())
+                .and_then(|id| u16::try_from(id).ok())
+        };
+        let vendor_id = query_usb_id(DevicePropertyKey::VendorId, "Vendor ID");
+        let product_id = query_usb_id(DevicePropertyKey::ProductId, "Product ID");
+        let serial = query_extended(DevicePropertyKey::Serial, "Serial");
 
-        let device_friendly_name = match devinst
-            .retrieve_string_property(&DEVPKEY_Device_FriendlyName)
-        {
-            Ok(prop) => Some(prop),
+        // Windows reports a disabled device with problem code 22, sysfs as not `authorized`.
+        let enabled = match backend.query_device_state(&device_id) {
+            Ok(state) => Some(state == DeviceState::Enable),
             Err(e) => {
                 println!(
-                    "Warning: Could not retrieve Device Friendly Name for Device ID {} because of an error: {:?}",
+                    "Warning: Could not retrieve State for Device ID {} because of an error: {:?}",
                     device_id, e
                 );
                 None
             }
         };
 
+        let vendor_name = vendor_id
+            .and_then(|vendor_id| usb_ids().vendor_name(vendor_id))
+            .map(Rc::from);
+        let product_name = vendor_id
+            .zip(product_id)
+            .and_then(|(vendor_id, product_id)| usb_ids().product_name(vendor_id, product_id))
+            .map(Rc::from);
+
         Ok(Device {
-            devinst,
             device_id,
             parent_id,
             tree_level: 0,
@@ -440,42 +466,46 @@ impl TryFrom<DeviceInstance> for Device {
             device_friendly_name,
             device_type,
             device_description,
+            vendor_id,
+            product_id,
+            serial,
+            vendor_name,
+            product_name,
+            manufacturer,
+            bus_reported_description,
+            hardware_ids,
+            compatible_ids,
+            location_paths,
+            location_info,
+            driver_provider,
+            driver_version,
+            install_date,
+            container_id,
+            problem_code,
+            status,
+            enabled,
         })
     }
 }
 
-impl Device {
-    /// Changes the state of the device (Enable/Disable).
-    ///
-    /// This function uses `SetupDiSetClassInstallParams` and `SetupDiCallClassInstaller` to modify the device state.
-    ///
-    /// # Arguments
-    ///
-    /// * `new_state` - The target state (`Enable` or `Disable`).
-    /// * `information_set` - The handle to the device information set.
-    fn change_state(&self, new_state: DeviceState) -> Result<(), Win32Error> {
-        let result = unsafe {
-            match new_state {
-                DeviceState::Enable => CM_Enable_DevNode(*self.devinst, 0),
-                DeviceState::Disable => CM_Disable_DevNode(*self.devinst, 0),
-            }
-        };
-
-        if result != CR_SUCCESS {
-            return Err(ConfigManagerError::from(result).into());
-        }
-
-        Ok(())
-    }
+/// Joins a list property with `, `, or returns `None` if it is empty.
+fn join(values: &[Rc<str>]) -> Option<Cow<'_, str>> {
+    (!values.is_empty()).then(|| values.join(", ").into())
 }
 
-/// Manages a collection of devices using the Windows Configuration Manager API.
+/// Manages a collection of devices on top of a `DeviceBackend`.
 ///
-/// This struct is the main entry point for querying and manipulating devices. It uses
-/// DEVINST handles from the CM API to interact with devices without requiring HDEVINFO sets.
+/// This struct is the main entry point for querying and manipulating devices. All
+/// system calls are delegated to the backend, so the tree logic itself is platform-neutral.
 pub struct DeviceTracker {
     /// A map of root-level devices managed by this tracker.
     pub devices: HashMap<DeviceId, Device>,
+    /// The backend used to query and modify devices on the system.
+    backend: Box<dyn DeviceBackend>,
+    /// The filter deciding which devices are tracked.
+    filter: DeviceFilter,
+    /// Whether the backend holds newly connected devices back (see `disable_new_devices`).
+    new_devices_disabled: bool,
 }
 
 impl std::fmt::Display for DeviceTracker {
@@ -530,6 +560,105 @@ impl DeviceTracker {
         Self::find_in_tree_mut(&mut self.devices, target_id)
     }
 
+    /// Returns every tracked device matching a query, ordered by device ID.
+    pub fn query<'a>(&'a self, query: &DeviceQuery) -> Vec<&'a Device> {
+        query.select(&self.devices)
+    }
+
+    /// Find the root-level device whose tree contains the device with the given ID.
+    ///
+    /// Returns the device itself if it is a root-level device.
+    pub fn find_root_device<'a>(&'a self, target_id: &DeviceId) -> Option<&'a Device> {
+        self.devices.values().find(|device| {
+            device.device_id == *target_id
+                || Self::find_in_tree(&device.devices, target_id).is_some()
+        })
+    }
+
+    /// Makes devices connected from now on start out disabled, if the backend supports it (see
+    /// `DeviceBackend::disable_new_devices`).
+    ///
+    /// New devices then stay disabled until the policy allows them. Devices the filter
+    /// excludes are enabled when they are inserted, since the policy never decides about them.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_backend::DevicePropertyKey;
+    /// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+    /// use comp_gate::helper::device_managment::{DeviceState, DeviceTracker};
+    ///
+    /// let backend = SimulatedBackend::new();
+    /// let mut tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+    /// tracker.disable_new_devices().unwrap();
+    ///
+    /// // A keyboard arrives disabled and waits for the policy.
+    /// backend.add_device(SimulatedDevice::new(r"USB\VID_046D&PID_C31C\1"));
+    /// tracker.insert_device_by_id(r"USB\VID_046D&PID_C31C\1").unwrap();
+    /// assert_eq!(backend.device_state(r"USB\VID_046D&PID_C31C\1"), Some(DeviceState::Disable));
+    ///
+    /// // A hub is not tracked, so it is enabled right away.
+    /// backend.add_device(
+    ///     SimulatedDevice::new(r"USB\VID_05E3&PID_0610\2")
+    ///         .with_property(DevicePropertyKey::Service, "usbhub"),
+    /// );
+    /// assert!(tracker.insert_device_by_id(r"USB\VID_05E3&PID_0610\2").is_err());
+    /// assert_eq!(backend.device_state(r"USB\VID_05E3&PID_0610\2"), Some(DeviceState::Enable));
+    /// ```
+    pub fn disable_new_devices(&mut self) -> Result<(), DeviceBackendError> {
+        self.new_devices_disabled = self.backend.disable_new_devices()?;
+        Ok(())
+    }
+
+    /// Retrieves the current state (Enable/Disable) of a specific device by its ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - The Instance ID of the device.
+    pub fn device_state(&self, device_id: &DeviceId) -> Result<DeviceState, DeviceBackendError> {
+        if self.find_device(device_id).is_some() {
+            self.backend.query_device_state(device_id)
+        } else {
+            Err(DeviceBackendError::DeviceNotFound(device_id.to_string()))
+        }
+    }
+
+    /// Re-reads the state of every tracked device into its `enabled` field.
+    ///
+    /// States change without the tracker noticing, e.g. when the policy is enforced or another
+    /// tool enables a device, so they should be refreshed before the tree is shown.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+    /// use comp_gate::helper::device_managment::{DeviceId, DeviceState, DeviceTracker};
+    ///
+    /// let backend = SimulatedBackend::new();
+    /// backend.add_device(SimulatedDevice::new(r"USB\VID_046D&PID_C52B\1"));
+    /// let mut tracker = DeviceTracker::load_with_backend(Box::new(backend)).unwrap();
+    ///
+    /// let device_id = DeviceId::from(r"USB\VID_046D&PID_C52B\1");
+    /// assert_eq!(tracker.find_device(&device_id).unwrap().enabled, Some(true));
+    ///
+    /// tracker.set_device_state(&device_id, DeviceState::Disable).unwrap();
+    /// tracker.refresh_device_states();
+    /// assert_eq!(tracker.find_device(&device_id).unwrap().enabled, Some(false));
+    /// ```
+    pub fn refresh_device_states(&mut self) {
+        fn refresh(backend: &dyn DeviceBackend, devices: &mut HashMap<DeviceId, Device>) {
+            for device in devices.values_mut() {
+                device.enabled = backend
+                    .query_device_state(&device.device_id)
+                    .ok()
+                    .map(|state| state == DeviceState::Enable);
+                refresh(backend, &mut device.devices);
+            }
+        }
+
+        refresh(self.backend.as_ref(), &mut self.devices);
+    }
+
     /// Sets the state (Enable/Disable) of a specific device by its ID.
     ///
     /// This function searches the entire device tree for the specified ID.
@@ -542,29 +671,67 @@ impl DeviceTracker {
         &self,
         device_id: &DeviceId,
         state: DeviceState,
-    ) -> Result<(), Win32Error> {
-        if let Some(device) = self.find_device(device_id) {
-            device.change_state(state)
+    ) -> Result<(), DeviceBackendError> {
+        if self.find_device(device_id).is_some() {
+            self.backend.change_device_state(device_id, state)
         } else {
-            Err(Win32Error::from(ERROR_DEV_NOT_EXIST))
+            Err(DeviceBackendError::DeviceNotFound(device_id.to_string()))
         }
     }
 
+    /// Creates a tracker from an already built device tree.
+    ///
+    /// The tree is taken as it is; the backend is only used for later queries and state changes.
+    /// Devices inserted later are checked against `filter`, which should be the filter the tree
+    /// was built with.
+    ///
+    /// # Arguments
+    ///
+    /// * `devices` - The root-level devices and their sub-devices.
+    /// * `backend` - The backend used to manipulate the devices.
+    /// * `filter` - The filter deciding which devices inserted later are tracked.
+    pub fn from_tree(
+        devices: HashMap<DeviceId, Device>,
+        backend: Box<dyn DeviceBackend>,
+        filter: DeviceFilter,
+    ) -> Self {
+        Self {
+            devices,
+            backend,
+            filter,
+            new_devices_disabled: false,
+        }
+    }
+
+    /// Returns the backend this tracker uses to talk to the system.
+    pub fn backend(&self) -> &dyn DeviceBackend {
+        self.backend.as_ref()
+    }
+
+    /// Returns the filter deciding which devices this tracker tracks.
+    pub fn filter(&self) -> &DeviceFilter {
+        &self.filter
+    }
+
     /// Inserts a new device into the tracker by its ID.
     ///
     /// This is typically called when a new device is detected via a system event.
-    /// It adds the device to the internal `HDEVINFO` set and places it in the device tree.
+    /// It queries the device through the backend and places it in the device tree.
     ///
     /// # Arguments
     ///
     /// * `device_id` - The Instance ID of the new device.
     pub fn insert_device_by_id(&mut self, device_id: &str) -> Result<(), DeviceInsertionError> {
-        let device_instance = DeviceInstance::try_from(device_id)
-            .map_err(|err| DeviceInsertionError::from(Win32Error::from(err)))?;
-        let new_device = Device::try_from(device_instance)?;
-
-        if device_filter_function(&new_device) {
-            return Err(DeviceInsertionError::DeviceFilteredNotUsb);
+        let device_id = DeviceId::from(Rc::<str>::from(device_id));
+        let new_device = Device::from_backend(self.backend.as_ref(), device_id)?;
+
+        if !self.filter.includes(&new_device) {
+            // The policy never decides about untracked devices, so they must not be held back.
+            if self.new_devices_disabled {
+                self.backend
+                    .change_device_state(&new_device.device_id, DeviceState::Enable)?;
+            }
+            return Err(DeviceInsertionError::DeviceFilteredOut);
         }
 
         self.insert_deivice_into_tree(new_device);
@@ -579,21 +746,24 @@ impl DeviceTracker {
     fn insert_deivice_into_tree(&mut self, new_device: Device) {
         let new_device_id = new_device.device_id.clone();
 
-        if let Some(_) = self.find_device(&new_device_id) {
+        if self.find_device(&new_device_id).is_some() {
             return;
         }
 
-        if let Some(parent_id) = &new_device.parent_id {
-            if let Some(parent) = Self::find_in_tree_mut(&mut self.devices, parent_id) {
-                // Update tree level based on parent
-                let mut child = new_device;
-                child.tree_level = parent.tree_level + 1;
+        let parent = new_device
+            .parent_id
+            .as_ref()
+            .and_then(|parent_id| Self::find_in_tree_mut(&mut self.devices, parent_id));
 
-                parent.devices.insert(child.device_id.clone(), child);
-            } else {
-                // If parent not found, insert the new device as a top device
-                self.devices.insert(new_device_id.clone(), new_device);
-            }
+        if let Some(parent) = parent {
+            // Update tree level based on parent
+            let mut child = new_device;
+            child.tree_level = parent.tree_level + 1;
+
+            parent.devices.insert(child.device_id.clone(), child);
+        } else {
+            // If parent not found, insert the new device as a top device
+            self.devices.insert(new_device_id.clone(), new_device);
         }
 
         let orphan_ids: Vec<DeviceId> = self
@@ -606,22 +776,22 @@ impl DeviceTracker {
             .collect();
 
         for orphan_id in orphan_ids {
-            if let Some(mut orphan) = self.devices.remove(&orphan_id) {
-                if let Some(new_parent) = self.find_device_mut(&new_device_id) {
-                    println!(
-                        "- Re-parenting orphan device {} under {}",
-                        orphan_id, new_device_id
-                    );
-                    orphan.tree_level = new_parent.tree_level + 1;
-                    new_parent.devices.insert(orphan_id, orphan);
-                }
+            if let Some(mut orphan) = self.devices.remove(&orphan_id)
+                && let Some(new_parent) = self.find_device_mut(&new_device_id)
+            {
+                println!(
+                    "- Re-parenting orphan device {} under {}",
+                    orphan_id, new_device_id
+                );
+                orphan.tree_level = new_parent.tree_level + 1;
+                new_parent.devices.insert(orphan_id, orphan);
             }
         }
     }
 
     /// Removes a device from the tracker by its ID.
     ///
-    /// This removes the device from the tree and deletes it from the `HDEVINFO` set.
+    /// This removes the device (and its sub-devices) from the tree.
     ///
     /// # Arguments
     ///
@@ -648,99 +818,109 @@ impl DeviceTracker {
 }
 
 impl DeviceTracker {
-    /// Helper to get a handle to devices of a specific class.
-    fn get_class_devs(class_name: *const u8) -> Result<HDEVINFO, Win32Error> {
-        let devinfo_set: HDEVINFO = unsafe {
-            SetupDiGetClassDevsA(
-                null(),
-                class_name,
-                null_mut(),
-                DIGCF_ALLCLASSES | DIGCF_PRESENT,
-            )
-        };
-
-        if devinfo_set == INVALID_HANDLE_VALUE as HDEVINFO {
-            return Err(unsafe { GetLastError().into() });
-        }
+    /// Loads all currently connected devices accepted by the built-in `DeviceFilter` (USB and
+    /// HID devices without hubs) into a new `DeviceTracker`.
+    ///
+    /// This is the primary factory method for creating a `DeviceTracker`. It uses the
+    /// backend for the platform the application was built for.
+    pub fn load() -> Result<Self, DeviceBackendError> {
+        Self::load_with_backend(default_backend()?)
+    }
 
-        Ok(devinfo_set)
+    /// Loads all devices reported by the given backend and accepted by the built-in
+    /// `DeviceFilter` into a new `DeviceTracker`.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend used to enumerate and later manipulate devices.
+    pub fn load_with_backend(backend: Box<dyn DeviceBackend>) -> Result<Self, DeviceBackendError> {
+        Self::load_with_filter(backend, DeviceFilter::default())
     }
 
-    /// Loads all currently connected USB and HID devices into a new `DeviceTracker`.
+    /// Loads all devices reported by the given backend and accepted by a filter into a new
+    /// `DeviceTracker`.
     ///
-    /// This is the primary factory method for creating a `DeviceTracker`.
-    pub fn load() -> Result<Self, Win32Error> {
-        let usb_device_information_set = Self::get_class_devs(c"USB".as_ptr() as *const u8)?;
-        let hid_device_information_set = Self::get_class_devs(c"HID".as_ptr() as *const u8)?;
+    /// The filter is kept and also decides about devices inserted later.
+    ///
+    /// # Arguments
+    ///
+    /// * `backend` - The backend used to enumerate and later manipulate devices.
+    /// * `filter` - The filter deciding which devices are tracked.
+    pub fn load_with_filter(
+        backend: Box<dyn DeviceBackend>,
+        filter: DeviceFilter,
+    ) -> Result<Self, DeviceBackendError> {
+        let (devices, _) = Self::enumerate(backend.as_ref(), &filter)?;
 
-        Self::merge_device_information_sets(&[
-            usb_device_information_set,
-            hid_device_information_set,
-        ])
+        Ok(Self {
+            devices,
+            backend,
+            filter,
+            new_devices_disabled: false,
+        })
     }
 
-    /// Enumerates all devices in a given `HDEVINFO` set.
-    fn get_listed_devices(devinfoset: HDEVINFO) -> Result<HashMap<DeviceId, Device>, Win32Error> {
-        let mut devices: HashMap<DeviceId, Device> = HashMap::new();
-        let mut index: u32 = 0;
-
-        loop {
-            unsafe {
-                println!("Attempting to enumerate device at index: {}", index);
-                let mut device_data: SP_DEVINFO_DATA = std::mem::zeroed();
-                device_data.cbSize = std::mem::size_of::<SP_DEVINFO_DATA>() as u32;
-                let operation_result = SetupDiEnumDeviceInfo(
-                    devinfoset,
-                    index,
-                    &mut device_data as *mut SP_DEVINFO_DATA,
-                ) == TRUE;
-
-                if operation_result {
-                    let device_instance = DeviceInstance::try_from(device_data.DevInst)
-                        .map_err(|err| Win32Error::from(err))?;
-                    let next_device = Device::try_from(device_instance)?;
-
-                    if !device_filter_function(&next_device) {
-                        devices.insert(next_device.device_id.clone(), next_device);
-                    }
-                    println!("\t- Device found at index: {}", index);
-                    index += 1;
-                } else {
-                    println!("\t- No device found at index: {}", index);
-                    let error = GetLastError();
-                    if error == ERROR_NO_MORE_ITEMS {
-                        break;
-                    } else {
-                        return Err(error.into());
-                    }
+    /// Re-enumerates the devices of the backend, replacing the device tree.
+    ///
+    /// This is used to catch up after connection events were lost. Like on insertion, devices
+    /// the filter excludes are enabled if new devices are held back (see `disable_new_devices`).
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+    /// use comp_gate::helper::device_managment::DeviceTracker;
+    ///
+    /// let backend = SimulatedBackend::new();
+    /// let mut tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+    ///
+    /// backend.add_device(SimulatedDevice::new(r"USB\VID_046D&PID_C52B\5&2752457F&0&2"));
+    /// tracker.reload().unwrap();
+    /// assert_eq!(tracker.devices.len(), 1);
+    /// ```
+    pub fn reload(&mut self) -> Result<(), DeviceBackendError> {
+        let (devices, excluded) = Self::enumerate(self.backend.as_ref(), &self.filter)?;
+
+        if self.new_devices_disabled {
+            for device_id in excluded {
+                if self.backend.query_device_state(&device_id)? == DeviceState::Disable {
+                    self.backend
+                        .change_device_state(&device_id, DeviceState::Enable)?;
                 }
             }
         }
 
-        println!("Total devices found: {}", devices.len());
-        Ok(convert_devices_into_tree(devices))
+        self.devices = devices;
+        Ok(())
     }
 
-    /// Merges multiple `HDEVINFO` sets into a single `DeviceTracker`.
-    fn merge_device_information_sets(sets: &[HDEVINFO]) -> Result<Self, Win32Error> {
-        let mut merged_devices = HashMap::new();
-
-        for set in sets.iter() {
-            let devices = DeviceTracker::get_listed_devices(*set)?;
-            Self::merge_device_trees(&mut merged_devices, devices);
+    /// Enumerates the devices of a backend.
+    ///
+    /// # Returns
+    ///
+    /// The tree of the devices the filter includes, and the IDs of the devices it excludes.
+    fn enumerate(
+        backend: &dyn DeviceBackend,
+        filter: &DeviceFilter,
+    ) -> Result<(HashMap<DeviceId, Device>, Vec<DeviceId>), DeviceBackendError> {
+        let mut devices: HashMap<DeviceId, Device> = HashMap::new();
+        let mut excluded = Vec::new();
 
-            // free the device information set
-            if *set == INVALID_HANDLE_VALUE as isize {
+        for device_id in backend.enumerate_devices()? {
+            if devices.contains_key(&device_id) {
                 continue;
             }
-            unsafe {
-                let _ = SetupDiDestroyDeviceInfoList(*set);
+
+            let next_device = Device::from_backend(backend, device_id)?;
+            if filter.includes(&next_device) {
+                devices.insert(next_device.device_id.clone(), next_device);
+            } else {
+                excluded.push(next_device.device_id);
             }
         }
 
-        Ok(Self {
-            devices: merged_devices,
-        })
+        println!("Total devices found: {}", devices.len());
+        Ok((convert_devices_into_tree(devices), excluded))
     }
 
     /// Merges two device trees into one by finding the correct parent-child relationships.
@@ -777,13 +957,13 @@ impl DeviceTracker {
             let device_id = device.device_id.clone();
 
             // Try to find the parent in the base tree
-            if let Some(parent_id) = &device.parent_id {
-                if let Some(parent) = Self::find_in_tree_mut(base_tree, parent_id) {
-                    // Found the parent, insert as a child
-                    device.tree_level = parent.tree_level + 1;
-                    parent.devices.insert(device_id, device);
-                    continue;
-                }
+            if let Some(parent_id) = &device.parent_id
+                && let Some(parent) = Self::find_in_tree_mut(base_tree, parent_id)
+            {
+                // Found the parent, insert as a child
+                device.tree_level = parent.tree_level + 1;
+                parent.devices.insert(device_id, device);
+                continue;
             }
 
             // No parent found (or no parent_id), insert at root level
@@ -803,11 +983,11 @@ impl DeviceTracker {
                 .collect();
 
             for orphan_id in orphan_ids {
-                if let Some(mut orphan) = base_tree.remove(&orphan_id) {
-                    if let Some(new_parent) = base_tree.get_mut(&inserted_device_id) {
-                        orphan.tree_level = new_parent.tree_level + 1;
-                        new_parent.devices.insert(orphan_id, orphan);
-                    }
+                if let Some(mut orphan) = base_tree.remove(&orphan_id)
+                    && let Some(new_parent) = base_tree.get_mut(&inserted_device_id)
+                {
+                    orphan.tree_level = new_parent.tree_level + 1;
+                    new_parent.devices.insert(orphan_id, orphan);
                 }
             }
         }
@@ -856,74 +1036,88 @@ impl DeviceTracker {
     }
 }
 
-/// Filters out devices that should not be tracked (e.g., USB hubs).
-fn device_filter_function(device: &Device) -> bool {
-    if let Some(service) = &device.device_service {
-        service.as_ref() == "usbhub3" || service.as_ref() == "usbhub"
-    } else {
-        false
-    }
-}
-
 /// Converts a flat map of devices into a hierarchical tree.
+///
+/// Devices whose parent is not part of the map become root-level devices.
 fn convert_devices_into_tree(mut devices: HashMap<DeviceId, Device>) -> HashMap<DeviceId, Device> {
-    let device_ids: Vec<DeviceId> = devices.keys().cloned().collect();
-    let parent_ids: Vec<(DeviceId, DeviceId)> = devices
+    let mut children_ids: HashMap<DeviceId, Vec<DeviceId>> = HashMap::new();
+    for device in devices.values() {
+        if let Some(parent_id) = &device.parent_id
+            && devices.contains_key(parent_id)
+        {
+            children_ids
+                .entry(parent_id.clone())
+                .or_default()
+                .push(device.device_id.clone());
+        }
+    }
+
+    let root_ids: Vec<DeviceId> = devices
         .values()
-        .filter_map(|d| {
-            if let Some(pid) = &d.parent_id {
-                Some((pid.clone(), d.device_id.clone()))
-            } else {
-                None
-            }
+        .filter(|d| {
+            d.parent_id
+                .as_ref()
+                .is_none_or(|parent_id| !devices.contains_key(parent_id))
         })
+        .map(|d| d.device_id.clone())
         .collect();
 
-    for (pid, cid) in parent_ids.iter() {
-        place_child_in_parent(pid, cid, &mut devices, &device_ids, &parent_ids, 0);
+    let mut tree = HashMap::new();
+    for root_id in root_ids {
+        if let Some(mut root) = devices.remove(&root_id) {
+            place_children_in_parent(&mut root, &mut devices, &children_ids, 0);
+            tree.insert(root_id, root);
+        }
+    }
+
+    // Devices left at this point form a parent cycle; keep them visible at the root.
+    for (id, mut device) in devices {
+        device.tree_level = 0;
+        tree.insert(id, device);
     }
 
-    devices
+    tree
 }
 
-/// Recursive helper to move a child device into its parent's `devices` map.
-fn place_child_in_parent(
-    parent_id: &DeviceId,
-    child_id: &DeviceId,
+/// Recursive helper to move the children of `parent` from the flat map into its `devices` map.
+fn place_children_in_parent(
+    parent: &mut Device,
     devices: &mut HashMap<DeviceId, Device>,
-    device_ids: &Vec<DeviceId>,
-    parent_ids: &Vec<(DeviceId, DeviceId)>,
+    children_ids: &HashMap<DeviceId, Vec<DeviceId>>,
     level: u32,
-) -> () {
-    if device_ids.contains(parent_id) {
-        // This code here tracks a bug if we have a more nested device tree
-        // what can happen is that a child_device can also be a perent of another device
-        // and since we are moving the child_device from the devices HashMap to the sub_interface_devices
-        // we need to track where when we find the device that has the child_device as parent
-        // we can get this child_device from the parent_device's sub_interface_devices
-        // instead of trying to get it from the devices HashMap which no longer contains it
-        while let Some((pid, cid)) = parent_ids.iter().find(|(p, _)| p == child_id) {
-            place_child_in_parent(pid, cid, devices, device_ids, parent_ids, level + 1);
-        }
-
-        let mut child_device = devices.remove(child_id).unwrap();
-        let parent_device = devices.get_mut(parent_id).unwrap();
+) {
+    parent.tree_level = level;
 
-        child_device.tree_level = level + 1;
+    let Some(child_ids) = children_ids.get(&parent.device_id) else {
+        return;
+    };
 
-        parent_device
-            .devices
-            .insert(child_device.device_id.clone(), child_device);
+    for child_id in child_ids {
+        if let Some(mut child) = devices.remove(child_id) {
+            place_children_in_parent(&mut child, devices, children_ids, level + 1);
+            parent.devices.insert(child_id.clone(), child);
+        }
     }
 }
 
 /// Extract device instance ID from device interface path.
 ///
+/// On Windows the path is a device interface path; on Linux it is the sysfs path of the
+/// device, whose last component is the kernel name used as the device ID.
+///
 /// # Example
 ///
 /// Input:  `\\?\USB#VID_046D&PID_C52B#5&2752457f&0&2#{a5dcbf10-6530-11d2-901f-00c04fb951ed}`
 /// Output: `USB\VID_046D&PID_C52B\5&2752457f&0&2`
+///
+/// Input:  `/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0`
+/// Output: `1-1:1.0`
 pub fn device_path_to_device_id(device_path: &str) -> DeviceId {
+    if device_path.starts_with('/') {
+        let kernel_name = device_path.rsplit('/').next().unwrap_or(device_path);
+        return Rc::<str>::from(kernel_name).into();
+    }
+
     // Remove \\?\ prefix
     let path = device_path.strip_prefix(r"\\?\").unwrap_or(device_path);
 
@@ -939,3 +1133,142 @@ pub fn device_path_to_device_id(device_path: &str) -> DeviceId {
 
     Rc::<str>::from(instance_id.to_uppercase()).into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        error::DeviceInsertionError,
+        helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice},
+    };
+
+    const HUB: &str = r"USB\VID_05E3&PID_0610\6&1";
+    const RECEIVER: &str = r"USB\VID_046D&PID_C52B\5&2752457F&0&2";
+    const KEYBOARD: &str = r"HID\VID_046D&PID_C52B&MI_00\7&1";
+
+    fn id(device_id: &str) -> DeviceId {
+        DeviceId::from(Rc::<str>::from(device_id))
+    }
+
+    fn tracker(backend: &SimulatedBackend) -> DeviceTracker {
+        DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap()
+    }
+
+    #[test]
+    fn devices_with_missing_parents_become_roots() {
+        let backend = SimulatedBackend::new();
+        backend.add_device(SimulatedDevice::new(RECEIVER).with_parent(HUB));
+        backend.add_device(SimulatedDevice::new(KEYBOARD).with_parent(RECEIVER));
+
+        let tracker = tracker(&backend);
+        assert_eq!(tracker.devices.len(), 1);
+        assert_eq!(tracker.devices[&id(RECEIVER)].tree_level, 0);
+        assert_eq!(tracker.find_device(&id(KEYBOARD)).unwrap().tree_level, 1);
+    }
+
+    #[test]
+    fn parent_cycles_stay_visible() {
+        let backend = SimulatedBackend::new();
+        backend.add_device(SimulatedDevice::new(HUB).with_parent(RECEIVER));
+        backend.add_device(SimulatedDevice::new(RECEIVER).with_parent(HUB));
+
+        let tracker = tracker(&backend);
+        assert_eq!(tracker.iter().count(), 2);
+        assert!(tracker.find_device(&id(HUB)).is_some());
+        assert!(tracker.find_device(&id(RECEIVER)).is_some());
+    }
+
+    #[test]
+    fn state_of_untracked_device_is_not_found() {
+        let backend = SimulatedBackend::new();
+        let tracker = tracker(&backend);
+
+        // Present in the backend, but unknown to the tracker.
+        backend.add_device(SimulatedDevice::new(RECEIVER));
+        assert!(matches!(
+            tracker.device_state(&id(RECEIVER)),
+            Err(DeviceBackendError::DeviceNotFound(_))
+        ));
+        assert!(matches!(
+            tracker.set_device_state(&id(RECEIVER), DeviceState::Disable),
+            Err(DeviceBackendError::DeviceNotFound(_))
+        ));
+        assert!(backend.state_changes().is_empty());
+    }
+
+    #[test]
+    fn state_of_vanished_device_fails_in_backend() {
+        let backend = SimulatedBackend::new();
+        backend.add_device(SimulatedDevice::new(RECEIVER));
+        let mut tracker = tracker(&backend);
+
+        backend.remove_device(RECEIVER);
+        assert!(tracker.device_state(&id(RECEIVER)).is_err());
+        assert!(
+            tracker
+                .set_device_state(&id(RECEIVER), DeviceState::Disable)
+                .is_err()
+        );
+
+        // Refreshing keeps the device, with an unknown state.
+        tracker.refresh_device_states();
+        assert_eq!(tracker.find_device(&id(RECEIVER)).unwrap().enabled, None);
+    }
+
+    #[test]
+    fn inserting_missing_or_filtered_devices_fails() {
+        let backend = SimulatedBackend::new();
+        let mut tracker = tracker(&backend);
+
+        assert!(matches!(
+            tracker.insert_device_by_id(RECEIVER),
+            Err(DeviceInsertionError::BackendError(
+                DeviceBackendError::DeviceNotFound(_)
+            ))
+        ));
+
+        backend.add_device(
+            SimulatedDevice::new(HUB).with_property(DevicePropertyKey::Service, "usbhub3"),
+        );
+        assert!(matches!(
+            tracker.insert_device_by_id(HUB),
+            Err(DeviceInsertionError::DeviceFilteredOut)
+        ));
+        assert!(tracker.devices.is_empty());
+    }
+
+    #[test]
+    fn inserting_a_parent_adopts_its_orphans() {
+        let backend = SimulatedBackend::new();
+        backend.add_device(SimulatedDevice::new(RECEIVER));
+        backend.add_device(SimulatedDevice::new(KEYBOARD).with_parent(RECEIVER));
+        let mut tracker = DeviceTracker::from_tree(
+            HashMap::new(),
+            Box::new(backend.clone()),
+            DeviceFilter::default(),
+        );
+
+        tracker.insert_device_by_id(KEYBOARD).unwrap();
+        tracker.insert_device_by_id(RECEIVER).unwrap();
+        // Inserting a tracked device again changes nothing.
+        tracker.insert_device_by_id(KEYBOARD).unwrap();
+
+        assert_eq!(tracker.devices.len(), 1);
+        assert_eq!(tracker.find_device(&id(KEYBOARD)).unwrap().tree_level, 1);
+        assert_eq!(tracker.iter().count(), 2);
+    }
+
+    #[test]
+    fn removing_a_device_removes_its_sub_devices() {
+        let backend = SimulatedBackend::new();
+        backend.add_device(SimulatedDevice::new(RECEIVER));
+        backend.add_device(SimulatedDevice::new(KEYBOARD).with_parent(RECEIVER));
+        let mut tracker = tracker(&backend);
+
+        assert!(tracker.remove_device_by_id(&id(HUB)).is_none());
+        let removed = tracker.remove_device_by_id(&id(RECEIVER)).unwrap();
+        assert_eq!(removed.devices.len(), 1);
+        assert!(tracker.devices.is_empty());
+        assert!(tracker.find_device(&id(KEYBOARD)).is_none());
+    }
+}
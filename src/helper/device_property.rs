@@ -0,0 +1,503 @@
+//! # Device Property Module
+//!
+//! This module defines `DeviceProperty`, the typed value of a device property, and decodes the
+//! raw buffers the Windows property store returns into it.
+//!
+//! A raw property is a byte buffer plus a `DEVPROPTYPE`. The lower bits of the type select the
+//! base type (`DEVPROP_MASK_TYPE`), the upper bits a modifier (`DEVPROP_MASK_TYPEMOD`):
+//!
+//! - `DEVPROP_TYPEMOD_ARRAY`: The buffer holds consecutive values of a fixed-size base type.
+//!   `DEVPROP_TYPE_BYTE` arrays are plain binary data (`DEVPROP_TYPE_BINARY`).
+//! - `DEVPROP_TYPEMOD_LIST`: The buffer holds NUL-terminated strings, ended by an empty string
+//!   (a `REG_MULTI_SZ`).
+//!
+//! Values are stored little-endian, which is what Windows uses on every architecture it runs on.
+//! Decoding only looks at the buffer, so it works (and is tested) on every platform.
+
+use std::{
+    fmt::Display,
+    rc::Rc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use windows_sys::Win32::Devices::Properties::{
+    DEVPROP_MASK_TYPE, DEVPROP_MASK_TYPEMOD, DEVPROP_TYPE_BOOLEAN, DEVPROP_TYPE_BYTE,
+    DEVPROP_TYPE_DATE, DEVPROP_TYPE_DEVPROPTYPE, DEVPROP_TYPE_DOUBLE, DEVPROP_TYPE_EMPTY,
+    DEVPROP_TYPE_ERROR, DEVPROP_TYPE_FILETIME, DEVPROP_TYPE_FLOAT, DEVPROP_TYPE_GUID,
+    DEVPROP_TYPE_INT16, DEVPROP_TYPE_INT32, DEVPROP_TYPE_INT64, DEVPROP_TYPE_NTSTATUS,
+    DEVPROP_TYPE_NULL, DEVPROP_TYPE_SBYTE, DEVPROP_TYPE_SECURITY_DESCRIPTOR,
+    DEVPROP_TYPE_SECURITY_DESCRIPTOR_STRING, DEVPROP_TYPE_STRING, DEVPROP_TYPE_STRING_INDIRECT,
+    DEVPROP_TYPE_UINT16, DEVPROP_TYPE_UINT32, DEVPROP_TYPE_UINT64, DEVPROP_TYPEMOD_ARRAY,
+    DEVPROP_TYPEMOD_LIST, DEVPROPTYPE,
+};
+
+/// The seconds between the FILETIME epoch (1601-01-01) and the UNIX epoch (1970-01-01).
+const FILETIME_UNIX_OFFSET: u64 = 11_644_473_600;
+
+/// The days between the OLE Automation date epoch (1899-12-30) and the UNIX epoch.
+const OLE_DATE_UNIX_OFFSET: f64 = 25_569.0;
+
+/// A globally unique identifier, e.g. a container ID or a device setup class GUID.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::device_property::Guid;
+///
+/// let guid = Guid::from_bytes([
+///     0x78, 0x56, 0x34, 0x12, 0x34, 0x12, 0x78, 0x56, 0x9a, 0xbc, 0xde, 0xf0, 0x12, 0x34, 0x56,
+///     0x78,
+/// ]);
+/// assert_eq!(guid.data1, 0x12345678);
+/// assert_eq!(guid.to_string(), "{12345678-1234-5678-9abc-def012345678}");
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Guid {
+    /// The first 8 hexadecimal digits.
+    pub data1: u32,
+    /// The first group of 4 hexadecimal digits.
+    pub data2: u16,
+    /// The second group of 4 hexadecimal digits.
+    pub data3: u16,
+    /// The last 16 hexadecimal digits.
+    pub data4: [u8; 8],
+}
+
+impl Guid {
+    /// Reads a GUID from its in-memory layout (the first three fields little-endian).
+    pub fn from_bytes(bytes: [u8; 16]) -> Self {
+        let mut data4 = [0; 8];
+        data4.copy_from_slice(&bytes[8..]);
+
+        Self {
+            data1: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            data2: u16::from_le_bytes([bytes[4], bytes[5]]),
+            data3: u16::from_le_bytes([bytes[6], bytes[7]]),
+            data4,
+        }
+    }
+}
+
+impl Display for Guid {
+    /// Formats the GUID in registry format, e.g. `{12345678-1234-5678-9abc-def012345678}`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{{{:08x}-{:04x}-{:04x}-{:02x}{:02x}-",
+            self.data1, self.data2, self.data3, self.data4[0], self.data4[1]
+        )?;
+        for byte in &self.data4[2..] {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+/// Represents a property retrieved from a device.
+///
+/// Backends that read typed values from the operating system (e.g. SetupAPI) decode them with
+/// `DeviceProperty::from((&[u8], DEVPROPTYPE))`; the others report strings.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::device_property::DeviceProperty;
+/// use windows_sys::Win32::Devices::Properties::{
+///     DEVPROP_TYPE_BOOLEAN, DEVPROP_TYPE_FILETIME, DEVPROP_TYPE_STRING_LIST, DEVPROP_TYPE_UINT32,
+///     DEVPROP_TYPEMOD_ARRAY,
+/// };
+///
+/// // Hardware IDs are a list of UTF-16 strings ended by an empty string.
+/// let utf16 = |s: &str| s.encode_utf16().flat_map(u16::to_le_bytes).collect::<Vec<u8>>();
+/// let raw = utf16("USB\\VID_046D&PID_C52B&REV_1211\0USB\\VID_046D&PID_C52B\0\0");
+/// let DeviceProperty::StringListProperty { data } =
+///     DeviceProperty::from((raw.as_slice(), DEVPROP_TYPE_STRING_LIST))
+/// else {
+///     panic!("not a string list");
+/// };
+/// assert_eq!(data, ["USB\\VID_046D&PID_C52B&REV_1211", "USB\\VID_046D&PID_C52B"]);
+///
+/// // A problem code.
+/// let property = DeviceProperty::from((&22u32.to_le_bytes()[..], DEVPROP_TYPE_UINT32));
+/// assert_eq!(property.as_u32(), Some(22));
+///
+/// // DEVPROP_TRUE is 0xFF.
+/// let property = DeviceProperty::from((&[0xFF][..], DEVPROP_TYPE_BOOLEAN));
+/// assert_eq!(property.to_string(), "true");
+///
+/// // An install date: 2024-01-01T00:00:00Z as 100ns ticks since 1601.
+/// let raw = 133_485_408_000_000_000u64.to_le_bytes();
+/// let property = DeviceProperty::from((&raw[..], DEVPROP_TYPE_FILETIME));
+/// assert_eq!(property.to_string(), "1704067200");
+///
+/// // Arrays of fixed-size values.
+/// let raw: Vec<u8> = [1u32, 2, 3].iter().flat_map(|n| n.to_le_bytes()).collect();
+/// let property = DeviceProperty::from((raw.as_slice(), DEVPROP_TYPE_UINT32 | DEVPROP_TYPEMOD_ARRAY));
+/// assert_eq!(property.to_string(), "1, 2, 3");
+///
+/// // A buffer that does not fit its type is kept as it is.
+/// let property = DeviceProperty::from((&[1, 2, 3][..], DEVPROP_TYPE_UINT32));
+/// assert!(matches!(property, DeviceProperty::UnsupportedProperty { .. }));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum DeviceProperty {
+    /// The property does not exist (`DEVPROP_TYPE_EMPTY`).
+    EmptyProperty,
+    /// The property exists but has no value (`DEVPROP_TYPE_NULL`).
+    NullProperty,
+    /// Represents a string property (REG_SZ).
+    StringProperty {
+        /// The string value of the property.
+        data: String,
+    },
+    /// Represents a list of strings (REG_MULTI_SZ), e.g. the hardware IDs of a device.
+    StringListProperty {
+        /// The strings, in the order they are stored.
+        data: Vec<String>,
+    },
+    /// Represents a boolean property.
+    BooleanProperty {
+        /// The value of the property.
+        data: bool,
+    },
+    /// Represents a signed 8-bit integer property.
+    SByteProperty {
+        /// The value of the property.
+        data: i8,
+    },
+    /// Represents an unsigned 8-bit integer property.
+    ByteProperty {
+        /// The value of the property.
+        data: u8,
+    },
+    /// Represents a signed 16-bit integer property.
+    Int16Property {
+        /// The value of the property.
+        data: i16,
+    },
+    /// Represents an unsigned 16-bit integer property.
+    UInt16Property {
+        /// The value of the property.
+        data: u16,
+    },
+    /// Represents a signed 32-bit integer property, including `NTSTATUS` codes.
+    Int32Property {
+        /// The value of the property.
+        data: i32,
+    },
+    /// Represents an unsigned 32-bit integer property, including Win32 error codes and
+    /// `DEVPROPTYPE` values.
+    UInt32Property {
+        /// The value of the property.
+        data: u32,
+    },
+    /// Represents a signed 64-bit integer property.
+    Int64Property {
+        /// The value of the property.
+        data: i64,
+    },
+    /// Represents an unsigned 64-bit integer property.
+    UInt64Property {
+        /// The value of the property.
+        data: u64,
+    },
+    /// Represents a single-precision floating point property.
+    FloatProperty {
+        /// The value of the property.
+        data: f32,
+    },
+    /// Represents a double-precision floating point property.
+    DoubleProperty {
+        /// The value of the property.
+        data: f64,
+    },
+    /// Represents a GUID property, e.g. a container ID.
+    GuidProperty {
+        /// The value of the property.
+        data: Guid,
+    },
+    /// Represents a point in time (`DEVPROP_TYPE_FILETIME` or `DEVPROP_TYPE_DATE`).
+    TimeProperty {
+        /// The value of the property.
+        data: SystemTime,
+    },
+    /// Represents binary data (`DEVPROP_TYPE_BINARY` or a security descriptor).
+    BinaryProperty {
+        /// The bytes of the property.
+        data: Rc<[u8]>,
+    },
+    /// Represents an array of fixed-size values (`DEVPROP_TYPEMOD_ARRAY`).
+    ArrayProperty {
+        /// The values, each decoded as a property of the base type.
+        data: Vec<DeviceProperty>,
+    },
+    /// Represents a property whose type is not decoded (e.g. `DEVPROP_TYPE_DECIMAL`), or whose
+    /// buffer does not fit its type.
+    UnsupportedProperty {
+        /// The raw byte data of the property.
+        raw_data: Rc<[u8]>,
+        /// The Windows property type identifier.
+        property_type: DEVPROPTYPE,
+    },
+}
+
+impl DeviceProperty {
+    /// Returns the value of a string property.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            DeviceProperty::StringProperty { data } => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of an unsigned integer property that fits into 32 bits.
+    pub fn as_u32(&self) -> Option<u32> {
+        match *self {
+            DeviceProperty::ByteProperty { data } => Some(data.into()),
+            DeviceProperty::UInt16Property { data } => Some(data.into()),
+            DeviceProperty::UInt32Property { data } => Some(data),
+            DeviceProperty::UInt64Property { data } => data.try_into().ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the strings of a string or string list property.
+    pub fn into_strings(self) -> Option<Vec<String>> {
+        match self {
+            DeviceProperty::StringProperty { data } => Some(vec![data]),
+            DeviceProperty::StringListProperty { data } => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Decodes a single value of a base type that is not a string.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(DeviceProperty)` - The decoded value.
+    /// * `None` - If the base type is not decoded or the buffer does not have its size.
+    fn decode_value(raw_data: &[u8], base_type: DEVPROPTYPE) -> Option<Self> {
+        let property = match base_type {
+            DEVPROP_TYPE_BOOLEAN => DeviceProperty::BooleanProperty {
+                data: u8::from_le_bytes(fixed(raw_data)?) != 0,
+            },
+            DEVPROP_TYPE_SBYTE => DeviceProperty::SByteProperty {
+                data: i8::from_le_bytes(fixed(raw_data)?),
+            },
+            DEVPROP_TYPE_BYTE => DeviceProperty::ByteProperty {
+                data: u8::from_le_bytes(fixed(raw_data)?),
+            },
+            DEVPROP_TYPE_INT16 => DeviceProperty::Int16Property {
+                data: i16::from_le_bytes(fixed(raw_data)?),
+            },
+            DEVPROP_TYPE_UINT16 => DeviceProperty::UInt16Property {
+                data: u16::from_le_bytes(fixed(raw_data)?),
+            },
+            DEVPROP_TYPE_INT32 | DEVPROP_TYPE_NTSTATUS => DeviceProperty::Int32Property {
+                data: i32::from_le_bytes(fixed(raw_data)?),
+            },
+            DEVPROP_TYPE_UINT32 | DEVPROP_TYPE_ERROR | DEVPROP_TYPE_DEVPROPTYPE => {
+                DeviceProperty::UInt32Property {
+                    data: u32::from_le_bytes(fixed(raw_data)?),
+                }
+            }
+            DEVPROP_TYPE_INT64 => DeviceProperty::Int64Property {
+                data: i64::from_le_bytes(fixed(raw_data)?),
+            },
+            DEVPROP_TYPE_UINT64 => DeviceProperty::UInt64Property {
+                data: u64::from_le_bytes(fixed(raw_data)?),
+            },
+            DEVPROP_TYPE_FLOAT => DeviceProperty::FloatProperty {
+                data: f32::from_le_bytes(fixed(raw_data)?),
+            },
+            DEVPROP_TYPE_DOUBLE => DeviceProperty::DoubleProperty {
+                data: f64::from_le_bytes(fixed(raw_data)?),
+            },
+            DEVPROP_TYPE_GUID => DeviceProperty::GuidProperty {
+                data: Guid::from_bytes(fixed(raw_data)?),
+            },
+            DEVPROP_TYPE_FILETIME => DeviceProperty::TimeProperty {
+                data: filetime_to_system_time(u64::from_le_bytes(fixed(raw_data)?))?,
+            },
+            DEVPROP_TYPE_DATE => DeviceProperty::TimeProperty {
+                data: ole_date_to_system_time(f64::from_le_bytes(fixed(raw_data)?))?,
+            },
+            _ => return None,
+        };
+
+        Some(property)
+    }
+
+    /// Decodes an array of fixed-size values.
+    fn decode_array(raw_data: &[u8], base_type: DEVPROPTYPE) -> Option<Self> {
+        let size = value_size(base_type)?;
+        if !raw_data.len().is_multiple_of(size) {
+            return None;
+        }
+
+        let data = raw_data
+            .chunks_exact(size)
+            .map(|chunk| Self::decode_value(chunk, base_type))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(DeviceProperty::ArrayProperty { data })
+    }
+}
+
+impl From<(&[u8], DEVPROPTYPE)> for DeviceProperty {
+    /// Converts a raw byte slice and property type into a `DeviceProperty`.
+    ///
+    /// This function handles the parsing of raw bytes into Rust types based on the `DEVPROPTYPE`.
+    /// Types that are not decoded and buffers that do not fit their type become an
+    /// `UnsupportedProperty`.
+    fn from(value: (&[u8], DEVPROPTYPE)) -> Self {
+        let (raw_data, property_type) = value;
+        let base_type = property_type & DEVPROP_MASK_TYPE;
+
+        let property = match (property_type & DEVPROP_MASK_TYPEMOD, base_type) {
+            (0, DEVPROP_TYPE_EMPTY) => Some(DeviceProperty::EmptyProperty),
+            (0, DEVPROP_TYPE_NULL) => Some(DeviceProperty::NullProperty),
+            (
+                0,
+                DEVPROP_TYPE_STRING
+                | DEVPROP_TYPE_STRING_INDIRECT
+                | DEVPROP_TYPE_SECURITY_DESCRIPTOR_STRING,
+            ) => Some(DeviceProperty::StringProperty {
+                data: decode_utf16(raw_data)
+                    .split('\0')
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+            }),
+            (0, DEVPROP_TYPE_SECURITY_DESCRIPTOR) => Some(DeviceProperty::BinaryProperty {
+                data: raw_data.into(),
+            }),
+            (0, _) => Self::decode_value(raw_data, base_type),
+            (DEVPROP_TYPEMOD_LIST, DEVPROP_TYPE_STRING | DEVPROP_TYPE_STRING_INDIRECT) => {
+                Some(DeviceProperty::StringListProperty {
+                    data: decode_utf16(raw_data)
+                        .split('\0')
+                        .take_while(|s| !s.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                })
+            }
+            // `DEVPROP_TYPE_BINARY`
+            (DEVPROP_TYPEMOD_ARRAY, DEVPROP_TYPE_BYTE) => Some(DeviceProperty::BinaryProperty {
+                data: raw_data.into(),
+            }),
+            (DEVPROP_TYPEMOD_ARRAY, _) => Self::decode_array(raw_data, base_type),
+            _ => None,
+        };
+
+        property.unwrap_or_else(|| DeviceProperty::UnsupportedProperty {
+            raw_data: raw_data.into(),
+            property_type,
+        })
+    }
+}
+
+impl Display for DeviceProperty {
+    /// Formats the value of the property; lists and arrays are separated by `, `, times are
+    /// written as seconds since the UNIX epoch and binary data as hexadecimal.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn join<T: Display>(f: &mut std::fmt::Formatter<'_>, values: &[T]) -> std::fmt::Result {
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}", value)?;
+            }
+            Ok(())
+        }
+
+        match self {
+            DeviceProperty::EmptyProperty | DeviceProperty::NullProperty => Ok(()),
+            DeviceProperty::StringProperty { data } => write!(f, "{}", data),
+            DeviceProperty::StringListProperty { data } => join(f, data),
+            DeviceProperty::BooleanProperty { data } => write!(f, "{}", data),
+            DeviceProperty::SByteProperty { data } => write!(f, "{}", data),
+            DeviceProperty::ByteProperty { data } => write!(f, "{}", data),
+            DeviceProperty::Int16Property { data } => write!(f, "{}", data),
+            DeviceProperty::UInt16Property { data } => write!(f, "{}", data),
+            DeviceProperty::Int32Property { data } => write!(f, "{}", data),
+            DeviceProperty::UInt32Property { data } => write!(f, "{}", data),
+            DeviceProperty::Int64Property { data } => write!(f, "{}", data),
+            DeviceProperty::UInt64Property { data } => write!(f, "{}", data),
+            DeviceProperty::FloatProperty { data } => write!(f, "{}", data),
+            DeviceProperty::DoubleProperty { data } => write!(f, "{}", data),
+            DeviceProperty::GuidProperty { data } => write!(f, "{}", data),
+            DeviceProperty::TimeProperty { data } => match data.duration_since(UNIX_EPOCH) {
+                Ok(duration) => write!(f, "{}", duration.as_secs()),
+                Err(e) => write!(f, "-{}", e.duration().as_secs()),
+            },
+            DeviceProperty::BinaryProperty { data }
+            | DeviceProperty::UnsupportedProperty { raw_data: data, .. } => {
+                for byte in data.iter() {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            DeviceProperty::ArrayProperty { data } => join(f, data),
+        }
+    }
+}
+
+/// Returns the size of a single value of a fixed-size base type.
+fn value_size(base_type: DEVPROPTYPE) -> Option<usize> {
+    match base_type {
+        DEVPROP_TYPE_BOOLEAN | DEVPROP_TYPE_SBYTE | DEVPROP_TYPE_BYTE => Some(1),
+        DEVPROP_TYPE_INT16 | DEVPROP_TYPE_UINT16 => Some(2),
+        DEVPROP_TYPE_INT32
+        | DEVPROP_TYPE_UINT32
+        | DEVPROP_TYPE_FLOAT
+        | DEVPROP_TYPE_ERROR
+        | DEVPROP_TYPE_NTSTATUS
+        | DEVPROP_TYPE_DEVPROPTYPE => Some(4),
+        DEVPROP_TYPE_INT64
+        | DEVPROP_TYPE_UINT64
+        | DEVPROP_TYPE_DOUBLE
+        | DEVPROP_TYPE_FILETIME
+        | DEVPROP_TYPE_DATE => Some(8),
+        DEVPROP_TYPE_GUID => Some(16),
+        _ => None,
+    }
+}
+
+/// Returns the buffer as an array, if it has exactly the size of one.
+fn fixed<const N: usize>(raw_data: &[u8]) -> Option<[u8; N]> {
+    raw_data.try_into().ok()
+}
+
+/// Decodes little-endian UTF-16; a trailing odd byte is ignored.
+fn decode_utf16(raw_data: &[u8]) -> String {
+    let units: Vec<u16> = raw_data
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+
+    String::from_utf16_lossy(&units)
+}
+
+/// Converts a FILETIME (100 ns intervals since 1601-01-01 UTC) into a `SystemTime`.
+fn filetime_to_system_time(ticks: u64) -> Option<SystemTime> {
+    let since_1601 = Duration::new(ticks / 10_000_000, (ticks % 10_000_000) as u32 * 100);
+    let offset = Duration::from_secs(FILETIME_UNIX_OFFSET);
+
+    match since_1601.checked_sub(offset) {
+        Some(after_epoch) => UNIX_EPOCH.checked_add(after_epoch),
+        None => UNIX_EPOCH.checked_sub(offset - since_1601),
+    }
+}
+
+/// Converts an OLE Automation date (fractional days since 1899-12-30) into a `SystemTime`.
+fn ole_date_to_system_time(days: f64) -> Option<SystemTime> {
+    let seconds = (days - OLE_DATE_UNIX_OFFSET) * 86_400.0;
+    let duration = Duration::try_from_secs_f64(seconds.abs()).ok()?;
+
+    if seconds >= 0.0 {
+        UNIX_EPOCH.checked_add(duration)
+    } else {
+        UNIX_EPOCH.checked_sub(duration)
+    }
+}
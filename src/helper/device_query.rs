@@ -0,0 +1,687 @@
+//! # Device Query Module
+//!
+//! This module provides `DeviceQuery`, a composable predicate selecting devices from a device
+//! tree. Queries are built in code or parsed from a small text syntax, which is also how they
+//! travel over the IOAPI (`IoApiCommand::QueryDevices`).
+//!
+//! ## Syntax
+//!
+//! A query is a combination of terms:
+//! - `id:<pattern>`: The Instance ID of the device.
+//! - `class:<pattern>`, `service:<pattern>`, `name:<pattern>`, `description:<pattern>`: The
+//!   class, service (driver), friendly name or description of the device.
+//! - `vendor:<pattern>`, `product:<pattern>`: The vendor or product name from `usb.ids`.
+//! - `serial:<pattern>`: The serial number reported by the device.
+//! - `manufacturer:<pattern>`, `driver:<pattern>`: The manufacturer of the device or the
+//!   provider of its driver.
+//! - `hwid:<pattern>`, `compatid:<pattern>`: One of the hardware or compatible IDs.
+//! - `location:<pattern>`: One of the location paths or the location info.
+//! - `container:<pattern>`: The container ID shared by the devices of one physical device.
+//! - `problem:<pattern>`: The problem code (e.g. `problem:22`; `has:problem` selects every
+//!   device with a problem).
+//! - `vid:<hex>`, `pid:<hex>`: The USB vendor or product ID as 4 hexadecimal digits (e.g.
+//!   `vid:046D`).
+//! - `under:<pattern>`: The device is a (direct or indirect) sub-device of a device whose
+//!   Instance ID matches the pattern.
+//! - `has:<field>`: The device has a value for one of the fields above (e.g. `has:service`).
+//!
+//! Patterns are wildcard patterns (see `policy::wildcard_match`) and ignore case; values
+//! containing spaces, parentheses or quotes are quoted with `"`, and a `"` inside quotes is
+//! written twice (`name:"5.25"" Drive"`). Terms are combined with `and`, `or`, `not` and
+//! parentheses; `not` binds strongest, then `and`, then `or`. Terms next to each other are
+//! combined with `and`. The empty query and empty parentheses (`()`) match every device, so
+//! `not ()` matches none. Parentheses and `not`s may be nested up to `MAX_QUERY_DEPTH` levels.
+//!
+//! ```text
+//! class:HIDClass under:"USB\VID_05E3&PID_0610*"
+//! vid:046D or vid:1A2C
+//! not has:service
+//! ```
+
+use std::{collections::HashMap, rc::Rc, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::DeviceQueryError,
+    helper::{
+        device_managment::{Device, DeviceId},
+        policy::wildcard_match,
+        usb_identity::parse_usb_id,
+    },
+};
+
+/// How deep parentheses and `not`s may be nested in a query.
+pub const MAX_QUERY_DEPTH: usize = 64;
+
+/// The longest query, in bytes, accepted when a query is deserialized (e.g. from the IOAPI).
+pub const MAX_QUERY_LENGTH: usize = 4096;
+
+/// A textual field of a device that queries can match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QueryField {
+    /// The Instance ID.
+    Id,
+    /// The device setup class.
+    Class,
+    /// The service (driver) driving the device.
+    Service,
+    /// The friendly name.
+    Name,
+    /// The description.
+    Description,
+    /// The vendor name from `usb.ids`.
+    Vendor,
+    /// The product name from `usb.ids`.
+    Product,
+    /// The serial number in the Instance ID.
+    Serial,
+    /// The manufacturer.
+    Manufacturer,
+    /// The provider of the driver.
+    Driver,
+    /// The hardware IDs.
+    HardwareId,
+    /// The compatible IDs.
+    CompatibleId,
+    /// The location paths and the location info.
+    Location,
+    /// The container ID.
+    Container,
+    /// The problem code.
+    Problem,
+}
+
+impl QueryField {
+    /// Every field, in declaration order.
+    pub const ALL: [QueryField; 15] = [
+        QueryField::Id,
+        QueryField::Class,
+        QueryField::Service,
+        QueryField::Name,
+        QueryField::Description,
+        QueryField::Vendor,
+        QueryField::Product,
+        QueryField::Serial,
+        QueryField::Manufacturer,
+        QueryField::Driver,
+        QueryField::HardwareId,
+        QueryField::CompatibleId,
+        QueryField::Location,
+        QueryField::Container,
+        QueryField::Problem,
+    ];
+
+    /// Returns the name of the field as used in the query syntax.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QueryField::Id => "id",
+            QueryField::Class => "class",
+            QueryField::Service => "service",
+            QueryField::Name => "name",
+            QueryField::Description => "description",
+            QueryField::Vendor => "vendor",
+            QueryField::Product => "product",
+            QueryField::Serial => "serial",
+            QueryField::Manufacturer => "manufacturer",
+            QueryField::Driver => "driver",
+            QueryField::HardwareId => "hwid",
+            QueryField::CompatibleId => "compatid",
+            QueryField::Location => "location",
+            QueryField::Container => "container",
+            QueryField::Problem => "problem",
+        }
+    }
+
+    /// Returns the values of the field for a device; list fields have one per entry.
+    pub fn values(&self, device: &Device) -> Vec<String> {
+        let property = |value: Option<&str>| value.map(str::to_string).into_iter().collect();
+        let list = |values: &[Rc<str>]| values.iter().map(|value| value.to_string()).collect();
+        match self {
+            QueryField::Id => vec![device.device_id.to_string()],
+            QueryField::Class => property(device.device_class.as_deref()),
+            QueryField::Service => property(device.device_service.as_deref()),
+            QueryField::Name => property(device.device_friendly_name.as_deref()),
+            QueryField::Description => property(device.device_description.as_deref()),
+            QueryField::Vendor => property(device.vendor_name.as_deref()),
+            QueryField::Product => property(device.product_name.as_deref()),
+            QueryField::Serial => property(device.serial.as_deref()),
+            QueryField::Manufacturer => property(device.manufacturer.as_deref()),
+            QueryField::Driver => property(device.driver_provider.as_deref()),
+            QueryField::HardwareId => list(&device.hardware_ids),
+            QueryField::CompatibleId => list(&device.compatible_ids),
+            QueryField::Location => {
+                let mut locations: Vec<String> = list(&device.location_paths);
+                locations.extend(device.location_info.as_deref().map(str::to_string));
+                locations
+            }
+            QueryField::Container => property(device.container_id.as_deref()),
+            QueryField::Problem => device
+                .problem_code
+                .map(|code| code.to_string())
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for QueryField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for QueryField {
+    type Err = DeviceQueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        QueryField::ALL
+            .into_iter()
+            .find(|field| field.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| DeviceQueryError::UnknownField(s.to_string()))
+    }
+}
+
+/// A predicate selecting devices from a device tree.
+///
+/// On the wire (and in `Display`/`FromStr`) a query is written in the text syntax described in
+/// the module documentation.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::device_backend::DevicePropertyKey;
+/// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+/// use comp_gate::helper::device_managment::DeviceTracker;
+/// use comp_gate::error::DeviceQueryError;
+/// use comp_gate::helper::device_query::{DeviceQuery, MAX_QUERY_DEPTH, QueryField};
+///
+/// let backend = SimulatedBackend::new();
+/// backend.add_device(SimulatedDevice::new(r"USB\VID_05E3&PID_0610\5&1"));
+/// backend.add_device(
+///     SimulatedDevice::new(r"USB\VID_046D&PID_C52B\6&2")
+///         .with_parent(r"USB\VID_05E3&PID_0610\5&1")
+///         .with_property(DevicePropertyKey::Service, "usbccgp"),
+/// );
+/// backend.add_device(
+///     SimulatedDevice::new(r"HID\VID_046D&PID_C52B&MI_00\7&3")
+///         .with_parent(r"USB\VID_046D&PID_C52B\6&2")
+///         .with_property(DevicePropertyKey::Class, "HIDClass"),
+/// );
+/// let tracker = DeviceTracker::load_with_backend(Box::new(backend)).unwrap();
+///
+/// // Built in code...
+/// let query = DeviceQuery::field(QueryField::Class, "hidclass")
+///     .and(DeviceQuery::under(r"USB\VID_05E3&PID_0610*"));
+/// let devices = tracker.query(&query);
+/// assert_eq!(devices.len(), 1);
+/// assert_eq!(devices[0].device_id.as_ref(), r"HID\VID_046D&PID_C52B&MI_00\7&3");
+///
+/// // ...or parsed from text.
+/// let query: DeviceQuery = r#"class:HIDClass under:"USB\VID_05E3&PID_0610*""#.parse().unwrap();
+/// assert_eq!(tracker.query(&query).len(), 1);
+///
+/// let query: DeviceQuery = "vid:046D and not has:service".parse().unwrap();
+/// assert_eq!(tracker.query(&query).len(), 1);
+/// assert_eq!(tracker.query(&"vid:046D or vid:05E3".parse().unwrap()).len(), 3);
+/// assert_eq!(tracker.query(&DeviceQuery::Any).len(), 3);
+///
+/// assert!("colour:red".parse::<DeviceQuery>().is_err());
+/// assert!("(vid:046D".parse::<DeviceQuery>().is_err());
+///
+/// // Deeply nested queries are rejected instead of exhausting the stack.
+/// let nested = format!("{}vid:046D{}", "(".repeat(100_000), ")".repeat(100_000));
+/// assert_eq!(
+///     nested.parse::<DeviceQuery>(),
+///     Err(DeviceQueryError::TooDeeplyNested(MAX_QUERY_DEPTH))
+/// );
+/// assert_eq!(
+///     "not ".repeat(100_000).parse::<DeviceQuery>(),
+///     Err(DeviceQueryError::TooDeeplyNested(MAX_QUERY_DEPTH))
+/// );
+/// assert!(DeviceQuery::try_from(nested).is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum DeviceQuery {
+    /// Matches every device.
+    Any,
+    /// A field of the device matches a wildcard pattern.
+    Field {
+        /// The field to match.
+        field: QueryField,
+        /// The wildcard pattern the value has to match.
+        pattern: String,
+    },
+    /// The device has a value for a field.
+    Has(QueryField),
+    /// The USB vendor ID of the device.
+    VendorId(u16),
+    /// The USB product ID of the device.
+    ProductId(u16),
+    /// The device is a sub-device of a device whose Instance ID matches a wildcard pattern.
+    Under(String),
+    /// The inner query does not match.
+    Not(Box<DeviceQuery>),
+    /// Every inner query matches.
+    And(Vec<DeviceQuery>),
+    /// At least one inner query matches.
+    Or(Vec<DeviceQuery>),
+}
+
+impl DeviceQuery {
+    /// Creates a query matching devices whose field matches a wildcard pattern.
+    pub fn field(field: QueryField, pattern: impl Into<String>) -> Self {
+        DeviceQuery::Field {
+            field,
+            pattern: pattern.into(),
+        }
+    }
+
+    /// Creates a query matching the sub-devices of devices whose ID matches a wildcard pattern.
+    pub fn under(pattern: impl Into<String>) -> Self {
+        DeviceQuery::Under(pattern.into())
+    }
+
+    /// Combines two queries, matching devices both match.
+    pub fn and(self, other: DeviceQuery) -> Self {
+        match (self, other) {
+            (DeviceQuery::Any, query) | (query, DeviceQuery::Any) => query,
+            (DeviceQuery::And(mut queries), DeviceQuery::And(others)) => {
+                queries.extend(others);
+                DeviceQuery::And(queries)
+            }
+            (DeviceQuery::And(mut queries), query) => {
+                queries.push(query);
+                DeviceQuery::And(queries)
+            }
+            (query, other) => DeviceQuery::And(vec![query, other]),
+        }
+    }
+
+    /// Combines two queries, matching devices either matches.
+    pub fn or(self, other: DeviceQuery) -> Self {
+        match (self, other) {
+            (DeviceQuery::Any, _) | (_, DeviceQuery::Any) => DeviceQuery::Any,
+            (DeviceQuery::Or(mut queries), DeviceQuery::Or(others)) => {
+                queries.extend(others);
+                DeviceQuery::Or(queries)
+            }
+            (DeviceQuery::Or(mut queries), query) => {
+                queries.push(query);
+                DeviceQuery::Or(queries)
+            }
+            (query, other) => DeviceQuery::Or(vec![query, other]),
+        }
+    }
+
+    /// Returns whether a device matches the query.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The device to match.
+    /// * `ancestors` - The devices the device is placed under, from the root down.
+    pub fn matches(&self, device: &Device, ancestors: &[&Device]) -> bool {
+        match self {
+            DeviceQuery::Any => true,
+            DeviceQuery::Field { field, pattern } => field
+                .values(device)
+                .iter()
+                .any(|value| wildcard_match(pattern, value)),
+            DeviceQuery::Has(field) => field.values(device).iter().any(|value| !value.is_empty()),
+            DeviceQuery::VendorId(vendor_id) => device.vendor_id == Some(*vendor_id),
+            DeviceQuery::ProductId(product_id) => device.product_id == Some(*product_id),
+            DeviceQuery::Under(pattern) => ancestors
+                .iter()
+                .any(|ancestor| wildcard_match(pattern, &ancestor.device_id)),
+            DeviceQuery::Not(query) => !query.matches(device, ancestors),
+            DeviceQuery::And(queries) => queries.iter().all(|q| q.matches(device, ancestors)),
+            DeviceQuery::Or(queries) => queries.iter().any(|q| q.matches(device, ancestors)),
+        }
+    }
+
+    /// Returns every device of a tree that matches the query, ordered by device ID.
+    ///
+    /// # Arguments
+    ///
+    /// * `devices` - The root-level devices of the tree.
+    pub fn select<'a>(&self, devices: &'a HashMap<DeviceId, Device>) -> Vec<&'a Device> {
+        fn visit<'a>(
+            query: &DeviceQuery,
+            devices: &'a HashMap<DeviceId, Device>,
+            ancestors: &mut Vec<&'a Device>,
+            selected: &mut Vec<&'a Device>,
+        ) {
+            for device in devices.values() {
+                if query.matches(device, ancestors) {
+                    selected.push(device);
+                }
+                ancestors.push(device);
+                visit(query, &device.devices, ancestors, selected);
+                ancestors.pop();
+            }
+        }
+
+        let mut selected = vec![];
+        visit(self, devices, &mut vec![], &mut selected);
+        selected.sort_by(|a, b| a.device_id.as_ref().cmp(b.device_id.as_ref()));
+        selected
+    }
+}
+
+impl std::ops::Not for DeviceQuery {
+    type Output = DeviceQuery;
+
+    /// Negates the query.
+    fn not(self) -> Self::Output {
+        match self {
+            DeviceQuery::Not(query) => *query,
+            query => DeviceQuery::Not(Box::new(query)),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceQuery {
+    /// Writes the query in the text syntax; the empty string matches every device.
+    ///
+    /// The text parses back into an equivalent query.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_query::{DeviceQuery, QueryField};
+    ///
+    /// let queries = [
+    ///     DeviceQuery::Any,
+    ///     !DeviceQuery::Any,
+    ///     DeviceQuery::field(QueryField::Name, r#"5.25" Drive (A:)"#),
+    ///     DeviceQuery::field(QueryField::Serial, r#"""#),
+    ///     DeviceQuery::field(QueryField::Description, ""),
+    ///     !DeviceQuery::under(r"USB\VID_05E3*").or(DeviceQuery::VendorId(0x046D)),
+    /// ];
+    /// for query in queries {
+    ///     let text = query.to_string();
+    ///     assert_eq!(text.parse::<DeviceQuery>().unwrap(), query, "{}", text);
+    /// }
+    ///
+    /// assert_eq!((!DeviceQuery::Any).to_string(), "not ()");
+    /// assert_eq!(
+    ///     DeviceQuery::field(QueryField::Name, r#"5.25" Drive"#).to_string(),
+    ///     r#"name:"5.25"" Drive""#
+    /// );
+    /// ```
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        /// Writes a query, in parentheses if it is a combination or matches every device.
+        fn operand(f: &mut std::fmt::Formatter<'_>, query: &DeviceQuery) -> std::fmt::Result {
+            match query {
+                DeviceQuery::Any => f.write_str("()"),
+                DeviceQuery::And(_) | DeviceQuery::Or(_) => write!(f, "({})", query),
+                _ => write!(f, "{}", query),
+            }
+        }
+
+        match self {
+            DeviceQuery::Any => Ok(()),
+            // Combinations of nothing are written as what they match.
+            DeviceQuery::And(queries) if queries.is_empty() => f.write_str("()"),
+            DeviceQuery::Or(queries) if queries.is_empty() => f.write_str("not ()"),
+            DeviceQuery::Field { field, pattern } => write!(f, "{}:{}", field, quote(pattern)),
+            DeviceQuery::Has(field) => write!(f, "has:{}", field),
+            DeviceQuery::VendorId(vendor_id) => write!(f, "vid:{:04X}", vendor_id),
+            DeviceQuery::ProductId(product_id) => write!(f, "pid:{:04X}", product_id),
+            DeviceQuery::Under(pattern) => write!(f, "under:{}", quote(pattern)),
+            DeviceQuery::Not(query) => {
+                f.write_str("not ")?;
+                operand(f, query)
+            }
+            DeviceQuery::And(queries) | DeviceQuery::Or(queries) => {
+                let separator = match self {
+                    DeviceQuery::And(_) => " and ",
+                    _ => " or ",
+                };
+                for (index, query) in queries.iter().enumerate() {
+                    if index > 0 {
+                        f.write_str(separator)?;
+                    }
+                    match (self, query) {
+                        // `and` binds stronger than `or`, so only `or` needs parentheses.
+                        (DeviceQuery::Or(_), DeviceQuery::And(_)) => write!(f, "{}", query)?,
+                        _ => operand(f, query)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for DeviceQuery {
+    type Err = DeviceQueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+            depth: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Ok(DeviceQuery::Any);
+        }
+
+        let query = parser.parse_or()?;
+        match parser.next() {
+            None => Ok(query),
+            Some(token) => Err(DeviceQueryError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+impl TryFrom<String> for DeviceQuery {
+    type Error = DeviceQueryError;
+
+    /// Parses a query, rejecting queries longer than `MAX_QUERY_LENGTH` bytes.
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if value.len() > MAX_QUERY_LENGTH {
+            return Err(DeviceQueryError::TooLong(value.len(), MAX_QUERY_LENGTH));
+        }
+
+        value.parse()
+    }
+}
+
+impl From<DeviceQuery> for String {
+    fn from(query: DeviceQuery) -> Self {
+        query.to_string()
+    }
+}
+
+/// Quotes a pattern if it would otherwise not be read back as a single value.
+///
+/// Quotes inside the pattern are doubled.
+fn quote(pattern: &str) -> String {
+    if pattern.is_empty() || pattern.contains(|c: char| c.is_whitespace() || "()\"".contains(c)) {
+        format!("\"{}\"", pattern.replace('"', "\"\""))
+    } else {
+        pattern.to_string()
+    }
+}
+
+/// A token of the query syntax.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// `(`
+    Open,
+    /// `)`
+    Close,
+    /// A keyword or term, with quotes removed.
+    Word(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+            Token::Word(word) => f.write_str(word),
+        }
+    }
+}
+
+/// Splits a query into tokens.
+///
+/// Words end at whitespace or parentheses outside of quotes. Inside quotes, `""` stands for a
+/// single `"`.
+fn tokenize(source: &str) -> Result<Vec<Token>, DeviceQueryError> {
+    let mut tokens = vec![];
+    let mut chars = source.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    if !quoted && (c.is_whitespace() || c == '(' || c == ')') {
+                        break;
+                    }
+                    chars.next();
+                    match c {
+                        '"' if quoted && chars.peek() == Some(&'"') => {
+                            chars.next();
+                            word.push('"');
+                        }
+                        '"' => quoted = !quoted,
+                        c => word.push(c),
+                    }
+                }
+                if quoted {
+                    return Err(DeviceQueryError::UnclosedQuote);
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// A recursive descent parser over the tokens of a query.
+struct Parser {
+    /// The tokens of the query.
+    tokens: Vec<Token>,
+    /// The index of the next token.
+    position: usize,
+    /// How many parentheses and `not`s enclose the next token.
+    depth: usize,
+}
+
+impl Parser {
+    /// Returns the next token without consuming it.
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    /// Consumes the next token.
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Returns whether the next token is a keyword.
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+    }
+
+    /// `or_expr := and_expr ("or" and_expr)*`
+    fn parse_or(&mut self) -> Result<DeviceQuery, DeviceQueryError> {
+        let mut query = self.parse_and()?;
+        while self.peek_keyword("or") {
+            self.next();
+            query = query.or(self.parse_and()?);
+        }
+        Ok(query)
+    }
+
+    /// `and_expr := unary (["and"] unary)*`
+    fn parse_and(&mut self) -> Result<DeviceQuery, DeviceQueryError> {
+        let mut query = self.parse_unary()?;
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) => return Ok(query),
+                _ if self.peek_keyword("or") => return Ok(query),
+                _ if self.peek_keyword("and") => {
+                    self.next();
+                }
+                _ => {}
+            }
+            query = query.and(self.parse_unary()?);
+        }
+    }
+
+    /// `unary := "not" unary | "(" ")" | "(" or_expr ")" | term`
+    ///
+    /// Fails once the nesting exceeds `MAX_QUERY_DEPTH`, so hostile queries cannot exhaust the
+    /// stack.
+    fn parse_unary(&mut self) -> Result<DeviceQuery, DeviceQueryError> {
+        if self.depth >= MAX_QUERY_DEPTH {
+            return Err(DeviceQueryError::TooDeeplyNested(MAX_QUERY_DEPTH));
+        }
+
+        self.depth += 1;
+        let query = self.parse_nested();
+        self.depth -= 1;
+        query
+    }
+
+    /// Parses a `unary` one nesting level deeper.
+    fn parse_nested(&mut self) -> Result<DeviceQuery, DeviceQueryError> {
+        match self.next() {
+            None => Err(DeviceQueryError::UnexpectedEnd),
+            Some(Token::Open) if self.peek() == Some(&Token::Close) => {
+                self.next();
+                Ok(DeviceQuery::Any)
+            }
+            Some(Token::Open) => {
+                let query = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(query),
+                    Some(token) => Err(DeviceQueryError::UnexpectedToken(token.to_string())),
+                    None => Err(DeviceQueryError::UnexpectedEnd),
+                }
+            }
+            Some(Token::Close) => Err(DeviceQueryError::UnexpectedToken(")".to_string())),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("not") => Ok(!self.parse_unary()?),
+            Some(Token::Word(word)) => parse_term(&word),
+        }
+    }
+}
+
+/// Parses a `<key>:<value>` term.
+fn parse_term(term: &str) -> Result<DeviceQuery, DeviceQueryError> {
+    let Some((key, value)) = term.split_once(':') else {
+        return Err(DeviceQueryError::UnexpectedToken(term.to_string()));
+    };
+
+    let usb_id =
+        || parse_usb_id(value).ok_or_else(|| DeviceQueryError::InvalidUsbId(value.to_string()));
+
+    match key.to_ascii_lowercase().as_str() {
+        "vid" => Ok(DeviceQuery::VendorId(usb_id()?)),
+        "pid" => Ok(DeviceQuery::ProductId(usb_id()?)),
+        "under" => Ok(DeviceQuery::under(value)),
+        "has" => Ok(DeviceQuery::Has(value.parse()?)),
+        _ => Ok(DeviceQuery::field(key.parse()?, value)),
+    }
+}
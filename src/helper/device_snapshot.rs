@@ -0,0 +1,307 @@
+//! # Device Snapshot Module
+//!
+//! This module exports the device tree of a `DeviceTracker` as a JSON snapshot and rebuilds a
+//! tracker from one. Unlike the tab-indented `Display` output, a snapshot keeps every property
+//! of every device, so it can be attached to bug reports, collected for fleet inventory or used
+//! as a test fixture.
+//!
+//! ```json
+//! {
+//!   "created_at": 1760601600,
+//!   "devices": {
+//!     "USB\\VID_046D&PID_C52B\\5&2752457F&0&2": {
+//!       "device_id": "USB\\VID_046D&PID_C52B\\5&2752457F&0&2",
+//!       "devices": {},
+//!       "device_class": "USB",
+//!       ...
+//!     }
+//!   },
+//!   "filter": {
+//!     "default_action": "exclude",
+//!     "rules": [ ... ]
+//!   },
+//!   "format_version": 1
+//! }
+//! ```
+//!
+//! The snapshot keeps the `DeviceFilter` the tree was built with, so a restored tracker tracks
+//! re-inserted devices the same way the original one did. Snapshots without a filter are
+//! restored with the built-in filter.
+//!
+//! Object keys are written in sorted order, so snapshots of the same tree only differ in their
+//! `created_at` timestamp.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::SnapshotError,
+    helper::{
+        device_backend::{
+            DevicePropertyKey,
+            simulated::{SimulatedBackend, SimulatedDevice},
+        },
+        device_filter::DeviceFilter,
+        device_managment::{Device, DeviceId, DeviceIterator, DeviceState, DeviceTracker},
+        device_property::DeviceProperty,
+        policy_file::write_atomically,
+    },
+};
+
+/// The version of the snapshot format written by this build.
+///
+/// It is bumped whenever a change to the format would make older builds misread snapshots.
+pub const SNAPSHOT_FORMAT_VERSION: u32 = 1;
+
+/// A point-in-time copy of a device tree.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::device_backend::DevicePropertyKey;
+/// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+/// use comp_gate::helper::device_filter::DeviceFilter;
+/// use comp_gate::helper::device_managment::{DeviceId, DeviceState, DeviceTracker};
+/// use comp_gate::helper::device_snapshot::DeviceSnapshot;
+///
+/// let backend = SimulatedBackend::new();
+/// backend.add_device(
+///     SimulatedDevice::new(r"USB\VID_046D&PID_C52B\5&2752457F&0&2")
+///         .with_property(DevicePropertyKey::Class, "USB"),
+/// );
+/// backend.add_device(
+///     SimulatedDevice::new(r"HID\VID_046D&PID_C52B&MI_00\7&1")
+///         .with_parent(r"USB\VID_046D&PID_C52B\5&2752457F&0&2")
+///         .with_property(DevicePropertyKey::Class, "HIDClass"),
+/// );
+/// let tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+///
+/// let json = DeviceSnapshot::capture(&tracker).to_json().unwrap();
+/// let restored = DeviceSnapshot::from_json(&json).unwrap().into_tracker();
+///
+/// let hid = DeviceId::from(r"HID\VID_046D&PID_C52B&MI_00\7&1");
+/// let device = restored.find_device(&hid).unwrap();
+/// assert_eq!(device.tree_level, 1);
+/// assert_eq!(device.device_class.as_deref(), Some("HIDClass"));
+///
+/// // The restored tracker is fully usable, e.g. in fixture-driven tests.
+/// restored.set_device_state(&hid, DeviceState::Disable).unwrap();
+///
+/// // Restoring loses nothing.
+/// let mut snapshot = DeviceSnapshot::capture(&restored);
+/// snapshot.created_at = DeviceSnapshot::from_json(&json).unwrap().created_at;
+/// assert_eq!(snapshot.to_json().unwrap(), json);
+///
+/// // The filter travels with the snapshot: a tracker that only tracks USB devices is restored
+/// // as one.
+/// let filter = DeviceFilter::from_toml(
+///     r#"
+///     default_action = "exclude"
+///
+///     [[rules]]
+///     action = "include"
+///     enumerator = "USB"
+///     "#,
+/// )
+/// .unwrap();
+/// let tracker = DeviceTracker::load_with_filter(Box::new(backend), filter.clone()).unwrap();
+/// let json = DeviceSnapshot::capture(&tracker).to_json().unwrap();
+/// let mut restored = DeviceSnapshot::from_json(&json).unwrap().into_tracker();
+/// assert_eq!(restored.filter(), &filter);
+/// assert!(restored.find_device(&hid).is_none());
+/// assert!(restored.insert_device_by_id(&hid).is_err());
+/// ```
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeviceSnapshot {
+    /// The version of the snapshot format (`SNAPSHOT_FORMAT_VERSION`).
+    pub format_version: u32,
+    /// When the snapshot was taken, in seconds since the UNIX epoch.
+    pub created_at: u64,
+    /// The root-level devices and their sub-devices.
+    pub devices: HashMap<DeviceId, Device>,
+    /// The filter the device tree was built with.
+    #[serde(default)]
+    pub filter: DeviceFilter,
+}
+
+impl DeviceSnapshot {
+    /// Creates a snapshot of a device tree, timestamped with the current time.
+    ///
+    /// The snapshot records the built-in `DeviceFilter`; use `capture` to keep the filter of a
+    /// tracker.
+    ///
+    /// # Arguments
+    ///
+    /// * `devices` - The root-level devices and their sub-devices, e.g. as returned by the core.
+    pub fn new(devices: HashMap<DeviceId, Device>) -> Self {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            created_at,
+            devices,
+            filter: DeviceFilter::default(),
+        }
+    }
+
+    /// Creates a snapshot of the device tree of a tracker.
+    pub fn capture(tracker: &DeviceTracker) -> Self {
+        Self {
+            filter: tracker.filter().clone(),
+            ..Self::new(tracker.devices.clone())
+        }
+    }
+
+    /// Returns an iterator over all devices in the snapshot.
+    pub fn iter(&self) -> DeviceIterator<'_> {
+        DeviceIterator::new(&self.devices)
+    }
+
+    /// Serializes the snapshot into pretty-printed JSON with sorted object keys.
+    pub fn to_json(&self) -> Result<String, SnapshotError> {
+        // Going through a `Value` sorts the keys of the device maps.
+        let value = serde_json::to_value(self)?;
+        Ok(serde_json::to_string_pretty(&value)?)
+    }
+
+    /// Parses a snapshot from JSON.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(DeviceSnapshot)` - The parsed snapshot.
+    /// * `Err(SnapshotError::UnsupportedVersion)` - If the snapshot uses another format version.
+    /// * `Err(SnapshotError::Malformed)` - If the JSON does not describe a snapshot.
+    pub fn from_json(json: &str) -> Result<Self, SnapshotError> {
+        #[derive(Deserialize)]
+        struct Header {
+            format_version: u32,
+        }
+
+        // Check the version first, so a newer format is not reported as malformed.
+        let header: Header = serde_json::from_str(json)?;
+        if header.format_version != SNAPSHOT_FORMAT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(
+                header.format_version,
+                SNAPSHOT_FORMAT_VERSION,
+            ));
+        }
+
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// Writes the snapshot to a file, replacing it atomically.
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        let mut json = self.to_json()?;
+        json.push('\n');
+        Ok(write_atomically(path, json.as_bytes())?)
+    }
+
+    /// Reads a snapshot from a file.
+    pub fn load(path: &Path) -> Result<Self, SnapshotError> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+
+    /// Rebuilds a `DeviceTracker` holding the device tree of the snapshot.
+    ///
+    /// The tracker is backed by a `SimulatedBackend` reporting the devices of the snapshot, so
+    /// state changes and re-inserted devices work as they would on the original system. Devices
+    /// inserted later are checked against the filter of the snapshot.
+    pub fn into_tracker(self) -> DeviceTracker {
+        let backend = SimulatedBackend::new();
+        for device in self.iter() {
+            backend.add_device(simulated_device(device));
+        }
+
+        DeviceTracker::from_tree(self.devices, Box::new(backend), self.filter)
+    }
+}
+
+/// Builds the simulated counterpart of a device, reporting the same properties.
+fn simulated_device(device: &Device) -> SimulatedDevice {
+    let mut simulated = SimulatedDevice::new(&device.device_id);
+    simulated.parent_id = device.parent_id.clone();
+    if device.enabled == Some(false) {
+        simulated.state = DeviceState::Disable;
+    }
+
+    let properties = [
+        (DevicePropertyKey::Service, &device.device_service),
+        (DevicePropertyKey::Class, &device.device_class),
+        (
+            DevicePropertyKey::FriendlyName,
+            &device.device_friendly_name,
+        ),
+        (DevicePropertyKey::DeviceType, &device.device_type),
+        (DevicePropertyKey::Description, &device.device_description),
+        (DevicePropertyKey::Serial, &device.serial),
+        (DevicePropertyKey::Manufacturer, &device.manufacturer),
+        (
+            DevicePropertyKey::BusReportedDescription,
+            &device.bus_reported_description,
+        ),
+        (DevicePropertyKey::LocationInfo, &device.location_info),
+        (DevicePropertyKey::DriverProvider, &device.driver_provider),
+        (DevicePropertyKey::DriverVersion, &device.driver_version),
+        (DevicePropertyKey::ContainerId, &device.container_id),
+    ];
+    for (key, value) in properties {
+        if let Some(value) = value {
+            simulated = simulated.with_property(key, value);
+        }
+    }
+
+    let lists = [
+        (DevicePropertyKey::HardwareIds, &device.hardware_ids),
+        (DevicePropertyKey::CompatibleIds, &device.compatible_ids),
+        (DevicePropertyKey::LocationPaths, &device.location_paths),
+    ];
+    for (key, values) in lists {
+        if !values.is_empty() {
+            let data = values.iter().map(|value| value.to_string()).collect();
+            simulated =
+                simulated.with_property_value(key, DeviceProperty::StringListProperty { data });
+        }
+    }
+
+    let usb_ids = [
+        (DevicePropertyKey::VendorId, device.vendor_id),
+        (DevicePropertyKey::ProductId, device.product_id),
+    ];
+    for (key, value) in usb_ids {
+        if let Some(data) = value {
+            simulated = simulated.with_property_value(key, DeviceProperty::UInt16Property { data });
+        }
+    }
+
+    if let Some(install_date) = device.install_date {
+        simulated = simulated.with_property_value(
+            DevicePropertyKey::InstallDate,
+            DeviceProperty::TimeProperty {
+                data: UNIX_EPOCH + Duration::from_secs(install_date),
+            },
+        );
+    }
+    if let Some(problem_code) = device.problem_code {
+        simulated = simulated.with_property_value(
+            DevicePropertyKey::ProblemCode,
+            DeviceProperty::UInt32Property { data: problem_code },
+        );
+    }
+    if let Some(status) = device.status {
+        simulated = simulated.with_property_value(
+            DevicePropertyKey::Status,
+            DeviceProperty::UInt32Property { data: status },
+        );
+    }
+
+    simulated
+}
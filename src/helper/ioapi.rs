@@ -4,51 +4,328 @@
 //! between different components of the `comp-gate` system (e.g., the core service and the shell/GUI).
 //!
 //! It handles:
-//! - Defining the command structure (`IoApiCommand`).
+//! - Defining the command structure (`IoApiCommand`) and the typed responses (`IoApiResponse`).
 //! - Serializing commands into byte requests (`IoApiRequest`).
+//! - Framing messages on the wire.
 //! - Locating the connection address for the core service.
+//!
+//! ## Transports
+//!
+//! On Linux the core listens on a Unix domain socket (`ioapi_socket_path`), which lets it
+//! identify the user behind every connection; listening on localhost TCP as well is opt-in. On
+//! other platforms TCP is the only transport. The addresses of the running core are published in
+//! the connection file (`connection_file_path`), one `CoreAddress` per line. TCP clients have
+//! to authenticate with the secret in the token file before they can send commands (see the
+//! `ioapi_auth` module); the `CoreClient` of the `client` module takes care of that.
+//!
+//! Every connection is assigned a `Role` (see the `ioapi_access` module), which limits the
+//! commands it may run; refused commands are answered with `IoApiStatus::Forbidden`.
+//!
+//! ## Wire Format
+//!
+//! Every message is a frame of `[4 bytes length (Big Endian)][JSON payload]`. A connection starts
+//! with a `Handshake` command carrying the client's `PROTOCOL_VERSION`; the core answers with its
+//! own version and refuses every other command until the handshake succeeded. Every response
+//! carries an `IoApiStatus` code.
+//!
+//! ## Subscriptions
+//!
+//! After a `Subscribe` command the core additionally pushes an `IoApiResponseBody::Event` frame
+//! for every matching `IoApiEvent`. Pushed frames are interleaved with the responses to any
+//! further commands, so clients tell them apart by their body.
+
+#[cfg(target_os = "linux")]
+use std::os::unix::net::UnixStream;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    ops::Deref,
+    path::PathBuf,
+    rc::Rc,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use std::{net::SocketAddr, ops::Deref, path::PathBuf, rc::Rc};
+use serde::{Deserialize, Serialize};
 
-use crate::helper::device_managment::DeviceId;
+use crate::{
+    error::IoApiError,
+    helper::{
+        device_managment::{Device, DeviceId},
+        device_query::DeviceQuery,
+        ioapi_access::Role,
+        policy::Policy,
+    },
+};
 
-/// Returns a per-user OS temporary directory path for the connection file.
+/// The version of the IOAPI protocol implemented by this build.
 ///
-/// This uses `std::env::temp_dir()` which maps to:
-/// - On Unix: typically `/tmp`
-/// - On Windows: `%TEMP%` / `%TMP%`
-/// - On macOS: typically `/var/folders/.../T` or similar
+/// It is bumped whenever a change to the messages would break existing clients.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// The largest frame accepted from the other side of a connection.
+pub const MAX_FRAME_LENGTH: u32 = 16 * 1024 * 1024;
+
+/// The environment variable overriding the location of the runtime directory.
+pub const RUNTIME_DIR_ENV: &str = "COMP_GATE_RUNTIME_DIR";
+
+/// Returns the runtime directory, which holds the files the running core shares with its
+/// clients: the connection file, the token file and the IOAPI socket.
 ///
-/// Using the OS temporary directory avoids hardcoding usernames or absolute paths
-/// that won't exist on other users' machines.
+/// Unlike the temporary directory, it is writable only by the user running the core (see
+/// `ioapi_auth::prepare_runtime_dir`), so other users cannot plant these files. The location can
+/// be overridden with the `COMP_GATE_RUNTIME_DIR` environment variable. Otherwise it is:
+/// - On Windows: `%ProgramData%\comp-gate`
+/// - Elsewhere: `/run/comp-gate`
+pub fn runtime_dir() -> PathBuf {
+    if let Some(path) = std::env::var_os(RUNTIME_DIR_ENV) {
+        return PathBuf::from(path);
+    }
+
+    if cfg!(windows) {
+        let program_data =
+            std::env::var_os("ProgramData").unwrap_or_else(|| r"C:\ProgramData".into());
+        PathBuf::from(program_data).join("comp-gate")
+    } else {
+        PathBuf::from("/run/comp-gate")
+    }
+}
+
+/// Returns the location of the connection file (`comp-gate.txt` in the runtime directory).
 pub fn connection_file_path() -> PathBuf {
-    std::env::temp_dir().join("comp-gate.txt")
+    runtime_dir().join("comp-gate.txt")
+}
+
+/// The environment variable overriding the location of the IOAPI Unix domain socket.
+pub const IOAPI_SOCKET_ENV: &str = "COMP_GATE_IOAPI_SOCKET";
+
+/// Returns the location of the Unix domain socket the core listens on (Linux only).
+///
+/// The location can be overridden with the `COMP_GATE_IOAPI_SOCKET` environment variable;
+/// otherwise it is `ioapi.sock` in the runtime directory (`/run/comp-gate/ioapi.sock`).
+pub fn ioapi_socket_path() -> PathBuf {
+    std::env::var_os(IOAPI_SOCKET_ENV)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| runtime_dir().join("ioapi.sock"))
+}
+
+/// An address the core service accepts IOAPI connections on.
+///
+/// Addresses are written as `unix:<path>` or `tcp:<ip>:<port>`; a bare `<ip>:<port>` is read as
+/// a TCP address.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::ioapi::CoreAddress;
+/// use std::path::PathBuf;
+///
+/// let address: CoreAddress = "unix:/run/comp-gate/ioapi.sock".parse().unwrap();
+/// assert_eq!(address, CoreAddress::Unix(PathBuf::from("/run/comp-gate/ioapi.sock")));
+///
+/// let address: CoreAddress = "127.0.0.1:4242".parse().unwrap();
+/// assert_eq!(address.to_string(), "tcp:127.0.0.1:4242");
+///
+/// assert!("pipe:comp-gate".parse::<CoreAddress>().is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CoreAddress {
+    /// A Unix domain socket.
+    Unix(PathBuf),
+    /// A TCP socket.
+    Tcp(SocketAddr),
+}
+
+impl CoreAddress {
+    /// Opens a connection to the address.
+    pub fn connect(&self) -> io::Result<IoApiStream> {
+        match self {
+            CoreAddress::Tcp(address) => TcpStream::connect(address).map(IoApiStream::Tcp),
+            #[cfg(target_os = "linux")]
+            CoreAddress::Unix(path) => UnixStream::connect(path).map(IoApiStream::Unix),
+            #[cfg(not(target_os = "linux"))]
+            CoreAddress::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix domain sockets are not supported on this platform",
+            )),
+        }
+    }
+
+    /// Opens a connection to the address, giving up on TCP connections after a timeout.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The longest time to wait for the connection to be established.
+    pub fn connect_timeout(&self, timeout: Duration) -> io::Result<IoApiStream> {
+        match self {
+            CoreAddress::Tcp(address) => {
+                TcpStream::connect_timeout(address, timeout).map(IoApiStream::Tcp)
+            }
+            // Connecting to a local socket does not wait for the other side.
+            CoreAddress::Unix(_) => self.connect(),
+        }
+    }
+}
+
+impl Display for CoreAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CoreAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+            CoreAddress::Tcp(address) => write!(f, "tcp:{}", address),
+        }
+    }
+}
+
+impl FromStr for CoreAddress {
+    type Err = IoApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(CoreAddress::Unix(PathBuf::from(path)));
+        }
+
+        s.strip_prefix("tcp:")
+            .unwrap_or(s)
+            .parse()
+            .map(CoreAddress::Tcp)
+            .map_err(|_| IoApiError::InvalidAddress(s.to_string()))
+    }
+}
+
+/// A connection to (or, inside the core, from) an IOAPI peer over any supported transport.
+pub enum IoApiStream {
+    /// A TCP connection.
+    Tcp(TcpStream),
+    /// A Unix domain socket connection.
+    #[cfg(target_os = "linux")]
+    Unix(UnixStream),
+}
+
+impl IoApiStream {
+    /// Moves the stream into or out of non-blocking mode.
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            IoApiStream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+            #[cfg(target_os = "linux")]
+            IoApiStream::Unix(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Sets the longest time a blocking read or write may take; `None` waits forever.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            IoApiStream::Tcp(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+            #[cfg(target_os = "linux")]
+            IoApiStream::Unix(stream) => {
+                stream.set_read_timeout(timeout)?;
+                stream.set_write_timeout(timeout)
+            }
+        }
+    }
+}
+
+impl Read for IoApiStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            IoApiStream::Tcp(stream) => stream.read(buf),
+            #[cfg(target_os = "linux")]
+            IoApiStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for IoApiStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            IoApiStream::Tcp(stream) => stream.write(buf),
+            #[cfg(target_os = "linux")]
+            IoApiStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            IoApiStream::Tcp(stream) => stream.flush(),
+            #[cfg(target_os = "linux")]
+            IoApiStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for IoApiStream {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        match self {
+            IoApiStream::Tcp(stream) => stream.as_raw_fd(),
+            #[cfg(target_os = "linux")]
+            IoApiStream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawSocket for IoApiStream {
+    fn as_raw_socket(&self) -> std::os::windows::io::RawSocket {
+        match self {
+            IoApiStream::Tcp(stream) => stream.as_raw_socket(),
+        }
+    }
 }
 
 /// Represents the available commands in the IOAPI protocol.
 ///
 /// Each variant corresponds to a specific action that can be requested from the core service.
-#[derive(Debug, Clone)]
-#[repr(u8)]
+/// On the wire a command is a JSON object like `{"command": "disable_device", "args": "USB\\..."}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", content = "args", rename_all = "snake_case")]
 pub enum IoApiCommand {
+    /// Opens the session; must be the first command on every connection.
+    Handshake {
+        /// The protocol version the client speaks.
+        protocol_version: u32,
+    },
     /// Request a list of all connected devices.
-    GetDeviceList = 2,
+    GetDeviceList,
     /// Request to disable a specific device by its ID.
-    DisableDevice(DeviceId) = 3,
+    DisableDevice(DeviceId),
     /// Request to enable a specific device by its ID.
-    EnableDevice(DeviceId) = 4,
+    EnableDevice(DeviceId),
+    /// Approves a device waiting for an operator decision (policy action `ask`): a rule allowing
+    /// it is added to the policy, which then enables it.
+    ApproveDevice(DeviceId),
     /// Request the logs of device connection events.
-    GetDeviceConnectionLogs = 5,
+    GetDeviceConnectionLogs,
+    /// Turns the connection into an event stream; matching events are pushed as they happen.
+    ///
+    /// Sending `Subscribe` again replaces the filter of the connection.
+    Subscribe(EventFilter),
+    /// Request the active policy.
+    GetPolicy,
+    /// Replaces the active policy and writes it to the policy file.
+    SetPolicy(Policy),
+    /// Request the devices matching a query (in the text syntax of the `device_query` module).
+    QueryDevices(DeviceQuery),
 }
 
 impl IoApiCommand {
-    /// Returns the numeric operation code associated with the command.
-    fn cmd_code(&self) -> u8 {
+    /// Returns the name of the command as used on the wire.
+    pub fn name(&self) -> &'static str {
         match self {
-            Self::GetDeviceList => 2,
-            Self::GetDeviceConnectionLogs => 5,
-            Self::DisableDevice(_) => 3,
-            Self::EnableDevice(_) => 4,
+            IoApiCommand::Handshake { .. } => "handshake",
+            IoApiCommand::GetDeviceList => "get_device_list",
+            IoApiCommand::DisableDevice(_) => "disable_device",
+            IoApiCommand::EnableDevice(_) => "enable_device",
+            IoApiCommand::ApproveDevice(_) => "approve_device",
+            IoApiCommand::GetDeviceConnectionLogs => "get_device_connection_logs",
+            IoApiCommand::Subscribe(_) => "subscribe",
+            IoApiCommand::GetPolicy => "get_policy",
+            IoApiCommand::SetPolicy(_) => "set_policy",
+            IoApiCommand::QueryDevices(_) => "query_devices",
         }
     }
 }
@@ -66,71 +343,65 @@ impl TryFrom<&[&str]> for IoApiCommand {
     /// use comp_gate::helper::ioapi::IoApiCommand;
     /// use std::rc::Rc;
     ///
-    /// let tokens = ["disable_device", "USB\\VID_1234&PID_5678"];
+    /// let tokens = ["disable", "USB\\VID_1234&PID_5678"];
     /// let cmd = IoApiCommand::try_from(&tokens[..]).unwrap();
     ///
     /// if let IoApiCommand::DisableDevice(id) = cmd {
     ///     assert_eq!(id.as_ref(), "USB\\VID_1234&PID_5678");
     /// }
+    ///
+    /// // A missing device ID is an error.
+    /// assert!(IoApiCommand::try_from(&["disable"][..]).is_err());
+    /// assert!(IoApiCommand::try_from(&[] as &[&str]).is_err());
     /// ```
     fn try_from(cmd_tokens: &[&str]) -> Result<Self, Self::Error> {
-        match cmd_tokens[0] {
+        let device_id = || {
+            cmd_tokens
+                .get(1)
+                .map(|id| DeviceId::from(Rc::<str>::from(*id)))
+                .ok_or(())
+        };
+
+        match cmd_tokens.first().copied().ok_or(())? {
             "list" => Ok(IoApiCommand::GetDeviceList),
-            "disable" => Ok(IoApiCommand::DisableDevice(DeviceId::from(
-                Rc::<str>::from(cmd_tokens[1]),
-            ))),
-            "enable" => Ok(IoApiCommand::EnableDevice(DeviceId::from(Rc::<str>::from(
-                cmd_tokens[1],
-            )))),
+            "disable" => Ok(IoApiCommand::DisableDevice(device_id()?)),
+            "enable" => Ok(IoApiCommand::EnableDevice(device_id()?)),
+            "approve" => Ok(IoApiCommand::ApproveDevice(device_id()?)),
             "logs" => Ok(IoApiCommand::GetDeviceConnectionLogs),
+            "policy" => Ok(IoApiCommand::GetPolicy),
+            "subscribe" => Ok(IoApiCommand::Subscribe(EventFilter::try_from(
+                &cmd_tokens[1..],
+            )?)),
+            "query" => Ok(IoApiCommand::QueryDevices(
+                cmd_tokens[1..].join(" ").parse().map_err(|_| ())?,
+            )),
             _ => Err(()),
         }
     }
 }
 
-impl TryFrom<(u8, Vec<Rc<str>>)> for IoApiCommand {
-    type Error = ();
+impl TryFrom<&[u8]> for IoApiCommand {
+    type Error = IoApiError;
 
-    /// Tries to reconstruct a command from a raw opcode and a list of arguments.
-    fn try_from((code, args): (u8, Vec<Rc<str>>)) -> Result<Self, Self::Error> {
-        match code {
-            2 => Ok(IoApiCommand::GetDeviceList),
-            3 => Ok(IoApiCommand::DisableDevice(args[0].clone().into())),
-            4 => Ok(IoApiCommand::EnableDevice(args[0].clone().into())),
-            5 => Ok(IoApiCommand::GetDeviceConnectionLogs),
-            _ => Err(()),
-        }
+    /// Tries to decode a command from the JSON payload of a frame.
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        Ok(serde_json::from_slice(payload)?)
     }
 }
 
 /// A serialized request ready to be sent over the network.
 ///
-/// This struct wraps the raw byte representation of an `IoApiCommand`.
+/// This struct wraps the framed byte representation of an `IoApiCommand`.
 pub struct IoApiRequest(Rc<[u8]>);
 
 impl From<IoApiCommand> for IoApiRequest {
     /// Converts an `IoApiCommand` into a serialized `IoApiRequest`.
     ///
-    /// The serialization format is generally `[opcode, payload...]`.
+    /// The serialization format is `[4 bytes length (Big Endian)][JSON payload]`.
     fn from(value: IoApiCommand) -> Self {
-        let cmd_code = value.cmd_code();
-
-        let result_bytes = match value {
-            IoApiCommand::GetDeviceList | IoApiCommand::GetDeviceConnectionLogs => vec![cmd_code],
-            IoApiCommand::DisableDevice(id) | IoApiCommand::EnableDevice(id) => vec![cmd_code]
-                .into_iter()
-                .chain(id.as_bytes().to_vec())
-                .collect(),
-        };
-
-        let prefix_length: u32 = result_bytes.len() as u32;
-        let result_bytes: Vec<u8> = prefix_length
-            .to_be_bytes()
-            .into_iter()
-            .chain(result_bytes.into_iter())
-            .collect();
+        let payload = serde_json::to_vec(&value).expect("IoApiCommand always serializes to JSON");
 
-        Self(result_bytes.into())
+        Self(frame_payload(&payload).into())
     }
 }
 
@@ -142,33 +413,571 @@ impl Deref for IoApiRequest {
     }
 }
 
-/// Retrieves the socket address of the running core service.
+/// The status code carried by every `IoApiResponse`.
+///
+/// The numeric values follow the HTTP status codes with the same meaning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "u16", try_from = "u16")]
+#[repr(u16)]
+pub enum IoApiStatus {
+    /// The command was executed successfully.
+    Ok = 200,
+    /// The command could not be parsed.
+    BadRequest = 400,
+    /// The client has to authenticate first (see the `ioapi_auth` module).
+    Unauthorized = 401,
+    /// The device the command refers to does not exist.
+    NotFound = 404,
+    /// The client is not allowed to execute the command.
+    Forbidden = 403,
+    /// A command other than `Handshake` was sent before the handshake.
+    HandshakeRequired = 428,
+    /// The command failed inside the core.
+    InternalError = 500,
+    /// The core cannot serve the connection right now (e.g. too many open connections).
+    ServiceUnavailable = 503,
+    /// The client speaks a protocol version the core does not support.
+    VersionMismatch = 505,
+}
+
+impl From<IoApiStatus> for u16 {
+    fn from(status: IoApiStatus) -> Self {
+        status as u16
+    }
+}
+
+impl TryFrom<u16> for IoApiStatus {
+    type Error = String;
+
+    fn try_from(code: u16) -> Result<Self, Self::Error> {
+        match code {
+            200 => Ok(IoApiStatus::Ok),
+            400 => Ok(IoApiStatus::BadRequest),
+            401 => Ok(IoApiStatus::Unauthorized),
+            403 => Ok(IoApiStatus::Forbidden),
+            404 => Ok(IoApiStatus::NotFound),
+            428 => Ok(IoApiStatus::HandshakeRequired),
+            500 => Ok(IoApiStatus::InternalError),
+            503 => Ok(IoApiStatus::ServiceUnavailable),
+            505 => Ok(IoApiStatus::VersionMismatch),
+            _ => Err(format!("unknown IOAPI status code {}", code)),
+        }
+    }
+}
+
+/// The typed data returned by a command.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum IoApiResponseBody {
+    /// The answer to a `Handshake`, carrying the protocol version of the core.
+    Handshake {
+        /// The protocol version the core speaks.
+        protocol_version: u32,
+        /// The role the core assigned to the connection.
+        #[serde(default)]
+        role: Role,
+    },
+    /// The tree of connected devices, keyed by the root-level device IDs.
+    DeviceList {
+        /// The root-level devices and their sub-devices.
+        devices: HashMap<DeviceId, Device>,
+    },
+    /// The devices matching a query, ordered by device ID.
+    Devices {
+        /// The matching devices, without their sub-devices.
+        devices: Vec<Device>,
+    },
+    /// The device connection events recorded by the core.
+    DeviceConnectionLogs {
+        /// The log lines, oldest first.
+        logs: Vec<String>,
+    },
+    /// The challenge a TCP connection is opened with.
+    AuthChallenge {
+        /// The nonce the client has to answer with its `AuthResponse`.
+        nonce: String,
+    },
+    /// The answer to a successful `AuthResponse`, proving the core knows the secret too.
+    AuthProof {
+        /// The hex encoded HMAC-SHA256 of both nonces, keyed with the secret.
+        proof: String,
+    },
+    /// The active policy.
+    Policy {
+        /// The rules and default action in effect.
+        policy: Policy,
+    },
+    /// An event pushed to a subscribed connection.
+    Event(IoApiEvent),
+    /// The command produced no data.
+    Empty,
+}
+
+/// The kinds of events the core pushes to subscribed connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IoApiEventKind {
+    /// A device was connected.
+    Connected,
+    /// A device was disconnected.
+    Disconnected,
+    /// A device was disabled.
+    Blocked,
+    /// A device was enabled.
+    Unblocked,
+    /// A device could not be put into the state the policy decided on.
+    EnforcementFailed,
+    /// A device was disabled until an operator approves it (policy action `ask`).
+    PendingApproval,
+    /// The active policy changed.
+    PolicyChanged,
+    /// A client was refused a command its role does not permit.
+    AccessDenied,
+}
+
+impl IoApiEventKind {
+    /// Every event kind, in declaration order.
+    pub const ALL: [IoApiEventKind; 8] = [
+        IoApiEventKind::Connected,
+        IoApiEventKind::Disconnected,
+        IoApiEventKind::Blocked,
+        IoApiEventKind::Unblocked,
+        IoApiEventKind::EnforcementFailed,
+        IoApiEventKind::PendingApproval,
+        IoApiEventKind::PolicyChanged,
+        IoApiEventKind::AccessDenied,
+    ];
+
+    /// Returns the name of the kind as used on the wire.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IoApiEventKind::Connected => "connected",
+            IoApiEventKind::Disconnected => "disconnected",
+            IoApiEventKind::Blocked => "blocked",
+            IoApiEventKind::Unblocked => "unblocked",
+            IoApiEventKind::EnforcementFailed => "enforcement_failed",
+            IoApiEventKind::PendingApproval => "pending_approval",
+            IoApiEventKind::PolicyChanged => "policy_changed",
+            IoApiEventKind::AccessDenied => "access_denied",
+        }
+    }
+}
+
+impl Display for IoApiEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for IoApiEventKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        IoApiEventKind::ALL
+            .into_iter()
+            .find(|kind| kind.as_str() == s)
+            .ok_or(())
+    }
+}
+
+/// An event pushed by the core to subscribed connections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IoApiEvent {
+    /// What happened.
+    pub kind: IoApiEventKind,
+    /// The device the event refers to; `None` for events that concern no single device.
+    pub device_id: Option<DeviceId>,
+    /// A human readable description of the event.
+    pub message: String,
+    /// When the event happened, in seconds since the UNIX epoch.
+    pub timestamp: u64,
+}
+
+impl IoApiEvent {
+    /// Creates an event timestamped with the current time.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - What happened.
+    /// * `device_id` - The device the event refers to, if any.
+    /// * `message` - A human readable description of the event.
+    pub fn new(
+        kind: IoApiEventKind,
+        device_id: Option<DeviceId>,
+        message: impl Into<String>,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+
+        Self {
+            kind,
+            device_id,
+            message: message.into(),
+            timestamp,
+        }
+    }
+}
+
+impl Display for IoApiEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[{}] {}: {}", self.timestamp, self.kind, self.message)
+    }
+}
+
+/// Selects the events pushed to a subscribed connection.
+///
+/// An event matches if its kind is listed in `kinds` (an empty list matches every kind) and,
+/// when a `device_id_prefix` is set, if it refers to a device whose ID starts with the prefix.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::device_managment::DeviceId;
+/// use comp_gate::helper::ioapi::{EventFilter, IoApiEvent, IoApiEventKind};
+/// use std::rc::Rc;
+///
+/// let filter = EventFilter::try_from(&["connected", "USB\\VID_046D"][..]).unwrap();
+/// assert_eq!(filter.kinds, vec![IoApiEventKind::Connected]);
+///
+/// let device_id = DeviceId::from(Rc::<str>::from("USB\\VID_046D&PID_C52B\\1"));
+/// let event = IoApiEvent::new(IoApiEventKind::Connected, Some(device_id.clone()), "connected");
+/// assert!(filter.matches(&event));
+///
+/// let event = IoApiEvent::new(IoApiEventKind::Disconnected, Some(device_id), "disconnected");
+/// assert!(!filter.matches(&event));
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EventFilter {
+    /// The event kinds to push; empty means every kind.
+    #[serde(default)]
+    pub kinds: Vec<IoApiEventKind>,
+    /// Only push events of devices whose ID starts with this prefix.
+    #[serde(default)]
+    pub device_id_prefix: Option<String>,
+}
+
+impl EventFilter {
+    /// Returns whether an event passes the filter.
+    ///
+    /// Events without a device never match a filter with a `device_id_prefix`.
+    pub fn matches(&self, event: &IoApiEvent) -> bool {
+        if !self.kinds.is_empty() && !self.kinds.contains(&event.kind) {
+            return false;
+        }
+
+        match (&self.device_id_prefix, &event.device_id) {
+            (None, _) => true,
+            (Some(prefix), Some(device_id)) => device_id.starts_with(prefix.as_str()),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl TryFrom<&[&str]> for EventFilter {
+    type Error = ();
+
+    /// Parses a filter from string tokens.
+    ///
+    /// Tokens naming an `IoApiEventKind` select that kind; at most one other token is
+    /// accepted and used as the device ID prefix.
+    fn try_from(tokens: &[&str]) -> Result<Self, Self::Error> {
+        let mut filter = EventFilter::default();
+
+        for token in tokens.iter().filter(|token| !token.is_empty()) {
+            match token.parse::<IoApiEventKind>() {
+                Ok(kind) => filter.kinds.push(kind),
+                Err(_) if filter.device_id_prefix.is_none() => {
+                    filter.device_id_prefix = Some(token.to_string())
+                }
+                Err(_) => return Err(()),
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+/// A response sent by the core for every received command.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::ioapi::{IoApiResponse, IoApiResponseBody, IoApiStatus};
+///
+/// let response = IoApiResponse::error(IoApiStatus::NotFound, "Device USB\\1 does not exist");
+/// let frame = response.to_frame();
+///
+/// let decoded = IoApiResponse::try_from(&frame[4..]).unwrap();
+/// assert_eq!(decoded.status, IoApiStatus::NotFound);
+/// assert!(matches!(decoded.body, IoApiResponseBody::Empty));
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct IoApiResponse {
+    /// Whether (and how) the command succeeded.
+    pub status: IoApiStatus,
+    /// A human readable description of the outcome, if any.
+    pub message: Option<String>,
+    /// The data returned by the command.
+    pub body: IoApiResponseBody,
+}
+
+impl IoApiResponse {
+    /// Creates a successful response carrying the given data.
+    pub fn ok(body: IoApiResponseBody) -> Self {
+        Self {
+            status: IoApiStatus::Ok,
+            message: None,
+            body,
+        }
+    }
+
+    /// Creates a successful response without data.
+    pub fn ok_with_message(message: impl Into<String>) -> Self {
+        Self {
+            status: IoApiStatus::Ok,
+            message: Some(message.into()),
+            body: IoApiResponseBody::Empty,
+        }
+    }
+
+    /// Creates a failed response without data.
+    pub fn error(status: IoApiStatus, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: Some(message.into()),
+            body: IoApiResponseBody::Empty,
+        }
+    }
+
+    /// Returns whether the command succeeded.
+    pub fn is_ok(&self) -> bool {
+        self.status == IoApiStatus::Ok
+    }
+
+    /// Serializes the response into a length-prefixed frame.
+    pub fn to_frame(&self) -> Box<[u8]> {
+        let payload = serde_json::to_vec(self).expect("IoApiResponse always serializes to JSON");
+
+        frame_payload(&payload)
+    }
+}
+
+impl TryFrom<&[u8]> for IoApiResponse {
+    type Error = IoApiError;
+
+    /// Tries to decode a response from the JSON payload of a frame.
+    fn try_from(payload: &[u8]) -> Result<Self, Self::Error> {
+        Ok(serde_json::from_slice(payload)?)
+    }
+}
+
+/// Wraps a byte slice into a length-prefixed frame.
+///
+/// The format is `[4 bytes length (Big Endian)][payload]`.
+pub fn frame_payload(bytes: &[u8]) -> Box<[u8]> {
+    let length_prefix = (bytes.len() as u32).to_be_bytes();
+    [&length_prefix, bytes].concat().into_boxed_slice()
+}
+
+/// Reads one length-prefixed frame from a blocking stream and returns its payload.
+///
+/// # Returns
+///
+/// * `Ok(Vec<u8>)` - The payload of the frame.
+/// * `Err(IoApiError::FrameTooLarge)` - If the announced length exceeds `MAX_FRAME_LENGTH`.
+/// * `Err(IoApiError::IoError)` - If reading from the stream fails.
+pub fn read_frame(stream: &mut impl Read) -> Result<Vec<u8>, IoApiError> {
+    let mut prefix_buf = [0u8; 4];
+    stream.read_exact(&mut prefix_buf)?;
+
+    let length = u32::from_be_bytes(prefix_buf);
+    if length > MAX_FRAME_LENGTH {
+        return Err(IoApiError::FrameTooLarge(length));
+    }
+
+    let mut payload = vec![0u8; length as usize];
+    stream.read_exact(&mut payload)?;
+
+    Ok(payload)
+}
+
+/// Reassembles length-prefixed frames from the chunks a non-blocking stream delivers.
+///
+/// A read may return any part of a frame (or several frames at once); the decoder buffers the
+/// bytes until a frame is complete, so a partial read never desynchronizes the stream.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::ioapi::{FrameDecoder, frame_payload};
+///
+/// let frame = frame_payload(b"{\"command\":\"get_device_list\"}");
+/// let mut decoder = FrameDecoder::new();
+///
+/// decoder.extend(&frame[..3]);
+/// assert_eq!(decoder.next_frame().unwrap(), None);
+/// decoder.extend(&frame[3..10]);
+/// assert_eq!(decoder.next_frame().unwrap(), None);
+/// decoder.extend(&frame[10..]);
+/// assert_eq!(
+///     decoder.next_frame().unwrap().as_deref(),
+///     Some(&b"{\"command\":\"get_device_list\"}"[..])
+/// );
+/// assert!(decoder.is_empty());
+///
+/// // Oversized frames are rejected as soon as their length prefix arrives.
+/// decoder.extend(&u32::MAX.to_be_bytes());
+/// assert!(decoder.next_frame().is_err());
+/// ```
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    /// The bytes received but not yet returned as a frame.
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    /// Creates an empty decoder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends bytes received from the stream.
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns whether no bytes are buffered.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Returns whether a complete frame is buffered.
+    pub fn has_frame(&self) -> bool {
+        self.announced_length()
+            .is_some_and(|length| self.buffer.len() >= 4 + length as usize)
+    }
+
+    /// Removes the next complete frame from the buffer and returns its payload.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(Some(Vec<u8>))` - The payload of the next frame.
+    /// * `Ok(None)` - If the next frame is not complete yet.
+    /// * `Err(IoApiError::FrameTooLarge)` - If the announced length exceeds `MAX_FRAME_LENGTH`.
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, IoApiError> {
+        let Some(length) = self.announced_length() else {
+            return Ok(None);
+        };
+        if length > MAX_FRAME_LENGTH {
+            return Err(IoApiError::FrameTooLarge(length));
+        }
+        if !self.has_frame() {
+            return Ok(None);
+        }
+
+        let payload = self.buffer[4..4 + length as usize].to_vec();
+        self.buffer.drain(..4 + length as usize);
+
+        Ok(Some(payload))
+    }
+
+    /// Returns the length announced by the prefix of the next frame, once it was received.
+    fn announced_length(&self) -> Option<u32> {
+        let prefix: [u8; 4] = self.buffer.get(..4)?.try_into().ok()?;
+        Some(u32::from_be_bytes(prefix))
+    }
+}
+
+/// Sends a command over a blocking stream and waits for its response.
 ///
-/// This function reads the connection file (located in the OS temporary directory)
-/// to find the IP and port where the core service is listening.
+/// # Arguments
+///
+/// * `stream` - The connection to the core service.
+/// * `cmd` - The command to send.
+pub fn send_command(
+    stream: &mut (impl Read + Write),
+    cmd: IoApiCommand,
+) -> Result<IoApiResponse, IoApiError> {
+    let request: IoApiRequest = cmd.into();
+    stream.write_all(&request)?;
+
+    let payload = read_frame(stream)?;
+    IoApiResponse::try_from(payload.as_slice())
+}
+
+/// Waits for the next event pushed to a subscribed connection.
+///
+/// Frames that do not carry an event (e.g. the response to the `Subscribe` command) are skipped.
+pub fn read_event(stream: &mut impl Read) -> Result<IoApiEvent, IoApiError> {
+    loop {
+        let payload = read_frame(stream)?;
+        if let IoApiResponseBody::Event(event) = IoApiResponse::try_from(payload.as_slice())?.body {
+            return Ok(event);
+        }
+    }
+}
+
+/// Performs the protocol handshake on a freshly opened connection.
 ///
 /// # Returns
 ///
-/// * `Ok(SocketAddr)` - The address of the core service.
-/// * `Err(anyhow::Error)` - If the file cannot be read or parsed.
-pub fn get_core_connection_addr() -> anyhow::Result<SocketAddr> {
+/// * `Ok(u32)` - The protocol version of the core.
+/// * `Err(IoApiError::HandshakeRejected)` - If the core refused the handshake.
+pub fn perform_handshake(stream: &mut (impl Read + Write)) -> Result<u32, IoApiError> {
+    let response = send_command(
+        stream,
+        IoApiCommand::Handshake {
+            protocol_version: PROTOCOL_VERSION,
+        },
+    )?;
+
+    match (response.status, response.body) {
+        (
+            IoApiStatus::Ok,
+            IoApiResponseBody::Handshake {
+                protocol_version, ..
+            },
+        ) => Ok(protocol_version),
+        (status, _) => Err(IoApiError::HandshakeRejected(
+            status,
+            response.message.unwrap_or_default(),
+        )),
+    }
+}
+
+/// Retrieves the addresses of the running core service, in order of preference.
+///
+/// This function reads the connection file (located in the runtime directory), which
+/// lists one `CoreAddress` per line. If there is no connection file, the default IOAPI socket
+/// is assumed (on Linux).
+///
+/// # Returns
+///
+/// * `Ok(Vec<CoreAddress>)` - The addresses of the core service.
+/// * `Err(IoApiError)` - If the file cannot be read or parsed.
+pub fn get_core_connection_addrs() -> Result<Vec<CoreAddress>, IoApiError> {
     let path = connection_file_path();
-    let content = std::fs::read_to_string(&path)?;
-    let first_line = content
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && cfg!(target_os = "linux") => {
+            return Ok(vec![CoreAddress::Unix(ioapi_socket_path())]);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let addresses = content
         .lines()
-        .find(|l| !l.trim().is_empty())
-        .ok_or_else(|| anyhow::anyhow!("Connection file is empty"))?
-        .trim()
-        .to_string();
-
-    let mut parts = first_line.split(':');
-    let ip_str = parts
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("Malformed address"))?;
-    let port_str = parts
-        .next()
-        .ok_or_else(|| anyhow::anyhow!("Malformed address"))?;
-    let port = port_str.parse::<u16>()?;
-
-    Ok(SocketAddr::new(ip_str.parse()?, port))
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.trim().parse())
+        .collect::<Result<Vec<CoreAddress>, _>>()?;
+    if addresses.is_empty() {
+        return Err(IoApiError::IoError(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The connection file {} is empty", path.display()),
+        )));
+    }
+
+    Ok(addresses)
 }
@@ -0,0 +1,349 @@
+//! # IOAPI Access Module
+//!
+//! This module assigns a `Role` to every IOAPI client and decides which commands the role may
+//! run:
+//! - `read-only`: Query the device list, the connection logs and the policy, and subscribe to
+//!   events.
+//! - `operator`: Additionally enable and disable devices, and approve devices waiting for an
+//!   operator decision.
+//! - `admin`: Additionally replace the policy.
+//!
+//! The roles are read from a TOML file:
+//!
+//! ```toml
+//! # Also listen on localhost TCP (always on where there are no Unix domain sockets).
+//! tcp = false
+//! # The role of clients that are not listed below.
+//! default_role = "read-only"
+//!
+//! [users]
+//! alice = "operator"
+//! 1001 = "admin"
+//!
+//! [groups]
+//! comp-gate = "operator"
+//! ```
+//!
+//! Users and groups can be given by name or by numeric ID. A client is identified by the
+//! credentials of its Unix domain socket connection, including the supplementary groups of its
+//! process (as the kernel recorded them when it connected), and gets the highest role assigned
+//! to its user, any of its groups or the default role. `root` and the user the core runs as are
+//! always admins.
+//!
+//! TCP clients authenticate with the secret of the core (see the `ioapi_auth` module), which
+//! only the user the core runs as can read, so they are admins as well. TCP clients that did not
+//! authenticate (only possible with an `IoApiServer` without secret) get the default role.
+//!
+//! On Windows TCP is the only transport, and the token file is readable by SYSTEM and the
+//! Administrators only. Every client of the core is therefore an administrator and gets the
+//! `admin` role; the `users`, `groups` and `default_role` settings only take effect for Unix
+//! domain socket clients.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::IoApiAccessError,
+    helper::{
+        ioapi::IoApiCommand,
+        ioapi_server::{PeerCredentials, PeerIdentity},
+    },
+};
+
+/// The environment variable overriding the location of the IOAPI access file.
+pub const IOAPI_ACCESS_ENV: &str = "COMP_GATE_IOAPI_ACCESS";
+
+/// Returns the location of the IOAPI access file.
+///
+/// The location can be overridden with the `COMP_GATE_IOAPI_ACCESS` environment variable.
+/// Otherwise it is:
+/// - On Windows: `%ProgramData%\comp-gate\ioapi.toml`
+/// - Elsewhere: `/etc/comp-gate/ioapi.toml`
+pub fn ioapi_access_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(IOAPI_ACCESS_ENV) {
+        return PathBuf::from(path);
+    }
+
+    if cfg!(windows) {
+        let program_data =
+            std::env::var_os("ProgramData").unwrap_or_else(|| r"C:\ProgramData".into());
+        PathBuf::from(program_data)
+            .join("comp-gate")
+            .join("ioapi.toml")
+    } else {
+        PathBuf::from("/etc/comp-gate/ioapi.toml")
+    }
+}
+
+/// The permission level of an IOAPI client. Every role includes the permissions of the roles
+/// before it.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// May query the core and subscribe to events.
+    #[default]
+    ReadOnly,
+    /// May additionally enable and disable devices.
+    Operator,
+    /// May additionally replace the policy.
+    Admin,
+}
+
+impl Role {
+    /// Returns the role a command requires.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command to run.
+    pub fn required_for(command: &IoApiCommand) -> Role {
+        match command {
+            IoApiCommand::Handshake { .. }
+            | IoApiCommand::GetDeviceList
+            | IoApiCommand::GetDeviceConnectionLogs
+            | IoApiCommand::GetPolicy
+            | IoApiCommand::Subscribe(_)
+            | IoApiCommand::QueryDevices(_) => Role::ReadOnly,
+            IoApiCommand::EnableDevice(_)
+            | IoApiCommand::DisableDevice(_)
+            | IoApiCommand::ApproveDevice(_) => Role::Operator,
+            IoApiCommand::SetPolicy(_) => Role::Admin,
+        }
+    }
+
+    /// Checks whether the role may run a command.
+    ///
+    /// # Arguments
+    ///
+    /// * `command` - The command to run.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(())` - If the role may run the command.
+    /// * `Err(IoApiAccessError::PermissionDenied)` - If the command requires a higher role.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::ioapi::IoApiCommand;
+    /// use comp_gate::helper::ioapi_access::Role;
+    /// use std::rc::Rc;
+    ///
+    /// let disable = IoApiCommand::DisableDevice(Rc::<str>::from("1-1").into());
+    /// assert!(Role::ReadOnly.authorize(&IoApiCommand::GetDeviceList).is_ok());
+    /// assert!(Role::ReadOnly.authorize(&disable).is_err());
+    /// assert!(Role::Operator.authorize(&disable).is_ok());
+    /// assert!(Role::Operator.authorize(&IoApiCommand::GetPolicy).is_ok());
+    /// ```
+    pub fn authorize(self, command: &IoApiCommand) -> Result<(), IoApiAccessError> {
+        let required = Role::required_for(command);
+        if self >= required {
+            Ok(())
+        } else {
+            Err(IoApiAccessError::PermissionDenied {
+                command: command.name(),
+                required,
+                role: self,
+            })
+        }
+    }
+
+    /// Returns the name of the role as used in the access file.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::ReadOnly => "read-only",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The access settings of the IOAPI.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::ioapi_access::{IoApiAccess, Role};
+/// use comp_gate::helper::ioapi_server::{PeerCredentials, PeerIdentity};
+///
+/// let access: IoApiAccess = toml::from_str(
+///     "default_role = \"read-only\"\n\
+///      [users]\n\
+///      1001 = \"operator\"\n\
+///      [groups]\n\
+///      4242 = \"admin\"\n",
+/// )
+/// .unwrap();
+///
+/// let client = |uid, gid, groups: &[u32]| {
+///     PeerIdentity::Unix(PeerCredentials { uid, gid, groups: groups.to_vec(), pid: None })
+/// };
+/// assert_eq!(access.role_of(&client(1001, 65534, &[])), Role::Operator);
+/// assert_eq!(access.role_of(&client(1002, 4242, &[])), Role::Admin);
+/// assert_eq!(access.role_of(&client(1002, 65534, &[100, 4242])), Role::Admin);
+/// assert_eq!(access.role_of(&client(1002, 65534, &[100])), Role::ReadOnly);
+/// assert_eq!(access.role_of(&client(0, 0, &[])), Role::Admin);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IoApiAccess {
+    /// Whether the core also listens on localhost TCP.
+    #[serde(default)]
+    pub tcp: bool,
+    /// The role of clients that are not listed.
+    #[serde(default)]
+    pub default_role: Role,
+    /// The roles of users, keyed by user name or ID.
+    #[serde(default)]
+    pub users: BTreeMap<String, Role>,
+    /// The roles of the members of groups, keyed by group name or ID.
+    #[serde(default)]
+    pub groups: BTreeMap<String, Role>,
+}
+
+impl IoApiAccess {
+    /// Loads the access settings from a file.
+    ///
+    /// A missing file yields the default settings: no TCP listener, and only `root` and the
+    /// user of the core have more than the default role.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The location of the access file.
+    pub fn load(path: &Path) -> Result<Self, IoApiAccessError> {
+        match std::fs::read_to_string(path) {
+            Ok(source) => Ok(toml::from_str(&source)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Returns whether the core listens on localhost TCP.
+    ///
+    /// TCP is always used on platforms without Unix domain socket support.
+    pub fn tcp_enabled(&self) -> bool {
+        self.tcp || !cfg!(target_os = "linux")
+    }
+
+    /// Returns the role of a client.
+    ///
+    /// # Arguments
+    ///
+    /// * `peer` - The identity of the client.
+    pub fn role_of(&self, peer: &PeerIdentity) -> Role {
+        match peer {
+            PeerIdentity::Tcp(_) => self.default_role,
+            PeerIdentity::Token(_) => Role::Admin,
+            PeerIdentity::Unix(credentials) => self.role_of_user(credentials),
+        }
+    }
+
+    /// Returns the highest role assigned to the user or one of the groups of a Unix domain
+    /// socket client.
+    fn role_of_user(&self, credentials: &PeerCredentials) -> Role {
+        if credentials.uid == 0 || Some(credentials.uid) == sys::current_uid() {
+            return Role::Admin;
+        }
+
+        let user_roles = self.users.iter().filter_map(|(user, role)| {
+            let uid = user.parse().ok().or_else(|| sys::user_id(user));
+            (uid == Some(credentials.uid)).then_some(*role)
+        });
+
+        let group_roles = self.groups.iter().filter_map(|(group, role)| {
+            let gid = group.parse().ok().or_else(|| sys::group_id(group));
+            gid.is_some_and(|gid| gid == credentials.gid || credentials.groups.contains(&gid))
+                .then_some(*role)
+        });
+
+        user_roles
+            .chain(group_roles)
+            .fold(self.default_role, Role::max)
+    }
+}
+
+/// Resolving users and groups (Linux).
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::CString;
+
+    /// The buffer size for the strings of `getpwnam_r`/`getgrnam_r` entries.
+    const NAME_BUFFER_SIZE: usize = 16 * 1024;
+
+    /// Returns the effective user ID of the current process.
+    pub fn current_uid() -> Option<u32> {
+        // SAFETY: `geteuid` has no preconditions and cannot fail.
+        Some(unsafe { libc::geteuid() })
+    }
+
+    /// Looks up the ID of a user by name.
+    pub fn user_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        // SAFETY: An all-zero passwd structure is a valid (empty) value.
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::passwd = std::ptr::null_mut();
+        let mut buffer = vec![0 as libc::c_char; NAME_BUFFER_SIZE];
+
+        // SAFETY: All pointers are valid for the duration of the call.
+        let error = unsafe {
+            libc::getpwnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+
+        (error == 0 && !result.is_null()).then_some(entry.pw_uid)
+    }
+
+    /// Looks up the ID of a group by name.
+    pub fn group_id(name: &str) -> Option<u32> {
+        let name = CString::new(name).ok()?;
+        // SAFETY: An all-zero group structure is a valid (empty) value.
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut result: *mut libc::group = std::ptr::null_mut();
+        let mut buffer = vec![0 as libc::c_char; NAME_BUFFER_SIZE];
+
+        // SAFETY: All pointers are valid for the duration of the call.
+        let error = unsafe {
+            libc::getgrnam_r(
+                name.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+
+        (error == 0 && !result.is_null()).then_some(entry.gr_gid)
+    }
+}
+
+/// Resolving users and groups (unsupported; Unix domain socket clients only exist on Linux).
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    pub fn current_uid() -> Option<u32> {
+        None
+    }
+
+    pub fn user_id(_name: &str) -> Option<u32> {
+        None
+    }
+
+    pub fn group_id(_name: &str) -> Option<u32> {
+        None
+    }
+}
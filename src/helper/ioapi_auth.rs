@@ -0,0 +1,515 @@
+//! # IOAPI Authentication Module
+//!
+//! This module implements the challenge-response handshake that authenticates TCP clients of
+//! the IOAPI.
+//!
+//! The address of the core is published in the connection file, so any local user can connect
+//! to its TCP listener. To tell legitimate clients apart, the core generates a random
+//! `AuthSecret` at startup and writes it to the token file (`ioapi_token_path`), which only the
+//! user running the core (and, on Windows, the Administrators) can read. Both sides prove they
+//! know the secret without sending it:
+//! 1. The core opens every TCP connection with an `Unauthorized` response carrying a random
+//!    nonce (`IoApiResponseBody::AuthChallenge`).
+//! 2. The client answers with an `AuthResponse` frame holding the HMAC-SHA256 of the nonce,
+//!    keyed with the secret, and a nonce of its own.
+//! 3. The core confirms with an `Ok` response carrying its proof: the HMAC-SHA256 of both nonces
+//!    (`IoApiResponseBody::AuthProof`). Otherwise it drops the connection. No `IoApiCommand` is
+//!    parsed before the handshake succeeded.
+//! 4. The client checks the proof, so a process that took over the port of a stopped core
+//!    cannot pose as the core.
+//!
+//! Both the token file and the connection file live in the runtime directory (`runtime_dir`),
+//! which only the user running the core can write to (see `prepare_runtime_dir`).
+//!
+//! Unix domain socket connections skip the handshake; they are authenticated by the
+//! credentials the kernel reports for them (see `PeerIdentity`).
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::IoApiError,
+    helper::ioapi::{
+        IoApiResponse, IoApiResponseBody, IoApiStatus, frame_payload, read_frame, runtime_dir,
+    },
+};
+
+/// The environment variable overriding the location of the token file.
+pub const IOAPI_TOKEN_ENV: &str = "COMP_GATE_IOAPI_TOKEN";
+
+/// The length of the secret and of the nonces, in bytes.
+const SECRET_LENGTH: usize = 32;
+
+/// Returns the location of the token file holding the secret of the running core.
+///
+/// The location can be overridden with the `COMP_GATE_IOAPI_TOKEN` environment variable;
+/// otherwise the file sits next to the connection file (`comp-gate.token` in the runtime
+/// directory).
+pub fn ioapi_token_path() -> PathBuf {
+    match std::env::var_os(IOAPI_TOKEN_ENV) {
+        Some(path) => PathBuf::from(path),
+        None => runtime_dir().join("comp-gate.token"),
+    }
+}
+
+/// Creates the runtime directory and makes sure only the user running the core can write to it.
+///
+/// The token file and the connection file are trusted because of where they are; a directory
+/// another user created or can write to would let them plant or replace either file. So:
+/// - On Unix the directory has to be owned by the current user; its mode is set to `0755`, as
+///   the IOAPI socket in it has to stay reachable for every user.
+/// - On Windows the directory is owned by the Administrators; SYSTEM and the Administrators have
+///   full access, other users may only read (the token file gets its own, stricter ACL).
+///
+/// # Arguments
+///
+/// * `path` - The runtime directory (see `runtime_dir`).
+pub fn prepare_runtime_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir_all(path)?;
+    sys::restrict_directory(path)
+}
+
+/// Replaces a file in the runtime directory.
+///
+/// An existing file is removed first rather than overwritten, so a file planted by another user
+/// (with permissions of their choosing) is never reused.
+///
+/// # Arguments
+///
+/// * `path` - The location of the file.
+/// * `contents` - The new contents of the file.
+/// * `private` - Whether only the user running the core (and, on Windows, the Administrators)
+///   may read the file.
+pub fn replace_runtime_file(path: &Path, contents: &[u8], private: bool) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, if private { 0o600 } else { 0o644 });
+
+    let mut file: File = options.open(path)?;
+    if private {
+        sys::restrict_file(path)?;
+    }
+    file.write_all(contents)
+}
+
+/// The frame a client answers an authentication challenge with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuthResponse {
+    /// The hex encoded HMAC-SHA256 of the nonce, keyed with the secret.
+    pub response: String,
+    /// The nonce the core has to answer with its proof (`IoApiResponseBody::AuthProof`).
+    pub nonce: String,
+}
+
+/// The secret shared by the core and its clients through the token file.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::ioapi_auth::{AuthSecret, generate_nonce};
+///
+/// let secret = AuthSecret::generate().unwrap();
+/// let nonce = generate_nonce().unwrap();
+///
+/// let response = secret.respond(&nonce);
+/// assert!(secret.verify(&nonce, &response));
+/// assert!(!AuthSecret::generate().unwrap().verify(&nonce, &response));
+///
+/// // The proof of the core covers the nonces of both sides and differs from any response.
+/// let client_nonce = generate_nonce().unwrap();
+/// let proof = secret.prove(&nonce, &client_nonce);
+/// assert!(secret.verify_proof(&nonce, &client_nonce, &proof));
+/// assert!(!secret.verify_proof(&client_nonce, &nonce, &proof));
+/// assert!(!secret.verify(&nonce, &proof));
+///
+/// let path = std::env::temp_dir().join("comp-gate-doctest.token");
+/// secret.write(&path).unwrap();
+/// assert_eq!(AuthSecret::read(&path).unwrap().respond(&nonce), response);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+#[derive(Clone, PartialEq, Eq)]
+pub struct AuthSecret([u8; SECRET_LENGTH]);
+
+impl AuthSecret {
+    /// Generates a new random secret.
+    pub fn generate() -> io::Result<Self> {
+        let mut secret = [0u8; SECRET_LENGTH];
+        sys::random_bytes(&mut secret)?;
+        Ok(Self(secret))
+    }
+
+    /// Reads the secret from a token file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The location of the token file.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let secret = decode_hex(contents.trim())
+            .and_then(|bytes| <[u8; SECRET_LENGTH]>::try_from(bytes).ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is not a valid IOAPI token file", path.display()),
+                )
+            })?;
+
+        Ok(Self(secret))
+    }
+
+    /// Writes the secret to a token file that only the current user (and, on Windows, the
+    /// Administrators) can read.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The location of the token file.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        replace_runtime_file(path, encode_hex(&self.0).as_bytes(), true)
+    }
+
+    /// Computes the response to a challenge.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonce` - The nonce sent by the core.
+    pub fn respond(&self, nonce: &str) -> String {
+        encode_hex(&hmac_sha256(&self.0, nonce.as_bytes()))
+    }
+
+    /// Checks the response of a client to a challenge, in constant time.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonce` - The nonce sent to the client.
+    /// * `response` - The response of the client.
+    pub fn verify(&self, nonce: &str, response: &str) -> bool {
+        constant_time_eq(&self.respond(nonce), response)
+    }
+
+    /// Computes the proof the core answers a successful `AuthResponse` with.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonce` - The nonce the core sent.
+    /// * `client_nonce` - The nonce the client sent.
+    pub fn prove(&self, nonce: &str, client_nonce: &str) -> String {
+        let message = format!("core:{}:{}", nonce, client_nonce);
+        encode_hex(&hmac_sha256(&self.0, message.as_bytes()))
+    }
+
+    /// Checks the proof of the core, in constant time.
+    ///
+    /// # Arguments
+    ///
+    /// * `nonce` - The nonce the core sent.
+    /// * `client_nonce` - The nonce the client sent.
+    /// * `proof` - The proof of the core.
+    pub fn verify_proof(&self, nonce: &str, client_nonce: &str, proof: &str) -> bool {
+        constant_time_eq(&self.prove(nonce, client_nonce), proof)
+    }
+}
+
+impl std::fmt::Debug for AuthSecret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("AuthSecret(..)")
+    }
+}
+
+/// Generates a random, hex encoded nonce for a challenge.
+pub fn generate_nonce() -> io::Result<String> {
+    let mut nonce = [0u8; SECRET_LENGTH];
+    sys::random_bytes(&mut nonce)?;
+    Ok(encode_hex(&nonce))
+}
+
+/// Answers the authentication challenge the core opens a TCP connection with, and checks the
+/// proof the core answers with.
+///
+/// # Arguments
+///
+/// * `stream` - A freshly opened TCP connection to the core.
+/// * `secret` - The secret read from the token file.
+///
+/// # Returns
+///
+/// * `Ok(())` - If the core accepted the response and proved it knows the secret.
+/// * `Err(IoApiError::AuthenticationFailed)` - If the core did not send a challenge, rejected
+///   the response or did not prove it knows the secret.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::error::IoApiError;
+/// use comp_gate::helper::ioapi::{IoApiResponse, IoApiResponseBody, IoApiStatus, read_frame};
+/// use comp_gate::helper::ioapi_auth::{AuthSecret, authenticate};
+/// use std::io::Write;
+/// use std::net::{Ipv4Addr, TcpListener, TcpStream};
+///
+/// // A process posing as the core, which accepts any response but does not know the secret.
+/// let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+/// let address = listener.local_addr().unwrap();
+/// std::thread::spawn(move || {
+///     let (mut stream, _) = listener.accept().unwrap();
+///     let challenge = IoApiResponse {
+///         status: IoApiStatus::Unauthorized,
+///         message: None,
+///         body: IoApiResponseBody::AuthChallenge { nonce: "00".repeat(32) },
+///     };
+///     stream.write_all(&challenge.to_frame()).unwrap();
+///     read_frame(&mut stream).unwrap();
+///     let proof = IoApiResponseBody::AuthProof { proof: "00".repeat(32) };
+///     stream.write_all(&IoApiResponse::ok(proof).to_frame()).unwrap();
+/// });
+///
+/// let mut stream = TcpStream::connect(address).unwrap();
+/// let result = authenticate(&mut stream, &AuthSecret::generate().unwrap());
+/// assert!(matches!(result, Err(IoApiError::AuthenticationFailed(IoApiStatus::Ok, _))));
+/// ```
+pub fn authenticate(
+    stream: &mut (impl Read + Write),
+    secret: &AuthSecret,
+) -> Result<(), IoApiError> {
+    let challenge = IoApiResponse::try_from(read_frame(stream)?.as_slice())?;
+    let IoApiResponseBody::AuthChallenge { nonce } = challenge.body else {
+        return Err(IoApiError::AuthenticationFailed(
+            challenge.status,
+            challenge
+                .message
+                .unwrap_or_else(|| "The core did not send a challenge".to_string()),
+        ));
+    };
+
+    let client_nonce = generate_nonce()?;
+    let response = AuthResponse {
+        response: secret.respond(&nonce),
+        nonce: client_nonce.clone(),
+    };
+    let payload = serde_json::to_vec(&response).expect("AuthResponse always serializes to JSON");
+    stream.write_all(&frame_payload(&payload))?;
+
+    let result = match read_frame(stream) {
+        Ok(payload) => IoApiResponse::try_from(payload.as_slice())?,
+        // The core drops connections that fail the handshake.
+        Err(IoApiError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            return Err(IoApiError::AuthenticationFailed(
+                IoApiStatus::Unauthorized,
+                "The core closed the connection".to_string(),
+            ));
+        }
+        Err(e) => return Err(e),
+    };
+    match (result.status, result.body) {
+        (IoApiStatus::Ok, IoApiResponseBody::AuthProof { proof })
+            if secret.verify_proof(&nonce, &client_nonce, &proof) =>
+        {
+            Ok(())
+        }
+        (IoApiStatus::Ok, _) => Err(IoApiError::AuthenticationFailed(
+            IoApiStatus::Ok,
+            "The core did not prove it knows the secret".to_string(),
+        )),
+        (status, _) => Err(IoApiError::AuthenticationFailed(
+            status,
+            result.message.unwrap_or_default(),
+        )),
+    }
+}
+
+/// Compares two strings in constant time.
+fn constant_time_eq(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0u8, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+/// Computes the HMAC-SHA256 (RFC 2104) of a message.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner = Sha256::new()
+        .chain_update(block.map(|byte| byte ^ 0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(block.map(|byte| byte ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+/// Encodes bytes as lowercase hex.
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes hex, returning `None` if the input is not valid hex.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Reading random bytes from and securing files with the operating system (Unix).
+#[cfg(unix)]
+mod sys {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    pub fn random_bytes(buffer: &mut [u8]) -> io::Result<()> {
+        File::open("/dev/urandom")?.read_exact(buffer)
+    }
+
+    pub fn restrict_directory(path: &Path) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(path)?;
+        #[cfg(target_os = "linux")]
+        // SAFETY: geteuid cannot fail.
+        let owned = metadata.uid() == unsafe { libc::geteuid() };
+        // Without libc the owner cannot be checked; only the mode is enforced.
+        #[cfg(not(target_os = "linux"))]
+        let owned = true;
+
+        if !metadata.is_dir() || !owned {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "{} is not a directory owned by the user running the core",
+                    path.display()
+                ),
+            ));
+        }
+
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))
+    }
+
+    pub fn restrict_file(_path: &Path) -> io::Result<()> {
+        // The mode the file was created with already keeps other users out.
+        Ok(())
+    }
+}
+
+/// Reading random bytes from and securing files with the operating system (Windows).
+#[cfg(windows)]
+mod sys {
+    use super::*;
+    use std::os::windows::ffi::OsStrExt;
+    use windows_sys::Win32::{
+        Foundation::LocalFree,
+        Security::{
+            Authorization::{
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1,
+            },
+            Cryptography::{BCRYPT_USE_SYSTEM_PREFERRED_RNG, BCryptGenRandom},
+            DACL_SECURITY_INFORMATION, OWNER_SECURITY_INFORMATION,
+            PROTECTED_DACL_SECURITY_INFORMATION, PSECURITY_DESCRIPTOR, SetFileSecurityW,
+        },
+    };
+
+    /// Owned by the Administrators; full access for SYSTEM and the Administrators, read access
+    /// for the other users. Inherited by files created in the directory.
+    const DIRECTORY_SDDL: &str = "O:BAD:P(A;OICI;FA;;;SY)(A;OICI;FA;;;BA)(A;OICI;FR;;;BU)";
+    /// Owned by the Administrators; full access for SYSTEM and the Administrators only.
+    const PRIVATE_FILE_SDDL: &str = "O:BAD:P(A;;FA;;;SY)(A;;FA;;;BA)";
+
+    pub fn random_bytes(buffer: &mut [u8]) -> io::Result<()> {
+        // SAFETY: The buffer is valid for writes of its length.
+        let status = unsafe {
+            BCryptGenRandom(
+                std::ptr::null_mut(),
+                buffer.as_mut_ptr(),
+                buffer.len() as u32,
+                BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+            )
+        };
+
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!(
+                "BCryptGenRandom failed with status {:#x}",
+                status
+            )))
+        }
+    }
+    pub fn restrict_directory(path: &Path) -> io::Result<()> {
+        set_security(path, DIRECTORY_SDDL)
+    }
+
+    pub fn restrict_file(path: &Path) -> io::Result<()> {
+        set_security(path, PRIVATE_FILE_SDDL)
+    }
+
+    /// Replaces the owner and the access control list of a file or directory.
+    fn set_security(path: &Path, sddl: &str) -> io::Result<()> {
+        let sddl: Vec<u16> = sddl.encode_utf16().chain(std::iter::once(0)).collect();
+        let path: Vec<u16> = path
+            .as_os_str()
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect();
+
+        let mut descriptor: PSECURITY_DESCRIPTOR = std::ptr::null_mut();
+        // SAFETY: The string is null-terminated; the descriptor is freed below.
+        let converted = unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1,
+                &mut descriptor,
+                std::ptr::null_mut(),
+            )
+        };
+        if converted == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: The path is null-terminated and the descriptor valid.
+        let applied = unsafe {
+            SetFileSecurityW(
+                path.as_ptr(),
+                OWNER_SECURITY_INFORMATION
+                    | DACL_SECURITY_INFORMATION
+                    | PROTECTED_DACL_SECURITY_INFORMATION,
+                descriptor,
+            )
+        };
+        let result = if applied == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        };
+
+        // SAFETY: The descriptor was allocated with LocalAlloc by the conversion.
+        unsafe { LocalFree(descriptor) };
+        result
+    }
+}
@@ -0,0 +1,1108 @@
+//! # IOAPI Server Module
+//!
+//! This module implements the transport side of the IOAPI: an event-driven server that
+//! multiplexes any number of client connections on a single thread.
+//!
+//! The server accepts connections from any number of `IoApiListener`s (TCP and, on Linux, Unix
+//! domain sockets) and reports the `PeerIdentity` of every client: its address for TCP, its
+//! user and groups (read with `SO_PEERCRED` and `SO_PEERGROUPS`) for Unix domain sockets.
+//!
+//! With an `AuthSecret` (`IoApiServer::with_auth_secret`), TCP clients have to pass the
+//! challenge-response handshake of the `ioapi_auth` module first. Their frames are not parsed as
+//! commands before, and their connections are only reported (`ServerEvent::Connected`) once the
+//! handshake succeeded; clients that fail it, or do not complete it within the handshake timeout
+//! (`HANDSHAKE_TIMEOUT`), are dropped.
+//!
+//! Besides the overall connection limit, the server limits the connections per peer
+//! (`IoApiServer::with_max_connections_per_peer`): per user for Unix domain sockets, and for TCP
+//! clients that did not authenticate yet as a whole, as they cannot be told apart. A single user
+//! or a flood of unauthenticated connections therefore cannot take up every slot.
+//!
+//! `IoApiServer::poll` blocks until a socket becomes ready, an `IoApiWaker` is woken (e.g. by
+//! the USB connection listener) or the timeout expires, so the core does not burn CPU while
+//! nothing happens. It then:
+//! - Accepts new connections, refusing them with `ServiceUnavailable` beyond the connection limit.
+//! - Reads whatever bytes are available into a per-connection `FrameDecoder`, so commands split
+//!   over several reads are reassembled instead of desynchronizing the stream.
+//! - Writes queued responses and events as far as the sockets accept them.
+//!
+//! ## Backpressure
+//!
+//! Every connection has an output queue. At most one command per connection is handed out per
+//! `poll`, and none while the output queue of the connection is above
+//! `OUTPUT_HIGH_WATER_MARK`; a client that does not read its responses therefore stops being
+//! served instead of making the core buffer without bound. Pushed events are queued regardless,
+//! but a connection whose queue grows beyond `MAX_PENDING_OUTPUT` is dropped as too slow.
+
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, UdpSocket},
+    time::{Duration, Instant},
+};
+#[cfg(target_os = "linux")]
+use std::{
+    os::unix::{
+        fs::PermissionsExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::Path,
+};
+
+use crate::{
+    error::IoApiError,
+    helper::{
+        ioapi::{
+            CoreAddress, FrameDecoder, IoApiCommand, IoApiResponse, IoApiResponseBody, IoApiStatus,
+            IoApiStream, MAX_FRAME_LENGTH,
+        },
+        ioapi_auth::{AuthResponse, AuthSecret, generate_nonce},
+    },
+};
+
+/// The queued output above which no further commands are taken from a connection.
+pub const OUTPUT_HIGH_WATER_MARK: usize = 1024 * 1024;
+
+/// The queued output above which a connection is dropped.
+pub const MAX_PENDING_OUTPUT: usize = 2 * MAX_FRAME_LENGTH as usize;
+
+/// The time a TCP client has to complete the authentication handshake before it is dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The size of the chunks read from a connection.
+const READ_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Identifies a connection of an `IoApiServer` for as long as the server runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(u64);
+
+impl std::fmt::Display for ConnectionId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// The credentials of the process on the other side of a Unix domain socket.
+///
+/// The credentials are those the process had when it connected; the kernel keeps them with the
+/// socket, so they cannot be swapped by a process that reuses the ID of the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerCredentials {
+    /// The user ID of the process.
+    pub uid: u32,
+    /// The primary group ID of the process.
+    pub gid: u32,
+    /// The supplementary group IDs of the process.
+    pub groups: Vec<u32>,
+    /// The process ID, if the kernel reported one.
+    pub pid: Option<u32>,
+}
+
+/// Who is on the other side of a connection, as far as the transport can tell.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PeerIdentity {
+    /// A TCP client; only its address is known.
+    Tcp(SocketAddr),
+    /// A TCP client that proved it knows the secret of the core, so it can read the token file.
+    Token(SocketAddr),
+    /// A Unix domain socket client, identified by its credentials.
+    Unix(PeerCredentials),
+}
+
+impl std::fmt::Display for PeerIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeerIdentity::Tcp(address) => write!(f, "tcp:{}", address),
+            PeerIdentity::Token(address) => write!(f, "token:{}", address),
+            PeerIdentity::Unix(credentials) => {
+                write!(f, "unix:uid={},gid={}", credentials.uid, credentials.gid)?;
+                if let Some(pid) = credentials.pid {
+                    write!(f, ",pid={}", pid)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The peers whose connections count against the same per-peer limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PeerKey {
+    /// TCP clients that did not authenticate (yet).
+    Tcp,
+    /// TCP clients that proved they know the secret.
+    Token,
+    /// Unix domain socket clients of a user.
+    User(u32),
+}
+
+impl From<&PeerIdentity> for PeerKey {
+    fn from(peer: &PeerIdentity) -> Self {
+        match peer {
+            PeerIdentity::Tcp(_) => PeerKey::Tcp,
+            PeerIdentity::Token(_) => PeerKey::Token,
+            PeerIdentity::Unix(credentials) => PeerKey::User(credentials.uid),
+        }
+    }
+}
+
+/// A socket the server accepts connections from.
+pub enum IoApiListener {
+    /// A TCP listener.
+    Tcp(TcpListener),
+    /// A Unix domain socket listener.
+    #[cfg(target_os = "linux")]
+    Unix(UnixListener),
+}
+
+impl From<TcpListener> for IoApiListener {
+    fn from(listener: TcpListener) -> Self {
+        IoApiListener::Tcp(listener)
+    }
+}
+
+impl IoApiListener {
+    /// Binds a Unix domain socket that every local user may connect to.
+    ///
+    /// Access is not restricted by the permissions of the socket file but by the credentials
+    /// of each client (see `PeerIdentity`). A socket file left behind by a core that is no
+    /// longer running is replaced; one that is still in use is not.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The location of the socket file.
+    #[cfg(target_os = "linux")]
+    pub fn bind_unix(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if path.exists() {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is in use by another process", path.display()),
+                ));
+            }
+            std::fs::remove_file(path)?;
+        }
+
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o666))?;
+
+        Ok(IoApiListener::Unix(listener))
+    }
+
+    /// Returns the address clients connect to.
+    pub fn address(&self) -> io::Result<CoreAddress> {
+        match self {
+            IoApiListener::Tcp(listener) => listener.local_addr().map(CoreAddress::Tcp),
+            #[cfg(target_os = "linux")]
+            IoApiListener::Unix(listener) => {
+                let address = listener.local_addr()?;
+                let path = address.as_pathname().ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "The socket has no path")
+                })?;
+                Ok(CoreAddress::Unix(path.to_path_buf()))
+            }
+        }
+    }
+
+    /// Moves the listener into or out of non-blocking mode.
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            IoApiListener::Tcp(listener) => listener.set_nonblocking(nonblocking),
+            #[cfg(target_os = "linux")]
+            IoApiListener::Unix(listener) => listener.set_nonblocking(nonblocking),
+        }
+    }
+
+    /// Accepts a connection and identifies the client.
+    fn accept(&self) -> io::Result<(IoApiStream, PeerIdentity)> {
+        match self {
+            IoApiListener::Tcp(listener) => {
+                let (stream, address) = listener.accept()?;
+                Ok((IoApiStream::Tcp(stream), PeerIdentity::Tcp(address)))
+            }
+            #[cfg(target_os = "linux")]
+            IoApiListener::Unix(listener) => {
+                let (stream, _) = listener.accept()?;
+                let credentials = sys::peer_credentials(&stream)?;
+                Ok((IoApiStream::Unix(stream), PeerIdentity::Unix(credentials)))
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+impl std::os::fd::AsRawFd for IoApiListener {
+    fn as_raw_fd(&self) -> std::os::fd::RawFd {
+        match self {
+            IoApiListener::Tcp(listener) => listener.as_raw_fd(),
+            #[cfg(target_os = "linux")]
+            IoApiListener::Unix(listener) => listener.as_raw_fd(),
+        }
+    }
+}
+
+#[cfg(windows)]
+impl std::os::windows::io::AsRawSocket for IoApiListener {
+    fn as_raw_socket(&self) -> std::os::windows::io::RawSocket {
+        match self {
+            IoApiListener::Tcp(listener) => listener.as_raw_socket(),
+        }
+    }
+}
+
+/// Something that happened on the server during a `poll`.
+#[derive(Debug)]
+pub enum ServerEvent {
+    /// A client connected.
+    Connected {
+        /// The new connection.
+        id: ConnectionId,
+        /// Who the client is.
+        peer: PeerIdentity,
+    },
+    /// A client sent a complete command frame.
+    Command {
+        /// The connection the command came from.
+        id: ConnectionId,
+        /// The decoded command, or why the frame could not be decoded.
+        command: Result<IoApiCommand, IoApiError>,
+    },
+    /// A connection was closed, by the client or because of an error.
+    Disconnected(ConnectionId),
+}
+
+/// Wakes up an `IoApiServer` blocked in `poll` from another thread.
+pub struct IoApiWaker {
+    /// A socket connected to the wake-up socket of the server.
+    socket: UdpSocket,
+}
+
+impl IoApiWaker {
+    /// Makes the current (or next) `poll` of the server return.
+    pub fn wake(&self) {
+        // A full socket buffer means a wake-up is already pending.
+        let _ = self.socket.send(&[1]);
+    }
+}
+
+/// A client connection and its buffers.
+struct Connection {
+    /// The stream to the client.
+    stream: IoApiStream,
+    /// The bytes received but not yet decoded into commands.
+    decoder: FrameDecoder,
+    /// The bytes queued for the client but not yet written.
+    output: Vec<u8>,
+    /// Who the client is.
+    peer: PeerIdentity,
+    /// The nonce of the authentication challenge, until the client answered it.
+    nonce: Option<String>,
+    /// When the connection was accepted.
+    opened: Instant,
+}
+
+impl Connection {
+    /// Returns whether the connection was reported to the user of the server, i.e. whether it
+    /// is not waiting for authentication.
+    fn is_announced(&self) -> bool {
+        self.nonce.is_none()
+    }
+
+    /// Returns whether the next command may be taken from the connection.
+    fn accepts_commands(&self) -> bool {
+        self.output.len() < OUTPUT_HIGH_WATER_MARK
+    }
+
+    /// Returns whether the connection should be read from.
+    ///
+    /// Nothing is read while a complete command is waiting, which bounds the input buffer to a
+    /// single frame.
+    fn wants_read(&self) -> bool {
+        self.accepts_commands() && !self.decoder.has_frame()
+    }
+
+    /// Reads the available bytes into the decoder.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(true)` - If the connection is still open.
+    /// * `Ok(false)` - If the client closed the connection.
+    fn fill(&mut self) -> io::Result<bool> {
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(read) => {
+                    self.decoder.extend(&chunk[..read]);
+                    if self.decoder.has_frame() {
+                        return Ok(true);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Writes as much of the queued output as the socket accepts.
+    fn flush(&mut self) -> io::Result<()> {
+        let mut written = 0;
+        let result = loop {
+            if written == self.output.len() {
+                break Ok(());
+            }
+            match self.stream.write(&self.output[written..]) {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(count) => written += count,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.output.drain(..written);
+
+        result
+    }
+}
+
+/// The event-driven IOAPI server.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::ioapi::{IoApiCommand, IoApiResponse, IoApiStatus, send_command};
+/// use comp_gate::helper::ioapi_server::{IoApiServer, ServerEvent};
+/// use std::net::{Ipv4Addr, TcpListener};
+/// use std::time::Duration;
+///
+/// let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+/// let mut server = IoApiServer::new(vec![listener.into()], 8).unwrap();
+///
+/// let address = server.addresses().unwrap().remove(0);
+/// let client = std::thread::spawn(move || {
+///     let mut stream = address.connect().unwrap();
+///     send_command(&mut stream, IoApiCommand::GetDeviceList).unwrap().status
+/// });
+///
+/// // Answer the first command with an error, whichever connection it comes from.
+/// loop {
+///     let events = server.poll(Duration::from_secs(5)).unwrap();
+///     let command = events.into_iter().find_map(|event| match event {
+///         ServerEvent::Command { id, command } => Some((id, command)),
+///         _ => None,
+///     });
+///     if let Some((id, command)) = command {
+///         assert!(matches!(command, Ok(IoApiCommand::GetDeviceList)));
+///         server.send(id, &IoApiResponse::error(IoApiStatus::NotFound, "nothing here").to_frame());
+///         break;
+///     }
+/// }
+///
+/// assert_eq!(client.join().unwrap(), IoApiStatus::NotFound);
+/// ```
+pub struct IoApiServer {
+    /// The listeners new connections are accepted from.
+    listeners: Vec<IoApiListener>,
+    /// The socket `IoApiWaker`s send their wake-ups to.
+    wake_socket: UdpSocket,
+    /// The open connections.
+    connections: HashMap<ConnectionId, Connection>,
+    /// The number of connections served at the same time.
+    max_connections: usize,
+    /// The number of connections served at the same time per peer (see `PeerKey`).
+    max_connections_per_peer: usize,
+    /// The time a TCP client has to complete the authentication handshake.
+    handshake_timeout: Duration,
+    /// The ID given to the next connection.
+    next_id: u64,
+    /// Events that happened outside of `poll` (connections dropped by `send`).
+    pending_events: Vec<ServerEvent>,
+    /// The secret TCP clients have to authenticate with, if they have to.
+    auth_secret: Option<AuthSecret>,
+}
+
+impl IoApiServer {
+    /// Creates a server accepting connections from bound listeners.
+    ///
+    /// # Arguments
+    ///
+    /// * `listeners` - The listeners to accept connections from.
+    /// * `max_connections` - The number of connections served at the same time (over all
+    ///   listeners); further clients are refused.
+    pub fn new(listeners: Vec<IoApiListener>, max_connections: usize) -> io::Result<Self> {
+        for listener in listeners.iter() {
+            listener.set_nonblocking(true)?;
+        }
+        let wake_socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        wake_socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            listeners,
+            wake_socket,
+            connections: HashMap::new(),
+            max_connections,
+            max_connections_per_peer: max_connections,
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            next_id: 0,
+            pending_events: vec![],
+            auth_secret: None,
+        })
+    }
+
+    /// Makes TCP clients authenticate with a secret before they can send commands.
+    ///
+    /// # Arguments
+    ///
+    /// * `secret` - The secret published in the token file.
+    pub fn with_auth_secret(mut self, secret: AuthSecret) -> Self {
+        self.auth_secret = Some(secret);
+        self
+    }
+
+    /// Limits the connections served at the same time per peer; without a limit, a peer may
+    /// take up every connection.
+    ///
+    /// Unix domain socket clients count per user. TCP clients cannot be told apart, so those
+    /// that did not authenticate yet count as one peer, and those that did as another.
+    ///
+    /// # Arguments
+    ///
+    /// * `max_connections_per_peer` - The number of connections per peer; further connections of
+    ///   the peer are refused.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::ioapi::{IoApiResponse, IoApiStatus, read_frame};
+    /// use comp_gate::helper::ioapi_server::IoApiServer;
+    /// use std::net::{Ipv4Addr, TcpListener};
+    /// use std::time::Duration;
+    ///
+    /// let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    /// let mut server = IoApiServer::new(vec![listener.into()], 8)
+    ///     .unwrap()
+    ///     .with_max_connections_per_peer(1);
+    /// let address = server.addresses().unwrap().remove(0);
+    ///
+    /// let _first = address.connect().unwrap();
+    /// while server.connection_count() == 0 {
+    ///     server.poll(Duration::from_millis(100)).unwrap();
+    /// }
+    ///
+    /// // The second connection of the same peer is refused.
+    /// let mut second = address.connect().unwrap();
+    /// server.poll(Duration::from_secs(1)).unwrap();
+    /// let response = IoApiResponse::try_from(read_frame(&mut second).unwrap().as_slice()).unwrap();
+    /// assert_eq!(response.status, IoApiStatus::ServiceUnavailable);
+    /// assert_eq!(server.connection_count(), 1);
+    /// ```
+    pub fn with_max_connections_per_peer(mut self, max_connections_per_peer: usize) -> Self {
+        self.max_connections_per_peer = max_connections_per_peer;
+        self
+    }
+
+    /// Sets the time TCP clients have to complete the authentication handshake
+    /// (`HANDSHAKE_TIMEOUT` by default).
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The time from accepting a connection to its `AuthResponse`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::ioapi::read_frame;
+    /// use comp_gate::helper::ioapi_auth::AuthSecret;
+    /// use comp_gate::helper::ioapi_server::IoApiServer;
+    /// use std::net::{Ipv4Addr, TcpListener};
+    /// use std::time::{Duration, Instant};
+    ///
+    /// let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+    /// let mut server = IoApiServer::new(vec![listener.into()], 8)
+    ///     .unwrap()
+    ///     .with_auth_secret(AuthSecret::generate().unwrap())
+    ///     .with_handshake_timeout(Duration::from_millis(100));
+    /// let address = server.addresses().unwrap().remove(0);
+    ///
+    /// // A client that reads the challenge but never answers it.
+    /// let mut idle = address.connect().unwrap();
+    /// while server.connection_count() == 0 {
+    ///     server.poll(Duration::from_millis(100)).unwrap();
+    /// }
+    /// read_frame(&mut idle).unwrap();
+    ///
+    /// let started = Instant::now();
+    /// while server.connection_count() > 0 {
+    ///     assert!(server.poll(Duration::from_secs(5)).unwrap().is_empty());
+    /// }
+    /// assert!(started.elapsed() < Duration::from_secs(5));
+    /// assert!(read_frame(&mut idle).is_err());
+    /// ```
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Returns the addresses the server accepts connections on, in the order of its listeners.
+    pub fn addresses(&self) -> io::Result<Vec<CoreAddress>> {
+        self.listeners.iter().map(IoApiListener::address).collect()
+    }
+
+    /// Creates a waker that can interrupt `poll` from another thread.
+    pub fn waker(&self) -> io::Result<IoApiWaker> {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))?;
+        socket.connect(self.wake_socket.local_addr()?)?;
+        socket.set_nonblocking(true)?;
+
+        Ok(IoApiWaker { socket })
+    }
+
+    /// Returns the number of open connections.
+    pub fn connection_count(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Waits for something to happen on the server and handles it.
+    ///
+    /// Returns as soon as there is at least one event, an `IoApiWaker` was woken or the
+    /// timeout expired, so the result may be empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The longest time to wait.
+    pub fn poll(&mut self, timeout: Duration) -> io::Result<Vec<ServerEvent>> {
+        let mut events = std::mem::take(&mut self.pending_events);
+
+        // Commands that are already buffered are handed out without waiting.
+        let ready = !events.is_empty()
+            || self
+                .connections
+                .values()
+                .any(|connection| connection.accepts_commands() && connection.decoder.has_frame());
+        let timeout = if ready { Duration::ZERO } else { timeout };
+        // Wake up in time to drop connections that do not complete the handshake.
+        let timeout = self
+            .connections
+            .values()
+            .filter(|connection| !connection.is_announced())
+            .map(|connection| {
+                (connection.opened + self.handshake_timeout)
+                    .saturating_duration_since(Instant::now())
+            })
+            .fold(timeout, Duration::min);
+
+        // The wake-up socket comes first, then the listeners, then the connections.
+        let ids: Vec<ConnectionId> = self.connections.keys().copied().collect();
+        let mut entries = vec![PollEntry::new(raw_socket(&self.wake_socket), true, false)];
+        entries.extend(
+            self.listeners
+                .iter()
+                .map(|listener| PollEntry::new(raw_socket(listener), true, false)),
+        );
+        entries.extend(ids.iter().map(|id| {
+            let connection = &self.connections[id];
+            PollEntry::new(
+                raw_socket(&connection.stream),
+                connection.wants_read(),
+                !connection.output.is_empty(),
+            )
+        }));
+
+        sys::poll(&mut entries, timeout)?;
+
+        let (wake_entry, entries) = entries.split_first().expect("the wake-up socket is polled");
+        let (listener_entries, connection_entries) = entries.split_at(self.listeners.len());
+
+        if wake_entry.readable {
+            let mut buffer = [0u8; 64];
+            while self.wake_socket.recv(&mut buffer).is_ok() {}
+        }
+        for (index, entry) in listener_entries.iter().enumerate() {
+            if entry.readable {
+                self.accept_connections(index, &mut events);
+            }
+        }
+
+        for (id, entry) in ids.into_iter().zip(connection_entries) {
+            if self.service_connection(id, entry, &mut events) {
+                continue;
+            }
+            if let Some(connection) = self.connections.remove(&id)
+                && connection.is_announced()
+            {
+                events.push(ServerEvent::Disconnected(id));
+            }
+        }
+
+        let handshake_timeout = self.handshake_timeout;
+        self.connections.retain(|id, connection| {
+            let expired =
+                !connection.is_announced() && connection.opened.elapsed() >= handshake_timeout;
+            if expired {
+                println!(
+                    "Closing IO API connection {} from {}: the handshake timed out",
+                    id, connection.peer
+                );
+            }
+            !expired
+        });
+
+        Ok(events)
+    }
+
+    /// Queues a frame for a connection and writes as much of it as possible right away.
+    ///
+    /// A connection that cannot be written to, or whose queue exceeds `MAX_PENDING_OUTPUT`,
+    /// is closed; its `Disconnected` event is returned by the next `poll`.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The connection to send to.
+    /// * `frame` - The framed message.
+    pub fn send(&mut self, id: ConnectionId, frame: &[u8]) {
+        let Some(connection) = self.connections.get_mut(&id) else {
+            return;
+        };
+
+        connection.output.extend_from_slice(frame);
+        let result = connection.flush();
+        let pending = connection.output.len();
+
+        let reason = match result {
+            Err(e) => format!("writing failed: {}", e),
+            Ok(()) if pending > MAX_PENDING_OUTPUT => {
+                format!("{} bytes are waiting to be read by the client", pending)
+            }
+            Ok(()) => return,
+        };
+
+        println!("Closing IO API connection {}: {}", id, reason);
+        self.connections.remove(&id);
+        self.pending_events.push(ServerEvent::Disconnected(id));
+    }
+
+    /// Accepts all pending connections of a listener, refusing those beyond the connection limits.
+    fn accept_connections(&mut self, listener: usize, events: &mut Vec<ServerEvent>) {
+        loop {
+            let (mut stream, peer) = match self.listeners[listener].accept() {
+                Ok(accepted) => accepted,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    println!("Error accepting IO API connection: {}", e);
+                    break;
+                }
+            };
+
+            if self.connections.len() >= self.max_connections {
+                println!(
+                    "Refusing IO API connection from {}: {} connections are open",
+                    peer,
+                    self.connections.len()
+                );
+                let response = IoApiResponse::error(
+                    IoApiStatus::ServiceUnavailable,
+                    "Too many open connections",
+                );
+                let _ = stream.write_all(&response.to_frame());
+                continue;
+            }
+
+            let key = PeerKey::from(&peer);
+            let peer_connections = self
+                .connections
+                .values()
+                .filter(|connection| PeerKey::from(&connection.peer) == key)
+                .count();
+            if peer_connections >= self.max_connections_per_peer {
+                println!(
+                    "Refusing IO API connection from {}: {} connections of the peer are open",
+                    peer, peer_connections
+                );
+                let response = IoApiResponse::error(
+                    IoApiStatus::ServiceUnavailable,
+                    "Too many open connections from this peer",
+                );
+                let _ = stream.write_all(&response.to_frame());
+                continue;
+            }
+
+            // Accepted sockets do not inherit the non-blocking mode on every platform.
+            if let Err(e) = stream.set_nonblocking(true) {
+                println!("Error configuring IO API connection: {}", e);
+                continue;
+            }
+
+            let nonce = match (&self.auth_secret, &peer) {
+                (Some(_), PeerIdentity::Tcp(_)) => match generate_nonce() {
+                    Ok(nonce) => Some(nonce),
+                    Err(e) => {
+                        println!("Error generating an authentication challenge: {}", e);
+                        continue;
+                    }
+                },
+                _ => None,
+            };
+
+            let id = ConnectionId(self.next_id);
+            self.next_id += 1;
+            let mut connection = Connection {
+                stream,
+                decoder: FrameDecoder::new(),
+                output: vec![],
+                peer: peer.clone(),
+                nonce: nonce.clone(),
+                opened: Instant::now(),
+            };
+
+            match nonce {
+                Some(nonce) => {
+                    let challenge = IoApiResponse {
+                        status: IoApiStatus::Unauthorized,
+                        message: Some("Authentication required".to_string()),
+                        body: IoApiResponseBody::AuthChallenge { nonce },
+                    };
+                    connection.output.extend_from_slice(&challenge.to_frame());
+                    // Errors surface on the next poll.
+                    let _ = connection.flush();
+                }
+                None => events.push(ServerEvent::Connected { id, peer }),
+            }
+            self.connections.insert(id, connection);
+        }
+    }
+
+    /// Handles the readiness of a connection and takes its next command.
+    ///
+    /// # Returns
+    ///
+    /// * `true` - If the connection stays open.
+    /// * `false` - If it has to be closed.
+    fn service_connection(
+        &mut self,
+        id: ConnectionId,
+        entry: &PollEntry,
+        events: &mut Vec<ServerEvent>,
+    ) -> bool {
+        let Some(connection) = self.connections.get_mut(&id) else {
+            return true;
+        };
+
+        if entry.failed {
+            return false;
+        }
+        if entry.writable
+            && let Err(e) = connection.flush()
+        {
+            println!("Error writing to IO API connection {}: {}", id, e);
+            return false;
+        }
+        if entry.readable {
+            match connection.fill() {
+                Ok(true) => {}
+                Ok(false) => return false,
+                Err(e) => {
+                    println!("Error reading from IO API connection {}: {}", id, e);
+                    return false;
+                }
+            }
+        }
+
+        if connection.accepts_commands() {
+            match connection.decoder.next_frame() {
+                Ok(Some(payload)) if !connection.is_announced() => {
+                    let secret = self.auth_secret.as_ref();
+                    return authenticate_connection(id, connection, secret, &payload, events);
+                }
+                Ok(Some(payload)) => events.push(ServerEvent::Command {
+                    id,
+                    command: IoApiCommand::try_from(payload.as_slice()),
+                }),
+                Ok(None) => {}
+                Err(e) => {
+                    println!("Closing IO API connection {}: {}", id, e);
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Checks the answer of a connection to its authentication challenge.
+///
+/// On success, the connection is confirmed and reported as `Connected`; on failure, the client
+/// is told so before the connection is dropped.
+///
+/// # Returns
+///
+/// * `true` - If the client authenticated.
+/// * `false` - If the connection has to be closed.
+fn authenticate_connection(
+    id: ConnectionId,
+    connection: &mut Connection,
+    secret: Option<&AuthSecret>,
+    payload: &[u8],
+    events: &mut Vec<ServerEvent>,
+) -> bool {
+    let nonce = connection.nonce.as_deref().unwrap_or_default();
+    let proof = serde_json::from_slice::<AuthResponse>(payload)
+        .ok()
+        .zip(secret)
+        .filter(|(answer, secret)| secret.verify(nonce, &answer.response))
+        .map(|(answer, secret)| secret.prove(nonce, &answer.nonce));
+
+    let Some(proof) = proof else {
+        println!(
+            "Closing IO API connection {} from {}: authentication failed",
+            id, connection.peer
+        );
+        let response = IoApiResponse::error(IoApiStatus::Unauthorized, "Authentication failed");
+        connection.output.extend_from_slice(&response.to_frame());
+        let _ = connection.flush();
+        return false;
+    };
+
+    connection.nonce = None;
+    if let PeerIdentity::Tcp(address) = connection.peer {
+        connection.peer = PeerIdentity::Token(address);
+    }
+    let response = IoApiResponse {
+        status: IoApiStatus::Ok,
+        message: Some("Authenticated.".to_string()),
+        body: IoApiResponseBody::AuthProof { proof },
+    };
+    connection.output.extend_from_slice(&response.to_frame());
+    events.push(ServerEvent::Connected {
+        id,
+        peer: connection.peer.clone(),
+    });
+
+    true
+}
+
+/// A socket to wait for, and what it is ready for after `sys::poll`.
+struct PollEntry {
+    /// The OS handle of the socket.
+    socket: RawSocket,
+    /// Whether to wait for the socket to become readable.
+    read: bool,
+    /// Whether to wait for the socket to become writable.
+    write: bool,
+    /// Whether the socket is readable.
+    readable: bool,
+    /// Whether the socket is writable.
+    writable: bool,
+    /// Whether the socket failed or the other side hung up.
+    failed: bool,
+}
+
+impl PollEntry {
+    fn new(socket: RawSocket, read: bool, write: bool) -> Self {
+        Self {
+            socket,
+            read,
+            write,
+            readable: false,
+            writable: false,
+            failed: false,
+        }
+    }
+}
+
+#[cfg(unix)]
+type RawSocket = std::os::fd::RawFd;
+#[cfg(windows)]
+type RawSocket = std::os::windows::io::RawSocket;
+
+/// Returns the OS handle of a socket.
+#[cfg(unix)]
+fn raw_socket(socket: &impl std::os::fd::AsRawFd) -> RawSocket {
+    socket.as_raw_fd()
+}
+
+/// Returns the OS handle of a socket.
+#[cfg(windows)]
+fn raw_socket(socket: &impl std::os::windows::io::AsRawSocket) -> RawSocket {
+    socket.as_raw_socket()
+}
+
+/// Waiting for socket readiness with `poll` (Linux).
+#[cfg(target_os = "linux")]
+mod sys {
+    use super::*;
+
+    /// Waits until one of the sockets is ready or the timeout expires.
+    pub fn poll(entries: &mut [PollEntry], timeout: Duration) -> io::Result<()> {
+        let mut fds: Vec<libc::pollfd> = entries
+            .iter()
+            .map(|entry| libc::pollfd {
+                fd: entry.socket,
+                events: (if entry.read { libc::POLLIN } else { 0 })
+                    | (if entry.write { libc::POLLOUT } else { 0 }),
+                revents: 0,
+            })
+            .collect();
+        let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+
+        // SAFETY: `fds` is a valid array of `fds.len()` pollfd structures.
+        let result = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if result < 0 {
+            let error = io::Error::last_os_error();
+            return match error.kind() {
+                io::ErrorKind::Interrupted => Ok(()),
+                _ => Err(error),
+            };
+        }
+
+        for (entry, fd) in entries.iter_mut().zip(&fds) {
+            entry.readable = fd.revents & libc::POLLIN != 0;
+            entry.writable = fd.revents & libc::POLLOUT != 0;
+            entry.failed = fd.revents & (libc::POLLERR | libc::POLLHUP | libc::POLLNVAL) != 0;
+        }
+
+        Ok(())
+    }
+
+    /// The `SO_PEERGROUPS` socket option (Linux 4.13), which the libc crate does not export.
+    #[cfg(not(target_arch = "sparc64"))]
+    const SO_PEERGROUPS: libc::c_int = 59;
+    #[cfg(target_arch = "sparc64")]
+    const SO_PEERGROUPS: libc::c_int = 0x3d;
+
+    /// The number of supplementary groups asked for first; the kernel reports when more are
+    /// needed.
+    const INITIAL_PEER_GROUPS: usize = 32;
+
+    /// Reads the credentials of the process on the other side of a Unix domain socket.
+    pub fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+        use std::os::fd::AsRawFd;
+
+        let mut credentials = libc::ucred {
+            pid: 0,
+            uid: 0,
+            gid: 0,
+        };
+        let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+        // SAFETY: `credentials` is a valid ucred structure of `length` bytes.
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERCRED,
+                &mut credentials as *mut libc::ucred as *mut libc::c_void,
+                &mut length,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(PeerCredentials {
+            uid: credentials.uid,
+            gid: credentials.gid,
+            groups: peer_groups(stream)?,
+            pid: u32::try_from(credentials.pid).ok().filter(|&pid| pid != 0),
+        })
+    }
+
+    /// Reads the supplementary groups of the process on the other side of a Unix domain socket.
+    ///
+    /// Kernels without `SO_PEERGROUPS` report no supplementary groups, so their clients only
+    /// get the roles of their user and primary group.
+    fn peer_groups(stream: &UnixStream) -> io::Result<Vec<u32>> {
+        use std::os::fd::AsRawFd;
+
+        let mut groups = vec![0 as libc::gid_t; INITIAL_PEER_GROUPS];
+        loop {
+            let mut length = std::mem::size_of_val(groups.as_slice()) as libc::socklen_t;
+
+            // SAFETY: `groups` is a valid buffer of `length` bytes.
+            let result = unsafe {
+                libc::getsockopt(
+                    stream.as_raw_fd(),
+                    libc::SOL_SOCKET,
+                    SO_PEERGROUPS,
+                    groups.as_mut_ptr() as *mut libc::c_void,
+                    &mut length,
+                )
+            };
+            let count = length as usize / std::mem::size_of::<libc::gid_t>();
+
+            if result == 0 {
+                groups.truncate(count);
+                return Ok(groups);
+            }
+            let error = io::Error::last_os_error();
+            match error.raw_os_error() {
+                // The kernel reported how large the buffer has to be.
+                Some(libc::ERANGE) if count > groups.len() => groups.resize(count, 0),
+                Some(libc::ENOPROTOOPT) => return Ok(vec![]),
+                _ => return Err(error),
+            }
+        }
+    }
+}
+
+/// Waiting for socket readiness with `WSAPoll` (Windows).
+#[cfg(windows)]
+mod sys {
+    use super::*;
+    use windows_sys::Win32::Networking::WinSock::{
+        POLLERR, POLLHUP, POLLNVAL, POLLRDNORM, POLLWRNORM, SOCKET, WSAPOLLFD, WSAPoll,
+    };
+
+    /// Waits until one of the sockets is ready or the timeout expires.
+    pub fn poll(entries: &mut [PollEntry], timeout: Duration) -> io::Result<()> {
+        let mut fds: Vec<WSAPOLLFD> = entries
+            .iter()
+            .map(|entry| WSAPOLLFD {
+                fd: entry.socket as SOCKET,
+                events: (if entry.read { POLLRDNORM } else { 0 })
+                    | (if entry.write { POLLWRNORM } else { 0 }),
+                revents: 0,
+            })
+            .collect();
+        let timeout = timeout.as_millis().min(i32::MAX as u128) as i32;
+
+        // SAFETY: `fds` is a valid array of `fds.len()` WSAPOLLFD structures.
+        let result = unsafe { WSAPoll(fds.as_mut_ptr(), fds.len() as u32, timeout) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        for (entry, fd) in entries.iter_mut().zip(&fds) {
+            entry.readable = fd.revents & POLLRDNORM != 0;
+            entry.writable = fd.revents & POLLWRNORM != 0;
+            entry.failed = fd.revents & (POLLERR | POLLHUP | POLLNVAL) != 0;
+        }
+
+        Ok(())
+    }
+}
+
+/// Fallback for platforms without a readiness API binding: wait briefly and let the
+/// non-blocking sockets report `WouldBlock`.
+#[cfg(not(any(target_os = "linux", windows)))]
+mod sys {
+    use super::*;
+
+    /// The longest time to sleep before checking the sockets again.
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+    /// Sleeps for the timeout (at most `POLL_INTERVAL`) and reports every socket as ready.
+    pub fn poll(entries: &mut [PollEntry], timeout: Duration) -> io::Result<()> {
+        std::thread::sleep(timeout.min(POLL_INTERVAL));
+        for entry in entries.iter_mut() {
+            entry.readable = entry.read;
+            entry.writable = entry.write;
+        }
+
+        Ok(())
+    }
+}
@@ -3,12 +3,42 @@
 //! This module aggregates various utility sub-modules that provide core functionality for the `comp-gate` application.
 //! It includes:
 //!
-//! - `device_managment`: Tools for interacting with the Windows SetupAPI to manage device drivers and properties.
+//! - `audit_log`: The persistent, hash-chained audit log of device events.
+//! - `connection_events`: Keeps the device tree in sync with USB connection events.
+//! - `device_backend`: The platform abstraction (and its implementations) used to talk to the OS device APIs.
+//! - `device_diff`: Comparing two device trees, e.g. the live tree against a snapshot.
+//! - `device_filter`: The configurable include/exclude chain deciding which devices are tracked.
+//! - `device_managment`: The platform-neutral device tree used to track and manage devices.
+//! - `device_property`: Typed device property values and the decoding of raw Windows property buffers.
+//! - `device_query`: Composable queries selecting devices from the device tree.
+//! - `device_snapshot`: Lossless JSON snapshots of the device tree.
+//! - `policy`: The rule-based policy deciding which devices may be used.
+//! - `policy_file`: Loading, validating and hot-reloading the TOML policy file.
 //! - `usb_connection_callback`: Event handling logic for USB device insertion and removal.
-//! - `whitelist`: Functionality to manage and check against a list of authorized USB devices.
+//! - `usb_identity`: Parsing of Instance IDs into vendor, product, interface and serial number.
+//! - `usb_ids`: Lookup of vendor, product and class names in the `usb.ids` database.
+//! - `whitelist`: Functionality to store and enforce the device policy.
 //! - `ioapi`: Input/Output utilities for handling configuration files and data persistence.
+//! - `ioapi_auth`: The challenge-response handshake authenticating TCP clients of the IOAPI.
+//! - `ioapi_access`: Deciding which IOAPI clients may change device states.
+//! - `ioapi_server`: The event-driven server multiplexing the IOAPI client connections.
 
+pub mod audit_log;
+pub mod connection_events;
+pub mod device_backend;
+pub mod device_diff;
+pub mod device_filter;
 pub mod device_managment;
+pub mod device_property;
+pub mod device_query;
+pub mod device_snapshot;
 pub mod ioapi;
+pub mod ioapi_access;
+pub mod ioapi_auth;
+pub mod ioapi_server;
+pub mod policy;
+pub mod policy_file;
 pub mod usb_connection_callback;
+pub mod usb_identity;
+pub mod usb_ids;
 pub mod whitelist;
@@ -0,0 +1,384 @@
+//! # Policy Module
+//!
+//! This module implements the rule-based device policy that decides which devices may be used.
+//!
+//! A `Policy` is an ordered list of `PolicyRule`s and a default action. Every rule carries an
+//! action (`allow`, `deny` or `ask`) and a set of match conditions; a rule matches a device when
+//! all of its conditions hold. Rules are evaluated top to bottom and the first matching rule
+//! decides. If no rule matches, the default action applies.
+//!
+//! Conditions that can be combined in a rule:
+//! - `vendor_id` / `product_id`: The USB VID/PID (hexadecimal, e.g. `"046D"`).
+//! - `serial`: The serial number reported by the device.
+//! - `device_class`: The class of the device itself (e.g. `"USB"`, `"HIDClass"`).
+//! - `interface_class`: The class of one of the device's interfaces (sub-devices).
+//! - `service`: The service/driver bound to the device.
+//! - `parent_hub`: A wildcard pattern for the Instance ID of the parent (hub) device.
+//! - `device_id`: A wildcard pattern for the Instance ID of the device.
+//! - `hardware_id`: A wildcard pattern one of the hardware IDs of the device must match.
+//! - `container_id`: The container ID of the device, which tells apart identical devices that
+//!   do not report a serial number.
+//!
+//! Wildcard patterns support `*` (any sequence) and `?` (any single character). All string
+//! comparisons ignore case.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::helper::{
+    device_managment::{Device, DeviceState},
+    usb_identity::parse_usb_id,
+};
+
+/// What to do with a device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyAction {
+    /// The device may be used.
+    Allow,
+    /// The device is blocked.
+    Deny,
+    /// The device is blocked until an operator approves it (`IoApiCommand::ApproveDevice`).
+    Ask,
+}
+
+impl std::fmt::Display for PolicyAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyAction::Allow => write!(f, "allow"),
+            PolicyAction::Deny => write!(f, "deny"),
+            PolicyAction::Ask => write!(f, "ask"),
+        }
+    }
+}
+
+/// A single policy rule.
+///
+/// Conditions left unset are ignored; a rule without any condition matches every device.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyRule {
+    /// An optional name identifying the rule in decisions and logs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The action taken for matching devices.
+    pub action: PolicyAction,
+
+    /// The USB vendor ID the device must have.
+    #[serde(default, with = "hex_id", skip_serializing_if = "Option::is_none")]
+    pub vendor_id: Option<u16>,
+    /// The USB product ID the device must have.
+    #[serde(default, with = "hex_id", skip_serializing_if = "Option::is_none")]
+    pub product_id: Option<u16>,
+    /// The serial number the device must report.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    /// A class one of the device's interfaces must have.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface_class: Option<String>,
+    /// The class the device must have.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_class: Option<String>,
+    /// The service (driver) the device must be bound to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service: Option<String>,
+    /// A wildcard pattern the Instance ID of the parent device must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_hub: Option<String>,
+    /// A wildcard pattern the Instance ID of the device must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    /// A wildcard pattern one of the hardware IDs of the device must match.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hardware_id: Option<String>,
+    /// The container ID the device must have.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_id: Option<String>,
+}
+
+impl PolicyRule {
+    /// Creates a rule without conditions.
+    pub fn new(action: PolicyAction) -> Self {
+        Self {
+            name: None,
+            action,
+            vendor_id: None,
+            product_id: None,
+            serial: None,
+            interface_class: None,
+            device_class: None,
+            service: None,
+            parent_hub: None,
+            device_id: None,
+            hardware_id: None,
+            container_id: None,
+        }
+    }
+
+    /// Creates a rule matching a single device as closely as its properties allow.
+    ///
+    /// Devices with a VID/PID are matched by those (and by their serial number, if they
+    /// report one), so the rule still applies when the device is plugged into another port.
+    /// Other devices are matched by their exact Instance ID.
+    pub fn for_device(device: &Device, action: PolicyAction) -> Self {
+        match (device.vendor_id, device.product_id) {
+            (Some(vendor_id), Some(product_id)) => Self {
+                vendor_id: Some(vendor_id),
+                product_id: Some(product_id),
+                serial: device.serial.as_deref().map(str::to_string),
+                ..Self::new(action)
+            },
+            _ => Self {
+                device_id: Some(device.device_id.to_string()),
+                ..Self::new(action)
+            },
+        }
+    }
+
+    /// Returns whether all conditions of the rule hold for a device.
+    pub fn matches(&self, device: &Device) -> bool {
+        if self.vendor_id.is_some() && self.vendor_id != device.vendor_id {
+            return false;
+        }
+        if self.product_id.is_some() && self.product_id != device.product_id {
+            return false;
+        }
+        if let Some(expected) = &self.serial
+            && !eq_ignore_case(device.serial.as_deref(), expected)
+        {
+            return false;
+        }
+        if let Some(expected) = &self.device_class
+            && !eq_ignore_case(device.device_class.as_deref(), expected)
+        {
+            return false;
+        }
+        if let Some(expected) = &self.interface_class
+            && !has_interface_class(device, expected)
+        {
+            return false;
+        }
+        if let Some(expected) = &self.service
+            && !eq_ignore_case(device.device_service.as_deref(), expected)
+        {
+            return false;
+        }
+        if let Some(pattern) = &self.parent_hub
+            && !device
+                .parent_id
+                .as_ref()
+                .is_some_and(|parent_id| wildcard_match(pattern, parent_id))
+        {
+            return false;
+        }
+        if let Some(pattern) = &self.device_id
+            && !wildcard_match(pattern, &device.device_id)
+        {
+            return false;
+        }
+        if let Some(pattern) = &self.hardware_id
+            && !device
+                .hardware_ids
+                .iter()
+                .any(|hardware_id| wildcard_match(pattern, hardware_id))
+        {
+            return false;
+        }
+        if let Some(expected) = &self.container_id
+            && !eq_ignore_case(device.container_id.as_deref(), expected)
+        {
+            return false;
+        }
+
+        true
+    }
+}
+
+/// The outcome of evaluating a `Policy` for a device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyDecision {
+    /// The action to take.
+    pub action: PolicyAction,
+    /// The rule that decided, or `None` if the default action applied.
+    ///
+    /// Rules are identified by their name, or by `#<index>` if they have none.
+    pub rule: Option<String>,
+}
+
+impl PolicyDecision {
+    /// Returns the device state the decision calls for.
+    ///
+    /// Devices waiting for an operator decision stay disabled.
+    pub fn device_state(&self) -> DeviceState {
+        match self.action {
+            PolicyAction::Allow => DeviceState::Enable,
+            PolicyAction::Deny | PolicyAction::Ask => DeviceState::Disable,
+        }
+    }
+}
+
+impl std::fmt::Display for PolicyDecision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.rule {
+            Some(rule) => write!(f, "{} (rule {})", self.action, rule),
+            None => write!(f, "{} (default action)", self.action),
+        }
+    }
+}
+
+/// An ordered set of rules deciding which devices may be used.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+/// use comp_gate::helper::device_managment::{DeviceId, DeviceTracker};
+/// use comp_gate::helper::policy::{Policy, PolicyAction, PolicyRule};
+/// use std::rc::Rc;
+///
+/// let backend = SimulatedBackend::new();
+/// backend.add_device(SimulatedDevice::new(r"USB\VID_046D&PID_C52B\5&2752457F&0&2"));
+/// backend.add_device(SimulatedDevice::new(r"USB\VID_0781&PID_5581\4C530001"));
+/// let tracker = DeviceTracker::load_with_backend(Box::new(backend)).unwrap();
+///
+/// let policy = Policy {
+///     rules: vec![PolicyRule {
+///         name: Some("logitech".to_string()),
+///         vendor_id: Some(0x046D),
+///         ..PolicyRule::new(PolicyAction::Allow)
+///     }],
+///     default_action: PolicyAction::Deny,
+/// };
+///
+/// let receiver = tracker
+///     .find_device(&DeviceId::from(Rc::<str>::from(r"USB\VID_046D&PID_C52B\5&2752457F&0&2")))
+///     .unwrap();
+/// let decision = policy.evaluate(receiver);
+/// assert_eq!(decision.action, PolicyAction::Allow);
+/// assert_eq!(decision.rule.as_deref(), Some("logitech"));
+///
+/// let stick = tracker
+///     .find_device(&DeviceId::from(Rc::<str>::from(r"USB\VID_0781&PID_5581\4C530001")))
+///     .unwrap();
+/// assert_eq!(policy.evaluate(stick).action, PolicyAction::Deny);
+/// assert_eq!(policy.evaluate(stick).rule, None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    /// The rules, in evaluation order.
+    #[serde(default)]
+    pub rules: Vec<PolicyRule>,
+    /// The action taken if no rule matches.
+    pub default_action: PolicyAction,
+}
+
+impl Default for Policy {
+    /// An empty policy that allows every device.
+    fn default() -> Self {
+        Self {
+            rules: vec![],
+            default_action: PolicyAction::Allow,
+        }
+    }
+}
+
+impl Policy {
+    /// Evaluates the policy for a device.
+    ///
+    /// The first rule matching the device decides; if no rule matches, the default action applies.
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - The device to evaluate, including its sub-devices.
+    pub fn evaluate(&self, device: &Device) -> PolicyDecision {
+        self.rules
+            .iter()
+            .enumerate()
+            .find(|(_, rule)| rule.matches(device))
+            .map(|(index, rule)| PolicyDecision {
+                action: rule.action,
+                rule: Some(rule.name.clone().unwrap_or_else(|| format!("#{}", index))),
+            })
+            .unwrap_or(PolicyDecision {
+                action: self.default_action,
+                rule: None,
+            })
+    }
+}
+
+/// Matches a text against a wildcard pattern, ignoring case.
+///
+/// `*` matches any sequence of characters (including none), `?` matches a single character.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::policy::wildcard_match;
+///
+/// assert!(wildcard_match(r"USB\VID_046D&PID_*", r"usb\vid_046d&pid_c52b\5&2752457F&0&2"));
+/// assert!(wildcard_match("1-1.?", "1-1.4"));
+/// assert!(!wildcard_match("1-1.?", "1-1.4:1.0"));
+/// ```
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+
+    // Iterative matching with backtracking to the last `*`.
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = last_star {
+            p = star_p + 1;
+            t = star_t + 1;
+            last_star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Compares an optional device property against an expected value, ignoring case.
+fn eq_ignore_case(value: Option<&str>, expected: &str) -> bool {
+    value.is_some_and(|value| value.eq_ignore_ascii_case(expected))
+}
+
+/// Returns whether the device or one of its sub-devices has the given class.
+fn has_interface_class(device: &Device, class: &str) -> bool {
+    device.devices.values().any(|sub_device| {
+        eq_ignore_case(sub_device.device_class.as_deref(), class)
+            || has_interface_class(sub_device, class)
+    })
+}
+
+/// (De)serializes optional USB IDs as hexadecimal strings like `"046D"`.
+mod hex_id {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Option<u16>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(id) => serializer.serialize_str(&format!("{:04X}", id)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u16>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        parse_usb_id(&value).map(Some).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "invalid USB ID `{}`, expected 4 hexadecimal digits",
+                value
+            ))
+        })
+    }
+}
@@ -0,0 +1,374 @@
+//! # Policy File Module
+//!
+//! This module stores the device `Policy` in a human-editable TOML file, so it can be reviewed,
+//! diffed and distributed through configuration management.
+//!
+//! ```toml
+//! default_action = "deny"
+//!
+//! [[rules]]
+//! name = "logitech-receivers"
+//! action = "allow"
+//! vendor_id = "046D"
+//! product_id = "C52B"
+//!
+//! [[rules]]
+//! name = "no-mass-storage"
+//! action = "deny"
+//! interface_class = "Mass Storage"
+//! ```
+//!
+//! Files are validated before they are used (`validate_policy`); every problem is reported with
+//! its line and column. `PolicyFile` watches the file for changes and swaps in a new policy only
+//! once it was validated completely, so an invalid edit leaves the last good policy in effect.
+//! Writes go to a temporary file that is renamed over the policy file, so readers never see a
+//! partially written policy.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    error::{PolicyFileError, PolicyValidationError},
+    helper::policy::{Policy, PolicyRule},
+};
+
+/// The environment variable overriding the location of the policy file.
+pub const POLICY_FILE_ENV: &str = "COMP_GATE_POLICY_FILE";
+
+/// Returns the location of the policy file.
+///
+/// The location can be overridden with the `COMP_GATE_POLICY_FILE` environment variable.
+/// Otherwise it is:
+/// - On Windows: `%ProgramData%\comp-gate\policy.toml`
+/// - Elsewhere: `/etc/comp-gate/policy.toml`
+pub fn policy_file_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(POLICY_FILE_ENV) {
+        return PathBuf::from(path);
+    }
+
+    if cfg!(windows) {
+        let program_data =
+            std::env::var_os("ProgramData").unwrap_or_else(|| r"C:\ProgramData".into());
+        PathBuf::from(program_data)
+            .join("comp-gate")
+            .join("policy.toml")
+    } else {
+        PathBuf::from("/etc/comp-gate/policy.toml")
+    }
+}
+
+/// Parses and validates the contents of a policy file.
+///
+/// Besides the schema (unknown keys, missing actions, malformed USB IDs, ...) the validator
+/// rejects rules that could never have an effect: empty conditions, duplicate rule names and
+/// rules placed after a rule that matches every device.
+///
+/// # Returns
+///
+/// * `Ok(Policy)` - The validated policy.
+/// * `Err(Vec<PolicyValidationError>)` - Every problem found, located by line and column.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::policy::PolicyAction;
+/// use comp_gate::helper::policy_file::validate_policy;
+///
+/// let policy = validate_policy(
+///     "default_action = \"deny\"\n\
+///      \n\
+///      [[rules]]\n\
+///      action = \"allow\"\n\
+///      vendor_id = \"046D\"\n",
+/// )
+/// .unwrap();
+/// assert_eq!(policy.default_action, PolicyAction::Deny);
+/// assert_eq!(policy.rules[0].vendor_id, Some(0x046D));
+///
+/// let errors = validate_policy(
+///     "default_action = \"deny\"\n\
+///      \n\
+///      [[rules]]\n\
+///      action = \"allow\"\n\
+///      vendor = \"046D\"\n",
+/// )
+/// .unwrap_err();
+/// assert_eq!((errors[0].line, errors[0].column), (5, 1));
+/// ```
+pub fn validate_policy(source: &str) -> Result<Policy, Vec<PolicyValidationError>> {
+    let policy: Policy = toml::from_str(source).map_err(|e| {
+        let offset = e.span().map(|span| span.start).unwrap_or_default();
+        vec![validation_error(source, offset, e.message())]
+    })?;
+
+    let rule_offsets = rule_header_offsets(source);
+    let mut errors = vec![];
+    let mut rule_names: HashMap<&str, usize> = HashMap::new();
+    let mut catch_all_rule: Option<usize> = None;
+
+    for (index, rule) in policy.rules.iter().enumerate() {
+        // Inline rule arrays have no `[[rules]]` header; point at the start of the file instead.
+        let offset = rule_offsets.get(index).copied().unwrap_or_default();
+        let mut report = |message: String| errors.push(validation_error(source, offset, &message));
+
+        if let Some(catch_all) = catch_all_rule {
+            report(format!(
+                "rule #{} is never evaluated, rule #{} before it matches every device",
+                index, catch_all
+            ));
+        }
+
+        for (field, value) in [
+            ("name", &rule.name),
+            ("serial", &rule.serial),
+            ("interface_class", &rule.interface_class),
+            ("device_class", &rule.device_class),
+            ("service", &rule.service),
+            ("parent_hub", &rule.parent_hub),
+            ("device_id", &rule.device_id),
+            ("hardware_id", &rule.hardware_id),
+            ("container_id", &rule.container_id),
+        ] {
+            if value
+                .as_deref()
+                .is_some_and(|value| value.trim().is_empty())
+            {
+                report(format!("`{}` of rule #{} must not be empty", field, index));
+            }
+        }
+
+        if let Some(name) = rule.name.as_deref()
+            && let Some(first) = rule_names.insert(name, index)
+        {
+            report(format!(
+                "rule #{} uses the name `{}` of rule #{}",
+                index, name, first
+            ));
+        }
+
+        let unconditional = PolicyRule {
+            name: rule.name.clone(),
+            ..PolicyRule::new(rule.action)
+        };
+        if catch_all_rule.is_none() && *rule == unconditional {
+            catch_all_rule = Some(index);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(policy)
+    } else {
+        Err(errors)
+    }
+}
+
+/// A policy stored in a file, reloaded whenever the file changes.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::policy::{Policy, PolicyAction};
+/// use comp_gate::helper::policy_file::PolicyFile;
+///
+/// let path = std::env::temp_dir().join("comp-gate-policy-doc.toml");
+/// let _ = std::fs::remove_file(&path);
+///
+/// // A missing file is created from the initial policy.
+/// let mut file = PolicyFile::load_or_create(&path, Policy::default).unwrap();
+/// assert_eq!(file.policy().default_action, PolicyAction::Allow);
+///
+/// // An invalid edit is rejected and the last good policy stays in effect.
+/// // (`mark_stale` guards against both writes sharing a file system timestamp.)
+/// std::fs::write(&path, "default_action = \"block\"\n").unwrap();
+/// file.mark_stale();
+/// assert!(file.reload_if_changed().is_err());
+/// assert_eq!(file.policy().default_action, PolicyAction::Allow);
+///
+/// // USB IDs are exactly 4 hexadecimal digits.
+/// std::fs::write(
+///     &path,
+///     "default_action = \"deny\"\n[[rules]]\naction = \"allow\"\nvendor_id = \"46D\"\n",
+/// )
+/// .unwrap();
+/// file.mark_stale();
+/// assert!(file.reload_if_changed().is_err());
+///
+/// std::fs::write(&path, "default_action = \"deny\"\n").unwrap();
+/// file.mark_stale();
+/// assert!(file.reload_if_changed().unwrap());
+/// assert_eq!(file.policy().default_action, PolicyAction::Deny);
+/// # std::fs::remove_file(&path).unwrap();
+/// ```
+pub struct PolicyFile {
+    /// The location of the policy file.
+    path: PathBuf,
+    /// The modification time of the file when it was last read or written.
+    modified: Option<SystemTime>,
+    /// The last good policy read from (or written to) the file.
+    policy: Policy,
+}
+
+impl PolicyFile {
+    /// Loads and validates a policy file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The location of the policy file.
+    pub fn load(path: impl Into<PathBuf>) -> Result<Self, PolicyFileError> {
+        let path = path.into();
+        let modified = modification_time(&path);
+        let policy = read_policy(&path)?;
+
+        Ok(Self {
+            path,
+            modified,
+            policy,
+        })
+    }
+
+    /// Loads a policy file, creating it from an initial policy if it does not exist yet.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The location of the policy file.
+    /// * `initial_policy` - Builds the policy written to a newly created file.
+    pub fn load_or_create(
+        path: impl Into<PathBuf>,
+        initial_policy: impl FnOnce() -> Policy,
+    ) -> Result<Self, PolicyFileError> {
+        let path = path.into();
+        if path.exists() {
+            return Self::load(path);
+        }
+
+        let mut file = Self {
+            path,
+            modified: None,
+            policy: Policy::default(),
+        };
+        file.save(initial_policy())?;
+
+        Ok(file)
+    }
+
+    /// Returns the location of the policy file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the last good policy.
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Reloads the policy if the file was modified since it was last read.
+    ///
+    /// The new policy only replaces the current one if it is valid; otherwise the current
+    /// policy stays in effect and the error is returned (once per modification).
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(true)` - If a new policy was loaded.
+    /// * `Ok(false)` - If the file did not change.
+    /// * `Err(PolicyFileError)` - If the changed file could not be read or is invalid.
+    pub fn reload_if_changed(&mut self) -> Result<bool, PolicyFileError> {
+        let modified = modification_time(&self.path);
+        if modified == self.modified {
+            return Ok(false);
+        }
+        self.modified = modified;
+
+        self.policy = read_policy(&self.path)?;
+        Ok(true)
+    }
+
+    /// Forgets the recorded modification time, so the next `reload_if_changed` reads the file
+    /// if it exists.
+    ///
+    /// Useful when the file may have been rewritten within the resolution of the file system's
+    /// timestamps.
+    pub fn mark_stale(&mut self) {
+        self.modified = None;
+    }
+
+    /// Validates a policy, writes it to the file atomically and puts it into effect.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The new policy.
+    pub fn save(&mut self, policy: Policy) -> Result<(), PolicyFileError> {
+        let source = toml::to_string_pretty(&policy)?;
+        validate_policy(&source).map_err(PolicyFileError::Invalid)?;
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomically(&self.path, source.as_bytes())?;
+
+        self.modified = modification_time(&self.path);
+        self.policy = policy;
+
+        Ok(())
+    }
+}
+
+/// Writes a file by renaming a fully written temporary file over it.
+///
+/// # Arguments
+///
+/// * `path` - The file to write.
+/// * `contents` - The new contents of the file.
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temporary_name = path.file_name().unwrap_or_default().to_os_string();
+    temporary_name.push(".tmp");
+    let temporary_path = path.with_file_name(temporary_name);
+
+    fs::write(&temporary_path, contents)?;
+    fs::rename(&temporary_path, path).inspect_err(|_| {
+        let _ = fs::remove_file(&temporary_path);
+    })
+}
+
+/// Reads and validates a policy file.
+fn read_policy(path: &Path) -> Result<Policy, PolicyFileError> {
+    let source = fs::read_to_string(path)?;
+    validate_policy(&source).map_err(PolicyFileError::Invalid)
+}
+
+/// Returns the modification time of a file, if it exists.
+fn modification_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Returns the byte offsets of the `[[rules]]` headers, in file order.
+fn rule_header_offsets(source: &str) -> Vec<usize> {
+    let mut offsets = vec![];
+    let mut line_start = 0;
+
+    for line in source.split_inclusive('\n') {
+        let indentation = line.len() - line.trim_start().len();
+        if line.trim_start().starts_with("[[rules]]") {
+            offsets.push(line_start + indentation);
+        }
+        line_start += line.len();
+    }
+
+    offsets
+}
+
+/// Builds a validation error located at a byte offset of the source.
+fn validation_error(source: &str, offset: usize, message: &str) -> PolicyValidationError {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or_default();
+
+    PolicyValidationError {
+        line,
+        column: before[line_start..].chars().count() + 1,
+        message: message.trim().to_string(),
+    }
+}
@@ -1,369 +0,0 @@
-//! # USB Connection Callback Module
-//!
-//! This module implements a mechanism to detect USB device insertion and removal events on Windows.
-//! It creates a hidden window to receive `WM_DEVICECHANGE` messages and broadcasts these events
-//! via a channel to the main application.
-//!
-//! Key components:
-//! - `UsbConnectionCallbacksHandle`: The main interface for setting up and polling events.
-//! - `UsbConnectionEvent`: Enum representing connection/disconnection events.
-//! - `window_proc`: The window procedure that processes Windows messages.
-
-use std::{
-    ops::Deref,
-    ptr::{null, null_mut},
-    rc::Rc,
-    sync::{
-        Arc, LazyLock, Mutex,
-        mpsc::{Receiver, Sender},
-    },
-    thread::JoinHandle,
-};
-
-use windows_sys::Win32::{
-    Foundation::{GetLastError, HWND, LPARAM, LRESULT, WPARAM},
-    System::LibraryLoader::GetModuleHandleW,
-    UI::WindowsAndMessaging::{
-        CreateWindowExW, DBT_DEVICEARRIVAL, DBT_DEVICEREMOVECOMPLETE, DBT_DEVTYP_DEVICEINTERFACE,
-        DEV_BROADCAST_DEVICEINTERFACE_W, DEVICE_NOTIFY_ALL_INTERFACE_CLASSES,
-        DEVICE_NOTIFY_WINDOW_HANDLE, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW,
-        HDEVNOTIFY, HWND_MESSAGE, RegisterClassW, RegisterDeviceNotificationW, TranslateMessage,
-        UnregisterClassW, UnregisterDeviceNotification, WM_DEVICECHANGE, WNDCLASSW,
-    },
-};
-
-use crate::error::{PollEventError, Win32Error};
-
-/// A global sender to transmit events from the window procedure (which is a static C-callback)
-/// to the Rust application logic.
-static EVENT_SENDER: LazyLock<Mutex<Option<Sender<UsbConnectionEvent>>>> =
-    LazyLock::new(|| Mutex::new(None));
-
-/// Extracts the device path string from a `DEV_BROADCAST_DEVICEINTERFACE_W` structure.
-///
-/// # Safety
-/// This function assumes `dev_brodcast` is a valid pointer to a `DEV_BROADCAST_DEVICEINTERFACE_W` struct.
-fn get_device_path(dev_brodcast: *const DEV_BROADCAST_DEVICEINTERFACE_W) -> String {
-    unsafe {
-        let dbcc_name_ptr = (*dev_brodcast).dbcc_name.as_ptr();
-
-        let mut len = 0;
-        while *dbcc_name_ptr.add(len) != 0 {
-            len += 1;
-        }
-        let slice = std::slice::from_raw_parts(dbcc_name_ptr, len);
-
-        String::from_utf16_lossy(slice)
-    }
-}
-
-/// Handles the `DBT_DEVICEARRIVAL` event.
-///
-/// Filters for USB and HID devices and sends a `Connected` event if the device matches.
-fn handle_device_arrival(dev_brodcast: *const DEV_BROADCAST_DEVICEINTERFACE_W) {
-    let dev_type = unsafe { (*dev_brodcast).dbcc_devicetype };
-    if dev_type != DBT_DEVTYP_DEVICEINTERFACE {
-        return;
-    }
-
-    let device_path_string = get_device_path(dev_brodcast).to_uppercase();
-
-    // Filter out devices that are not USB or HID
-    let filter = !device_path_string.starts_with(r"\\?\USB#")
-        && !device_path_string.starts_with(r"\\?\HID#");
-
-    if filter {
-        println!("Filtered out a device: {}", device_path_string);
-        return;
-    }
-    let device_path = device_path_string.into();
-
-    let mutex_guard = EVENT_SENDER.lock();
-    if mutex_guard.is_err() {
-        return;
-    }
-
-    if let Some(sender) = &*mutex_guard.unwrap() {
-        let _ = sender.send(UsbConnectionEvent::Connected(device_path));
-    }
-}
-
-/// Handles the `DBT_DEVICEREMOVECOMPLETE` event.
-///
-/// Filters for USB and HID devices and sends a `Disconnected` event if the device matches.
-fn handle_device_removal(dev_brodcast: *const DEV_BROADCAST_DEVICEINTERFACE_W) {
-    let dev_type = unsafe { (*dev_brodcast).dbcc_devicetype };
-    if dev_type != DBT_DEVTYP_DEVICEINTERFACE {
-        return;
-    }
-
-    let device_path_string = get_device_path(dev_brodcast).to_uppercase();
-
-    let filter = !device_path_string.starts_with(r"\\?\USB#")
-        && !device_path_string.starts_with(r"\\?\HID#");
-
-    if filter {
-        println!("Filtered out a device: {}", device_path_string);
-        return;
-    }
-    let device_path = device_path_string.into();
-
-    let mutex_guard = EVENT_SENDER.lock();
-    if mutex_guard.is_err() {
-        return;
-    }
-
-    if let Some(sender) = &*mutex_guard.unwrap() {
-        let _ = sender.send(UsbConnectionEvent::Disconnected(device_path));
-    }
-}
-
-/// The Windows Procedure function for the hidden window.
-///
-/// This function intercepts `WM_DEVICECHANGE` messages to detect device insertion/removal.
-extern "system" fn window_proc(hwnd: HWND, msg: u32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
-    match msg {
-        WM_DEVICECHANGE => {
-            match wparam as u32 {
-                DBT_DEVICEARRIVAL => {
-                    let dev_brodcast = lparam as *const DEV_BROADCAST_DEVICEINTERFACE_W;
-                    if !dev_brodcast.is_null() {
-                        handle_device_arrival(dev_brodcast);
-                    }
-                }
-                DBT_DEVICEREMOVECOMPLETE => {
-                    let dev_brodcast = lparam as *const DEV_BROADCAST_DEVICEINTERFACE_W;
-                    if !dev_brodcast.is_null() {
-                        handle_device_removal(dev_brodcast);
-                    }
-                }
-                _ => {}
-            }
-            0
-        }
-        _ => unsafe { DefWindowProcW(hwnd, msg, wparam, lparam) },
-    }
-}
-
-/// RAII wrapper for a Window Handle (HWND).
-///
-/// Ensures `DestroyWindow` is called when the handle goes out of scope.
-struct WindowHandle(HWND);
-
-impl Deref for WindowHandle {
-    type Target = HWND;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl Drop for WindowHandle {
-    fn drop(&mut self) {
-        if !self.0.is_null() {
-            unsafe {
-                let _ = DestroyWindow(self.0);
-            }
-        }
-    }
-}
-
-/// RAII wrapper for a Device Notification Handle (HDEVNOTIFY).
-///
-/// Ensures `UnregisterDeviceNotification` is called when the handle goes out of scope.
-struct NotificationHandle(HDEVNOTIFY);
-
-impl Deref for NotificationHandle {
-    type Target = HDEVNOTIFY;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl Drop for NotificationHandle {
-    fn drop(&mut self) {
-        if !self.0.is_null() {
-            unsafe {
-                let _ = UnregisterDeviceNotification(self.0);
-            }
-        }
-    }
-}
-
-/// RAII wrapper for a Window Class.
-///
-/// Ensures `UnregisterClassW` is called when the class goes out of scope.
-struct WindowClass(Rc<[u16]>);
-
-impl Deref for WindowClass {
-    type Target = Rc<[u16]>;
-
-    fn deref(&self) -> &Self::Target {
-        &self.0
-    }
-}
-
-impl Drop for WindowClass {
-    fn drop(&mut self) {
-        unsafe {
-            let hinstance = GetModuleHandleW(null());
-            let _ = UnregisterClassW(self.0.as_ptr(), hinstance);
-        }
-    }
-}
-
-/// Represents a USB connection event.
-pub enum UsbConnectionEvent {
-    /// A device was connected. Contains the device path.
-    Connected(Arc<str>),
-    /// A device was disconnected. Contains the device path.
-    Disconnected(Arc<str>),
-}
-
-/// Manages the background thread and resources for monitoring USB events.
-pub struct UsbConnectionCallbacksHandle {
-    event_receiver: Receiver<UsbConnectionEvent>,
-    thread_finish_receiver: Receiver<Result<(), Win32Error>>,
-    #[allow(dead_code)] // Kept alive to ensure the thread runs
-    thread_handle: JoinHandle<Result<(), Win32Error>>,
-}
-
-impl UsbConnectionCallbacksHandle {
-    /// Sets up the background thread to listen for USB connection events.
-    ///
-    /// This function spawns a thread that creates a hidden message-only window
-    /// and registers for device notifications.
-    ///
-    /// # Returns
-    ///
-    /// * `Ok(UsbConnectionCallbacksHandle)` - Handle to manage the listener.
-    /// * `Err(anyhow::Error)` - If initialization fails.
-    pub fn setup_connection_callbacks() -> anyhow::Result<Self> {
-        let (event_sender, event_receiver) = std::sync::mpsc::channel::<UsbConnectionEvent>();
-        let (thread_finish_sender, thread_finish_receiver) =
-            std::sync::mpsc::channel::<Result<(), Win32Error>>();
-
-        if let Ok(mut sender_lock) = EVENT_SENDER.lock() {
-            *sender_lock = Some(event_sender);
-        } else {
-            return Err(anyhow::anyhow!("Failed to acquire lock for EVENT_SENDER"));
-        }
-
-        let thread_handle = std::thread::spawn(move || -> Result<(), Win32Error> {
-            let class_name = "UsbConnectionDetector\0"
-                .encode_utf16()
-                .collect::<Rc<[u16]>>();
-
-            unsafe {
-                let window_class = WNDCLASSW {
-                    lpfnWndProc: Some(window_proc),
-                    hInstance: GetModuleHandleW(null()),
-                    lpszClassName: class_name.as_ptr(),
-                    ..std::mem::zeroed()
-                };
-                let class_name = WindowClass(class_name.clone());
-
-                let class_registration = RegisterClassW(&window_class as *const _);
-
-                if class_registration == 0 {
-                    if let Err(_) = thread_finish_sender.send(Err(GetLastError().into())) {
-                        println!("Failed to send error from USB callback thread");
-                    }
-                    return Err(GetLastError().into());
-                }
-
-                let hwnd = WindowHandle(CreateWindowExW(
-                    0,
-                    class_name.as_ptr(),
-                    class_name.as_ptr(),
-                    0,
-                    0,
-                    0,
-                    0,
-                    0,
-                    HWND_MESSAGE,
-                    null_mut(),
-                    null_mut(),
-                    null_mut(),
-                ));
-
-                if hwnd.is_null() {
-                    if let Err(_) = thread_finish_sender.send(Err(GetLastError().into())) {
-                        println!("Failed to send error from USB callback thread");
-                    }
-                    return Err(GetLastError().into());
-                }
-
-                let filter = DEV_BROADCAST_DEVICEINTERFACE_W {
-                    dbcc_size: std::mem::size_of::<DEV_BROADCAST_DEVICEINTERFACE_W>() as u32,
-                    dbcc_devicetype: DBT_DEVTYP_DEVICEINTERFACE,
-                    ..std::mem::zeroed()
-                };
-
-                let notification_handle = NotificationHandle(RegisterDeviceNotificationW(
-                    *hwnd,
-                    &filter as *const _ as *const _,
-                    DEVICE_NOTIFY_WINDOW_HANDLE | DEVICE_NOTIFY_ALL_INTERFACE_CLASSES,
-                ));
-
-                if notification_handle.is_null() {
-                    if let Err(_) = thread_finish_sender.send(Err(GetLastError().into())) {
-                        println!("Failed to send error from USB callback thread");
-                    }
-                    return Err(GetLastError().into());
-                }
-
-                let mut msg = std::mem::zeroed();
-                loop {
-                    let ret = GetMessageW(&mut msg, *hwnd, 0, 0);
-                    match ret {
-                        -1 => {
-                            if let Err(_) = thread_finish_sender.send(Err(GetLastError().into())) {
-                                println!("Failed to send error from USB callback thread");
-                            }
-                            return Err(GetLastError().into());
-                        }
-                        0 => break,
-                        _ => {
-                            TranslateMessage(&msg);
-                            DispatchMessageW(&msg);
-                        }
-                    }
-                }
-
-                Ok(())
-            }
-        });
-
-        Ok(Self {
-            event_receiver,
-            thread_finish_receiver,
-            thread_handle,
-        })
-    }
-
-    /// Polls for new USB connection events.
-    ///
-    /// This function is non-blocking and should be called periodically in the main loop.
-    ///
-    /// # Returns
-    ///
-    /// * `Ok(UsbConnectionEvent)` - A new event (Connected/Disconnected).
-    /// * `Err(PollEventError::ThreadFinished)` - If the background thread has stopped.
-    /// * `Err(PollEventError::ThreadRecvError)` - If the channel is empty (no new events).
-    pub fn poll_events(&self) -> Result<UsbConnectionEvent, PollEventError> {
-        let thread_finished = self.thread_finish_receiver.try_recv();
-        if thread_finished.is_ok() {
-            let result = thread_finished.unwrap();
-            return match result {
-                Ok(_) => Err(PollEventError::ThreadFinished),
-                Err(e) => Err(e.into()),
-            };
-        }
-
-        self.event_receiver
-            .try_recv()
-            .map_err(|e| PollEventError::from(e))
-    }
-}
@@ -0,0 +1,173 @@
+//! # USB Connection Callback Module
+//!
+//! This module implements a mechanism to detect USB device insertion and removal events.
+//! A platform-specific listener runs on a background thread and broadcasts these events
+//! via a channel to the main application.
+//!
+//! Key components:
+//! - `UsbConnectionCallbacksHandle`: The main interface for setting up and polling events.
+//! - `UsbConnectionEvent`: Enum representing connection/disconnection events.
+//! - `win32`: The Windows listener built on a hidden window receiving `WM_DEVICECHANGE` messages.
+//! - `netlink`: The Linux listener reading kernel uevents from a `NETLINK_KOBJECT_UEVENT` socket.
+
+#[cfg(target_os = "linux")]
+pub mod netlink;
+#[cfg(windows)]
+mod win32;
+
+use std::{
+    sync::{
+        Arc,
+        mpsc::{Receiver, Sender},
+    },
+    thread::JoinHandle,
+};
+
+use crate::error::PollEventError;
+
+/// The error the platform listener thread finishes with.
+#[cfg(windows)]
+type ListenerError = crate::error::Win32Error;
+#[cfg(not(windows))]
+type ListenerError = std::io::Error;
+
+/// Represents a USB connection event.
+///
+/// The device path is a device interface path on Windows and a sysfs path on Linux;
+/// `device_path_to_device_id` turns either into a `DeviceId`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UsbConnectionEvent {
+    /// A device was connected. Contains the device path.
+    Connected(Arc<str>),
+    /// A device was disconnected. Contains the device path.
+    Disconnected(Arc<str>),
+    /// Events were lost (e.g. the kernel's uevent buffer overflowed), so the device tree has to
+    /// be re-enumerated.
+    EventsLost,
+}
+
+/// Manages the background thread and resources for monitoring USB events.
+pub struct UsbConnectionCallbacksHandle {
+    event_receiver: Receiver<UsbConnectionEvent>,
+    thread_finish_receiver: Receiver<Result<(), ListenerError>>,
+    #[allow(dead_code)] // Kept alive to ensure the thread runs
+    thread_handle: Option<JoinHandle<Result<(), ListenerError>>>,
+}
+
+impl UsbConnectionCallbacksHandle {
+    /// Sets up the background thread to listen for USB connection events.
+    ///
+    /// This function spawns the platform listener thread. On Windows it creates a hidden
+    /// message-only window and registers for device notifications; on Linux it subscribes
+    /// to kernel uevents over netlink.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(UsbConnectionCallbacksHandle)` - Handle to manage the listener.
+    /// * `Err(anyhow::Error)` - If initialization fails.
+    pub fn setup_connection_callbacks() -> anyhow::Result<Self> {
+        let (event_sender, event_receiver) = std::sync::mpsc::channel::<UsbConnectionEvent>();
+
+        Self::spawn_listener(event_sender, event_receiver)
+    }
+
+    /// Sets up the listener like `setup_connection_callbacks`, additionally calling `wake`
+    /// whenever an event was queued or the listener finished.
+    ///
+    /// This lets an event loop that blocks on something else (e.g. its sockets) wake up for
+    /// connection events instead of polling for them.
+    ///
+    /// # Arguments
+    ///
+    /// * `wake` - Called from a background thread after every queued event.
+    pub fn setup_connection_callbacks_with_waker(
+        wake: impl Fn() + Send + 'static,
+    ) -> anyhow::Result<Self> {
+        let (listener_sender, listener_receiver) = std::sync::mpsc::channel::<UsbConnectionEvent>();
+        let (event_sender, event_receiver) = std::sync::mpsc::channel::<UsbConnectionEvent>();
+
+        // Relays the events of the listener thread; ends once the listener thread finished
+        // (dropping its sender) or the handle was dropped.
+        std::thread::spawn(move || {
+            for event in listener_receiver {
+                if event_sender.send(event).is_err() {
+                    break;
+                }
+                wake();
+            }
+            wake();
+        });
+
+        Self::spawn_listener(listener_sender, event_receiver)
+    }
+
+    /// Spawns the platform listener thread sending its events into `event_sender`.
+    fn spawn_listener(
+        event_sender: Sender<UsbConnectionEvent>,
+        event_receiver: Receiver<UsbConnectionEvent>,
+    ) -> anyhow::Result<Self> {
+        let (thread_finish_sender, thread_finish_receiver) =
+            std::sync::mpsc::channel::<Result<(), ListenerError>>();
+
+        #[cfg(any(windows, target_os = "linux"))]
+        {
+            #[cfg(windows)]
+            let thread_handle = win32::spawn_listener_thread(event_sender, thread_finish_sender)?;
+            #[cfg(target_os = "linux")]
+            let thread_handle = netlink::spawn_listener_thread(event_sender, thread_finish_sender)?;
+
+            Ok(Self {
+                event_receiver,
+                thread_finish_receiver,
+                thread_handle: Some(thread_handle),
+            })
+        }
+
+        #[cfg(not(any(windows, target_os = "linux")))]
+        {
+            let _ = (event_sender, event_receiver);
+            let _ = (thread_finish_sender, thread_finish_receiver);
+            Err(anyhow::anyhow!(
+                "USB connection callbacks are not supported on this platform"
+            ))
+        }
+    }
+
+    /// Creates a handle that delivers the events sent into a channel instead of
+    /// listening to the system.
+    ///
+    /// This is used to drive the connection handling from simulated sources.
+    ///
+    /// # Arguments
+    ///
+    /// * `event_receiver` - The receiving end of the channel the events are sent to.
+    pub fn from_event_receiver(event_receiver: Receiver<UsbConnectionEvent>) -> Self {
+        let (_, thread_finish_receiver) = std::sync::mpsc::channel::<Result<(), ListenerError>>();
+
+        Self {
+            event_receiver,
+            thread_finish_receiver,
+            thread_handle: None,
+        }
+    }
+
+    /// Polls for new USB connection events.
+    ///
+    /// This function is non-blocking and should be called periodically in the main loop.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(UsbConnectionEvent)` - A new event (Connected/Disconnected).
+    /// * `Err(PollEventError::ThreadFinished)` - If the background thread has stopped.
+    /// * `Err(PollEventError::ThreadRecvError)` - If the channel is empty (no new events).
+    pub fn poll_events(&self) -> Result<UsbConnectionEvent, PollEventError> {
+        if let Ok(result) = self.thread_finish_receiver.try_recv() {
+            return match result {
+                Ok(_) => Err(PollEventError::ThreadFinished),
+                Err(e) => Err(e.into()),
+            };
+        }
+
+        self.event_receiver.try_recv().map_err(PollEventError::from)
+    }
+}
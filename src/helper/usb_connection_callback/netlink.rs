@@ -0,0 +1,377 @@
+//! # Netlink USB Connection Listener
+//!
+//! Linux implementation of the USB connection listener. It reads kernel uevents from a
+//! `NETLINK_KOBJECT_UEVENT` socket and forwards USB `add`/`remove` events as
+//! `UsbConnectionEvent`s carrying the sysfs path of the device.
+//!
+//! Bursts of uevents (e.g. when a docking station is connected) can overflow the socket buffer,
+//! in which case the kernel drops events and `recv` fails with `ENOBUFS`. The buffer is enlarged
+//! to make this unlikely; if it happens anyway, `UsbConnectionEvent::EventsLost` is reported so
+//! the device tree gets re-enumerated, and listening continues.
+//!
+//! Parsing is done by `parse_uevent`, which works on raw uevent buffers so it can be
+//! exercised without a socket.
+
+use std::{
+    io,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    sync::{Arc, mpsc::Sender},
+    thread::JoinHandle,
+};
+
+use super::UsbConnectionEvent;
+
+/// The mount point of sysfs, prepended to the `DEVPATH` of an uevent.
+const SYSFS_ROOT: &str = "/sys";
+
+/// The netlink multicast group the kernel broadcasts its uevents on.
+const KERNEL_UEVENT_GROUP: u32 = 1;
+
+/// The size of the receive buffer; uevents are limited to a few kilobytes by the kernel.
+const UEVENT_BUFFER_SIZE: usize = 8192;
+
+/// The size requested for the socket's receive buffer, large enough to hold the uevent burst of
+/// a docking station or a fully populated hub.
+const SOCKET_RECEIVE_BUFFER_SIZE: libc::c_int = 4 * 1024 * 1024;
+
+/// Parses a raw kernel uevent into a `UsbConnectionEvent`.
+///
+/// A kernel uevent is a header of the form `action@devpath` followed by NUL separated
+/// `KEY=VALUE` pairs. Only `add` and `remove` events of the `usb` subsystem for USB devices
+/// and interfaces are turned into events; everything else yields `None`.
+///
+/// # Arguments
+///
+/// * `buffer` - The raw bytes of a single uevent message.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::usb_connection_callback::{UsbConnectionEvent, netlink::parse_uevent};
+///
+/// let buffer = b"add@/devices/pci0000:00/0000:00:14.0/usb1/1-1\0\
+///     ACTION=add\0DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-1\0\
+///     SUBSYSTEM=usb\0DEVTYPE=usb_device\0SEQNUM=4242\0";
+///
+/// assert_eq!(
+///     parse_uevent(buffer),
+///     Some(UsbConnectionEvent::Connected(
+///         "/sys/devices/pci0000:00/0000:00:14.0/usb1/1-1".into()
+///     ))
+/// );
+///
+/// // Events of other subsystems are ignored.
+/// assert_eq!(parse_uevent(b"add@/devices/virtual/net/tun0\0ACTION=add\0SUBSYSTEM=net\0"), None);
+/// ```
+pub fn parse_uevent(buffer: &[u8]) -> Option<UsbConnectionEvent> {
+    let mut fields = buffer
+        .split(|&b| b == 0)
+        .filter(|field| !field.is_empty())
+        .map(String::from_utf8_lossy);
+
+    // Messages relayed by udev start with "libudev" instead of an "action@devpath" header.
+    let header = fields.next()?;
+    let (header_action, header_devpath) = header.split_once('@')?;
+
+    let mut action = header_action.to_string();
+    let mut devpath = header_devpath.to_string();
+    let mut subsystem = None;
+    let mut devtype = None;
+
+    for field in fields {
+        let Some((key, value)) = field.split_once('=') else {
+            continue;
+        };
+        match key {
+            "ACTION" => action = value.to_string(),
+            "DEVPATH" => devpath = value.to_string(),
+            "SUBSYSTEM" => subsystem = Some(value.to_string()),
+            "DEVTYPE" => devtype = Some(value.to_string()),
+            _ => {}
+        }
+    }
+
+    if subsystem.as_deref() != Some("usb") {
+        return None;
+    }
+    if !matches!(devtype.as_deref(), Some("usb_device" | "usb_interface")) {
+        return None;
+    }
+
+    let device_path: Arc<str> = format!("{}{}", SYSFS_ROOT, devpath).into();
+    match action.as_str() {
+        "add" => Some(UsbConnectionEvent::Connected(device_path)),
+        "remove" => Some(UsbConnectionEvent::Disconnected(device_path)),
+        _ => None,
+    }
+}
+
+/// Opens a netlink socket subscribed to kernel uevents.
+fn open_uevent_socket() -> io::Result<OwnedFd> {
+    // SAFETY: Plain socket creation; the returned descriptor is checked before use.
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_NETLINK,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::NETLINK_KOBJECT_UEVENT,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: `fd` is a freshly created, valid descriptor owned by nobody else.
+    let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    // SAFETY: `sockaddr_nl` is a plain C struct for which all-zero is a valid value.
+    let mut address: libc::sockaddr_nl = unsafe { std::mem::zeroed() };
+    address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+    address.nl_groups = KERNEL_UEVENT_GROUP;
+
+    // SAFETY: We pass a valid socket and a pointer to a properly sized `sockaddr_nl`.
+    let result = unsafe {
+        libc::bind(
+            socket.as_raw_fd(),
+            &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // `SO_RCVBUFFORCE` ignores the `rmem_max` limit but requires CAP_NET_ADMIN; without it,
+    // fall back to `SO_RCVBUF`, which the kernel caps at `rmem_max`.
+    let set_receive_buffer = |option: libc::c_int| {
+        // SAFETY: We pass a valid socket and a pointer to a `c_int` of the given size.
+        unsafe {
+            libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                option,
+                &SOCKET_RECEIVE_BUFFER_SIZE as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        }
+    };
+    if set_receive_buffer(libc::SO_RCVBUFFORCE) < 0 && set_receive_buffer(libc::SO_RCVBUF) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(socket)
+}
+
+/// Spawns the background thread that reads uevents from the netlink socket.
+///
+/// The socket is opened before the thread is spawned, so setup errors are reported directly.
+///
+/// # Arguments
+///
+/// * `event_sender` - The channel the connection events are sent to.
+/// * `thread_finish_sender` - The channel the thread reports its exit status to.
+pub(super) fn spawn_listener_thread(
+    event_sender: Sender<UsbConnectionEvent>,
+    thread_finish_sender: Sender<Result<(), io::Error>>,
+) -> anyhow::Result<JoinHandle<Result<(), io::Error>>> {
+    let socket = open_uevent_socket()?;
+
+    let thread_handle = std::thread::spawn(move || -> Result<(), io::Error> {
+        let mut buffer = vec![0u8; UEVENT_BUFFER_SIZE];
+
+        loop {
+            // SAFETY: We pass a valid socket and a buffer of the given length.
+            let received = unsafe {
+                libc::recv(
+                    socket.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+
+            if received < 0 {
+                let errno = io::Error::last_os_error()
+                    .raw_os_error()
+                    .unwrap_or_default();
+                match errno {
+                    libc::EINTR => continue,
+                    // The socket buffer overflowed and the kernel dropped events.
+                    libc::ENOBUFS => {
+                        println!(
+                            "USB connection events were lost: {}",
+                            io::Error::from_raw_os_error(errno)
+                        );
+                        if event_sender.send(UsbConnectionEvent::EventsLost).is_err() {
+                            let _ = thread_finish_sender.send(Ok(()));
+                            return Ok(());
+                        }
+                        continue;
+                    }
+                    _ => {}
+                }
+
+                if thread_finish_sender
+                    .send(Err(io::Error::from_raw_os_error(errno)))
+                    .is_err()
+                {
+                    println!("Failed to send error from USB callback thread");
+                }
+                return Err(io::Error::from_raw_os_error(errno));
+            }
+
+            if let Some(event) = parse_uevent(&buffer[..received as usize])
+                && event_sender.send(event).is_err()
+            {
+                // The receiving side is gone, nobody is listening anymore.
+                let _ = thread_finish_sender.send(Ok(()));
+                return Ok(());
+            }
+        }
+    });
+
+    Ok(thread_handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The sysfs path of a device on the first root port.
+    const DEVICE: &str = "/devices/pci0000:00/0000:00:14.0/usb1/1-1";
+    /// The sysfs path of the first interface of `DEVICE`.
+    const INTERFACE: &str = "/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0";
+
+    /// Builds a kernel uevent of the `usb` subsystem.
+    fn uevent(action: &str, devpath: &str, devtype: &str) -> Vec<u8> {
+        format!(
+            "{action}@{devpath}\0ACTION={action}\0DEVPATH={devpath}\0\
+             SUBSYSTEM=usb\0DEVTYPE={devtype}\0SEQNUM=1\0"
+        )
+        .into_bytes()
+    }
+
+    fn connected(devpath: &str) -> Option<UsbConnectionEvent> {
+        Some(UsbConnectionEvent::Connected(
+            format!("{}{}", SYSFS_ROOT, devpath).into(),
+        ))
+    }
+
+    fn disconnected(devpath: &str) -> Option<UsbConnectionEvent> {
+        Some(UsbConnectionEvent::Disconnected(
+            format!("{}{}", SYSFS_ROOT, devpath).into(),
+        ))
+    }
+
+    #[test]
+    fn malformed_uevents_are_ignored() {
+        assert_eq!(parse_uevent(b""), None);
+        assert_eq!(parse_uevent(b"\0\0\0"), None);
+        // A header without `@`.
+        assert_eq!(
+            parse_uevent(b"add\0ACTION=add\0SUBSYSTEM=usb\0DEVTYPE=usb_device\0"),
+            None
+        );
+        // No subsystem or device type.
+        assert_eq!(parse_uevent(format!("add@{DEVICE}\0").as_bytes()), None);
+        assert_eq!(
+            parse_uevent(format!("add@{DEVICE}\0SUBSYSTEM=usb\0").as_bytes()),
+            None
+        );
+        // USB endpoints and other device types are not devices.
+        assert_eq!(
+            parse_uevent(&uevent(
+                "add",
+                &format!("{INTERFACE}/ep_81"),
+                "usb_endpoint"
+            )),
+            None
+        );
+        // A truncated message missing its last NUL is still read to its end.
+        let mut truncated = uevent("add", DEVICE, "usb_device");
+        truncated.pop();
+        assert_eq!(parse_uevent(&truncated), connected(DEVICE));
+    }
+
+    #[test]
+    fn invalid_fields_are_skipped() {
+        let buffer =
+            format!("add@{DEVICE}\0garbage\0\u{FFFD}\0SUBSYSTEM=usb\0=\0DEVTYPE=usb_device\0");
+        assert_eq!(parse_uevent(buffer.as_bytes()), connected(DEVICE));
+
+        // Invalid UTF-8 in an unrelated field does not hide the event.
+        let mut buffer = uevent("remove", DEVICE, "usb_device");
+        buffer.extend_from_slice(b"PRODUCT=\xFF\xFE\0");
+        assert_eq!(parse_uevent(&buffer), disconnected(DEVICE));
+    }
+
+    #[test]
+    fn fields_override_the_header() {
+        let buffer = format!(
+            "add@/devices/virtual/other\0ACTION=remove\0DEVPATH={DEVICE}\0\
+             SUBSYSTEM=usb\0DEVTYPE=usb_device\0"
+        );
+        assert_eq!(parse_uevent(buffer.as_bytes()), disconnected(DEVICE));
+    }
+
+    #[test]
+    fn libudev_messages_are_ignored() {
+        // udev relays events with a binary header after the `libudev` prefix.
+        let mut buffer = b"libudev\0".to_vec();
+        buffer.extend_from_slice(&0xFEED_CAFEu32.to_be_bytes());
+        buffer.extend_from_slice(&[40, 0, 0, 0, 40, 0, 0, 0, 64, 0, 0, 0]);
+        buffer.extend_from_slice(&[0; 16]);
+        buffer.extend_from_slice(
+            format!("ACTION=add\0DEVPATH={DEVICE}\0SUBSYSTEM=usb\0DEVTYPE=usb_device\0SEQNUM=1\0")
+                .as_bytes(),
+        );
+
+        assert_eq!(parse_uevent(&buffer), None);
+    }
+
+    #[test]
+    fn plug_sequence_connects_device_and_interface() {
+        let events: Vec<_> = [
+            uevent("add", DEVICE, "usb_device"),
+            uevent("add", INTERFACE, "usb_interface"),
+            uevent("bind", INTERFACE, "usb_interface"),
+            uevent("bind", DEVICE, "usb_device"),
+        ]
+        .iter()
+        .filter_map(|buffer| parse_uevent(buffer))
+        .collect();
+
+        assert_eq!(
+            events,
+            [connected(DEVICE).unwrap(), connected(INTERFACE).unwrap()]
+        );
+    }
+
+    #[test]
+    fn unplug_sequence_disconnects_interface_and_device() {
+        let events: Vec<_> = [
+            uevent("unbind", INTERFACE, "usb_interface"),
+            uevent("remove", INTERFACE, "usb_interface"),
+            uevent("unbind", DEVICE, "usb_device"),
+            uevent("remove", DEVICE, "usb_device"),
+        ]
+        .iter()
+        .filter_map(|buffer| parse_uevent(buffer))
+        .collect();
+
+        assert_eq!(
+            events,
+            [
+                disconnected(INTERFACE).unwrap(),
+                disconnected(DEVICE).unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn driver_changes_are_not_connections() {
+        // Rebinding a driver (e.g. after `authorized` was toggled) neither connects nor
+        // disconnects the device.
+        for action in ["bind", "unbind", "change", "move", "online", "offline"] {
+            assert_eq!(parse_uevent(&uevent(action, DEVICE, "usb_device")), None);
+        }
+    }
+}
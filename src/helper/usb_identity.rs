@@ -0,0 +1,230 @@
+//! # USB Identity Module
+//!
+//! This module parses device Instance IDs into a structured `UsbIdentity`, so code can look at
+//! the vendor, product, interface or serial number of a device instead of matching on raw
+//! `DeviceId` strings.
+//!
+//! Two Instance ID formats are understood:
+//! - Windows: `<ENUMERATOR>\<HARDWARE ID>\<INSTANCE SUFFIX>`, e.g.
+//!   `USB\VID_046D&PID_C52B&MI_01\6&2752457F&0&0001`. The hardware ID carries `VID_`, `PID_`,
+//!   `MI_` and `REV_` fields; the suffix is either the serial number reported by the device or
+//!   an ID generated by Windows (recognizable by its `&` separators).
+//! - Linux: sysfs kernel names like `1-1.2:1.0`, which describe the bus path of the device
+//!   (`1-1.2`: bus 1, root port 1, hub port 2) and the interface (`:<config>.<interface>`).
+//!   They carry neither vendor/product IDs nor serial numbers.
+
+use crate::helper::device_managment::{DeviceId, device_path_to_device_id};
+
+/// The last part of a Windows Instance ID.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstanceSuffix {
+    /// The serial number reported by the device.
+    Serial(String),
+    /// An ID generated by Windows for a device without a (unique) serial number.
+    Generated(String),
+    /// The Instance ID has no suffix.
+    None,
+}
+
+/// The structured identity of a USB device, parsed from its Instance ID.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::usb_identity::{InstanceSuffix, UsbIdentity};
+///
+/// let identity = UsbIdentity::parse(r"USB\VID_046D&PID_C52B&MI_01\6&2752457F&0&0001");
+/// assert_eq!(identity.enumerator.as_deref(), Some("USB"));
+/// assert_eq!(identity.vendor_id, Some(0x046D));
+/// assert_eq!(identity.product_id, Some(0xC52B));
+/// assert_eq!(identity.interface_number, Some(1));
+/// assert_eq!(identity.instance, InstanceSuffix::Generated("6&2752457F&0&0001".to_string()));
+///
+/// let identity = UsbIdentity::parse(r"USB\VID_0781&PID_5581\4C530001");
+/// assert_eq!(identity.serial(), Some("4C530001"));
+///
+/// // Linux kernel names describe where the device sits on the bus.
+/// let identity = UsbIdentity::parse("1-1.2:1.0");
+/// assert_eq!(identity.bus_path.as_deref(), Some("1-1.2"));
+/// assert_eq!(identity.interface_number, Some(0));
+/// assert_eq!(identity.vendor_id, None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsbIdentity {
+    /// The bus enumerator that reported the device (e.g. `USB`, `HID`, `USBSTOR`).
+    pub enumerator: Option<String>,
+    /// The USB vendor ID (`VID_xxxx`).
+    pub vendor_id: Option<u16>,
+    /// The USB product ID (`PID_xxxx`).
+    pub product_id: Option<u16>,
+    /// The interface number of a composite device's interface (`MI_xx`).
+    pub interface_number: Option<u8>,
+    /// The device revision (`REV_xxxx`).
+    pub revision: Option<String>,
+    /// The instance suffix of a Windows Instance ID.
+    pub instance: InstanceSuffix,
+    /// The position of the device on the bus (Linux kernel names only, e.g. `1-1.2`).
+    pub bus_path: Option<String>,
+}
+
+impl UsbIdentity {
+    /// Parses an Instance ID.
+    ///
+    /// Parsing never fails; fields that cannot be found in the ID are left empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - A Windows Instance ID or a Linux kernel name.
+    pub fn parse(device_id: &str) -> Self {
+        if device_id.contains('\\') {
+            Self::parse_instance_id(device_id)
+        } else {
+            Self::parse_kernel_name(device_id)
+        }
+    }
+
+    /// Parses the Instance ID of the device a connection event was reported for.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_path` - The device interface path (Windows) or sysfs path (Linux).
+    pub fn from_device_path(device_path: &str) -> Self {
+        Self::parse(&device_path_to_device_id(device_path))
+    }
+
+    /// Returns the serial number of the device, if it reports one.
+    pub fn serial(&self) -> Option<&str> {
+        match &self.instance {
+            InstanceSuffix::Serial(serial) => Some(serial),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the device was reported by the HID enumerator.
+    ///
+    /// HID devices are children of the USB device that can actually be enabled or disabled.
+    pub fn is_hid(&self) -> bool {
+        self.enumerator
+            .as_deref()
+            .is_some_and(|enumerator| enumerator.eq_ignore_ascii_case("HID"))
+    }
+
+    /// Parses a Windows style `<ENUMERATOR>\<HARDWARE ID>\<INSTANCE SUFFIX>` Instance ID.
+    fn parse_instance_id(device_id: &str) -> Self {
+        let mut segments = device_id.splitn(3, '\\');
+        let enumerator = segments.next().map(str::to_uppercase);
+        let hardware_id = segments.next().unwrap_or_default();
+
+        let field = |prefix: &str| {
+            hardware_id.split('&').find_map(|field| {
+                let (name, value) = field.split_once('_')?;
+                name.eq_ignore_ascii_case(prefix).then(|| value.to_string())
+            })
+        };
+        let hex_field = |prefix: &str| field(prefix).and_then(|v| u16::from_str_radix(&v, 16).ok());
+
+        let instance = match segments.next() {
+            Some(suffix) if suffix.contains('&') => InstanceSuffix::Generated(suffix.to_string()),
+            Some(suffix) if !suffix.is_empty() => InstanceSuffix::Serial(suffix.to_string()),
+            _ => InstanceSuffix::None,
+        };
+
+        Self {
+            enumerator,
+            vendor_id: hex_field("VID"),
+            product_id: hex_field("PID"),
+            interface_number: field("MI").and_then(|v| u8::from_str_radix(&v, 16).ok()),
+            revision: field("REV"),
+            instance,
+            bus_path: None,
+        }
+    }
+
+    /// Parses a Linux sysfs kernel name (`usb1`, `1-1.2` or `1-1.2:1.0`).
+    fn parse_kernel_name(kernel_name: &str) -> Self {
+        let (bus_path, interface) = match kernel_name.split_once(':') {
+            Some((bus_path, interface)) => (bus_path, Some(interface)),
+            None => (kernel_name, None),
+        };
+
+        // Interfaces are named `<config>.<interface>`.
+        let interface_number = interface
+            .and_then(|interface| interface.split_once('.'))
+            .and_then(|(_config, number)| number.parse().ok());
+
+        Self {
+            enumerator: Some("USB".to_string()),
+            vendor_id: None,
+            product_id: None,
+            interface_number,
+            revision: None,
+            instance: InstanceSuffix::None,
+            bus_path: (!bus_path.is_empty()).then(|| bus_path.to_string()),
+        }
+    }
+}
+
+impl From<&DeviceId> for UsbIdentity {
+    fn from(device_id: &DeviceId) -> Self {
+        Self::parse(device_id)
+    }
+}
+
+impl std::fmt::Display for UsbIdentity {
+    /// Formats the identity like `USB 046D:C52B MI_01 serial 4C530001`, leaving out unknown fields.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = vec![];
+        if let Some(enumerator) = &self.enumerator {
+            parts.push(enumerator.clone());
+        }
+        match (self.vendor_id, self.product_id) {
+            (Some(vendor_id), Some(product_id)) => {
+                parts.push(format!("{:04X}:{:04X}", vendor_id, product_id))
+            }
+            (Some(vendor_id), None) => parts.push(format!("{:04X}:????", vendor_id)),
+            _ => {}
+        }
+        if let Some(interface_number) = self.interface_number {
+            parts.push(format!("MI_{:02X}", interface_number));
+        }
+        if let Some(revision) = &self.revision {
+            parts.push(format!("REV_{}", revision));
+        }
+        if let Some(serial) = self.serial() {
+            parts.push(format!("serial {}", serial));
+        }
+        if let Some(bus_path) = &self.bus_path {
+            parts.push(format!("at {}", bus_path));
+        }
+
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Parses a USB vendor or product ID written as exactly 4 hexadecimal digits, optionally
+/// prefixed with `0x` (e.g. `046D` or `0x046D`).
+///
+/// # Returns
+///
+/// * `Some(u16)` - The ID.
+/// * `None` - If the value is not 4 hexadecimal digits.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::usb_identity::parse_usb_id;
+///
+/// assert_eq!(parse_usb_id("046D"), Some(0x046D));
+/// assert_eq!(parse_usb_id("0xc52b"), Some(0xC52B));
+/// assert_eq!(parse_usb_id("0x0x046D"), None);
+/// assert_eq!(parse_usb_id("46D"), None);
+/// assert_eq!(parse_usb_id("+46D"), None);
+/// ```
+pub fn parse_usb_id(value: &str) -> Option<u16> {
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u16::from_str_radix(digits, 16).ok()
+}
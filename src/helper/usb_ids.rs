@@ -0,0 +1,321 @@
+//! # USB IDs Module
+//!
+//! This module resolves USB vendor, product and class codes to human readable names using the
+//! `usb.ids` database maintained at <http://www.linux-usb.org/usb-ids.html>.
+//!
+//! The database is a plain text file. Vendors (`046d  Logitech, Inc.`) sit at the start of a
+//! line, followed by their products indented by one tab (`c52b  Unifying Receiver`). Class
+//! lines start with `C` (`C 03  Human Interface Device`), followed by their subclasses (one tab)
+//! and protocols (two tabs). The other sections of the file (languages, HID usages, ...) are
+//! skipped.
+//!
+//! The database is looked up in this order:
+//! 1. The file named by the `COMP_GATE_USB_IDS` environment variable.
+//! 2. The copy installed by the system (`hwdata` / `usbutils` on Linux,
+//!    `%ProgramData%\comp-gate\usb.ids` on Windows).
+//! 3. A subset of the database bundled with `comp-gate`, covering common vendors and every
+//!    class code.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+/// The environment variable overriding the location of the `usb.ids` file.
+pub const USB_IDS_ENV: &str = "COMP_GATE_USB_IDS";
+
+/// The subset of the `usb.ids` database bundled with `comp-gate`.
+pub const BUNDLED_USB_IDS: &str = include_str!("../../data/usb.ids");
+
+/// A USB vendor and the products it is known for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbVendor {
+    /// The name of the vendor.
+    pub name: String,
+    /// The names of the vendor's products, keyed by product ID.
+    pub products: HashMap<u16, String>,
+}
+
+/// A USB device/interface class and its subclasses.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbClass {
+    /// The name of the class.
+    pub name: String,
+    /// The subclasses of the class, keyed by subclass code.
+    pub subclasses: HashMap<u8, UsbSubclass>,
+}
+
+/// A USB subclass and its protocols.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbSubclass {
+    /// The name of the subclass.
+    pub name: String,
+    /// The names of the subclass's protocols, keyed by protocol code.
+    pub protocols: HashMap<u8, String>,
+}
+
+/// The section of a `usb.ids` file a line belongs to.
+enum Section {
+    /// Products of a vendor.
+    Vendor(u16),
+    /// Subclasses of a class.
+    Class(u8),
+    /// A section that is not used.
+    Skipped,
+}
+
+/// The `usb.ids` database.
+///
+/// # Example
+///
+/// ```rust
+/// use comp_gate::helper::usb_ids::UsbIds;
+///
+/// let usb_ids = UsbIds::parse(
+///     "# Vendors\n\
+///      046d  Logitech, Inc.\n\
+///      \tc52b  Unifying Receiver\n\
+///      \n\
+///      C 03  Human Interface Device\n\
+///      \t01  Boot Interface Subclass\n\
+///      \t\t02  Mouse\n\
+///      \n\
+///      L 0409  English (US)\n",
+/// );
+///
+/// assert_eq!(usb_ids.vendor_name(0x046D), Some("Logitech, Inc."));
+/// assert_eq!(usb_ids.product_name(0x046D, 0xC52B), Some("Unifying Receiver"));
+/// assert_eq!(usb_ids.product_name(0x046D, 0xC077), None);
+/// assert_eq!(usb_ids.class_name(0x03), Some("Human Interface Device"));
+/// assert_eq!(usb_ids.subclass_name(0x03, 0x01), Some("Boot Interface Subclass"));
+/// assert_eq!(usb_ids.protocol_name(0x03, 0x01, 0x02), Some("Mouse"));
+///
+/// // The bundled database knows the common vendors.
+/// assert_eq!(UsbIds::bundled().vendor_name(0x0781), Some("SanDisk Corp."));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsbIds {
+    /// The known vendors, keyed by vendor ID.
+    pub vendors: HashMap<u16, UsbVendor>,
+    /// The known classes, keyed by class code.
+    pub classes: HashMap<u8, UsbClass>,
+}
+
+impl UsbIds {
+    /// Parses a database in the `usb.ids` format.
+    ///
+    /// Parsing never fails; lines that cannot be understood are skipped, so a newer file with
+    /// additional sections still yields every vendor, product and class it contains.
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The contents of a `usb.ids` file.
+    pub fn parse(source: &str) -> Self {
+        let mut usb_ids = Self::default();
+        let mut section = Section::Skipped;
+        // The subclass following protocol lines belong to, if the last class line was one.
+        let mut subclass: Option<u8> = None;
+
+        for line in source.lines() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(entry) = line.strip_prefix("\t\t") {
+                // Protocols of a subclass (interfaces of products are not used).
+                if let (Section::Class(class), Some(subclass)) = (&section, subclass)
+                    && let Some((code, name)) = parse_entry::<u8>(entry, 2)
+                    && let Some(subclass) = usb_ids
+                        .classes
+                        .get_mut(class)
+                        .and_then(|class| class.subclasses.get_mut(&subclass))
+                {
+                    subclass.protocols.insert(code, name);
+                }
+            } else if let Some(entry) = line.strip_prefix('\t') {
+                match &section {
+                    Section::Vendor(vendor_id) => {
+                        if let Some((product_id, name)) = parse_entry::<u16>(entry, 4)
+                            && let Some(vendor) = usb_ids.vendors.get_mut(vendor_id)
+                        {
+                            vendor.products.insert(product_id, name);
+                        }
+                    }
+                    Section::Class(class) => {
+                        subclass = None;
+                        if let Some((code, name)) = parse_entry::<u8>(entry, 2)
+                            && let Some(class) = usb_ids.classes.get_mut(class)
+                        {
+                            class.subclasses.insert(
+                                code,
+                                UsbSubclass {
+                                    name,
+                                    protocols: HashMap::new(),
+                                },
+                            );
+                            subclass = Some(code);
+                        }
+                    }
+                    Section::Skipped => {}
+                }
+            } else if let Some(entry) = line.strip_prefix("C ") {
+                section = match parse_entry::<u8>(entry, 2) {
+                    Some((code, name)) => {
+                        usb_ids.classes.insert(
+                            code,
+                            UsbClass {
+                                name,
+                                subclasses: HashMap::new(),
+                            },
+                        );
+                        Section::Class(code)
+                    }
+                    None => Section::Skipped,
+                };
+                subclass = None;
+            } else {
+                // Vendor lines; every other section starts with a keyword that is not a vendor ID.
+                section = match parse_entry::<u16>(line, 4) {
+                    Some((vendor_id, name)) => {
+                        usb_ids.vendors.insert(
+                            vendor_id,
+                            UsbVendor {
+                                name,
+                                products: HashMap::new(),
+                            },
+                        );
+                        Section::Vendor(vendor_id)
+                    }
+                    None => Section::Skipped,
+                };
+                subclass = None;
+            }
+        }
+
+        usb_ids
+    }
+
+    /// Returns the database bundled with `comp-gate`.
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_USB_IDS)
+    }
+
+    /// Reads and parses a `usb.ids` file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The location of the file.
+    pub fn load_from(path: &Path) -> io::Result<Self> {
+        // Older copies of the file are Latin-1 encoded; replace what is not valid UTF-8.
+        let bytes = fs::read(path)?;
+        Ok(Self::parse(&String::from_utf8_lossy(&bytes)))
+    }
+
+    /// Loads the most complete database available.
+    ///
+    /// Tries the `COMP_GATE_USB_IDS` environment variable and the system locations in turn
+    /// (see the module documentation), falling back to the bundled database.
+    pub fn load() -> Self {
+        for path in usb_ids_paths() {
+            match Self::load_from(&path) {
+                Ok(usb_ids) => return usb_ids,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => println!(
+                    "Warning: Could not read USB IDs from {} because of an error: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+
+        Self::bundled()
+    }
+
+    /// Returns the name of a vendor.
+    pub fn vendor_name(&self, vendor_id: u16) -> Option<&str> {
+        self.vendors
+            .get(&vendor_id)
+            .map(|vendor| vendor.name.as_str())
+    }
+
+    /// Returns the name of a product of a vendor.
+    pub fn product_name(&self, vendor_id: u16, product_id: u16) -> Option<&str> {
+        self.vendors
+            .get(&vendor_id)?
+            .products
+            .get(&product_id)
+            .map(String::as_str)
+    }
+
+    /// Returns the name of a class.
+    pub fn class_name(&self, class: u8) -> Option<&str> {
+        self.classes.get(&class).map(|class| class.name.as_str())
+    }
+
+    /// Returns the name of a subclass of a class.
+    pub fn subclass_name(&self, class: u8, subclass: u8) -> Option<&str> {
+        self.classes
+            .get(&class)?
+            .subclasses
+            .get(&subclass)
+            .map(|subclass| subclass.name.as_str())
+    }
+
+    /// Returns the name of a protocol of a subclass.
+    pub fn protocol_name(&self, class: u8, subclass: u8, protocol: u8) -> Option<&str> {
+        self.classes
+            .get(&class)?
+            .subclasses
+            .get(&subclass)?
+            .protocols
+            .get(&protocol)
+            .map(String::as_str)
+    }
+}
+
+/// Returns the database shared by the whole process, loading it on first use.
+pub fn usb_ids() -> &'static UsbIds {
+    static USB_IDS: OnceLock<UsbIds> = OnceLock::new();
+    USB_IDS.get_or_init(UsbIds::load)
+}
+
+/// Returns the locations a `usb.ids` file is looked for, in order.
+fn usb_ids_paths() -> Vec<PathBuf> {
+    if let Some(path) = std::env::var_os(USB_IDS_ENV) {
+        return vec![PathBuf::from(path)];
+    }
+
+    if cfg!(windows) {
+        let program_data =
+            std::env::var_os("ProgramData").unwrap_or_else(|| r"C:\ProgramData".into());
+        vec![
+            PathBuf::from(program_data)
+                .join("comp-gate")
+                .join("usb.ids"),
+        ]
+    } else {
+        [
+            "/usr/share/hwdata/usb.ids",
+            "/usr/share/misc/usb.ids",
+            "/usr/share/usb.ids",
+            "/var/lib/usbutils/usb.ids",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect()
+    }
+}
+
+/// Parses a `<hex code>  <name>` entry whose code has exactly `digits` hexadecimal digits.
+fn parse_entry<T: TryFrom<u32>>(entry: &str, digits: usize) -> Option<(T, String)> {
+    let code = entry.get(..digits)?;
+    let name = entry[digits..].strip_prefix(char::is_whitespace)?.trim();
+    if !code.chars().all(|c| c.is_ascii_hexdigit()) || name.is_empty() {
+        return None;
+    }
+
+    let code = u32::from_str_radix(code, 16).ok()?;
+    Some((T::try_from(code).ok()?, name.to_string()))
+}
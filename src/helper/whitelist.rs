@@ -1,34 +1,89 @@
 //! # Whitelist Module
 //!
-//! This module manages the list of authorized USB devices.
-//! It uses the system keyring to securely store the whitelist of device IDs.
+//! This module manages which USB devices are authorized.
+//! The decisions are made by a rule-based `Policy` (see the `policy` module), which is
+//! stored in a TOML policy file (see the `policy_file` module).
 //!
 //! The `Whitelist` struct provides methods to:
-//! - Initialize the whitelist from currently connected devices.
-//! - Apply the whitelist (disable unauthorized devices).
-//! - Add or remove devices from the whitelist.
-//! - Persist the whitelist state.
+//! - Initialize the policy from currently connected devices.
+//! - Apply the policy (disable unauthorized devices, enable authorized ones).
+//! - Evaluate and enforce the policy for a single (e.g. newly connected) device.
+//! - Add allow or deny rules for single devices, and approve devices waiting for an operator.
+//! - Persist the policy and pick up edits of the policy file.
 
-use keyring::Entry;
-use std::{collections::HashSet, rc::Rc, str};
+use std::path::PathBuf;
 
-use crate::helper::device_managment::{DeviceId, DeviceTracker};
-use anyhow::{Result, anyhow};
+use crate::{
+    error::{DeviceBackendError, PolicyFileError},
+    helper::{
+        device_managment::{DeviceId, DeviceState, DeviceTracker},
+        policy::{Policy, PolicyAction, PolicyDecision, PolicyRule},
+        policy_file::{PolicyFile, policy_file_path},
+    },
+};
 
-/// Manages the authorized device list and enforces it on the system.
+/// The outcome of enforcing the policy on a device.
+#[derive(Debug)]
+pub struct EnforcementRecord {
+    /// The device the policy was enforced on.
+    pub device_id: DeviceId,
+    /// The decision of the policy, including the rule that caused it.
+    pub decision: PolicyDecision,
+    /// The result of putting the device into the state the decision calls for.
+    pub result: Result<(), DeviceBackendError>,
+    /// Whether the state of the device was changed; `false` if the device already was in the
+    /// state the decision calls for.
+    pub changed: bool,
+}
+
+impl EnforcementRecord {
+    /// Returns the state the device was put into (or failed to be put into).
+    pub fn device_state(&self) -> DeviceState {
+        self.decision.device_state()
+    }
+}
+
+impl std::fmt::Display for EnforcementRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let verb = match self.device_state() {
+            DeviceState::Enable => "enabled",
+            DeviceState::Disable => "disabled",
+        };
+        match &self.result {
+            Ok(_) if !self.changed => write!(
+                f,
+                "USB Device stays {} by policy: {} -> {}",
+                verb, self.device_id, self.decision
+            ),
+            Ok(_) => write!(
+                f,
+                "USB Device {} by policy: {} -> {}",
+                verb, self.device_id, self.decision
+            ),
+            Err(e) => write!(
+                f,
+                "USB Device could not be {} by policy: {} -> {}: {}",
+                verb, self.device_id, self.decision, e
+            ),
+        }
+    }
+}
+
+/// Manages the device policy and enforces it on the system.
 pub struct Whitelist {
-    /// The keyring entry used for secure storage.
-    entry: Entry,
+    /// The policy file holding the policy currently in effect.
+    policy_file: PolicyFile,
 
     /// The tracker used to interact with system devices.
     pub device_tracker: DeviceTracker,
 }
 
 impl Whitelist {
-    /// Creates a new `Whitelist` instance.
+    /// Creates a new `Whitelist` instance using the policy file at `policy_file_path()`.
     ///
-    /// This initializes the keyring entry and, if creating for the first time,
-    /// might populate it with the currently connected devices (based on the implementation logic).
+    /// If the policy file does not exist yet, it is created with a policy that allows the
+    /// currently connected devices (by VID/PID and serial number where available) and denies
+    /// everything else.
     ///
     /// # Arguments
     ///
@@ -37,198 +92,494 @@ impl Whitelist {
     /// # Returns
     ///
     /// * `Ok(Whitelist)` - The initialized whitelist manager.
-    /// * `Err(anyhow::Error)` - If keyring access fails.
-    pub fn new(device_tracker: DeviceTracker) -> anyhow::Result<Self> {
-        let entry = Entry::new("comp-gate.xfajk", "device_whitelist")?;
+    /// * `Err(PolicyFileError)` - If the policy file cannot be read, created or is invalid.
+    pub fn new(device_tracker: DeviceTracker) -> Result<Self, PolicyFileError> {
+        Self::with_policy_file(device_tracker, policy_file_path())
+    }
 
-        // collect ids
-        let whitelist_entries: HashSet<DeviceId> = device_tracker
-            .devices
-            .iter()
-            .map(|(id, _)| id.clone())
-            .collect();
+    /// Creates a new `Whitelist` instance using the given policy file.
+    ///
+    /// See `Whitelist::new`.
+    pub fn with_policy_file(
+        device_tracker: DeviceTracker,
+        path: impl Into<PathBuf>,
+    ) -> Result<Self, PolicyFileError> {
+        let policy_file = PolicyFile::load_or_create(path, || Policy {
+            rules: device_tracker
+                .devices
+                .values()
+                .map(|device| PolicyRule::for_device(device, PolicyAction::Allow))
+                .collect(),
+            default_action: PolicyAction::Deny,
+        })?;
 
-        let whitelist = Whitelist {
-            entry,
+        Ok(Whitelist {
+            policy_file,
             device_tracker,
-        };
+        })
+    }
 
-        whitelist.store_whitelist(&whitelist_entries)?;
+    /// Returns the policy currently in effect.
+    pub fn policy(&self) -> &Policy {
+        self.policy_file.policy()
+    }
 
-        Ok(whitelist)
+    /// Replaces the policy currently in effect and writes it to the policy file.
+    ///
+    /// Note: This does not change any device state; `apply_whitelist` must be called.
+    pub fn set_policy(&mut self, policy: Policy) -> Result<(), PolicyFileError> {
+        self.policy_file.save(policy)
     }
 
-    /// Enforces the whitelist on the system.
+    /// Reloads the policy if the policy file was edited.
+    ///
+    /// An invalid policy file is reported as an error and the current policy stays in effect.
     ///
-    /// Iterates through all connected devices. If a device ID is not found in the
-    /// stored whitelist, it is disabled. If it is found, it is enabled.
+    /// Note: This does not change any device state; `apply_whitelist` must be called.
     ///
     /// # Returns
     ///
-    /// * `Ok(())` - If all operations succeed.
-    /// * `Err(anyhow::Error)` - If loading the whitelist or changing device state fails.
-    pub fn apply_whitelist(&mut self) -> anyhow::Result<()> {
-        let whitelist_entries = self.load_whitelist()?;
-
-        for d in self.device_tracker.iter() {
-            if !whitelist_entries.contains(&d.device_id) {
-                self.device_tracker.set_device_state(
-                    &d.device_id,
-                    super::device_managment::DeviceState::Disable,
-                )?;
-            } else {
-                self.device_tracker
-                    .set_device_state(&d.device_id, super::device_managment::DeviceState::Enable)?;
-            }
-        }
+    /// * `Ok(true)` - If a new policy was loaded.
+    /// * `Ok(false)` - If the policy file did not change.
+    /// * `Err(PolicyFileError)` - If the edited policy file could not be read or is invalid.
+    pub fn reload_policy_if_changed(&mut self) -> Result<bool, PolicyFileError> {
+        self.policy_file.reload_if_changed()
+    }
 
-        Ok(())
+    /// Evaluates the policy for a tracked device.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(PolicyDecision)` - The decision for the device.
+    /// * `None` - If the device is not tracked.
+    pub fn evaluate(&self, device_id: &DeviceId) -> Option<PolicyDecision> {
+        self.device_tracker
+            .find_device(device_id)
+            .map(|device| self.policy().evaluate(device))
     }
 
-    /// Adds a device ID to the authorized list.
+    /// Enforces the policy on the system.
     ///
-    /// # Arguments
+    /// Evaluates the policy for every root-level device: allowed devices are enabled, denied
+    /// devices (and devices waiting for an operator decision) are disabled. Sub-devices are not
+    /// evaluated on their own, they follow their parent.
     ///
-    /// * `device_id` - The Instance ID of the device to authorize.
-    pub fn whitelist_device(&mut self, device_id: &str) -> anyhow::Result<()> {
-        let mut whitelist_entries = self.load_whitelist()?;
+    /// A device that cannot be put into its state does not stop the others from being processed.
+    ///
+    /// # Returns
+    ///
+    /// A record of every decision that changed the state of a device or failed to. Devices that
+    /// already are in the state the policy calls for are left alone and not reported.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+    /// use comp_gate::helper::device_managment::{DeviceState, DeviceTracker};
+    /// use comp_gate::helper::policy::{Policy, PolicyAction, PolicyRule};
+    /// use comp_gate::helper::whitelist::Whitelist;
+    ///
+    /// let backend = SimulatedBackend::new();
+    /// backend.add_device(SimulatedDevice::new(r"USB\VID_046D&PID_C52B\5&2752457F&0&2"));
+    /// backend.add_device(SimulatedDevice::new(r"USB\VID_0781&PID_5581\4C530001"));
+    /// let tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+    ///
+    /// let path = std::env::temp_dir().join("comp-gate-apply-doc.toml");
+    /// let _ = std::fs::remove_file(&path);
+    /// let mut whitelist = Whitelist::with_policy_file(tracker, &path).unwrap();
+    /// whitelist
+    ///     .set_policy(Policy {
+    ///         rules: vec![PolicyRule {
+    ///             vendor_id: Some(0x0781),
+    ///             ..PolicyRule::new(PolicyAction::Deny)
+    ///         }],
+    ///         default_action: PolicyAction::Allow,
+    ///     })
+    ///     .unwrap();
+    ///
+    /// // Only the stick changes state; the receiver already is enabled.
+    /// let records = whitelist.apply_whitelist();
+    /// assert_eq!(records.len(), 1);
+    /// assert_eq!(records[0].device_id.as_ref(), r"USB\VID_0781&PID_5581\4C530001");
+    ///
+    /// // Applying the same policy again changes nothing.
+    /// assert!(whitelist.apply_whitelist().is_empty());
+    /// assert_eq!(backend.state_changes().len(), 1);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn apply_whitelist(&self) -> Vec<EnforcementRecord> {
+        self.device_tracker
+            .devices
+            .values()
+            .map(|device| self.enforce_root_device(&device.device_id))
+            .filter(|record| record.changed || record.result.is_err())
+            .collect()
+    }
+
+    /// Enforces the policy on the device tree containing a device.
+    ///
+    /// The policy is evaluated for the root-level device of the tree (see `apply_whitelist`),
+    /// so a newly connected interface or HID device is handled through its USB device.
+    ///
+    /// # Returns
+    ///
+    /// * `Some(EnforcementRecord)` - The decision for the root-level device and its outcome.
+    /// * `None` - If the device is not tracked.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+    /// use comp_gate::helper::device_filter::{DeviceFilter, DeviceFilterRule, FilterAction};
+    /// use comp_gate::helper::device_managment::{DeviceId, DeviceState, DeviceTracker};
+    /// use comp_gate::helper::policy::{Policy, PolicyAction, PolicyRule};
+    /// use comp_gate::helper::whitelist::Whitelist;
+    /// use std::rc::Rc;
+    ///
+    /// let backend = SimulatedBackend::new();
+    /// backend.add_device(SimulatedDevice::new(r"USB\VID_0781&PID_5581\4C530001"));
+    /// backend.add_device(
+    ///     SimulatedDevice::new(r"USBSTOR\DISK&VEN_SANDISK\4C530001&0")
+    ///         .with_parent(r"USB\VID_0781&PID_5581\4C530001"),
+    /// );
+    ///
+    /// // Track mass storage devices too.
+    /// let mut filter = DeviceFilter::default();
+    /// filter.rules.push(DeviceFilterRule {
+    ///     enumerator: Some("USBSTOR".to_string()),
+    ///     ..DeviceFilterRule::new(FilterAction::Include)
+    /// });
+    /// let tracker = DeviceTracker::load_with_filter(Box::new(backend.clone()), filter).unwrap();
+    ///
+    /// let path = std::env::temp_dir().join("comp-gate-enforce-doc.toml");
+    /// let _ = std::fs::remove_file(&path);
+    /// let mut whitelist = Whitelist::with_policy_file(tracker, &path).unwrap();
+    /// whitelist
+    ///     .set_policy(Policy {
+    ///         rules: vec![PolicyRule {
+    ///             name: Some("sandisk".to_string()),
+    ///             vendor_id: Some(0x0781),
+    ///             ..PolicyRule::new(PolicyAction::Deny)
+    ///         }],
+    ///         default_action: PolicyAction::Allow,
+    ///     })
+    ///     .unwrap();
+    ///
+    /// // The disk is handled through the USB device it belongs to.
+    /// let disk = DeviceId::from(Rc::<str>::from(r"USBSTOR\DISK&VEN_SANDISK\4C530001&0"));
+    /// let record = whitelist.enforce_device(&disk).unwrap();
+    ///
+    /// assert_eq!(record.device_id.as_ref(), r"USB\VID_0781&PID_5581\4C530001");
+    /// assert_eq!(record.decision.rule.as_deref(), Some("sandisk"));
+    /// assert_eq!(
+    ///     backend.device_state(r"USB\VID_0781&PID_5581\4C530001"),
+    ///     Some(DeviceState::Disable)
+    /// );
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn enforce_device(&self, device_id: &DeviceId) -> Option<EnforcementRecord> {
+        let root_device = self.device_tracker.find_root_device(device_id)?;
 
-        let rc_id: Rc<str> = Rc::from(device_id);
-        let id = DeviceId::from(rc_id);
+        Some(self.enforce_root_device(&root_device.device_id))
+    }
 
-        whitelist_entries.insert(id);
+    /// Evaluates the policy for a root-level device and puts the device into the resulting state.
+    ///
+    /// Devices already in that state are not touched. If the current state cannot be read, the
+    /// state is set anyway.
+    fn enforce_root_device(&self, device_id: &DeviceId) -> EnforcementRecord {
+        let decision = self
+            .evaluate(device_id)
+            .expect("root-level devices are tracked");
+        let state = decision.device_state();
 
-        self.store_whitelist(&whitelist_entries)?;
+        let changed = self.device_tracker.device_state(device_id).ok() != Some(state);
+        let result = if changed {
+            self.device_tracker.set_device_state(device_id, state)
+        } else {
+            Ok(())
+        };
 
-        Ok(())
+        EnforcementRecord {
+            device_id: device_id.clone(),
+            decision,
+            result,
+            changed,
+        }
     }
 
-    /// Removes a device ID from the authorized list.
-    ///
-    /// Note: This does not immediately disable the device; `apply_whitelist` must be called.
+    /// Adds a rule allowing a device, ahead of every other rule.
     ///
     /// # Arguments
     ///
-    /// * `device_id` - The Instance ID of the device to de-authorize.
-    pub fn blacklist_device(&mut self, device_id: &str) -> anyhow::Result<()> {
-        let mut whitelist_entries = self.load_whitelist()?;
-        let rc_id: Rc<str> = Rc::from(device_id);
-        let id = DeviceId::from(rc_id);
-
-        whitelist_entries.remove(&id);
-
-        self.store_whitelist(&whitelist_entries)?;
-
-        Ok(())
+    /// * `device_id` - The Instance ID of the device to authorize.
+    pub fn whitelist_device(&mut self, device_id: &str) -> Result<(), PolicyFileError> {
+        self.prepend_device_rule(device_id, PolicyAction::Allow)
     }
 
-    /// Loads the whitelist from the system keyring.
+    /// Approves a device waiting for an operator decision (policy action `ask`) by adding a rule
+    /// allowing its root-level device, ahead of every other rule.
+    ///
+    /// The rule matches the device like `PolicyRule::for_device` does, by its VID/PID and serial
+    /// number where it reports them. Instance IDs like the sysfs kernel names only name the port
+    /// a device is plugged into, so allowing one would allow any device on that port.
+    ///
+    /// Note: This does not immediately enable the device; `apply_whitelist` must be called.
+    ///
+    /// # Arguments
+    ///
+    /// * `device_id` - The Instance ID of the device or one of its sub-devices.
     ///
     /// # Returns
     ///
-    /// * `Ok(HashSet<Rc<str>>)` - The set of authorized device IDs.
-    /// * `Err(anyhow::Error)` - If the keyring cannot be accessed or data is corrupt.
-    pub fn load_whitelist(&self) -> Result<HashSet<DeviceId>> {
-        let hex = match self.entry.get_password() {
-            Ok(s) => s,
-            Err(e) => return Err(anyhow!("failed to read whitelist from keyring: {}", e)),
+    /// * `Ok(Some(DeviceId))` - The root-level device that was approved.
+    /// * `Ok(None)` - If the device is not tracked or not waiting for approval.
+    /// * `Err(PolicyFileError)` - If the policy could not be saved.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// use comp_gate::helper::device_backend::DevicePropertyKey;
+    /// use comp_gate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+    /// use comp_gate::helper::device_managment::{DeviceId, DeviceState, DeviceTracker};
+    /// use comp_gate::helper::device_property::DeviceProperty;
+    /// use comp_gate::helper::policy::{Policy, PolicyAction, PolicyRule};
+    /// use comp_gate::helper::whitelist::Whitelist;
+    /// use std::rc::Rc;
+    ///
+    /// let backend = SimulatedBackend::new();
+    /// backend.add_device(SimulatedDevice::new(r"USB\VID_046D&PID_C52B\5&2752457F&0&2"));
+    /// backend.add_device(SimulatedDevice::new(r"USB\VID_0781&PID_5581\4C530001"));
+    /// let tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+    ///
+    /// let path = std::env::temp_dir().join("comp-gate-approve-doc.toml");
+    /// let _ = std::fs::remove_file(&path);
+    /// let mut whitelist = Whitelist::with_policy_file(tracker, &path).unwrap();
+    /// whitelist
+    ///     .set_policy(Policy {
+    ///         rules: vec![PolicyRule {
+    ///             vendor_id: Some(0x046D),
+    ///             ..PolicyRule::new(PolicyAction::Allow)
+    ///         }],
+    ///         default_action: PolicyAction::Ask,
+    ///     })
+    ///     .unwrap();
+    /// whitelist.apply_whitelist();
+    ///
+    /// let stick = DeviceId::from(Rc::<str>::from(r"USB\VID_0781&PID_5581\4C530001"));
+    /// assert_eq!(backend.device_state(&stick), Some(DeviceState::Disable));
+    ///
+    /// // The allowed receiver is not waiting for approval, the stick is.
+    /// let receiver = DeviceId::from(Rc::<str>::from(r"USB\VID_046D&PID_C52B\5&2752457F&0&2"));
+    /// assert_eq!(whitelist.approve_device(&receiver).unwrap(), None);
+    /// assert_eq!(whitelist.approve_device(&stick).unwrap(), Some(stick.clone()));
+    ///
+    /// whitelist.apply_whitelist();
+    /// assert_eq!(backend.device_state(&stick), Some(DeviceState::Enable));
+    /// assert_eq!(whitelist.approve_device(&stick).unwrap(), None);
+    ///
+    /// // A sysfs-style device is approved by its VID/PID, not by the port it is plugged into.
+    /// let sysfs_device = |vendor_id: u16, product_id: u16| {
+    ///     SimulatedDevice::new("1-2")
+    ///         .with_property_value(
+    ///             DevicePropertyKey::VendorId,
+    ///             DeviceProperty::UInt16Property { data: vendor_id },
+    ///         )
+    ///         .with_property_value(
+    ///             DevicePropertyKey::ProductId,
+    ///             DeviceProperty::UInt16Property { data: product_id },
+    ///         )
+    /// };
+    /// let port = DeviceId::from(Rc::<str>::from("1-2"));
+    /// backend.add_device(sysfs_device(0x1A2C, 0x2124));
+    /// whitelist.device_tracker.insert_device_by_id("1-2").unwrap();
+    /// assert_eq!(whitelist.approve_device(&port).unwrap(), Some(port.clone()));
+    /// assert_eq!(whitelist.evaluate(&port).unwrap().action, PolicyAction::Allow);
+    ///
+    /// // Another device plugged into the same port still waits for approval.
+    /// whitelist.device_tracker.remove_device_by_id(&port);
+    /// backend.remove_device("1-2");
+    /// backend.add_device(sysfs_device(0x0781, 0x5581));
+    /// whitelist.device_tracker.insert_device_by_id("1-2").unwrap();
+    /// assert_eq!(whitelist.evaluate(&port).unwrap().action, PolicyAction::Ask);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn approve_device(
+        &mut self,
+        device_id: &DeviceId,
+    ) -> Result<Option<DeviceId>, PolicyFileError> {
+        let Some(root_device) = self.device_tracker.find_root_device(device_id) else {
+            return Ok(None);
         };
+        let root_id = root_device.device_id.clone();
+        let rule = PolicyRule::for_device(root_device, PolicyAction::Allow);
 
-        let bytes = decode_hex(&hex)?;
-        let set = deserialize_set_bytes(&bytes)?;
-        Ok(set)
+        match self.evaluate(&root_id) {
+            Some(decision) if decision.action == PolicyAction::Ask => {
+                self.prepend_rule(rule)?;
+                Ok(Some(root_id))
+            }
+            _ => Ok(None),
+        }
     }
 
-    /// Saves the whitelist to the system keyring.
+    /// Adds a rule denying a device, ahead of every other rule.
+    ///
+    /// Note: This does not immediately disable the device; `apply_whitelist` must be called.
     ///
     /// # Arguments
     ///
-    /// * `set` - The set of device IDs to store.
-    pub fn store_whitelist(&self, set: &HashSet<DeviceId>) -> Result<()> {
-        let bytes = serialize_set_bytes(set);
-        let hex = encode_hex(&bytes);
-        self.entry
-            .set_password(&hex)
-            .map_err(|e| anyhow!("failed to write whitelist to keyring: {}", e))?;
-        Ok(())
+    /// * `device_id` - The Instance ID of the device to de-authorize.
+    pub fn blacklist_device(&mut self, device_id: &str) -> Result<(), PolicyFileError> {
+        self.prepend_device_rule(device_id, PolicyAction::Deny)
     }
-}
 
-// helper: serialize as [u64 len LE][bytes][u64 len][bytes]...
-fn serialize_set_bytes(set: &HashSet<DeviceId>) -> Vec<u8> {
-    let mut out = Vec::new();
-    for s in set {
-        let b = s.as_bytes();
-        let len = b.len() as u64;
-        out.extend_from_slice(&len.to_le_bytes());
-        out.extend_from_slice(b);
+    /// Replaces any rule for exactly this Instance ID by a new rule at the top of the policy.
+    fn prepend_device_rule(
+        &mut self,
+        device_id: &str,
+        action: PolicyAction,
+    ) -> Result<(), PolicyFileError> {
+        self.prepend_rule(PolicyRule {
+            device_id: Some(device_id.to_string()),
+            ..PolicyRule::new(action)
+        })
     }
-    out
-}
 
-fn deserialize_set_bytes(bytes: &[u8]) -> Result<HashSet<DeviceId>> {
-    let mut out = HashSet::new();
-    let mut i = 0usize;
-    while i < bytes.len() {
-        if i + 8 > bytes.len() {
-            return Err(anyhow!(
-                "corrupt whitelist data: unexpected EOF reading length"
-            ));
-        }
-        let mut len_bytes = [0u8; 8];
-        len_bytes.copy_from_slice(&bytes[i..i + 8]);
-        let len = u64::from_le_bytes(len_bytes) as usize;
-        i += 8;
-        if i + len > bytes.len() {
-            return Err(anyhow!(
-                "corrupt whitelist data: unexpected EOF reading string"
-            ));
-        }
-        let slice = &bytes[i..i + len];
-        let s = str::from_utf8(slice)
-            .map_err(|e| anyhow!("corrupt whitelist data: invalid UTF-8: {}", e))?;
-        let rc = Rc::<str>::from(s.to_owned().into_boxed_str());
-        let id = DeviceId::from(rc);
-        out.insert(id);
-        i += len;
-    }
-    Ok(out)
-}
+    /// Replaces any rule with the same conditions by a new rule at the top of the policy.
+    fn prepend_rule(&mut self, new_rule: PolicyRule) -> Result<(), PolicyFileError> {
+        let mut policy = self.policy().clone();
+        policy.rules.retain(|rule| {
+            rule != &PolicyRule {
+                action: rule.action,
+                ..new_rule.clone()
+            }
+        });
+        policy.rules.insert(0, new_rule);
 
-// small hex encoder/decoder to avoid extra deps
-fn encode_hex(bytes: &[u8]) -> String {
-    const HEX: &[u8; 16] = b"0123456789abcdef";
-    let mut s = String::with_capacity(bytes.len() * 2);
-    for &b in bytes {
-        let hi = HEX[(b >> 4) as usize];
-        let lo = HEX[(b & 0x0f) as usize];
-        s.push(hi as char);
-        s.push(lo as char);
-    }
-    s
+        self.set_policy(policy)
+    }
 }
 
-fn decode_hex(s: &str) -> Result<Vec<u8>> {
-    let bytes = s.as_bytes();
-    if bytes.len() % 2 != 0 {
-        return Err(anyhow!("invalid hex string length"));
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::helper::device_backend::simulated::{SimulatedBackend, SimulatedDevice};
+
+    const RECEIVER: &str = r"USB\VID_046D&PID_C52B\5&2752457F&0&2";
+    const STICK: &str = r"USB\VID_0781&PID_5581\4C530001";
+    const HUB: &str = r"USB\VID_05E3&PID_0610\6&1";
+
+    fn id(device_id: &str) -> DeviceId {
+        DeviceId::from(Rc::<str>::from(device_id))
     }
-    let mut out = Vec::with_capacity(bytes.len() / 2);
-    let mut i = 0;
-    while i < bytes.len() {
-        let hi = hex_val(bytes[i])?;
-        let lo = hex_val(bytes[i + 1])?;
-        out.push((hi << 4) | lo);
-        i += 2;
+
+    /// Creates a whitelist over the simulated devices that denies every device.
+    fn deny_all(name: &str, backend: &SimulatedBackend) -> (Whitelist, PathBuf) {
+        let tracker = DeviceTracker::load_with_backend(Box::new(backend.clone())).unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "comp-gate-whitelist-test-{}-{}.toml",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let mut whitelist = Whitelist::with_policy_file(tracker, &path).unwrap();
+        whitelist
+            .set_policy(Policy {
+                rules: vec![],
+                default_action: PolicyAction::Deny,
+            })
+            .unwrap();
+        (whitelist, path)
     }
-    Ok(out)
-}
 
-fn hex_val(c: u8) -> Result<u8> {
-    match c {
-        b'0'..=b'9' => Ok(c - b'0'),
-        b'a'..=b'f' => Ok(10 + (c - b'a')),
-        b'A'..=b'F' => Ok(10 + (c - b'A')),
-        _ => Err(anyhow!("invalid hex char: {}", c as char)),
+    #[test]
+    fn failing_device_does_not_stop_enforcement() {
+        let backend = SimulatedBackend::new();
+        backend.add_device(SimulatedDevice::new(RECEIVER));
+        backend.add_device(SimulatedDevice::new(STICK));
+        backend.fail_state_changes(RECEIVER);
+        let (whitelist, path) = deny_all("failing", &backend);
+
+        let mut records = whitelist.apply_whitelist();
+        records.sort_by(|a, b| a.device_id.as_ref().cmp(b.device_id.as_ref()));
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].device_id, id(RECEIVER));
+        assert!(records[0].changed);
+        assert!(matches!(
+            &records[0].result,
+            Err(DeviceBackendError::IoError(e)) if e.kind() == std::io::ErrorKind::PermissionDenied
+        ));
+        assert!(records[0].to_string().contains("could not be disabled"));
+        assert_eq!(records[1].device_id, id(STICK));
+        assert!(records[1].result.is_ok());
+
+        assert_eq!(backend.device_state(RECEIVER), Some(DeviceState::Enable));
+        assert_eq!(backend.device_state(STICK), Some(DeviceState::Disable));
+        assert_eq!(
+            backend.state_changes(),
+            vec![(id(STICK), DeviceState::Disable)]
+        );
+
+        // The failure is reported again on every pass, until the device gives in.
+        let records = whitelist.apply_whitelist();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].result.is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn vanished_device_reports_not_found() {
+        let backend = SimulatedBackend::new();
+        backend.add_device(SimulatedDevice::new(STICK));
+        let (whitelist, path) = deny_all("vanished", &backend);
+
+        // The device is gone before its disconnection event was handled.
+        backend.remove_device(STICK);
+        let record = whitelist.enforce_device(&id(STICK)).unwrap();
+
+        // Its state cannot be read, so setting it is attempted anyway.
+        assert!(record.changed);
+        assert!(matches!(
+            record.result,
+            Err(DeviceBackendError::DeviceNotFound(_))
+        ));
+        assert!(backend.state_changes().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failing_sub_device_is_not_touched() {
+        let backend = SimulatedBackend::new();
+        backend.add_device(SimulatedDevice::new(HUB));
+        backend.add_device(SimulatedDevice::new(STICK).with_parent(HUB));
+        backend.fail_state_changes(STICK);
+        let (whitelist, path) = deny_all("sub-device", &backend);
+
+        // Only the root-level device is enforced on, so the sub-device cannot fail.
+        let record = whitelist.enforce_device(&id(STICK)).unwrap();
+        assert_eq!(record.device_id, id(HUB));
+        assert!(record.result.is_ok());
+        assert_eq!(
+            backend.state_changes(),
+            vec![(id(HUB), DeviceState::Disable)]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn untracked_device_is_not_enforced() {
+        let backend = SimulatedBackend::new();
+        let (whitelist, path) = deny_all("untracked", &backend);
+
+        backend.add_device(SimulatedDevice::new(STICK));
+        assert!(whitelist.enforce_device(&id(STICK)).is_none());
+        assert!(whitelist.apply_whitelist().is_empty());
+        assert!(backend.state_changes().is_empty());
+        std::fs::remove_file(&path).unwrap();
     }
 }
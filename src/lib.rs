@@ -1,15 +1,17 @@
 //! # Comp-Gate Library
 //!
-//! `comp-gate` is a library designed for managing and monitoring USB devices on Windows systems.
+//! `comp-gate` is a library designed for managing and monitoring USB devices on Windows and Linux systems.
 //! It provides functionality for:
 //!
 //! - Detecting USB device insertion and removal events.
 //! - Managing device drivers (enabling/disabling devices).
 //! - Whitelisting specific USB devices based on their hardware IDs.
-//! - Interacting with the Windows SetupAPI for device information.
+//! - Interacting with the Windows SetupAPI or the Linux sysfs tree for device information.
 //!
 //! This library is structured into modules handling errors, helper functions for IO and device management,
-//! and core logic for device monitoring.
+//! and core logic for device monitoring. Tools talking to the core service use the IOAPI client in
+//! the `client` module.
 
+pub mod client;
 pub mod error;
 pub mod helper;